@@ -1,295 +1,402 @@
-use avg32::parser::{AVG32Scene, Header, Pos, Opcode};
+use avg32::labels::*;
+use avg32::parser::{AVG32Scene, Pos, Opcode};
 use avg32::write::Writeable;
 use std::collections::HashMap;
+use std::io::Write;
 use anyhow::{anyhow, Result};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
-enum LabelKind {
-    Condition,
-    Call,
-    Jump,
-    TableCall,
-    TableJump,
+/// `disassemble_to` into a `String`, for tests.
+#[cfg(test)]
+pub fn disassemble(scene: &AVG32Scene) -> Result<String> {
+    let mut bytes = Vec::new();
+    disassemble_to(scene, &mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
-struct LabelPos {
-    kind: LabelKind,
-    pos: Pos
+/// Streams the disassembly to `writer` instead of building a `String`.
+pub fn disassemble_to<W: Write>(scene: &AVG32Scene, writer: &mut W) -> Result<()> {
+    disassemble_with(scene, writer, &mut DefaultNamer, &[])
 }
 
-impl LabelPos {
-    fn new(kind: LabelKind, pos: Pos) -> Self {
-        LabelPos {
-            kind: kind,
-            pos: pos
-        }
+/// Like `disassemble_to`, naming labels with `namer` and starting with a
+/// comment line for each of `comments`.
+pub fn disassemble_with<W: Write>(scene: &AVG32Scene, writer: &mut W, namer: &mut dyn LabelNamer, comments: &[String]) -> Result<()> {
+    let resolved = resolve_labels(&scene, namer)?;
+
+    writer.write_all(b";; -*- mode: lisp -*- \n\n")?;
+    for comment in comments.iter() {
+        writeln!(writer, ";; {}", comment)?;
+    }
+    if !comments.is_empty() {
+        writer.write_all(b"\n")?;
     }
+    serde_lexpr::to_writer(&mut *writer, &resolved)?;
+
+    Ok(())
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
-struct Label {
-    name: String,
-    opcodes: Vec<Opcode>
+/// Renders the scene one opcode per line, prefixed with its offset from the
+/// start of the opcode body, with a line for each jump target. Meant for
+/// reading, grepping and diffing; it can't be assembled back.
+///
+/// Only opcodes for which `filter` returns true are listed. Offsets stay
+/// those of the full scene, and a label line, named with `namer`, is kept if
+/// any opcode under it is listed.
+pub fn to_listing_with<F: Fn(&Opcode) -> bool>(scene: &AVG32Scene, filter: F, namer: &mut dyn LabelNamer) -> Result<String> {
+    listing(scene, filter, namer, false)
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-struct LabelResolvedScene {
-    header: Header,
-    labels: Vec<Label>
+/// Like `to_listing_with`, with each opcode's bytes as written between its
+/// offset and decoded form, e.g. `0002  1c 01 00 00 00 | Jump(...)`.
+/// Text is shown as its Shift_JIS bytes.
+pub fn to_annotated_hex_with<F: Fn(&Opcode) -> bool>(scene: &AVG32Scene, filter: F, namer: &mut dyn LabelNamer) -> Result<String> {
+    listing(scene, filter, namer, true)
 }
 
-fn extract_label(opcode: &Opcode) -> Option<Vec<LabelPos>> {
-    match opcode {
-        Opcode::Condition(_, pos) => Some(vec![LabelPos::new(LabelKind::Condition, pos.clone())]),
-        Opcode::Call(pos) => Some(vec![LabelPos::new(LabelKind::Call, pos.clone())]),
-        Opcode::Jump(pos) => Some(vec![LabelPos::new(LabelKind::Jump, pos.clone())]),
-        Opcode::TableCall(_, poss) => {
-            let mut res = vec![];
-            for pos in poss.iter() {
-                res.push(LabelPos::new(LabelKind::TableCall, pos.clone()))
+fn listing<F: Fn(&Opcode) -> bool>(scene: &AVG32Scene, filter: F, namer: &mut dyn LabelNamer, hex: bool) -> Result<String> {
+    let labels = extract_labels(&scene.opcodes);
+    let names: HashMap<u32, String> = label_names(&labels, namer)?.into_iter().collect();
+
+    let mut opcodes = scene.opcodes.clone();
+    convert_byte_to_label_positions(&mut opcodes, &names)?;
+
+    let mut res = String::new();
+    let mut pos = 0;
+    let mut label = None;
+    for (index, (opcode, orig)) in opcodes.iter().zip(scene.opcodes.iter()).enumerate() {
+        if let Some(name) = names.get(&pos) {
+            label = Some(name);
+        }
+        if filter(orig) {
+            if let Some(name) = label.take() {
+                res.push_str(&format!("{}:\n", name));
             }
-            Some(res)
-        },
-        Opcode::TableJump(_, poss) => {
-            let mut res = vec![];
-            for pos in poss.iter() {
-                res.push(LabelPos::new(LabelKind::TableJump, pos.clone()))
+            if hex {
+                let bytes: Vec<String> = match orig.to_vec() {
+                    Ok(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+                    Err(_) => vec![String::from("??")]
+                };
+                res.push_str(&format!("    {:04x}  {} | {:?}\n", pos, bytes.join(" "), opcode));
+            } else {
+                res.push_str(&format!("    {:04x}  {:?}\n", pos, opcode));
             }
-            Some(res)
-        },
-        _ => None
+        }
+        pos += scene.opcode_size(index) as u32;
     }
+    Ok(res)
 }
 
-fn extract_labels(opcodes: &[Opcode]) -> Vec<LabelPos> {
-    opcodes.iter().map(extract_label).filter(|x| x.is_some()).map(|x| x.unwrap()).flatten().collect()
-}
+pub fn assemble(sexp: &str) -> Result<AVG32Scene> {
+    let value = lexpr::from_str(sexp).map_err(|e| anyhow!("Invalid disassembly: {}", e))?;
 
-fn resolve_labels(scene: &AVG32Scene) -> Result<LabelResolvedScene> {
-    let mut labels = extract_labels(&scene.opcodes);
-    labels.sort();
+    // Checked before the rest, which a newer format might not match.
+    if let Some(version) = value.get("version") {
+        check_format_version(version.as_u64().ok_or_else(|| anyhow!("Invalid format version: {}", version))?)?;
+    }
+    let resolved: LabelResolvedScene = serde_lexpr::from_value(&value).map_err(|e| anyhow!("Invalid disassembly: {}", e))?;
 
-    let mut positions: HashMap<u32, Label> = HashMap::new();
+    let scene = compile_labels(&resolved)?;
 
-    positions.insert(0, Label {
-        name: String::from("start"),
-        opcodes: Vec::new()
-    });
+    Ok(scene)
+}
 
-    for label in labels.into_iter() {
-        if let Pos::Offset(pos) = label.pos {
-            if !positions.contains_key(&pos) {
-                positions.insert(pos, Label {
-                    name: format!("{:?}_0x{:x?}", label.kind, pos).to_lowercase(),
-                    opcodes: Vec::new()
-                });
-            }
-        } else {
-            return Err(anyhow!("Labels were already resolved"));
-        }
-    }
+/// Parses a single label from `disassemble`'s output, e.g. after editing
+/// it, without assembling the rest of the scene. Errors name the label and
+/// the index of the opcode at fault. Jump targets are left as label names,
+/// since their offsets depend on the whole scene.
+pub fn assemble_label(sexp: &str) -> Result<Vec<Opcode>> {
+    let value = lexpr::from_str(sexp).map_err(|e| anyhow!("Invalid label: {}", e))?;
+    let name = value.get("name").and_then(|n| n.as_str())
+        .ok_or_else(|| anyhow!("Invalid label: expected ((name . \"...\") (opcodes ...))"))?;
+    let items = value.get("opcodes").and_then(|o| o.list_iter())
+        .ok_or_else(|| anyhow!("Label {}: expected a list of opcodes", name))?;
 
-    let mut offsets: Vec<u32> = positions.keys().cloned().collect();
-    offsets.sort();
-    let mut offset_iter = offsets.iter();
-    let mut offset = offset_iter.next();
-    let mut next_offset = offset_iter.next();
-    let mut cur_pos = 0;
-    let mut cur_label = positions.get_mut(&cur_pos).unwrap();
-
-    let start_pos = scene.header.byte_size() as u32;
-
-    for opcode in scene.opcodes.iter() {
-        match next_offset {
-            Some(noff) => {
-                if cur_pos < *noff {
-                    debug!("{:04x?}-{:04x}: 0x{:04x?} (0x{:04x?}) + 0x{:02x?} - {:x?}", offset.unwrap() + start_pos, *next_offset.unwrap_or(&0) + start_pos, cur_pos + start_pos, cur_pos, opcode.byte_size(), opcode);
-                    cur_label.opcodes.push(opcode.clone());
-                    cur_pos += opcode.byte_size() as u32;
-                } else if cur_pos == *noff {
-                    cur_label = positions.get_mut(noff).unwrap();
-                    debug!("    {}:", cur_label.name);
-                    debug!("{:04x?}-{:04x}: 0x{:04x?} (0x{:04x?}) + 0x{:02x?} - {:x?}", offset.unwrap() + start_pos, *next_offset.unwrap_or(&0) + start_pos, cur_pos + start_pos, cur_pos, opcode.byte_size(), opcode);
-                    cur_label.opcodes.push(opcode.clone());
-                    offset = next_offset;
-                    next_offset = offset_iter.next();
-                    cur_pos += opcode.byte_size() as u32;
-                } else {
-                    return Err(anyhow!("Misaligned opcode at pos 0x{:04x?}: offset 0x{:04x?} opcode {:x?}", cur_pos, offset, opcode));
-                }
-            },
-            None => {
-                debug!("{:04x?}-{:04x}: 0x{:04x?} (0x{:04x?}) + 0x{:02x?} - {:x?}", offset.unwrap() + start_pos, *next_offset.unwrap_or(&0) + start_pos, cur_pos + start_pos, cur_pos, opcode.byte_size(), opcode);
-                cur_label.opcodes.push(opcode.clone());
-                cur_pos += opcode.byte_size() as u32;
+    let mut opcodes = Vec::new();
+    for (i, item) in items.enumerate() {
+        let opcode: Opcode = serde_lexpr::from_value(item)
+            .map_err(|e| anyhow!("Label {}, opcode {}: {}", name, i, e))?;
+
+        // Check that it can be written once its jumps are resolved.
+        let mut positions = HashMap::new();
+        for label_pos in extract_label(&opcode).unwrap_or_default() {
+            match label_pos.pos {
+                Pos::Label(target) => { positions.insert(target, 0); },
+                Pos::Offset(offset) => return Err(anyhow!("Label {}, opcode {}: jumps to offset 0x{:x} instead of a label", name, i, offset))
             }
         }
-    }
-
-    let mut resolved_labels = Vec::new();
-    for offset in offsets.iter() {
-        resolved_labels.push(positions.get(offset).unwrap().clone());
-    }
+        let mut resolved = [opcode.clone()];
+        convert_label_to_byte_positions(&mut resolved, &positions)?;
+        resolved[0].to_vec().map_err(|e| anyhow!("Label {}, opcode {}: {}", name, i, e))?;
 
-    for label in resolved_labels.iter_mut() {
-        convert_byte_to_label_positions(&mut label.opcodes, &positions);
+        opcodes.push(opcode);
     }
 
-    Ok(LabelResolvedScene {
-        header: scene.header.clone(),
-        labels: resolved_labels
-    })
+    Ok(opcodes)
 }
 
-fn convert_byte_to_label_positions(opcodes: &mut [Opcode], positions: &HashMap<u32, Label>) {
-    for opcode in opcodes.iter_mut() {
-        match opcode {
-            Opcode::Condition(_, ref mut pos) => {
-                if let Pos::Offset(b) = pos {
-                    let label = positions.get(b).unwrap();
-                    *pos = Pos::Label(label.name.clone());
-                } else {
-                    unreachable!()
-                }
-            },
-            Opcode::Call(ref mut pos) => {
-                if let Pos::Offset(b) = pos {
-                    let label = positions.get(b).unwrap();
-                    *pos = Pos::Label(label.name.clone());
-                } else {
-                    unreachable!()
-                }
-            },
-            Opcode::Jump(ref mut pos) => {
-                if let Pos::Offset(b) = pos {
-                    let label = positions.get(b).unwrap();
-                    *pos = Pos::Label(label.name.clone());
-                } else {
-                    unreachable!()
-                }
-            },
-            Opcode::TableCall(_, poss) => {
-                for pos in poss.iter_mut() {
-                    if let Pos::Offset(b) = pos {
-                        let label = positions.get(b).unwrap();
-                        *pos = Pos::Label(label.name.clone());
-                    } else {
-                        unreachable!()
-                    }
-                }
-            },
-            Opcode::TableJump(_, poss) => {
-                for pos in poss.iter_mut() {
-                    if let Pos::Offset(b) = pos {
-                        let label = positions.get(b).unwrap();
-                        *pos = Pos::Label(label.name.clone());
-                    } else {
-                        unreachable!()
-                    }
-                }
-            },
-            _ => ()
-        }
-    }
-}
+#[cfg(test)]
+mod tests {
+    use avg32;
+    use super::*;
+    use pretty_assertions::assert_eq;
 
-fn compile_labels(resolved: &LabelResolvedScene) -> Result<AVG32Scene> {
-    let mut opcodes = Vec::new();
-    let mut positions: HashMap<String, u32> = HashMap::new();
-    let mut cur_pos = 0;
-
-    for label in resolved.labels.iter() {
-        positions.insert(label.name.clone(), cur_pos);
-        for opcode in label.opcodes.iter() {
-            opcodes.push(opcode.clone());
-            cur_pos += opcode.byte_size() as u32;
+    #[test]
+    fn test_label_namer() {
+        use avg32::build::SceneBuilder;
+
+        struct Numbered(Vec<u32>);
+
+        impl LabelNamer for Numbered {
+            fn name(&mut self, _kind: LabelKind, offset: u32) -> String {
+                self.0.push(offset);
+                format!("L{}", self.0.len() - 1)
+            }
         }
-    }
 
-    convert_label_to_byte_positions(&mut opcodes, &positions);
+        // Labels are referenced out of address order, and by different
+        // kinds of jump.
+        let scene = SceneBuilder::new()
+            .call("c")
+            .jump("b")
+            .label("a")
+            .wait_mouse()
+            .label("b")
+            .newline()
+            .jump("a")
+            .label("c")
+            .newline()
+            .jump("c")
+            .build()
+            .unwrap();
+
+        let mut namer = Numbered(Vec::new());
+        let resolved = resolve_labels(&scene, &mut namer).unwrap();
+        let names: Vec<&str> = resolved.labels.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(vec!["start", "L0", "L1", "L2"], names);
+        assert_eq!(scene.header.labels[1..].to_vec(), namer.0);
+        assert_eq!(scene, compile_labels(&resolved).unwrap());
+
+        let resolved = resolve_labels(&scene, &mut SequentialNamer::default()).unwrap();
+        assert_eq!(names, resolved.labels.iter().map(|l| l.name.as_str()).collect::<Vec<_>>());
+
+        let listing = to_listing_with(&scene, |_| true, &mut AddressNamer).unwrap();
+        assert!(listing.contains(&format!("loc_{:x}:\n", scene.header.labels[3])));
+        assert!(listing.contains(&format!("Call(Label(\"loc_{:x}\"))", scene.header.labels[3])));
+    }
 
-    Ok(AVG32Scene {
-        header: resolved.header.clone(),
-        opcodes: opcodes
-    })
-}
+    #[test]
+    fn test_disassemble_to() {
+        use avg32::build::SceneBuilder;
+
+        let scene = SceneBuilder::new()
+            .text("あいうえお")
+            .label("loop")
+            .wait_mouse()
+            .jump("loop")
+            .build()
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        disassemble_to(&scene, &mut bytes).unwrap();
+        let sexp = disassemble(&scene).unwrap();
+        assert_eq!(sexp.as_bytes(), &bytes[..]);
+        assert_eq!(scene, assemble(&sexp).unwrap());
+    }
 
-fn convert_label_to_byte_positions(opcodes: &mut [Opcode], positions: &HashMap<String, u32>) {
-    for opcode in opcodes.iter_mut() {
-        match opcode {
-            Opcode::Condition(_, ref mut pos) => {
-                if let Pos::Label(name) = pos {
-                    let b = positions.get(name).unwrap();
-                    *pos = Pos::Offset(*b);
-                } else {
-                    unreachable!()
-                }
-            },
-            Opcode::Call(ref mut pos) => {
-                if let Pos::Label(name) = pos {
-                    let b = positions.get(name).unwrap();
-                    *pos = Pos::Offset(*b);
-                } else {
-                    unreachable!()
-                }
-            },
-            Opcode::Jump(ref mut pos) => {
-                if let Pos::Label(name) = pos {
-                    let b = positions.get(name).unwrap();
-                    *pos = Pos::Offset(*b);
-                } else {
-                    unreachable!()
-                }
-            },
-            Opcode::TableCall(_, poss) => {
-                for pos in poss.iter_mut() {
-                    if let Pos::Label(name) = pos {
-                        let b = positions.get(name).unwrap();
-                        *pos = Pos::Offset(*b);
-                    } else {
-                        unreachable!()
-                    }
-                }
-            },
-            Opcode::TableJump(_, poss) => {
-                for pos in poss.iter_mut() {
-                    if let Pos::Label(name) = pos {
-                        let b = positions.get(name).unwrap();
-                        *pos = Pos::Offset(*b);
-                    } else {
-                        unreachable!()
-                    }
-                }
-            },
-            _ => ()
-        }
+    #[test]
+    fn test_to_listing() {
+        use avg32::build::SceneBuilder;
+
+        let scene = SceneBuilder::new()
+            .wait_mouse()
+            .label("loop")
+            .newline()
+            .jump("loop")
+            .build()
+            .unwrap();
+
+        let expected = "start:
+    0000  WaitMouse
+jump_0x1:
+    0001  Newline
+    0002  Jump(Label(\"jump_0x1\"))
+";
+        assert_eq!(expected, to_listing_with(&scene, |_| true, &mut DefaultNamer).unwrap());
     }
-}
 
-pub fn disassemble(scene: &AVG32Scene) -> Result<String> {
-    let resolved = resolve_labels(&scene)?;
+    #[test]
+    fn test_to_listing_filtered() {
+        use avg32::build::SceneBuilder;
+        use avg32::parser::{SceneText, SndCmd, Val};
+
+        let scene = SceneBuilder::new()
+            .text("あ")
+            .opcode(Opcode::Sound(SndCmd::BgmLoop(SceneText::Literal(String::from("BGM01")))))
+            .label("a")
+            .wait_mouse()
+            .label("b")
+            .newline()
+            .opcode(Opcode::Sound(SndCmd::BgmFadeOut(Val::constant(1))))
+            .jump("a")
+            .build()
+            .unwrap();
+
+        let listing = to_listing_with(&scene, |op| avg32::stats::variant_name(op) == "Sound", &mut DefaultNamer).unwrap();
+        let expected = "start:
+    0008  Sound(BgmLoop(Literal(\"BGM01\")))
+jump_0x10:
+    0012  Sound(BgmFadeOut(Val(1, Const)))
+";
+        assert_eq!(expected, listing);
+    }
 
-    let sexp = format!(";; -*- mode: lisp -*- \n\n{}", serde_lexpr::to_string(&resolved).unwrap());
+    #[test]
+    fn test_to_annotated_hex() {
+        use avg32::build::SceneBuilder;
+        use avg32::parser::{SceneText, SndCmd};
+
+        let scene = SceneBuilder::new()
+            .wait_mouse()
+            .label("loop")
+            .opcode(Opcode::TextZenkaku(Some(0), SceneText::Literal(String::from("あい"))))
+            .opcode(Opcode::Sound(SndCmd::BgmLoop(SceneText::Literal(String::from("BGM01")))))
+            .jump("loop")
+            .build()
+            .unwrap();
+
+        let expected = "start:
+    0000  01 | WaitMouse
+jump_0x1:
+    0001  ff 00 00 00 00 82 a0 82 a2 00 | TextZenkaku(Some(0), Literal(\"あい\"))
+    000b  0e 01 42 47 4d 30 31 00 | Sound(BgmLoop(Literal(\"BGM01\")))
+    0013  1c 01 00 00 00 | Jump(Label(\"jump_0x1\"))
+";
+        assert_eq!(expected, to_annotated_hex_with(&scene, |_| true, &mut DefaultNamer).unwrap());
+
+        let filtered = to_annotated_hex_with(&scene, |op| matches!(op, Opcode::Jump(_)), &mut AddressNamer).unwrap();
+        assert_eq!("loc_1:\n    0013  1c 01 00 00 00 | Jump(Label(\"loc_1\"))\n", filtered);
+    }
 
-    Ok(sexp)
-}
+    #[test]
+    fn test_assemble_label() {
+        use avg32::parser::Val;
+
+        let fragment = "((name . \"jump_0x8\") (opcodes WaitMouse (SetFlag #(3 Const) #(1 Const)) (Jump Label . \"jump_0x8\")))";
+        assert_eq!(vec![
+            Opcode::WaitMouse,
+            Opcode::SetFlag(Val::constant(3), Val::constant(1)),
+            Opcode::Jump(Pos::Label(String::from("jump_0x8"))),
+        ], assemble_label(fragment).unwrap());
+
+        let scene = avg32::build::SceneBuilder::new()
+            .text("あ")
+            .label("loop")
+            .wait_mouse()
+            .jump("loop")
+            .build()
+            .unwrap();
+        let sexp = disassemble(&scene).unwrap();
+        let start = sexp.find("((name . \"jump_0x8\")").unwrap();
+        let fragment = &sexp[start..sexp.len() - 2];
+        assert_eq!(vec![Opcode::WaitMouse, Opcode::Jump(Pos::Label(String::from("jump_0x8")))], assemble_label(fragment).unwrap());
+
+        let err = assemble_label("((name . \"a\") (opcodes WaitMouse (Bogus 1)))").unwrap_err();
+        assert!(err.to_string().starts_with("Label a, opcode 1: "), "{}", err);
+        let err = assemble_label("((name . \"a\") (opcodes (Jump Offset . 4)))").unwrap_err();
+        assert_eq!("Label a, opcode 0: jumps to offset 0x4 instead of a label", err.to_string());
+        let err = assemble_label("((name . \"a\") (opcodes (TextZenkaku () (Literal . \"😀\"))))").unwrap_err();
+        assert!(err.to_string().starts_with("Label a, opcode 0: "), "{}", err);
+        assert!(assemble_label("((name . \"a\")").is_err());
+    }
 
-pub fn assemble(sexp: &str) -> Result<AVG32Scene> {
-    let resolved = serde_lexpr::from_str(sexp).unwrap();
+    #[test]
+    fn test_roundtrip_table_jump_order() {
+        use avg32::build::SceneBuilder;
+        use avg32::parser::Val;
+
+        // Targets out of address order, so sorting them would be noticed.
+        let targets = vec![Pos::Label(String::from("c")), Pos::Label(String::from("a")), Pos::Label(String::from("b"))];
+        let scene = SceneBuilder::new()
+            .opcode(Opcode::TableJump(Val::variable(4), targets))
+            .label("a")
+            .text("あ")
+            .label("b")
+            .text("い")
+            .label("c")
+            .wait_mouse()
+            .build()
+            .unwrap();
+        let starts: Vec<u32> = scene.opcodes_with_offsets().map(|(offset, _)| offset).collect();
+        let (a, b, c) = (starts[1], starts[2], starts[3]);
+        assert_eq!(Opcode::TableJump(Val::variable(4), vec![Pos::Offset(c), Pos::Offset(a), Pos::Offset(b)]), scene.opcodes[0]);
+
+        let sexp = disassemble(&scene).unwrap();
+        let table = format!("((Label . \"tablejump_0x{:x}\") (Label . \"tablejump_0x{:x}\") (Label . \"tablejump_0x{:x}\"))", c, a, b);
+        assert!(sexp.contains(&table), "{}", sexp);
+
+        let reassembled = assemble(&sexp).unwrap();
+        assert_eq!(scene, reassembled);
+        assert_eq!(scene.to_vec().unwrap(), reassembled.to_vec().unwrap());
+    }
 
-    let scene = compile_labels(&resolved)?;
+    #[test]
+    fn test_assemble_format_version() {
+        use avg32::build::SceneBuilder;
+        use avg32::parser::{BRGRectColor, BufferRegionGrpCmd, Condition, Ret, TextWinCmd, Val};
+
+        let v = Val::constant;
+        let rect = BRGRectColor { srcx1: v(1), srcy1: v(2), srcx2: v(3), srcy2: v(4), srcpdt: v(0), r: v(5), g: v(6), b: v(7) };
+        let scene = SceneBuilder::new()
+            .text("あ")
+            .opcode(Opcode::TextWin(TextWinCmd::Hide))
+            .opcode(Opcode::BufferRegion(BufferRegionGrpCmd::ClearRect(rect)))
+            .label("loop")
+            .wait_mouse()
+            .condition(vec![Condition::Ret(Ret::Choice)], "loop")
+            .jump("loop")
+            .build()
+            .unwrap();
+
+        // Written before the format version was added.
+        let zeros = |n| vec!["0"; n].join(" ");
+        let old = format!(concat!(
+            "((header (unk1 {}) (labels 0 20) (unk2 {}) (counter_start . 0) (menus) (menu_strings) (unk3 {}))",
+            " (labels ((name . \"start\") (opcodes (TextZenkaku (0) (Literal . \"あ\")) (TextWin . Hide)",
+            " (BufferRegion ClearRect (srcx1 . #(1 Const)) (srcy1 . #(2 Const)) (srcx2 . #(3 Const)) (srcy2 . #(4 Const))",
+            " (srcpdt . #(0 Const)) (r . #(5 Const)) (g . #(6 Const)) (b . #(7 Const)))))",
+            " ((name . \"condition_0x14\") (opcodes WaitMouse",
+            " (Condition (IncDepth (Ret . Choice) DecDepth) (Label . \"condition_0x14\")) (Jump Label . \"condition_0x14\")))))"
+        ), zeros(0x13), zeros(0x30), zeros(0x05));
+        assert_eq!(scene, assemble(&old).unwrap());
+
+        let sexp = disassemble(&scene).unwrap();
+        assert!(sexp.contains(&format!("((version . {}) (header", FORMAT_VERSION)), "{}", sexp);
+        assert_eq!(scene, assemble(&sexp).unwrap());
+
+        let newer = sexp.replace(&format!("(version . {})", FORMAT_VERSION), &format!("(version . {})", FORMAT_VERSION + 1));
+        let err = assemble(&newer).unwrap_err().to_string();
+        assert!(err.contains(&format!("format version {}", FORMAT_VERSION + 1)), "{}", err);
+        assert!(assemble(&sexp.replace("(version . 1)", "(version . 0)")).is_err());
+    }
 
-    Ok(scene)
-}
+    #[test]
+    fn test_roundtrip_sample_scene() {
+        let scene = avg32::build::sample_scene();
+        let disasm = disassemble(&scene).unwrap();
+        assert_eq!(scene, assemble(&disasm).unwrap());
+    }
 
-#[cfg(test)]
-mod tests {
-    use avg32;
-    use super::*;
-    use pretty_assertions::assert_eq;
+    #[test]
+    fn test_assemble_byte_size() {
+        let disasm = disassemble(&avg32::build::sample_scene()).unwrap();
+        let scene = assemble(&disasm).unwrap();
+        let bytes = scene.to_vec().unwrap();
+        assert_eq!(scene.byte_size(), bytes.len());
+        assert_eq!(bytes.len(), bytes.capacity());
+    }
 
     #[test]
+    #[ignore = "needs the original game's SEEN files in ../SEEN"]
     fn test_roundtrip_scene() {
         use std::fs;
         for entry in fs::read_dir("../SEEN").unwrap() {
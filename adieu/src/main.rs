@@ -1,8 +1,5 @@
-#![feature(map_into_keys_values)]
-
 extern crate avg32;
 extern crate serde;
-#[macro_use] extern crate serde_derive;
 extern crate lexpr;
 extern crate serde_lexpr;
 extern crate anyhow;
@@ -10,25 +7,48 @@ extern crate anyhow;
 extern crate env_logger;
 extern crate clap;
 
+#[cfg(feature = "parallel")]
+extern crate rayon;
+
 #[cfg(test)]
 extern crate pretty_assertions;
 
 mod disasm;
+mod symbols;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::{Arg, App, SubCommand, ArgMatches, crate_version, crate_authors};
 use avg32::archive::{self, Archive};
+use avg32::assets::AssetRef;
 use avg32::font;
+use avg32::labels::{AddressNamer, DefaultNamer, LabelNamer, SequentialNamer};
 use avg32::write::Writeable;
+use avg32::parser::{AVG32Scene, ParseConfig};
+use avg32::FileKind;
+use symbols::SymbolMap;
 
 fn get_app<'a, 'b>() -> App<'a, 'b> {
     App::new("adieu")
         .version(crate_version!())
         .author(crate_authors!())
         .about("AVG32 bytecode disassembler/reassembler")
+        .arg(Arg::with_name("sys-version")
+             .long("sys-version")
+             .help("engine version to parse scenes as, e.g. 1714 or 17N")
+             .takes_value(true)
+             .value_name("VERSION")
+             .global(true))
+        .arg(Arg::with_name("game-dir")
+             .long("game-dir")
+             .help("detect the engine version from the SEEN.TXT of the game in DIR")
+             .takes_value(true)
+             .value_name("DIR")
+             .global(true)
+             .conflicts_with("sys-version"))
         .subcommand(SubCommand::with_name("unpack")
                     .about("Unpack a SEEN.TXT file")
                     .arg(Arg::with_name("output-dir")
@@ -41,11 +61,47 @@ fn get_app<'a, 'b>() -> App<'a, 'b> {
                          .short("r")
                          .long("raw")
                          .help("don't automatically dissassemble files"))
+                    .arg(Arg::with_name("keep-names")
+                         .short("k")
+                         .long("keep-names")
+                         .help("keep each member's full filename, appending .adieu instead of replacing the extension"))
+                    .arg(Arg::with_name("lenient")
+                         .short("l")
+                         .long("lenient")
+                         .help("keep unparseable opcodes as raw bytes instead of failing"))
+                    .arg(Arg::with_name("keep-going")
+                         .long("keep-going")
+                         .help("skip files that fail instead of stopping, and list them at the end"))
                     .arg(Arg::with_name("FILE")
                          .required(true)
                          .help("SEEN.TXT file")
                          .index(1))
         )
+        .subcommand(SubCommand::with_name("extract")
+                    .about("Disassemble a single member of a SEEN.TXT file")
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .help("output file, instead of stdout")
+                         .takes_value(true)
+                         .value_name("FILE"))
+                    .arg(Arg::with_name("raw")
+                         .short("r")
+                         .long("raw")
+                         .help("write the decompressed member instead of disassembling it"))
+                    .arg(Arg::with_name("lenient")
+                         .short("l")
+                         .long("lenient")
+                         .help("keep unparseable opcodes as raw bytes instead of failing"))
+                    .arg(Arg::with_name("FILE")
+                         .required(true)
+                         .help("SEEN.TXT file")
+                         .index(1))
+                    .arg(Arg::with_name("MEMBER")
+                         .required(true)
+                         .help("Member filename, e.g. SEEN011.TXT, or index in the archive")
+                         .index(2))
+        )
         .subcommand(SubCommand::with_name("repack")
                     .about("Packs a directory to a SEEN.TXT file")
                     .arg(Arg::with_name("output-dir")
@@ -58,6 +114,13 @@ fn get_app<'a, 'b>() -> App<'a, 'b> {
                          .short("r")
                          .long("raw")
                          .help("don't automatically assemble files"))
+                    .arg(Arg::with_name("keep-names")
+                         .short("k")
+                         .long("keep-names")
+                         .help("use filenames as written by unpack --keep-names, only removing .adieu"))
+                    .arg(Arg::with_name("keep-going")
+                         .long("keep-going")
+                         .help("check every file instead of stopping at the first failure, and list the failures at the end; SEEN.TXT is only written if none fail"))
                     .arg(Arg::with_name("DIR")
                          .required(true)
                          .help("Directory containing bytecode files")
@@ -71,9 +134,39 @@ fn get_app<'a, 'b>() -> App<'a, 'b> {
                          .help("output directory")
                          .takes_value(true)
                          .value_name("DIR"))
+                    .arg(Arg::with_name("lenient")
+                         .short("l")
+                         .long("lenient")
+                         .help("keep unparseable opcodes as raw bytes instead of failing"))
+                    .arg(Arg::with_name("listing")
+                         .long("listing")
+                         .help("write a plain text listing (.lst) for reading and diffing instead of a .adieu file"))
+                    .arg(Arg::with_name("hex")
+                         .long("hex")
+                         .help("write a listing (.lst) with each opcode's raw bytes next to it"))
+                    .arg(Arg::with_name("only")
+                         .long("only")
+                         .help("only list opcodes of these kinds, e.g. Sound,Graphics,Jump (implies --listing)")
+                         .takes_value(true)
+                         .value_name("KINDS"))
+                    .arg(Arg::with_name("label-style")
+                         .long("label-style")
+                         .help("how to name labels: kind (jump_0x1a2b), address (loc_1a2b) or sequential (L0, L1, ...)")
+                         .takes_value(true)
+                         .possible_values(&["kind", "address", "sequential"])
+                         .default_value("kind")
+                         .value_name("STYLE"))
+                    .arg(Arg::with_name("symbols")
+                         .long("symbols")
+                         .help("name labels and comment variables using a symbol map (lines of `0x1a2b = name`, `label N = name` or `var N = name`)")
+                         .takes_value(true)
+                         .value_name("FILE"))
+                    .arg(Arg::with_name("keep-going")
+                         .long("keep-going")
+                         .help("with a directory, skip files that fail instead of stopping, and list them at the end"))
                     .arg(Arg::with_name("FILE")
                          .required(true)
-                         .help("SEEN<XXX>.TXT file")
+                         .help("SEEN<XXX>.TXT file, or a directory of them")
                          .index(1))
         )
         .subcommand(SubCommand::with_name("asm")
@@ -89,14 +182,253 @@ fn get_app<'a, 'b>() -> App<'a, 'b> {
                          .help("SEEN<XXX>.adieu file")
                          .index(1))
         )
+        .subcommand(SubCommand::with_name("check-label")
+                    .about("Checks a single label cut from a .adieu file, e.g. from an editor, without assembling the scene")
+                    .arg(Arg::with_name("FILE")
+                         .required(true)
+                         .help("File containing one ((name . \"...\") (opcodes ...)) label, or - for stdin")
+                         .index(1))
+        )
+        .subcommand(SubCommand::with_name("diff")
+                    .about("Compare the opcodes of two AVG32 scenes")
+                    .arg(Arg::with_name("A")
+                         .required(true)
+                         .help("SEEN<XXX>.TXT or .adieu file")
+                         .index(1))
+                    .arg(Arg::with_name("B")
+                         .required(true)
+                         .help("SEEN<XXX>.TXT or .adieu file")
+                         .index(2))
+        )
+        .subcommand(SubCommand::with_name("stats")
+                    .about("Count opcodes across a directory of scenes")
+                    .arg(Arg::with_name("DIR")
+                         .required(true)
+                         .help("Directory containing .TXT or .adieu files")
+                         .index(1))
+        )
+        .subcommand(SubCommand::with_name("metrics")
+                    .about("Count lines, characters and choices per scene, for estimating translation work")
+                    .arg(Arg::with_name("DIR")
+                         .required(true)
+                         .help("Directory containing .TXT or .adieu files")
+                         .index(1))
+        )
+        .subcommand(SubCommand::with_name("opcodes")
+                    .about("Lists the opcodes used across a game and how many scenes use each")
+                    .arg(Arg::with_name("subcommands")
+                         .long("subcommands")
+                         .help("Also list the nested commands used"))
+                    .arg(Arg::with_name("DIR")
+                         .required(true)
+                         .help("Directory containing .TXT or .adieu files")
+                         .index(1))
+        )
+        .subcommand(SubCommand::with_name("grep")
+                    .about("Finds the opcodes in an archive with a given name, or that load a given asset")
+                    .arg(Arg::with_name("asset")
+                         .short("a")
+                         .long("asset")
+                         .help("match opcodes loading an asset whose name contains PATTERN instead"))
+                    .arg(Arg::with_name("FILE")
+                         .required(true)
+                         .help("SEEN.TXT file")
+                         .index(1))
+                    .arg(Arg::with_name("PATTERN")
+                         .required(true)
+                         .help("Opcode or command name, e.g. System::GameEnd, System or GameEnd")
+                         .index(2))
+        )
+        .subcommand(SubCommand::with_name("assets")
+                    .about("Lists the graphics, sounds and movies a game loads and how many scenes load each")
+                    .arg(Arg::with_name("DIR")
+                         .required(true)
+                         .help("Directory containing .TXT or .adieu files")
+                         .index(1))
+        )
+        .subcommand(SubCommand::with_name("choices")
+                    .about("Lists every choice shown to the player and its options")
+                    .arg(Arg::with_name("PATH")
+                         .required(true)
+                         .help("SEEN.TXT, a scene, or a directory of SEEN<XXX>.TXT files")
+                         .index(1)))
+        .subcommand(SubCommand::with_name("verify")
+                    .about("Checks that every scene survives being parsed, written and parsed again")
+                    .arg(Arg::with_name("PATH")
+                         .required(true)
+                         .help("SEEN.TXT, a scene, or a directory of SEEN<XXX>.TXT files")
+                         .index(1)))
+        .subcommand(SubCommand::with_name("info")
+                    .about("Identifies a scene, archive or font and summarizes it")
+                    .arg(Arg::with_name("FILE")
+                         .required(true)
+                         .help("File to identify")
+                         .index(1)))
+        .subcommand(SubCommand::with_name("spec")
+                    .about("Prints the opcode byte layout as JSON"))
         .subcommand(SubCommand::with_name("font")
                     .about("Reads FN.DAT")
+                    .arg(Arg::with_name("preview")
+                         .short("p")
+                         .long("preview")
+                         .help("only draw the glyph at this index, shaded with ' .:#'")
+                         .takes_value(true)
+                         .value_name("INDEX"))
                     .arg(Arg::with_name("FILE")
                          .required(true)
                          .help("FN.DAT file")
                          .index(1)))
 }
 
+fn load_scene_bytes(bytes: &[u8], name: &str, lenient: bool) -> Result<AVG32Scene> {
+    if !lenient {
+        return avg32::load_bytes(bytes);
+    }
+
+    let (scene, unknown) = avg32::load_bytes_lenient(bytes)?;
+    for op in unknown.iter() {
+        warn!("{}: unknown opcode 0x{:02x} at offset 0x{:x}, skipped {} bytes", name, op.opcode, op.offset, op.skipped);
+    }
+    if !unknown.is_empty() {
+        println!("{}: kept {} unknown opcodes as raw bytes.", name, unknown.len());
+    }
+
+    Ok(scene)
+}
+
+/// Name to unpack an archive member to. Disassembled members get their
+/// extension replaced with `.adieu`, or with `keep_names`, `.adieu`
+/// appended so the original name can be restored exactly.
+fn unpacked_filename(member: &str, raw: bool, keep_names: bool) -> PathBuf {
+    if raw {
+        PathBuf::from(member)
+    } else if keep_names {
+        PathBuf::from(format!("{}.adieu", member))
+    } else {
+        PathBuf::from(member).with_extension("adieu")
+    }
+}
+
+/// Archive member name for an unpacked file, the inverse of
+/// `unpacked_filename`.
+fn packed_filename(path: &Path, raw: bool, keep_names: bool) -> String {
+    let name = if keep_names {
+        if raw {
+            PathBuf::from(path.file_name().unwrap())
+        } else {
+            PathBuf::from(path.file_stem().unwrap())
+        }
+    } else {
+        PathBuf::from(path.with_extension("TXT").file_name().unwrap())
+    };
+    name.to_string_lossy().into_owned()
+}
+
+/// Fails with a hint at the right subcommand unless `bytes` are of kind
+/// `expected`.
+fn check_kind(path: &Path, bytes: &[u8], expected: FileKind) -> Result<()> {
+    let kind = avg32::identify(bytes);
+    if kind == expected {
+        return Ok(());
+    }
+    let hint = match kind {
+        FileKind::Scene => " Use `adieu disasm` for scenes.",
+        FileKind::Archive => " Use `adieu unpack` for archives.",
+        FileKind::Font => " Use `adieu font` for fonts.",
+        FileKind::ArchiveMember | FileKind::Unknown => ""
+    };
+    Err(anyhow!("{:?} is {}, expected {}.{}", path, kind, expected, hint))
+}
+
+fn print_progress(done: usize, total: usize) {
+    print!("\r{:3}% ({}/{})", done * 100 / total, done, total);
+    let _ = std::io::stdout().flush();
+}
+
+fn print_member_sizes(arc: &Archive) {
+    let sizes = arc.member_sizes();
+    println!("{:<16} {:>10} {:>10} {:>7}", "Member", "Size", "Stored", "Ratio");
+    for member in sizes.iter() {
+        println!("{:<16} {:>10} {:>10} {:>6.1}%", member.filename, member.orgsize, member.arcsize, member.ratio() * 100.0);
+    }
+
+    let orgsize: u64 = sizes.iter().map(|m| m.orgsize as u64).sum();
+    let arcsize: u64 = sizes.iter().map(|m| m.arcsize as u64).sum();
+    let ratio = if orgsize == 0 { 0.0 } else { arcsize as f64 / orgsize as f64 };
+    println!("{:<16} {:>10} {:>10} {:>6.1}%", "Total", orgsize, arcsize, ratio * 100.0);
+}
+
+/// Files that failed in a batch command. Without `keep_going`, the first
+/// failure is returned instead of being recorded.
+struct Failures {
+    keep_going: bool,
+    names: Vec<String>
+}
+
+impl Failures {
+    fn new(keep_going: bool) -> Self {
+        Failures {
+            keep_going: keep_going,
+            names: Vec::new()
+        }
+    }
+
+    /// Gives the value of `result`, or `None` if it failed and the failure
+    /// was recorded.
+    fn check<T>(&mut self, name: &str, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.keep_going => {
+                error!("{}: {}", name, e);
+                self.names.push(String::from(name));
+                Ok(None)
+            },
+            Err(e) => Err(anyhow!("{}: {}", name, e))
+        }
+    }
+
+    /// Lists the recorded failures, failing if there were any.
+    fn finish(&self, action: &str) -> Result<()> {
+        if self.names.is_empty() {
+            return Ok(());
+        }
+        println!("Failed to {} {} files:", action, self.names.len());
+        for name in self.names.iter() {
+            println!("  {}", name);
+        }
+        Err(anyhow!("{} files failed to {}", self.names.len(), action))
+    }
+}
+
+/// Writes each member of `arc` to `output_dir`, disassembled unless `raw`
+/// is set. Returns how many were written.
+fn unpack_members(arc: &Archive, output_dir: &Path, raw: bool, lenient: bool, keep_names: bool, failures: &mut Failures) -> Result<usize> {
+    let total = arc.entries.len();
+    let mut written = 0;
+    for (i, (entry, data)) in arc.entries.iter().zip(arc.data.iter()).enumerate() {
+        let filename = &entry.filename;
+        let output_file = output_dir.join(unpacked_filename(filename, raw, keep_names));
+        let result = data.decompress().and_then(|decomp| {
+            if raw {
+                let mut file = File::create(&output_file)?;
+                decomp.write(&mut file)?;
+            } else {
+                let scene = load_scene_bytes(&decomp, filename, lenient)?;
+                let mut file = BufWriter::new(File::create(&output_file)?);
+                disasm::disassemble_to(&scene, &mut file)?;
+                file.flush()?;
+            }
+            Ok(())
+        });
+        if failures.check(filename, result)?.is_some() {
+            written += 1;
+        }
+        print_progress(i + 1, total);
+    }
+    println!("");
+    Ok(written)
+}
+
 fn cmd_unpack(sub_matches: &ArgMatches) -> Result<()> {
     let input_file = Path::new(sub_matches.value_of("FILE").unwrap());
     let output_dir = match sub_matches.value_of("output-dir") {
@@ -104,30 +436,97 @@ fn cmd_unpack(sub_matches: &ArgMatches) -> Result<()> {
         None => input_file.parent().unwrap()
     };
     let raw = sub_matches.is_present("raw");
+    let lenient = sub_matches.is_present("lenient");
+    let keep_names = sub_matches.is_present("keep-names");
+    let mut failures = Failures::new(sub_matches.is_present("keep-going"));
 
-    fs::create_dir_all(output_dir)?;
-    let arc = archive::load(&input_file)?;
+    let bytes = fs::read(&input_file)?;
+    check_kind(input_file, &bytes, FileKind::Archive)?;
 
-    for (i, entry) in arc.entries.iter().enumerate() {
-        let data = &arc.data[i];
-        let decomp = data.decompress()?;
-        if raw {
-            let output_file = output_dir.join(&entry.filename);
-            let mut file = File::create(&output_file)?;
-            decomp.write(&mut file)?;
-        } else {
-            let scene = avg32::load_bytes(&decomp)?;
-            let output_file = output_dir.join(PathBuf::from(&entry.filename).with_extension("adieu"));
-            let mut file = File::create(&output_file)?;
-            let sexp = disasm::disassemble(&scene)?;
-            file.write_all(&sexp.as_bytes())?;
+    fs::create_dir_all(output_dir)?;
+    let arc = if lenient {
+        let (arc, mismatches) = archive::load_bytes_lenient(&bytes)?;
+        for mismatch in mismatches.iter() {
+            warn!("{:?}: {:?}", input_file, mismatch);
         }
+        arc
+    } else {
+        archive::load_bytes(&bytes)?
+    };
+
+    let written = unpack_members(&arc, output_dir, raw, lenient, keep_names, &mut failures)?;
+
+    print_member_sizes(&arc);
+    println!("Wrote {} files to {:?}.", written, output_dir);
+    failures.finish("unpack")
+}
+
+/// Decompresses the member of `arc` named `member`, or at index `member`
+/// if no member has that name, and disassembles it unless `raw` is set.
+fn extract_member(arc: &Archive, member: &str, raw: bool, lenient: bool) -> Result<Vec<u8>> {
+    let (entry, data) = match (arc.member(member), member.parse::<usize>()) {
+        (Some(found), _) => found,
+        (None, Ok(i)) if i < arc.entries.len() => (&arc.entries[i], &arc.data[i]),
+        _ => return Err(anyhow!("No member named {} in archive, and it has {} members", member, arc.entries.len()))
+    };
+    let decomp = data.decompress()?;
+    if raw {
+        return Ok(decomp);
     }
 
-    println!("Wrote {} files to {:?}.", arc.entries.len(), output_dir);
+    let scene = load_scene_bytes(&decomp, &entry.filename, lenient)?;
+    let mut out = Vec::new();
+    disasm::disassemble_to(&scene, &mut out)?;
+    Ok(out)
+}
+
+fn cmd_extract(sub_matches: &ArgMatches) -> Result<()> {
+    let input_file = Path::new(sub_matches.value_of("FILE").unwrap());
+    let member = sub_matches.value_of("MEMBER").unwrap();
+
+    let bytes = fs::read(&input_file)?;
+    check_kind(input_file, &bytes, FileKind::Archive)?;
+    let arc = archive::load_bytes(&bytes)?;
+    let out = extract_member(&arc, member, sub_matches.is_present("raw"), sub_matches.is_present("lenient"))?;
+
+    match sub_matches.value_of("output") {
+        Some(output_file) => {
+            fs::write(output_file, &out)?;
+            println!("Wrote {} to {:?}.", member, output_file);
+        },
+        None => std::io::stdout().write_all(&out)?
+    }
     Ok(())
 }
 
+/// Reads every file in `input_dir` as an archive member, assembling them
+/// unless `raw` is set.
+fn repack_members(input_dir: &Path, raw: bool, keep_names: bool, failures: &mut Failures) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(input_dir)? {
+        let path = entry?.path();
+        if fs::metadata(&path)?.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut members = Vec::new();
+    for path in paths.iter() {
+        let scene = if raw {
+            avg32::load(&path)
+        } else {
+            fs::read_to_string(&path).map_err(anyhow::Error::from).and_then(|sexp| disasm::assemble(&sexp))
+        };
+
+        let bytes = scene.and_then(|scene| Ok(scene.to_vec()?));
+        if let Some(bytes) = failures.check(&path.to_string_lossy(), bytes)? {
+            members.push((packed_filename(path, raw, keep_names), bytes));
+        }
+    }
+    Ok(members)
+}
+
 fn cmd_repack(sub_matches: &ArgMatches) -> Result<()> {
     let input_dir = Path::new(sub_matches.value_of("DIR").unwrap());
     let output_dir = match sub_matches.value_of("output-dir") {
@@ -135,54 +534,167 @@ fn cmd_repack(sub_matches: &ArgMatches) -> Result<()> {
         None => input_dir.parent().unwrap()
     };
     let raw = sub_matches.is_present("raw");
+    let keep_names = sub_matches.is_present("keep-names");
+    let mut failures = Failures::new(sub_matches.is_present("keep-going"));
+
+    let output_file = output_dir.join("SEEN.TXT");
+    let count = repack(input_dir, &output_file, raw, keep_names, &mut failures)?;
+
+    println!("Packed {} files to {:?}.", count, output_file);
+    Ok(())
+}
 
+/// Packs the files in `input_dir` to `output_file`, returning how many were
+/// packed. Nothing is written if any file failed, since the archive would
+/// be missing scenes.
+fn repack(input_dir: &Path, output_file: &Path, raw: bool, keep_names: bool, failures: &mut Failures) -> Result<usize> {
     let mut arc = Archive::new();
+    let members = repack_members(input_dir, raw, keep_names, failures)?;
+    if !failures.names.is_empty() {
+        println!("Not writing {:?}.", output_file);
+        failures.finish("pack")?;
+    }
 
-    for entry in fs::read_dir(input_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    // Compression happens here, in parallel with the "parallel" feature.
+    arc.add_entries_with_progress(members, Some(&mut print_progress))?;
+    println!("");
 
-        let metadata = fs::metadata(&path)?;
-        if metadata.is_file() {
-            let scene = if raw {
-                avg32::load(&path)?
-            } else {
-                let sexp = fs::read_to_string(&path)?;
-                disasm::assemble(&sexp)?
-            };
+    let mut file = File::create(&output_file)?;
+    arc.finalize();
+    arc.write(&mut file)?;
+
+    Ok(arc.entries.len())
+}
+
+/// How `disasm_file` writes its output.
+struct DisasmOptions<'a> {
+    lenient: bool,
+    listing: bool,
+    /// Show each opcode's bytes in the listing.
+    hex: bool,
+    /// Opcode kinds to keep in the listing, or all of them if `None`.
+    only: Option<Vec<&'a str>>,
+    /// One of the `--label-style` values.
+    label_style: &'a str,
+    symbols: Option<&'a SymbolMap>
+}
 
-            let mut bytes = Vec::new();
-            scene.write(&mut bytes)?;
-            let comp = archive::compress(&bytes)?;
+fn label_namer(style: &str) -> Box<dyn LabelNamer> {
+    match style {
+        "address" => Box::new(AddressNamer),
+        "sequential" => Box::new(SequentialNamer::default()),
+        _ => Box::new(DefaultNamer)
+    }
+}
 
-            let filename = String::from(path.with_extension("TXT").file_name().unwrap().to_str().unwrap());
-            arc.add_entry(filename, comp)?;
+/// Disassembles one scene into `output_dir`, returning the file written.
+fn disasm_file(input_file: &Path, output_dir: &Path, options: &DisasmOptions) -> Result<PathBuf> {
+    let bytes = fs::read(&input_file)?;
+    if avg32::identify(&bytes) != FileKind::ArchiveMember {
+        check_kind(input_file, &bytes, FileKind::Scene)?;
+    }
+    let scene = load_scene_bytes(&bytes, &input_file.to_string_lossy(), options.lenient)?;
+
+    let mut namer = label_namer(options.label_style);
+    let mut comments = Vec::new();
+    if let Some(symbols) = options.symbols {
+        namer = Box::new(symbols.namer(&scene.header, namer));
+        comments = symbols.var_comments(&scene);
+    }
+
+    if options.listing || options.hex || options.only.is_some() {
+        let filter = |op: &avg32::Opcode| match &options.only {
+            Some(kinds) => kinds.contains(&avg32::stats::variant_name(op).as_str()),
+            None => true
+        };
+        let mut listing: String = comments.iter().map(|c| format!("; {}\n", c)).collect();
+        listing.push_str(&if options.hex {
+            disasm::to_annotated_hex_with(&scene, filter, &mut *namer)?
+        } else {
+            disasm::to_listing_with(&scene, filter, &mut *namer)?
+        });
+        let output_file = output_dir.join(input_file.with_extension("lst").file_name().unwrap());
+        fs::write(&output_file, listing)?;
+        return Ok(output_file);
+    }
+
+    let output_file = output_dir.join(input_file.with_extension("adieu").file_name().unwrap());
+    let mut file = BufWriter::new(File::create(&output_file)?);
+    disasm::disassemble_with(&scene, &mut file, &mut *namer, &comments)?;
+    file.flush()?;
+
+    Ok(output_file)
+}
+
+/// The `SEEN*.TXT` files in `dir`, sorted by name.
+fn scene_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().to_uppercase();
+        if name.starts_with("SEEN") && name.ends_with(".TXT") && fs::metadata(&path)?.is_file() {
+            paths.push(path);
         }
     }
+    paths.sort();
+    Ok(paths)
+}
 
-    let output_file = output_dir.join("SEEN.TXT");
-    let mut file = File::create(&output_file)?;
-    arc.finalize();
-    arc.write(&mut file)?;
+/// Disassembles every scene in `input_dir`. Returns how many succeeded.
+fn disasm_dir(input_dir: &Path, output_dir: &Path, options: &DisasmOptions, failures: &mut Failures) -> Result<usize> {
+    let paths = scene_files(input_dir)?;
 
-    println!("Packed {} files to {:?}.", arc.entries.len(), output_file);
-    Ok(())
+    #[cfg(feature = "parallel")]
+    let results = {
+        use rayon::prelude::*;
+        let config = avg32::parser::current_config();
+        let results: Vec<Result<PathBuf>> = paths.par_iter().map(|path| avg32::parser::with_config(&config, || disasm_file(path, output_dir, options))).collect();
+        results.into_iter()
+    };
+    #[cfg(not(feature = "parallel"))]
+    let results = paths.iter().map(|path| disasm_file(path, output_dir, options));
+
+    let mut done = 0;
+    for (path, result) in paths.iter().zip(results) {
+        if failures.check(&path.to_string_lossy(), result)?.is_some() {
+            done += 1;
+        }
+    }
+
+    Ok(done)
 }
 
 fn cmd_disasm(sub_matches: &ArgMatches) -> Result<()> {
     let input_file = Path::new(sub_matches.value_of("FILE").unwrap());
     let output_dir = match sub_matches.value_of("output-dir") {
         Some(dir) => Path::new(dir),
+        None if input_file.is_dir() => input_file,
         None => input_file.parent().unwrap()
     };
 
-    let scene = avg32::load(&input_file.to_str().unwrap())?;
-    let sexp = disasm::disassemble(&scene)?;
+    let symbols = match sub_matches.value_of("symbols") {
+        Some(path) => Some(SymbolMap::load(path)?),
+        None => None
+    };
 
-    let output_file = output_dir.join(input_file.with_extension("adieu").file_name().unwrap());
-    let mut file = File::create(&output_file)?;
-    file.write_all(&sexp.as_bytes())?;
+    let options = DisasmOptions {
+        lenient: sub_matches.is_present("lenient"),
+        listing: sub_matches.is_present("listing"),
+        hex: sub_matches.is_present("hex"),
+        only: sub_matches.value_of("only").map(|only| only.split(',').map(|s| s.trim()).collect()),
+        label_style: sub_matches.value_of("label-style").unwrap(),
+        symbols: symbols.as_ref()
+    };
 
+    if input_file.is_dir() {
+        fs::create_dir_all(output_dir)?;
+        let mut failures = Failures::new(sub_matches.is_present("keep-going"));
+        let done = disasm_dir(input_file, output_dir, &options, &mut failures)?;
+        println!("Disassembled {} files to {:?}.", done, output_dir);
+        return failures.finish("disassemble");
+    }
+
+    let output_file = disasm_file(input_file, output_dir, &options)?;
     println!("Dissassembled bytecode to {:?}.", output_file);
     Ok(())
 }
@@ -198,10 +710,324 @@ fn cmd_asm(sub_matches: &ArgMatches) -> Result<()> {
     let scene = disasm::assemble(&sexp)?;
 
     let output_file = output_dir.join(input_file.with_extension("TXT").file_name().unwrap());
-    let mut file = File::create(&output_file)?;
-    scene.write(&mut file)?;
+    let bytes = scene.to_vec()?;
+    fs::write(&output_file, &bytes)?;
+
+    println!("Assembled {} bytes of bytecode to {:?}.", bytes.len(), output_file);
+    Ok(())
+}
+
+fn cmd_check_label(sub_matches: &ArgMatches) -> Result<()> {
+    let input_file = sub_matches.value_of("FILE").unwrap();
+    let sexp = if input_file == "-" {
+        let mut sexp = String::new();
+        std::io::stdin().read_to_string(&mut sexp)?;
+        sexp
+    } else {
+        fs::read_to_string(input_file)?
+    };
+
+    let opcodes = disasm::assemble_label(&sexp)?;
+
+    println!("Label is valid, {} opcodes.", opcodes.len());
+    Ok(())
+}
+
+fn load_scene_or_source(path: &Path) -> Result<AVG32Scene> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("adieu") => {
+            let sexp = fs::read_to_string(path)?;
+            disasm::assemble(&sexp)
+        },
+        _ => avg32::load(path)
+    }
+}
+
+/// Every `.TXT` scene and `.adieu` source in `dir`, sorted.
+fn scene_or_source_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_scene = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.eq_ignore_ascii_case("txt") || ext == "adieu",
+            None => false
+        };
+        if path.is_file() && is_scene {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn cmd_diff(sub_matches: &ArgMatches) -> Result<()> {
+    let file_a = Path::new(sub_matches.value_of("A").unwrap());
+    let file_b = Path::new(sub_matches.value_of("B").unwrap());
+
+    let scene_a = load_scene_or_source(&file_a)?;
+    let scene_b = load_scene_or_source(&file_b)?;
+
+    let diffs = avg32::diff(&scene_a, &scene_b);
+    for diff in diffs.iter() {
+        println!("{}", diff);
+    }
+
+    println!("{} differences between {:?} and {:?}.", diffs.len(), file_a, file_b);
+    Ok(())
+}
+
+fn cmd_stats(sub_matches: &ArgMatches) -> Result<()> {
+    let input_dir = Path::new(sub_matches.value_of("DIR").unwrap());
+
+    let mut stats = avg32::OpcodeStats::new();
+    let mut scenes = 0;
+
+    for path in scene_or_source_files(input_dir)? {
+        match load_scene_or_source(&path) {
+            Ok(scene) => {
+                stats.merge(&avg32::stats(&scene));
+                scenes += 1;
+            },
+            Err(e) => warn!("Skipping {:?}: {}", path, e)
+        }
+    }
+
+    let mut opcodes: Vec<(&String, &usize)> = stats.opcodes.iter().collect();
+    opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (name, count) in opcodes {
+        println!("{:>8}  {}", count, name);
+    }
+    println!("");
+
+    let mut subcommands: Vec<(&String, &usize)> = stats.subcommands.iter().collect();
+    subcommands.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (name, count) in subcommands {
+        println!("{:>8}  {}", count, name);
+    }
+    println!("");
+
+    println!("{} opcodes, {} branches, {} text characters in {} scenes.",
+             stats.total(), stats.branches, stats.text_chars, scenes);
+    Ok(())
+}
+
+fn cmd_metrics(sub_matches: &ArgMatches) -> Result<()> {
+    let input_dir = Path::new(sub_matches.value_of("DIR").unwrap());
+    let paths = scene_or_source_files(input_dir)?;
+
+    let mut total = avg32::TextMetrics::default();
+    let mut scenes = 0;
+    println!("{:<20} {:>8} {:>8} {:>8}", "Scene", "Lines", "Chars", "Choices");
+    for path in paths.iter() {
+        match load_scene_or_source(&path) {
+            Ok(scene) => {
+                let metrics = scene.text_metrics();
+                let name = path.file_name().unwrap().to_string_lossy();
+                println!("{:<20} {:>8} {:>8} {:>8}", name, metrics.lines, metrics.chars, metrics.choices);
+                total.merge(&metrics);
+                scenes += 1;
+            },
+            Err(e) => warn!("Skipping {:?}: {}", path, e)
+        }
+    }
+    println!("{:<20} {:>8} {:>8} {:>8}", "Total", total.lines, total.chars, total.choices);
+    println!("{} scenes.", scenes);
+
+    Ok(())
+}
+
+fn cmd_opcodes(sub_matches: &ArgMatches) -> Result<()> {
+    let input_dir = Path::new(sub_matches.value_of("DIR").unwrap());
+
+    let mut opcodes: BTreeMap<u8, usize> = BTreeMap::new();
+    let mut subcommands: BTreeMap<String, usize> = BTreeMap::new();
+    let mut scenes = 0;
+    for path in scene_or_source_files(input_dir)? {
+        match load_scene_or_source(&path) {
+            Ok(scene) => {
+                for byte in scene.opcode_set() {
+                    *opcodes.entry(byte).or_insert(0) += 1;
+                }
+                for name in scene.subcommand_set() {
+                    *subcommands.entry(name).or_insert(0) += 1;
+                }
+                scenes += 1;
+            },
+            Err(e) => warn!("Skipping {:?}: {}", path, e)
+        }
+    }
+
+    println!("{:<6} {:<16} {:>8}", "Byte", "Opcode", "Scenes");
+    for (byte, count) in opcodes.iter() {
+        let name = avg32::spec::OPCODES.iter().find(|spec| spec.byte == *byte).map(|spec| spec.name).unwrap_or("?");
+        println!("0x{:02x}   {:<16} {:>8}", byte, name, count);
+    }
+    if sub_matches.is_present("subcommands") {
+        println!("");
+        for (name, count) in subcommands.iter() {
+            println!("{:<40} {:>8}", name, count);
+        }
+    }
+    println!("{} distinct opcodes in {} scenes.", opcodes.len(), scenes);
+
+    Ok(())
+}
+
+/// Whether `opcode` is named `pattern`, ignoring case, or with `asset`,
+/// loads an asset whose name contains it.
+fn grep_matches(opcode: &avg32::Opcode, pattern: &str, asset: bool) -> bool {
+    if asset {
+        let pattern = pattern.to_uppercase();
+        return opcode.asset_references().iter()
+            .filter_map(|a| a.name.as_ref())
+            .any(|name| name.to_uppercase().contains(&pattern));
+    }
+
+    let name = avg32::stats::qualified_name(opcode);
+    name.eq_ignore_ascii_case(pattern) || name.split("::").any(|part| part.eq_ignore_ascii_case(pattern))
+}
+
+fn cmd_grep(sub_matches: &ArgMatches) -> Result<()> {
+    let input_file = Path::new(sub_matches.value_of("FILE").unwrap());
+    let pattern = sub_matches.value_of("PATTERN").unwrap();
+    let asset = sub_matches.is_present("asset");
+
+    let bytes = fs::read(&input_file)?;
+    check_kind(input_file, &bytes, FileKind::Archive)?;
+    let arc = archive::load_bytes(&bytes)?;
+
+    let found = arc.find_opcodes(|opcode| grep_matches(opcode, pattern, asset))?;
+    for (member, offset, opcode) in found.iter() {
+        println!("{:<16} {:04x}  {:?}", member, offset, opcode);
+    }
+    let members: BTreeSet<&String> = found.iter().map(|(member, _, _)| member).collect();
+    println!("{} matches in {} members.", found.len(), members.len());
+
+    Ok(())
+}
+
+fn cmd_assets(sub_matches: &ArgMatches) -> Result<()> {
+    let input_dir = Path::new(sub_matches.value_of("DIR").unwrap());
+
+    let mut assets: BTreeMap<AssetRef, usize> = BTreeMap::new();
+    let mut scenes = 0;
+    for path in scene_or_source_files(input_dir)? {
+        match load_scene_or_source(&path) {
+            Ok(scene) => {
+                let refs: BTreeSet<AssetRef> = scene.asset_references().into_iter().collect();
+                for asset in refs {
+                    *assets.entry(asset).or_insert(0) += 1;
+                }
+                scenes += 1;
+            },
+            Err(e) => warn!("Skipping {:?}: {}", path, e)
+        }
+    }
+
+    println!("{:<8} {:<24} {:>8}", "Kind", "Name", "Scenes");
+    for (asset, count) in assets.iter() {
+        println!("{:<8} {:<24} {:>8}", asset.kind, asset.name.as_deref().unwrap_or("(runtime)"), count);
+    }
+    let unresolved = assets.keys().filter(|asset| asset.name.is_none()).count();
+    println!("{} assets, {} only known at runtime, in {} scenes.", assets.len() - unresolved, unresolved, scenes);
+
+    Ok(())
+}
+
+/// The name and bytes of each scene in `input`: an archive, a scene, or a
+/// directory of scenes.
+fn read_scenes(input: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    if input.is_dir() {
+        let mut scenes = Vec::new();
+        for path in scene_files(input)? {
+            scenes.push((path.to_string_lossy().into_owned(), fs::read(&path)?));
+        }
+        return Ok(scenes);
+    }
+
+    let bytes = fs::read(&input)?;
+    match avg32::identify(&bytes) {
+        FileKind::Archive => archive::load_bytes(&bytes)?.extract_all(None),
+        FileKind::Scene => Ok(vec![(input.to_string_lossy().into_owned(), bytes)]),
+        kind => Err(anyhow!("{:?} is {}, expected an archive or a scene", input, kind))
+    }
+}
+
+fn cmd_choices(sub_matches: &ArgMatches) -> Result<()> {
+    let input = Path::new(sub_matches.value_of("PATH").unwrap());
+
+    let mut count = 0;
+    for (name, bytes) in read_scenes(input)?.iter() {
+        let scene = match avg32::load_bytes(&bytes) {
+            Ok(scene) => scene,
+            Err(e) => {
+                warn!("Skipping {}: {}", name, e);
+                continue;
+            }
+        };
+        for choice in scene.choices() {
+            println!("{:<16} {:04x}  {:?}", name, choice.offset, choice.flag);
+            for (i, text) in choice.texts.iter().enumerate() {
+                println!("  {}: {}", i, text);
+            }
+            count += 1;
+        }
+    }
+    println!("{} choices.", count);
+
+    Ok(())
+}
+
+fn cmd_verify(sub_matches: &ArgMatches) -> Result<()> {
+    let input = Path::new(sub_matches.value_of("PATH").unwrap());
+    let scenes = read_scenes(input)?;
+
+    let mut failed = 0;
+    for (name, bytes) in scenes.iter() {
+        if let Err(e) = avg32::verify_roundtrip(&bytes) {
+            println!("{}: {}", name, e);
+            failed += 1;
+        }
+    }
+
+    println!("Verified {} scenes, {} failed.", scenes.len(), failed);
+    if failed > 0 {
+        return Err(anyhow!("{} scenes don't round-trip", failed));
+    }
+    Ok(())
+}
+
+fn cmd_info(sub_matches: &ArgMatches) -> Result<()> {
+    let input_file = Path::new(sub_matches.value_of("FILE").unwrap());
+    let bytes = fs::read(&input_file)?;
+    let kind = avg32::identify(&bytes);
+
+    println!("{:?}: {}", input_file, kind);
+    match kind {
+        FileKind::Scene => {
+            let scene = avg32::load_bytes(&bytes)?;
+            println!("  opcodes: {}", scene.opcodes.len());
+            println!("  labels:  {}", scene.header.labels.len());
+            println!("  menus:   {}", scene.header.menus.len());
+        },
+        FileKind::Archive => {
+            let arc = archive::load_bytes(&bytes)?;
+            let size: u32 = arc.entries.iter().map(|e| e.filesize).sum();
+            println!("  members: {}", arc.entries.len());
+            println!("  uncompressed size: {} bytes", size);
+        },
+        FileKind::ArchiveMember => {
+            let member = archive::load_member_bytes(&bytes)?;
+            println!("  compressed size:   {} bytes", member.arcsize);
+            println!("  uncompressed size: {} bytes", member.orgsize);
+        },
+        FileKind::Font => {
+            let font = font::load_bytes(&bytes)?;
+            println!("  glyphs: {}", font.chars.len());
+        },
+        FileKind::Unknown => ()
+    }
 
-    println!("Assembled bytecode to {:?}.", output_file);
     Ok(())
 }
 
@@ -210,6 +1036,13 @@ fn cmd_font(sub_matches: &ArgMatches) -> Result<()> {
 
     let font = font::load(&input_file)?;
 
+    if let Some(index) = sub_matches.value_of("preview") {
+        let index: usize = index.parse().map_err(|_| anyhow!("Invalid glyph index {}", index))?;
+        let preview = font.preview_glyph(index).ok_or_else(|| anyhow!("No glyph {}, the font has {}", index, font.chars.len()))?;
+        print!("{}", preview);
+        return Ok(());
+    }
+
     let print = |c| {
         match c {
             0 => print!("\""),
@@ -232,15 +1065,12 @@ fn cmd_font(sub_matches: &ArgMatches) -> Result<()> {
         }
     };
 
+    let size = font::FN_DAT_GLYPH;
     for char in font.chars.iter() {
-        for y in 0..24 {
-            for x in 0..12 {
-                let c = char[y * 12 + x];
-                let a = (c & 0xF0) >> 4;
-                let b = c & 0x0F;
-
-                print(a);
-                print(b);
+        let pixels = font::render_glyph(char, size)?;
+        for row in pixels.chunks(size.width) {
+            for &c in row.iter() {
+                print(c);
             }
             println!("")
         }
@@ -250,19 +1080,246 @@ fn cmd_font(sub_matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn cmd_spec() -> Result<()> {
+    println!("{:#}", avg32::spec::dump());
+    Ok(())
+}
+
+/// The parse options given by `--sys-version` or `--game-dir`.
+fn parse_config(matches: &ArgMatches) -> Result<ParseConfig> {
+    let sys_version = match (matches.value_of("sys-version"), matches.value_of("game-dir")) {
+        (Some(version), _) => Some(avg32::version::parse_version(version)?),
+        (None, Some(dir)) => {
+            let version = avg32::detect_version(dir).ok_or_else(|| anyhow!("Unable to detect the version of the game in {:?}", dir))?;
+            info!("Detected version {} from {:?}", version, dir);
+            Some(version)
+        },
+        (None, None) => None
+    };
+
+    let mut config = ParseConfig::default();
+    if let Some(sys_version) = sys_version {
+        config.sys_version = sys_version;
+    }
+    Ok(config)
+}
+
 fn main() -> Result<()> {
     env_logger::init();
 
     let matches = get_app().get_matches();
+    let config = match matches.subcommand() {
+        (_, Some(sub_matches)) => parse_config(sub_matches)?,
+        _ => ParseConfig::default()
+    };
+
+    avg32::parser::with_config(&config, || run(&matches))
+}
 
+fn run(matches: &ArgMatches) -> Result<()> {
     match matches.subcommand() {
         ("unpack", Some(sub_matches)) => cmd_unpack(&sub_matches)?,
+        ("extract", Some(sub_matches)) => cmd_extract(&sub_matches)?,
         ("repack", Some(sub_matches)) => cmd_repack(&sub_matches)?,
         ("disasm", Some(sub_matches)) => cmd_disasm(&sub_matches)?,
         ("asm",    Some(sub_matches)) => cmd_asm(&sub_matches)?,
+        ("check-label", Some(sub_matches)) => cmd_check_label(&sub_matches)?,
+        ("diff",   Some(sub_matches)) => cmd_diff(&sub_matches)?,
+        ("stats",  Some(sub_matches)) => cmd_stats(&sub_matches)?,
+        ("metrics", Some(sub_matches)) => cmd_metrics(&sub_matches)?,
+        ("opcodes", Some(sub_matches)) => cmd_opcodes(&sub_matches)?,
+        ("grep",   Some(sub_matches)) => cmd_grep(&sub_matches)?,
+        ("assets", Some(sub_matches)) => cmd_assets(&sub_matches)?,
+        ("choices", Some(sub_matches)) => cmd_choices(&sub_matches)?,
+        ("verify", Some(sub_matches)) => cmd_verify(&sub_matches)?,
+        ("info",   Some(sub_matches)) => cmd_info(&sub_matches)?,
+        ("spec",   Some(_)) => cmd_spec()?,
         ("font",   Some(sub_matches)) => cmd_font(&sub_matches)?,
         _ => get_app().print_long_help()?
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_filename_roundtrip() {
+        for &keep_names in [false, true].iter() {
+            for &raw in [false, true].iter() {
+                let unpacked = unpacked_filename("SEEN011.TXT", raw, keep_names);
+                assert_eq!("SEEN011.TXT", packed_filename(&Path::new("out").join(&unpacked), raw, keep_names));
+            }
+        }
+
+        assert_eq!(PathBuf::from("SEEN011.adieu"), unpacked_filename("SEEN011.TXT", false, false));
+        assert_eq!(PathBuf::from("SEEN011.TXT"), unpacked_filename("SEEN011.TXT", true, false));
+        assert_eq!(PathBuf::from("seen011.txt.adieu"), unpacked_filename("seen011.txt", false, true));
+        assert_eq!("seen011.txt", packed_filename(Path::new("seen011.txt.adieu"), false, true));
+        assert_eq!("seen011.TXT", packed_filename(Path::new("seen011.adieu"), false, false));
+    }
+
+    #[test]
+    fn test_disasm_dir() {
+        use avg32::build::SceneBuilder;
+
+        let dir = std::env::temp_dir().join(format!("adieu-test-disasm-dir-{}", std::process::id()));
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+
+        let scene = SceneBuilder::new().text("あいう").wait_mouse().build().unwrap();
+        let mut bytes = Vec::new();
+        scene.write(&mut bytes).unwrap();
+        fs::write(dir.join("SEEN001.TXT"), &bytes).unwrap();
+        fs::write(dir.join("SEEN002.TXT"), &bytes).unwrap();
+        fs::write(dir.join("SEEN003.TXT"), b"TPC32 truncated").unwrap();
+        fs::write(dir.join("README.TXT"), b"not a scene").unwrap();
+
+        let options = DisasmOptions { lenient: false, listing: false, hex: false, only: None, label_style: "kind", symbols: None };
+        let stopped = disasm_dir(&dir, &out, &options, &mut Failures::new(false));
+        let mut failures = Failures::new(true);
+        let done = disasm_dir(&dir, &out, &options, &mut failures).unwrap();
+
+        let sexp = fs::read_to_string(out.join("SEEN002.adieu")).unwrap();
+        let written: Vec<String> = {
+            let mut names: Vec<String> = fs::read_dir(&out).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+            names.sort();
+            names
+        };
+        fs::remove_dir_all(&dir).unwrap();
+
+        let err = stopped.unwrap_err().to_string();
+        assert!(err.contains("SEEN003.TXT"), "{}", err);
+        assert_eq!(2, done);
+        assert_eq!(1, failures.names.len());
+        assert!(failures.names[0].ends_with("SEEN003.TXT"));
+        assert!(failures.finish("disassemble").is_err());
+        assert_eq!(vec!["SEEN001.adieu", "SEEN002.adieu"], written);
+        assert_eq!(scene, disasm::assemble(&sexp).unwrap());
+    }
+
+    #[test]
+    fn test_keep_going() {
+        use avg32::build::SceneBuilder;
+
+        let dir = std::env::temp_dir().join(format!("adieu-test-keep-going-{}", std::process::id()));
+        let src = dir.join("src");
+        let out = dir.join("out");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&out).unwrap();
+
+        let scene = SceneBuilder::new().text("あいう").wait_mouse().build().unwrap();
+        let sexp = disasm::disassemble(&scene).unwrap();
+        fs::write(src.join("SEEN001.adieu"), &sexp).unwrap();
+        fs::write(src.join("SEEN002.adieu"), "(not a scene").unwrap();
+        fs::write(src.join("SEEN003.adieu"), &sexp).unwrap();
+
+        let mut failures = Failures::new(false);
+        let stopped = repack_members(&src, false, false, &mut failures);
+        let mut failures = Failures::new(true);
+        let members = repack_members(&src, false, false, &mut failures).unwrap();
+        let names: Vec<&str> = members.iter().map(|(name, _)| name.as_str()).collect();
+        let repack_failures = failures.names.clone();
+        let repack_finish = failures.finish("pack");
+        let mut failures = Failures::new(true);
+        let repacked = repack(&src, &dir.join("SEEN.TXT"), false, false, &mut failures);
+        let repack_written = dir.join("SEEN.TXT").exists();
+
+        let mut arc = Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), scene.to_vec().unwrap()).unwrap();
+        arc.add_entry(String::from("SEEN002.TXT"), b"TPC32 truncated".to_vec()).unwrap();
+        arc.add_entry(String::from("SEEN003.TXT"), scene.to_vec().unwrap()).unwrap();
+        arc.finalize();
+        let mut failures = Failures::new(true);
+        let written = unpack_members(&arc, &out, false, false, false, &mut failures).unwrap();
+        let mut unpacked: Vec<String> = fs::read_dir(&out).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        unpacked.sort();
+        let unpacked_sexp = fs::read_to_string(out.join("SEEN003.adieu")).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        let err = stopped.unwrap_err().to_string();
+        assert!(err.contains("SEEN002.adieu"), "{}", err);
+        assert_eq!(vec!["SEEN001.TXT", "SEEN003.TXT"], names);
+        assert_eq!(1, repack_failures.len());
+        assert!(repack_failures[0].ends_with("SEEN002.adieu"));
+        assert!(repack_finish.is_err());
+        assert!(repacked.is_err());
+        assert!(!repack_written);
+
+        assert_eq!(2, written);
+        assert_eq!(vec!["SEEN002.TXT"], failures.names);
+        assert_eq!(vec!["SEEN001.adieu", "SEEN003.adieu"], unpacked);
+        assert_eq!(scene, disasm::assemble(&unpacked_sexp).unwrap());
+    }
+
+    #[test]
+    fn test_extract_member() {
+        use avg32::build::SceneBuilder;
+
+        let scene = SceneBuilder::new().text("あいう").wait_mouse().build().unwrap();
+        let other = SceneBuilder::new().newline().build().unwrap();
+        let mut arc = Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), other.to_vec().unwrap()).unwrap();
+        arc.add_entry(String::from("SEEN002.TXT"), scene.to_vec().unwrap()).unwrap();
+        arc.finalize();
+
+        let sexp = String::from_utf8(extract_member(&arc, "seen002.txt", false, false).unwrap()).unwrap();
+        assert_eq!(disasm::disassemble(&scene).unwrap(), sexp);
+        assert_eq!(scene, disasm::assemble(&sexp).unwrap());
+
+        assert_eq!(other.to_vec().unwrap(), extract_member(&arc, "0", true, false).unwrap());
+        assert!(extract_member(&arc, "SEEN003.TXT", false, false).is_err());
+        assert!(extract_member(&arc, "2", false, false).is_err());
+    }
+
+    #[test]
+    fn test_grep_matches() {
+        use avg32::parser::{GrpCmd, SceneText, SystemCmd, Val};
+
+        let game_end = avg32::Opcode::System(SystemCmd::GameEnd);
+        let load = avg32::Opcode::Graphics(GrpCmd::Load(SceneText::Literal(String::from("BG001")), Val::constant(0)));
+
+        assert!(grep_matches(&game_end, "System::GameEnd", false));
+        assert!(grep_matches(&game_end, "system", false));
+        assert!(grep_matches(&game_end, "GameEnd", false));
+        assert!(!grep_matches(&game_end, "Game", false));
+        assert!(!grep_matches(&load, "bg00", false));
+        assert!(grep_matches(&load, "bg00", true));
+        assert!(!grep_matches(&load, "BG002", true));
+        assert!(!grep_matches(&game_end, "", true));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = |args: &[&str]| {
+            let matches = get_app().get_matches_from_safe(args).unwrap();
+            let (_, sub_matches) = matches.subcommand();
+            parse_config(sub_matches.unwrap())
+        };
+
+        assert_eq!(ParseConfig::default(), config(&["adieu", "info", "SEEN.TXT"]).unwrap());
+        assert_eq!(1704, config(&["adieu", "info", "--sys-version", "17D", "SEEN.TXT"]).unwrap().sys_version);
+        assert_eq!(1613, config(&["adieu", "--sys-version", "1613", "info", "SEEN.TXT"]).unwrap().sys_version);
+        assert!(config(&["adieu", "info", "--sys-version", "new", "SEEN.TXT"]).is_err());
+        assert!(config(&["adieu", "info", "--game-dir", "/nonexistent", "SEEN.TXT"]).is_err());
+    }
+
+    #[test]
+    fn test_filename_entry_table() {
+        let name = packed_filename(&unpacked_filename("SEEN011.TXT", false, false), false, false);
+        let mut arc = Archive::new();
+        arc.add_entry(name, vec![1, 2, 3]).unwrap();
+        arc.finalize();
+
+        let bytes = arc.to_vec().unwrap();
+
+        // The first entry's 16-byte filename field follows the 0x20 byte
+        // archive header.
+        let field = &bytes[0x20..0x30];
+        assert_eq!(b"SEEN011.TXT\0\0\0\0\0", field);
+    }
+}
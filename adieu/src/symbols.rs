@@ -0,0 +1,223 @@
+use avg32::labels::{LabelKind, LabelNamer};
+use avg32::parser::{AVG32Scene, Header, Opcode};
+use avg32::visit::VisitMut;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+
+/// Names for offsets and variables, shared as a plain text file with one
+/// `key = name` per line:
+///
+/// ```text
+/// # Comments and blank lines are ignored.
+/// 0x1a2b = route_a        # offset in the opcode body
+/// label 3 = ending        # entry 3 of the header's label table
+/// var 12 = met_heroine    # variable index
+/// ```
+///
+/// Offsets may also be given in decimal. A key may only appear once, and
+/// offsets and labels need distinct names other than `start`, which always
+/// names the beginning of the scene.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct SymbolMap {
+    pub offsets: BTreeMap<u32, String>,
+    pub header_labels: BTreeMap<usize, String>,
+    pub vars: BTreeMap<u32, String>,
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+impl SymbolMap {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut res = SymbolMap::default();
+        let mut label_names: HashMap<String, usize> = HashMap::new();
+        let mut var_names: HashMap<String, usize> = HashMap::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let err = || anyhow!("Line {}: expected `key = name`, got {:?}", i + 1, line);
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let name = parts.next().ok_or_else(err)?.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(err());
+            }
+            let name = String::from(name);
+
+            let mut words = key.split_whitespace();
+            let (is_label, old) = match (words.next(), words.next(), words.next()) {
+                (Some("label"), Some(n), None) => {
+                    let n = parse_number(n).ok_or_else(err)?;
+                    (true, res.header_labels.insert(n as usize, name.clone()))
+                },
+                (Some("var"), Some(n), None) => {
+                    let n = parse_number(n).ok_or_else(err)?;
+                    (false, res.vars.insert(n, name.clone()))
+                },
+                (Some(n), None, None) => {
+                    let n = parse_number(n).ok_or_else(err)?;
+                    (true, res.offsets.insert(n, name.clone()))
+                },
+                _ => return Err(err())
+            };
+
+            if old.is_some() {
+                return Err(anyhow!("Line {}: {} is already named", i + 1, key));
+            }
+            if is_label && name == "start" {
+                return Err(anyhow!("Line {}: `start` is reserved for the beginning of the scene", i + 1));
+            }
+            let names = if is_label { &mut label_names } else { &mut var_names };
+            if let Some(line) = names.insert(name.clone(), i + 1) {
+                return Err(anyhow!("Line {}: {} is already used on line {}", i + 1, name, line));
+            }
+        }
+
+        Ok(res)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        SymbolMap::parse(&fs::read_to_string(path)?)
+    }
+
+    /// A namer using the names in this map for the labels of a scene with
+    /// `header`, and `fallback` for the rest.
+    pub fn namer(&self, header: &Header, fallback: Box<dyn LabelNamer>) -> SymbolNamer {
+        let mut names: HashMap<u32, String> = HashMap::new();
+        for (index, name) in self.header_labels.iter() {
+            match header.labels.get(*index) {
+                Some(offset) => { names.insert(*offset, name.clone()); },
+                None => warn!("Symbol {} refers to label {}, but the scene only has {}", name, index, header.labels.len())
+            }
+        }
+        for (offset, name) in self.offsets.iter() {
+            names.insert(*offset, name.clone());
+        }
+
+        SymbolNamer {
+            names: names,
+            fallback: fallback
+        }
+    }
+
+    /// One comment line per named variable that `scene` uses.
+    pub fn var_comments(&self, scene: &AVG32Scene) -> Vec<String> {
+        let mut used = BTreeSet::new();
+        for opcode in scene.opcodes.iter() {
+            used.extend(vars_of(opcode));
+        }
+
+        used.iter()
+            .filter_map(|idx| self.vars.get(idx).map(|name| format!("var {}: {}", idx, name)))
+            .collect()
+    }
+}
+
+/// Indices of the variables read or written by `opcode`.
+fn vars_of(opcode: &Opcode) -> Vec<u32> {
+    let mut res = Vec::new();
+    opcode.clone().visit_vals_mut(&mut |val| {
+        if val.is_var() {
+            res.push(val.0);
+        }
+    });
+    res
+}
+
+/// See `SymbolMap::namer`.
+pub struct SymbolNamer {
+    names: HashMap<u32, String>,
+    fallback: Box<dyn LabelNamer>
+}
+
+impl LabelNamer for SymbolNamer {
+    fn name(&mut self, kind: LabelKind, offset: u32) -> String {
+        match self.names.get(&offset) {
+            Some(name) => name.clone(),
+            None => self.fallback.name(kind, offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use avg32::labels::DefaultNamer;
+    use crate::disasm;
+    use avg32::build::SceneBuilder;
+    use avg32::parser::{Condition, Val};
+
+    #[test]
+    fn test_parse_symbols() {
+        let symbols = SymbolMap::parse("
+# shared notes
+0x1a = route_a
+label 2 = ending  # the good one
+var 12 = met_heroine
+30 = route_b
+").unwrap();
+
+        assert_eq!(Some(&String::from("route_a")), symbols.offsets.get(&0x1a));
+        assert_eq!(Some(&String::from("route_b")), symbols.offsets.get(&30));
+        assert_eq!(Some(&String::from("ending")), symbols.header_labels.get(&2));
+        assert_eq!(Some(&String::from("met_heroine")), symbols.vars.get(&12));
+
+        assert!(SymbolMap::parse("0x1a route_a").is_err());
+        assert!(SymbolMap::parse("label x = a").is_err());
+        assert!(SymbolMap::parse("0x1a = two words").is_err());
+
+        assert!(SymbolMap::parse("0x1a = start").is_err());
+        assert!(SymbolMap::parse("label 0 = start").is_err());
+        assert!(SymbolMap::parse("0x1a = a\n0x1a = b").is_err());
+        assert!(SymbolMap::parse("0x1a = a\n26 = b").is_err());
+        assert!(SymbolMap::parse("0x1a = a\n0x1b = a").is_err());
+        assert!(SymbolMap::parse("0x1a = a\nlabel 1 = a").is_err());
+        assert!(SymbolMap::parse("var 1 = a\nvar 2 = a").is_err());
+        assert!(SymbolMap::parse("var 1 = start\n0x1a = a\nvar 2 = b").is_ok());
+    }
+
+    #[test]
+    fn test_symbols_rename_labels() {
+        let scene = SceneBuilder::new()
+            .condition(vec![Condition::FlagEqConst(Val::variable(12), Val::constant(1))], "bad")
+            .text("はい")
+            .jump("end")
+            .label("bad")
+            .text("いいえ")
+            .label("end")
+            .wait_mouse()
+            .build()
+            .unwrap();
+        let bad = scene.header.labels[1];
+        let end = scene.header.labels[2];
+
+        let symbols = SymbolMap::parse(&format!("0x{:x} = bad_end\nlabel 2 = the_end\nvar 12 = met_heroine\nvar 13 = unused", bad)).unwrap();
+        let mut namer = symbols.namer(&scene.header, Box::new(DefaultNamer));
+        assert_eq!("bad_end", namer.name(LabelKind::Condition, bad));
+        assert_eq!("the_end", namer.name(LabelKind::Jump, end));
+        assert_eq!("jump_0x5", namer.name(LabelKind::Jump, 5));
+
+        let comments = symbols.var_comments(&scene);
+        assert_eq!(vec!["var 12: met_heroine"], comments);
+
+        let mut bytes = Vec::new();
+        let mut namer = symbols.namer(&scene.header, Box::new(DefaultNamer));
+        disasm::disassemble_with(&scene, &mut bytes, &mut namer, &comments).unwrap();
+        let sexp = String::from_utf8(bytes).unwrap();
+        assert!(sexp.contains(";; var 12: met_heroine\n"));
+        assert!(sexp.contains("\"the_end\""));
+        assert!(sexp.contains("\"bad_end\""));
+        assert_eq!(scene, disasm::assemble(&sexp).unwrap());
+    }
+}
@@ -0,0 +1,43 @@
+#[macro_use] extern crate bencher;
+
+use avg32::build::SceneBuilder;
+use avg32::labels::{resolve_labels, DefaultNamer};
+use avg32::parser::{AVG32Scene, Condition, Val};
+use bencher::Bencher;
+
+/// A scene the size of a long route: `count` blocks of text, each ending
+/// in a condition that jumps to the next block.
+fn long_scene(count: usize) -> AVG32Scene {
+    let mut builder = SceneBuilder::new();
+    for i in 0..count {
+        builder = builder
+            .label(&format!("block{}", i))
+            .text("あいうえおかきくけこさしすせそ")
+            .wait_mouse()
+            .condition(vec![Condition::FlagEqConst(Val::variable(i as u32 % 16), Val::constant(1))], &format!("block{}", i + 1));
+    }
+    builder.label(&format!("block{}", count)).wait_mouse().build().unwrap()
+}
+
+/// A scene where every opcode is a jump to the next one, so each opcode
+/// gets a label of its own.
+fn label_heavy_scene(count: usize) -> AVG32Scene {
+    let mut builder = SceneBuilder::new();
+    for i in 0..count {
+        builder = builder.label(&format!("l{}", i)).jump(&format!("l{}", i + 1));
+    }
+    builder.label(&format!("l{}", count)).wait_mouse().build().unwrap()
+}
+
+fn resolve_long_scene(b: &mut Bencher) {
+    let scene = long_scene(2000);
+    b.iter(|| resolve_labels(&scene, &mut DefaultNamer).unwrap());
+}
+
+fn resolve_label_heavy_scene(b: &mut Bencher) {
+    let scene = label_heavy_scene(5000);
+    b.iter(|| resolve_labels(&scene, &mut DefaultNamer).unwrap());
+}
+
+benchmark_group!(benches, resolve_long_scene, resolve_label_heavy_scene);
+benchmark_main!(benches);
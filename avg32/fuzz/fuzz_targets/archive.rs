@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(arc) = avg32::archive::try_parse(data) {
+        for data in arc.data.iter() {
+            let _ = data.decompress();
+        }
+    }
+});
@@ -0,0 +1,234 @@
+use std::fmt;
+use crate::parser::*;
+
+/// What an asset a scene refers to is used for.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum AssetKind {
+    /// An image, loaded by `Graphics` or shown by `MultiPdt`.
+    Graphic,
+    Bgm,
+    Wav,
+    /// A voice clip, named by its number.
+    Koe,
+    /// A sound effect, named by its number.
+    Se,
+    Movie,
+    /// A clickable area definition, loaded by `AreaBuffer`.
+    Area,
+}
+
+impl fmt::Display for AssetKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AssetKind::Graphic => "graphic",
+            AssetKind::Bgm => "bgm",
+            AssetKind::Wav => "wav",
+            AssetKind::Koe => "koe",
+            AssetKind::Se => "se",
+            AssetKind::Movie => "movie",
+            AssetKind::Area => "area",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl AssetKind {
+    /// The extension the engine adds to names given without one.
+    pub fn implied_extension(&self) -> Option<&'static str> {
+        match self {
+            AssetKind::Graphic => Some("PDT"),
+            AssetKind::Wav => Some("WAV"),
+            _ => None
+        }
+    }
+}
+
+/// Puts an asset name as written in a scene, or the name of a file on
+/// disk, in a form where names the engine treats as the same file are
+/// equal: ASCII letters uppercased, `\` path separators made `/`, and
+/// `AssetKind::implied_extension` added if there's no extension.
+pub fn normalize(name: &str, kind: AssetKind) -> String {
+    let mut res = name.trim().replace('\\', "/").to_ascii_uppercase();
+    let file_name = res.rsplit('/').next().unwrap_or("");
+    if let Some(ext) = kind.implied_extension() {
+        if !file_name.is_empty() && !file_name.contains('.') {
+            res.push('.');
+            res.push_str(ext);
+        }
+    }
+    res
+}
+
+/// An external file a scene loads.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct AssetRef {
+    pub kind: AssetKind,
+    /// The name as written in the scene, with pointers resolved by
+    /// `SceneText::resolve`. `None` if it's only known at runtime.
+    pub name: Option<String>,
+}
+
+struct Collector<'a> {
+    scene: Option<&'a AVG32Scene>,
+    refs: Vec<AssetRef>,
+}
+
+impl<'a> Collector<'a> {
+    fn text(&mut self, kind: AssetKind, text: &SceneText) {
+        self.refs.push(AssetRef {
+            kind,
+            name: match self.scene {
+                Some(scene) => text.resolve(scene),
+                None => text.literal().map(String::from)
+            }
+        });
+    }
+
+    fn number(&mut self, kind: AssetKind, val: &Val) {
+        self.refs.push(AssetRef {
+            kind,
+            name: if val.is_var() { None } else { Some(val.value().to_string()) }
+        });
+    }
+
+    fn multi_pdt(&mut self, entries: &[MultiPdtEntry]) {
+        for entry in entries.iter() {
+            self.text(AssetKind::Graphic, &entry.text);
+        }
+    }
+
+    fn composite(&mut self, children: &[GrpCompositeChild]) {
+        for child in children.iter() {
+            self.text(AssetKind::Graphic, &child.file);
+        }
+    }
+
+    fn opcode(&mut self, opcode: &Opcode) {
+        match opcode {
+            Opcode::Graphics(cmd) => match cmd {
+                GrpCmd::Load(file, _) | GrpCmd::Load2(file, _) | GrpCmd::Load3(file, _) |
+                GrpCmd::LoadToBuf(file, _) | GrpCmd::LoadToBuf2(file, _) | GrpCmd::LoadToBuf3(file, _) |
+                GrpCmd::LoadCaching(file) => self.text(AssetKind::Graphic, file),
+                GrpCmd::LoadEffect(effect) | GrpCmd::LoadEffect2(effect) | GrpCmd::LoadEffect3(effect) =>
+                    self.text(AssetKind::Graphic, &effect.file),
+                GrpCmd::LoadComposite(composite) => {
+                    self.text(AssetKind::Graphic, &composite.base_file);
+                    self.composite(&composite.children);
+                },
+                GrpCmd::LoadCompositeIndexed(composite) => self.composite(&composite.children),
+                _ => ()
+            },
+            Opcode::Sound(cmd) => match cmd {
+                SndCmd::BgmLoop(file) | SndCmd::BgmWait(file) | SndCmd::BgmOnce(file) |
+                SndCmd::BgmFadeInLoop(file, _) | SndCmd::BgmFadeInWait(file, _) | SndCmd::BgmFadeInOnce(file, _) =>
+                    self.text(AssetKind::Bgm, file),
+                SndCmd::KoePlayWait(koe) | SndCmd::KoePlay(koe) | SndCmd::KoePlay2(koe, _) =>
+                    self.number(AssetKind::Koe, koe),
+                SndCmd::WavPlay(file) | SndCmd::WavPlay2(file, _) | SndCmd::WavLoop(file) |
+                SndCmd::WavLoop2(file, _) | SndCmd::WavPlayWait(file) | SndCmd::WavPlayWait2(file, _) =>
+                    self.text(AssetKind::Wav, file),
+                SndCmd::SePlay(se) => self.number(AssetKind::Se, se),
+                SndCmd::MoviePlay(file, ..) | SndCmd::MovieLoop(file, ..) |
+                SndCmd::MovieWait(file, ..) | SndCmd::MovieWaitCancelable(file, ..) =>
+                    self.text(AssetKind::Movie, file),
+                SndCmd::MovieWait2(a, b, ..) | SndCmd::MovieWaitCancelable2(a, b, ..) => {
+                    self.text(AssetKind::Movie, a);
+                    self.text(AssetKind::Movie, b);
+                },
+                _ => ()
+            },
+            Opcode::MultiPdt(cmd) => match cmd {
+                MultiPdtCmd::Slideshow(_, _, entries) | MultiPdtCmd::SlideshowLoop(_, _, entries) |
+                MultiPdtCmd::Scroll(_, _, _, _, entries) | MultiPdtCmd::Scroll2(_, _, _, _, entries) |
+                MultiPdtCmd::ScrollWithCancel(_, _, _, _, _, entries) => self.multi_pdt(entries),
+                MultiPdtCmd::StopSlideshowLoop => ()
+            },
+            Opcode::AreaBuffer(AreaBufferCmd::ReadCurArd(a, b)) => {
+                self.text(AssetKind::Area, a);
+                self.text(AssetKind::Area, b);
+            },
+            _ => ()
+        }
+    }
+}
+
+impl AVG32Scene {
+    /// Every external file the scene loads, in the order they appear,
+    /// including repeats.
+    pub fn asset_references(&self) -> Vec<AssetRef> {
+        let mut collector = Collector {
+            scene: Some(self),
+            refs: Vec::new()
+        };
+        for opcode in self.opcodes.iter() {
+            collector.opcode(opcode);
+        }
+        collector.refs
+    }
+}
+
+impl Opcode {
+    /// Like `AVG32Scene::asset_references`, for one opcode. Without the
+    /// rest of the scene, names given by pointers are left as `None`.
+    pub fn asset_references(&self) -> Vec<AssetRef> {
+        let mut collector = Collector {
+            scene: None,
+            refs: Vec::new()
+        };
+        collector.opcode(self);
+        collector.refs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::SceneBuilder;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!("BG001.PDT", normalize("BG001", AssetKind::Graphic));
+        assert_eq!("BG001.PDT", normalize("bg001", AssetKind::Graphic));
+        assert_eq!("BG001.PDT", normalize("Bg001.pdt", AssetKind::Graphic));
+        assert_eq!("SE01.WAV", normalize("se01", AssetKind::Wav));
+        assert_eq!("SE01.OGG", normalize("se01.ogg", AssetKind::Wav));
+        assert_eq!("PDT/CG02.PDT", normalize("pdt\\cg02", AssetKind::Graphic));
+        assert_eq!("BGM01", normalize(" bgm01 ", AssetKind::Bgm));
+        assert_eq!("1042", normalize("1042", AssetKind::Koe));
+        assert_eq!("背景A.PDT", normalize("背景a", AssetKind::Graphic));
+
+        assert_eq!(normalize("BG001", AssetKind::Graphic), normalize("bg001.PDT", AssetKind::Graphic));
+        assert_ne!(normalize("BG001", AssetKind::Graphic), normalize("BG001", AssetKind::Wav));
+    }
+
+    #[test]
+    fn test_asset_references() {
+        let text = |s: &str| SceneText::Literal(String::from(s));
+        let asset = |kind, name: Option<&str>| AssetRef { kind: kind, name: name.map(String::from) };
+        let scene = SceneBuilder::new()
+            .opcode(Opcode::Graphics(GrpCmd::Load(text("BG001"), Val::constant(0))))
+            .opcode(Opcode::Sound(SndCmd::BgmLoop(text("BGM01"))))
+            .opcode(Opcode::String(StringCmd::StrcpyLiteral(Val::constant(2), text("CG002"))))
+            .opcode(Opcode::Graphics(GrpCmd::LoadCaching(SceneText::Pointer(Val::constant(2)))))
+            .opcode(Opcode::Sound(SndCmd::WavPlay(SceneText::Pointer(Val::variable(3)))))
+            .opcode(Opcode::Sound(SndCmd::KoePlay(Val::constant(1042))))
+            .opcode(Opcode::Sound(SndCmd::SePlay(Val::variable(5))))
+            .opcode(Opcode::Sound(SndCmd::BgmStop))
+            .opcode(Opcode::Graphics(GrpCmd::Load(text("BG001"), Val::constant(1))))
+            .build()
+            .unwrap();
+
+        assert_eq!(vec![
+            asset(AssetKind::Graphic, Some("BG001")),
+            asset(AssetKind::Bgm, Some("BGM01")),
+            asset(AssetKind::Graphic, Some("CG002")),
+            asset(AssetKind::Wav, None),
+            asset(AssetKind::Koe, Some("1042")),
+            asset(AssetKind::Se, None),
+            asset(AssetKind::Graphic, Some("BG001")),
+        ], scene.asset_references());
+
+        assert_eq!(vec![asset(AssetKind::Graphic, None)], scene.opcodes[3].asset_references());
+        assert!(scene.opcodes[2].asset_references().is_empty());
+    }
+}
@@ -0,0 +1,678 @@
+use std::collections::{BTreeMap, HashMap};
+use anyhow::{anyhow, Result};
+use crate::parser::*;
+use crate::write::Writeable;
+
+/// The jump targets of `opcode`, if it has any.
+pub(crate) fn jump_targets(opcode: &Opcode) -> Vec<&Pos> {
+    match opcode {
+        Opcode::Condition(_, pos) | Opcode::Call(pos) | Opcode::Jump(pos) => vec![pos],
+        Opcode::TableCall(_, poss) | Opcode::TableJump(_, poss) => poss.iter().collect(),
+        _ => Vec::new()
+    }
+}
+
+pub(crate) fn jump_targets_mut(opcode: &mut Opcode) -> Vec<&mut Pos> {
+    match opcode {
+        Opcode::Condition(_, pos) | Opcode::Call(pos) | Opcode::Jump(pos) => vec![pos],
+        Opcode::TableCall(_, poss) | Opcode::TableJump(_, poss) => poss.iter_mut().collect(),
+        _ => Vec::new()
+    }
+}
+
+/// Accumulates opcodes for a new scene. Jump targets are given as label
+/// names and resolved to byte offsets by `build`.
+#[derive(Debug, Clone)]
+pub struct SceneBuilder {
+    header: Header,
+    opcodes: Vec<Opcode>,
+    labels: Vec<(String, usize)>,
+    text_count: u32
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        SceneBuilder {
+            header: Header::empty(),
+            opcodes: Vec::new(),
+            labels: vec![(String::from("start"), 0)],
+            text_count: 0
+        }
+    }
+
+    /// Uses `header` instead of an empty one. Its `labels` are replaced on
+    /// `build`.
+    pub fn header(mut self, header: Header) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn opcode(mut self, opcode: Opcode) -> Self {
+        self.opcodes.push(opcode);
+        self
+    }
+
+    /// Marks the position of the next opcode. `start` is predefined.
+    pub fn label(mut self, name: &str) -> Self {
+        self.labels.push((String::from(name), self.opcodes.len()));
+        self
+    }
+
+    /// Adds a text opcode, numbering it after the previous ones.
+    pub fn text(mut self, text: &str) -> Self {
+        let index = self.text_count;
+        self.text_count += 1;
+        self.opcode(Opcode::TextZenkaku(Some(index), SceneText::Literal(String::from(text))))
+    }
+
+    pub fn wait_mouse(self) -> Self {
+        self.opcode(Opcode::WaitMouse)
+    }
+
+    pub fn newline(self) -> Self {
+        self.opcode(Opcode::Newline)
+    }
+
+    pub fn jump(self, label: &str) -> Self {
+        self.opcode(Opcode::Jump(Pos::Label(String::from(label))))
+    }
+
+    pub fn call(self, label: &str) -> Self {
+        self.opcode(Opcode::Call(Pos::Label(String::from(label))))
+    }
+
+    /// Jumps to `label` unless `conditions` hold. The surrounding
+    /// `IncDepth`/`DecDepth` pair is added automatically.
+    pub fn condition(self, conditions: Vec<Condition>, label: &str) -> Self {
+        let mut conds = vec![Condition::IncDepth];
+        conds.extend(conditions);
+        conds.push(Condition::DecDepth);
+        self.opcode(Opcode::Condition(conds, Pos::Label(String::from(label))))
+    }
+
+    /// Sets variable `idx` to the constant `value`.
+    pub fn set_val(self, idx: u32, value: u32) -> Self {
+        self.opcode(Opcode::SetValLiteral(Val::constant(idx), Val::constant(value)))
+    }
+
+    /// Shows a choice between `options`, storing the selected index in
+    /// variable `idx`.
+    pub fn choice(self, idx: u32, options: &[&str]) -> Self {
+        let texts = options.iter()
+            .map(|s| SceneFormattedText(vec![SceneFormattedTextEntry::TextZenkaku(String::from(*s))]))
+            .collect();
+        let text = ChoiceText {
+            pad: Some(0x22),
+            texts
+        };
+        self.opcode(Opcode::Choice(ChoiceCmd::Choice(Val::constant(idx), 0x22, Some(text))))
+    }
+
+    /// Resolves labels to byte offsets and fills in the header's label
+    /// table, in the order the labels were defined.
+    pub fn build(self) -> Result<AVG32Scene> {
+        let mut offsets = Vec::with_capacity(self.opcodes.len() + 1);
+        let mut pos = 0;
+        for opcode in self.opcodes.iter() {
+            offsets.push(pos);
+            pos += opcode.byte_size() as u32;
+        }
+        offsets.push(pos);
+
+        let mut positions: HashMap<&str, u32> = HashMap::new();
+        let mut header_labels = Vec::new();
+        for (name, index) in self.labels.iter() {
+            if positions.insert(name, offsets[*index]).is_some() {
+                return Err(anyhow!("Label defined twice: {}", name));
+            }
+            header_labels.push(offsets[*index]);
+        }
+
+        let resolve = |pos: &mut Pos| -> Result<()> {
+            if let Pos::Label(name) = pos {
+                match positions.get(name.as_str()) {
+                    Some(offset) => *pos = Pos::Offset(*offset),
+                    None => return Err(anyhow!("Undefined label: {}", name))
+                }
+            }
+            Ok(())
+        };
+
+        let mut opcodes = self.opcodes;
+        for opcode in opcodes.iter_mut() {
+            for pos in jump_targets_mut(opcode) {
+                resolve(pos)?;
+            }
+        }
+
+        let mut header = self.header;
+        header.labels = header_labels;
+
+        Ok(AVG32Scene {
+            header,
+            opcodes,
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        })
+    }
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        SceneBuilder::new()
+    }
+}
+
+impl AVG32Scene {
+    /// Appends `other`'s opcodes, moving its jump targets and header labels
+    /// past the end of this scene's body. Both headers must agree on
+    /// everything but their labels, except that menus may be missing from
+    /// one of them.
+    pub fn append(&mut self, other: &AVG32Scene) -> Result<()> {
+        let (a, b) = (&self.header, &other.header);
+        if a.unk1 != b.unk1 || a.unk2 != b.unk2 || a.unk3 != b.unk3 {
+            return Err(anyhow!("Scenes have different unknown header bytes"));
+        }
+        if a.counter_start != b.counter_start {
+            return Err(anyhow!("Scenes have different counter_start: {} != {}", a.counter_start, b.counter_start));
+        }
+        let has_menus = |h: &Header| !h.menus.is_empty() || !h.menu_strings.is_empty();
+        if has_menus(a) && has_menus(b) && (a.menus != b.menus || a.menu_strings != b.menu_strings) {
+            return Err(anyhow!("Scenes have different menus"));
+        }
+
+        let base: u32 = (0..self.opcodes.len()).map(|index| self.opcode_size(index) as u32).sum();
+        let rebase = |pos: &mut Pos| -> Result<()> {
+            match pos {
+                Pos::Offset(offset) => *offset += base,
+                Pos::Label(name) => return Err(anyhow!("Label wasn't resolved: {}", name))
+            }
+            Ok(())
+        };
+
+        let mut opcodes = other.opcodes.clone();
+        for opcode in opcodes.iter_mut() {
+            for pos in jump_targets_mut(opcode) {
+                rebase(pos)?;
+            }
+        }
+
+        if !has_menus(a) {
+            self.header.menus = b.menus.clone();
+            self.header.menu_strings = b.menu_strings.clone();
+        }
+        self.header.labels.extend(b.labels.iter().map(|l| l + base));
+        let count = self.opcodes.len();
+        self.val_lengths.extend(other.val_lengths.iter().map(|(index, lengths)| (count + index, lengths.clone())));
+        self.opcodes.extend(opcodes);
+
+        Ok(())
+    }
+
+    /// Removes every opcode that displays text, keeping control flow,
+    /// flags and choices. Jumps to a removed opcode and header labels
+    /// pointing at one move to the next opcode that's kept. Targets that
+    /// aren't the start of an opcode are left as they are.
+    pub fn strip_text(&mut self) {
+        let is_text = |opcode: &Opcode| matches!(opcode,
+            Opcode::TextHankaku(..) | Opcode::TextZenkaku(..) | Opcode::DrawValText(_));
+
+        // Maps the offset of each opcode, and of the end of the body, to
+        // its offset once the text is gone.
+        let mut moved: HashMap<u32, u32> = HashMap::new();
+        let mut val_lengths = BTreeMap::new();
+        let (mut old, mut new, mut kept) = (0, 0, 0);
+        for (index, opcode) in self.opcodes.iter().enumerate() {
+            moved.insert(old, new);
+            let size = self.opcode_size(index) as u32;
+            old += size;
+            if !is_text(opcode) {
+                new += size;
+                if let Some(lengths) = self.val_lengths.get(&index) {
+                    val_lengths.insert(kept, lengths.clone());
+                }
+                kept += 1;
+            }
+        }
+        moved.insert(old, new);
+
+        self.opcodes.retain(|opcode| !is_text(opcode));
+        self.val_lengths = val_lengths;
+        self.move_targets(&moved);
+    }
+
+    /// Moves jump targets and header labels found in `moved` to the offset
+    /// they map to.
+    fn move_targets(&mut self, moved: &HashMap<u32, u32>) {
+        for opcode in self.opcodes.iter_mut() {
+            for pos in jump_targets_mut(opcode) {
+                if let Pos::Offset(offset) = pos {
+                    if let Some(to) = moved.get(offset) {
+                        *offset = *to;
+                    }
+                }
+            }
+        }
+        for label in self.header.labels.iter_mut() {
+            if let Some(to) = moved.get(label) {
+                *label = *to;
+            }
+        }
+    }
+
+    /// Rewrites the scene in a canonical byte form, so that scenes that
+    /// only differ in quirks of how they were encoded write identically.
+    /// This changes:
+    ///
+    /// - `Val`s, which are all written in their shortest form.
+    /// - The pad byte of choice texts, which is set to 0x22, the value
+    ///   `SceneBuilder` writes.
+    /// - Fields only present from some `ParseConfig::sys_version` on: text
+    ///   indices and the `flag` of `BufferGrpCmd` copies. They are removed
+    ///   below that version and added from it on, text indices numbering
+    ///   the text opcodes in order and flags as 0.
+    ///
+    /// Opcodes may change size, so jump targets and header labels are
+    /// moved to match.
+    pub fn normalize(&mut self) {
+        let sys_version = config(|c| c.sys_version);
+        let gate = |flag: &mut Option<Val>, min_version: u32| {
+            if sys_version < min_version {
+                *flag = None;
+            } else if flag.is_none() {
+                *flag = Some(Val::constant(0));
+            }
+        };
+
+        self.resize_opcodes(|scene| {
+            scene.val_lengths.clear();
+            let mut texts = 0;
+            for opcode in scene.opcodes.iter_mut() {
+                match opcode {
+                    Opcode::TextHankaku(index, _) | Opcode::TextZenkaku(index, _) => {
+                        if sys_version < 1714 {
+                            *index = None;
+                        } else if index.is_none() {
+                            *index = Some(texts);
+                        }
+                        texts += 1;
+                    },
+                    Opcode::Choice(ChoiceCmd::Choice(_, _, Some(text))) | Opcode::Choice(ChoiceCmd::Choice2(_, _, Some(text))) =>
+                        text.pad = Some(0x22),
+                    Opcode::Buffer(BufferGrpCmd::CopyNewPos(copy)) => gate(&mut copy.flag, 1704),
+                    Opcode::Buffer(BufferGrpCmd::CopyNewPosMask(copy)) => gate(&mut copy.flag, 1613),
+                    Opcode::Buffer(BufferGrpCmd::CopyWholeScreen(copy)) => gate(&mut copy.flag, 1704),
+                    Opcode::Buffer(BufferGrpCmd::CopyWholeScreenMask(copy)) => gate(&mut copy.flag, 1613),
+                    _ => ()
+                }
+            }
+        });
+    }
+
+    /// Runs `f`, which may change the size of opcodes but must not add,
+    /// remove or reorder them, then moves jump targets and header labels to
+    /// where the opcodes they pointed at ended up.
+    pub(crate) fn resize_opcodes<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        let old_offsets: Vec<(u32, u32)> = self.opcodes_with_offsets().enumerate()
+            .map(|(index, (offset, _))| (offset, offset + self.opcode_size(index) as u32))
+            .collect();
+
+        f(self);
+
+        let mut moved: HashMap<u32, u32> = HashMap::new();
+        for (index, ((old_start, old_end), (new_start, _))) in old_offsets.iter().zip(self.opcodes_with_offsets()).enumerate() {
+            moved.insert(*old_start, new_start);
+            moved.insert(*old_end, new_start + self.opcode_size(index) as u32);
+        }
+        self.move_targets(&moved);
+    }
+
+    /// Splits the opcodes at every jump target, for working on one
+    /// subroutine at a time. The chunk at offset 0 is named `start` and the
+    /// others `loc_<offset>`. See `split_at_labels`.
+    pub fn split_by_label(&self) -> Vec<(String, Vec<Opcode>)> {
+        let mut names = BTreeMap::new();
+        names.insert(0, String::from("start"));
+        for opcode in self.opcodes.iter() {
+            for pos in jump_targets(opcode) {
+                if let Pos::Offset(offset) = pos {
+                    names.entry(*offset).or_insert_with(|| format!("loc_{:x}", offset));
+                }
+            }
+        }
+        self.split_at_labels(&names)
+    }
+
+    /// Splits the opcodes into a chunk for each offset in `names`, in
+    /// address order. Jumps to those offsets become `Pos::Label`s naming
+    /// the chunk. Offsets at or past the end of the body get an empty
+    /// chunk. Offsets inside an opcode get no chunk, and jumps to them are
+    /// left as they are.
+    pub fn split_at_labels(&self, names: &BTreeMap<u32, String>) -> Vec<(String, Vec<Opcode>)> {
+        let mut chunks: Vec<(String, Vec<Opcode>)> = Vec::new();
+        let mut labels: HashMap<u32, &String> = HashMap::new();
+        let mut pos = 0;
+        for (index, opcode) in self.opcodes.iter().enumerate() {
+            if let Some(name) = names.get(&pos) {
+                chunks.push((name.clone(), Vec::new()));
+                labels.insert(pos, name);
+            }
+            if chunks.is_empty() {
+                chunks.push((String::from("start"), Vec::new()));
+            }
+            chunks.last_mut().unwrap().1.push(opcode.clone());
+            pos += self.opcode_size(index) as u32;
+        }
+        for (offset, name) in names.range(pos..) {
+            chunks.push((name.clone(), Vec::new()));
+            labels.insert(*offset, name);
+        }
+
+        for (_, opcodes) in chunks.iter_mut() {
+            for opcode in opcodes.iter_mut() {
+                for pos in jump_targets_mut(opcode) {
+                    if let Pos::Offset(offset) = pos {
+                        if let Some(name) = labels.get(offset) {
+                            *pos = Pos::Label((*name).clone());
+                        }
+                    }
+                }
+            }
+        }
+        chunks
+    }
+}
+
+/// A small scene using at least one variant of every command enum, for
+/// checking round-trips without the original game data. Only built for
+/// tests, or with the `test-support` feature.
+#[cfg(any(test, feature = "test-support"))]
+pub fn sample_scene() -> AVG32Scene {
+    let c = Val::constant;
+    let v = Val::variable;
+    let lit = |s: &str| SceneText::Literal(String::from(s));
+
+    SceneBuilder::new()
+        .set_val(0, 1)
+        .opcode(Opcode::TextWin(TextWinCmd::ClearText))
+        .opcode(Opcode::Graphics(GrpCmd::Load(lit("BG001"), c(0))))
+        .opcode(Opcode::Sound(SndCmd::BgmLoop(lit("BGM01"))))
+        .opcode(Opcode::DrawValText(FormattedTextCmd::Integer(v(2))))
+        .opcode(Opcode::Fade(FadeCmd::FadeTimed(c(1), c(500))))
+        .opcode(Opcode::ScreenShake(ScreenShakeCmd::ScreenShake(c(3))))
+        .opcode(Opcode::Wait(WaitCmd::Wait(c(1000))))
+        .opcode(Opcode::ScenarioMenu(ScenarioMenuCmd::SetBit(c(1))))
+        .opcode(Opcode::TextRank(TextRankCmd::Set(c(2))))
+        .choice(1, &["はい", "いいえ"])
+        .opcode(Opcode::String(StringCmd::Strlen(v(3), c(4))))
+        .opcode(Opcode::SetMulti(SetMultiCmd::Val(v(10), v(20), c(0))))
+        .opcode(Opcode::System(SystemCmd::LoadGame(c(1))))
+        .opcode(Opcode::Name(NameCmd::GetName(c(0), v(5))))
+        .opcode(Opcode::BufferRegion(BufferRegionGrpCmd::ClearRect(BRGRectColor {
+            srcx1: c(0), srcy1: c(0), srcx2: c(639), srcy2: c(479),
+            srcpdt: c(1), r: c(255), g: c(255), b: c(255)
+        })))
+        .opcode(Opcode::Buffer(BufferGrpCmd::CopySamePos(BGCopySamePos {
+            srcx1: c(0), srcy1: c(0), srcx2: c(639), srcy2: c(479),
+            srcpdt: c(1), flag: c(0)
+        })))
+        .opcode(Opcode::Flash(FlashGrpCmd::FillColor(c(0), c(255), c(0), c(0))))
+        .opcode(Opcode::MultiPdt(MultiPdtCmd::StopSlideshowLoop))
+        .opcode(Opcode::AreaBuffer(AreaBufferCmd::Init))
+        .opcode(Opcode::MouseCtrl(MouseCtrlCmd::SetPos(c(320), c(240), c(0))))
+        .opcode(Opcode::WindowVar(WindowVarCmd::GetWindowMove(v(6))))
+        .opcode(Opcode::MessageWin(MessageWinCmd::GetWindowMsgPos(v(7), v(8))))
+        .opcode(Opcode::SystemVar(SystemVarCmd::GetMojiColor(v(9))))
+        .opcode(Opcode::PopupMenu(PopupMenuCmd::SetMenuDisabled(c(1))))
+        .opcode(Opcode::Volume(VolumeCmd::GetBgmVolume(v(11))))
+        .opcode(Opcode::NovelMode(NovelModeCmd::SetEnabled(c(1))))
+        .condition(vec![Condition::FlagEqConst(v(1), c(0))], "second")
+        .text("はい")
+        .call("sub")
+        .jump("end")
+        .label("second")
+        .text("いいえ")
+        .opcode(Opcode::TableJump(v(1), vec![Pos::Label(String::from("start")), Pos::Label(String::from("end"))]))
+        .label("sub")
+        .opcode(Opcode::AddVal(v(0), c(1)))
+        .opcode(Opcode::Return(RetCmd::SameScene))
+        .label("end")
+        .newline()
+        .wait_mouse()
+        .opcode(Opcode::JumpToScene(JumpToSceneCmd::Jump(c(2))))
+        .build()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_two_branches() {
+        let flag = Val::constant(0);
+        let scene = SceneBuilder::new()
+            .set_val(0, 1)
+            .choice(1, &["はい", "いいえ"])
+            .condition(vec![Condition::FlagEqConst(flag, Val::constant(1))], "no")
+            .text("はい")
+            .jump("end")
+            .label("no")
+            .text("いいえ")
+            .label("end")
+            .wait_mouse()
+            .build()
+            .unwrap();
+
+        let sizes: Vec<u32> = scene.opcodes.iter().map(|o| o.byte_size() as u32).collect();
+        let no = sizes[..5].iter().sum::<u32>();
+        let end = sizes[..6].iter().sum::<u32>();
+        assert_eq!(vec![0, no, end], scene.header.labels);
+        assert_eq!(Opcode::Jump(Pos::Offset(end)), scene.opcodes[4]);
+        match &scene.opcodes[2] {
+            Opcode::Condition(_, pos) => assert_eq!(&Pos::Offset(no), pos),
+            other => panic!("Expected condition, got {:?}", other)
+        }
+
+        let mut bytes = Vec::new();
+        scene.write(&mut bytes).unwrap();
+        assert_eq!(scene, crate::load_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_sample_scene_roundtrip() {
+        let scene = sample_scene();
+        let bytes = scene.to_vec().unwrap();
+        assert_eq!(scene.byte_size(), bytes.len());
+
+        let parsed = crate::load_bytes(&bytes).unwrap();
+        for (a, b) in scene.opcodes.iter().zip(parsed.opcodes.iter()) {
+            assert_eq!(a, b);
+        }
+        assert_eq!(scene, parsed);
+
+        let out = parsed.to_vec().unwrap();
+        assert_eq!(bytes, out);
+    }
+
+    #[test]
+    fn test_append() {
+        let a = SceneBuilder::new()
+            .text("あ")
+            .label("loop")
+            .wait_mouse()
+            .jump("loop")
+            .build()
+            .unwrap();
+        let b = SceneBuilder::new()
+            .condition(vec![Condition::FlagEqConst(Val::variable(0), Val::constant(1))], "skip")
+            .text("い")
+            .label("skip")
+            .call("sub")
+            .opcode(Opcode::TableJump(Val::variable(1), vec![Pos::Label(String::from("start")), Pos::Label(String::from("skip"))]))
+            .label("sub")
+            .newline()
+            .build()
+            .unwrap();
+
+        let mut merged = a.clone();
+        merged.append(&b).unwrap();
+        assert_eq!(a.opcodes.len() + b.opcodes.len(), merged.opcodes.len());
+
+        let base: u32 = a.opcodes.iter().map(|o| o.byte_size() as u32).sum();
+        let offset_of = |index: usize| merged.opcodes[..index].iter().map(|o| o.byte_size() as u32).sum::<u32>();
+        assert_eq!(vec![0, offset_of(1), base, base + b.header.labels[1], base + b.header.labels[2]], merged.header.labels);
+
+        // Every jump still lands on the opcode it pointed at before.
+        assert_eq!(Opcode::Jump(Pos::Offset(offset_of(1))), merged.opcodes[2]);
+        match &merged.opcodes[3] {
+            Opcode::Condition(_, pos) => assert_eq!(&Pos::Offset(offset_of(5)), pos),
+            other => panic!("Expected condition, got {:?}", other)
+        }
+        assert_eq!(Opcode::Call(Pos::Offset(offset_of(7))), merged.opcodes[5]);
+        assert_eq!(Opcode::TableJump(Val::variable(1), vec![Pos::Offset(offset_of(3)), Pos::Offset(offset_of(5))]), merged.opcodes[6]);
+
+        let bytes = merged.to_vec().unwrap();
+        assert_eq!(merged, crate::load_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_strip_text() {
+        let is_text = |op: &Opcode| matches!(op, Opcode::TextHankaku(..) | Opcode::TextZenkaku(..) | Opcode::DrawValText(_));
+        let targets = |opcodes: &[Opcode]| -> Vec<u32> {
+            opcodes.iter().cloned().flat_map(|mut op| {
+                jump_targets_mut(&mut op).into_iter().map(|pos| match pos {
+                    Pos::Offset(offset) => *offset,
+                    Pos::Label(name) => panic!("Unresolved label {}", name)
+                }).collect::<Vec<_>>()
+            }).collect()
+        };
+
+        let before = sample_scene();
+        let mut scene = before.clone();
+        scene.strip_text();
+
+        assert!(!scene.opcodes.iter().any(is_text));
+        assert_eq!(before.opcodes.iter().filter(|op| !is_text(op)).cloned().map(|mut op| {
+            for pos in jump_targets_mut(&mut op) {
+                *pos = Pos::Offset(0);
+            }
+            op
+        }).collect::<Vec<_>>(), scene.opcodes.iter().cloned().map(|mut op| {
+            for pos in jump_targets_mut(&mut op) {
+                *pos = Pos::Offset(0);
+            }
+            op
+        }).collect::<Vec<_>>());
+
+        // Each jump lands on the opcode it pointed at before, or on the one
+        // after it if that was text.
+        let old_starts: HashMap<u32, usize> = before.opcodes_with_offsets().enumerate().map(|(i, (o, _))| (o, i)).collect();
+        let new_starts: HashMap<u32, usize> = scene.opcodes_with_offsets().enumerate().map(|(i, (o, _))| (o, i)).collect();
+        let old_targets = targets(&before.opcodes);
+        let new_targets = targets(&scene.opcodes);
+        assert_eq!(5, new_targets.len());
+        for (old, new) in old_targets.iter().zip(new_targets.iter()) {
+            let kept_before = before.opcodes[..old_starts[old]].iter().filter(|op| !is_text(op)).count();
+            assert_eq!(kept_before, new_starts[new]);
+        }
+        for label in scene.header.labels.iter() {
+            assert!(new_starts.contains_key(label));
+        }
+
+        let bytes = scene.to_vec().unwrap();
+        assert_eq!(scene, crate::load_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_append_conflicting_headers() {
+        let a = SceneBuilder::new().wait_mouse().build().unwrap();
+
+        let mut header = Header::empty();
+        header.counter_start = 5;
+        let b = SceneBuilder::new().header(header).wait_mouse().build().unwrap();
+        assert!(a.clone().append(&b).is_err());
+
+        let mut header = Header::empty();
+        header.unk3[0] = 1;
+        let b = SceneBuilder::new().header(header).wait_mouse().build().unwrap();
+        assert!(a.clone().append(&b).is_err());
+
+        let unresolved = AVG32Scene { header: Header::empty(), opcodes: vec![Opcode::Jump(Pos::Label(String::from("x")))], val_lengths: BTreeMap::new(), trailer: Vec::new() };
+        assert!(a.clone().append(&unresolved).is_err());
+    }
+
+    #[test]
+    fn test_build_undefined_label() {
+        assert!(SceneBuilder::new().jump("nowhere").build().is_err());
+        assert!(SceneBuilder::new().label("a").label("a").build().is_err());
+    }
+
+    #[test]
+    fn test_split_by_label() {
+        let scene = sample_scene();
+        let chunks = scene.split_by_label();
+
+        let names: Vec<&str> = chunks.iter().map(|(name, _)| name.as_str()).collect();
+        let expected: Vec<String> = scene.header.labels.iter()
+            .map(|l| if *l == 0 { String::from("start") } else { format!("loc_{:x}", l) })
+            .collect();
+        assert_eq!(expected, names);
+        assert!(chunks.iter().all(|(_, opcodes)| !opcodes.is_empty()));
+
+        let joined = AVG32Scene {
+            header: scene.header.clone(),
+            opcodes: chunks.into_iter().flat_map(|(_, opcodes)| opcodes).collect(),
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        };
+        assert_ne!(scene, joined);
+        assert!(scene.semantically_eq(&joined));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let texts = vec![SceneFormattedText(vec![SceneFormattedTextEntry::TextZenkaku(String::from("はい"))])];
+        let mut scene = SceneBuilder::new()
+            .jump("end")
+            .opcode(Opcode::SetValLiteral(Val::constant(0), Val::constant(1)))
+            .opcode(Opcode::TextZenkaku(None, SceneText::Literal(String::from("あ"))))
+            .opcode(Opcode::Choice(ChoiceCmd::Choice(Val::constant(1), 0x22, Some(ChoiceText { pad: None, texts: texts }))))
+            .label("end")
+            .wait_mouse()
+            .build()
+            .unwrap();
+        // Both values padded to four bytes, which moves the label.
+        scene.val_lengths.insert(1, vec![Some(4), Some(4)]);
+        let end = scene.opcodes_with_offsets().last().unwrap().0;
+        scene.opcodes[0] = Opcode::Jump(Pos::Offset(end));
+        scene.header.labels[1] = end;
+
+        let mut normalized = scene.clone();
+        normalized.normalize();
+        assert!(normalized.byte_size() < scene.byte_size());
+        assert!(normalized.val_lengths.is_empty());
+        assert_eq!(Opcode::SetValLiteral(Val::constant(0), Val::constant(1)), normalized.opcodes[1]);
+        assert_eq!(Opcode::TextZenkaku(Some(0), SceneText::Literal(String::from("あ"))), normalized.opcodes[2]);
+        match &normalized.opcodes[3] {
+            Opcode::Choice(ChoiceCmd::Choice(_, _, Some(text))) => assert_eq!(Some(0x22), text.pad),
+            other => panic!("Expected choice, got {:?}", other)
+        }
+        let end = normalized.opcodes_with_offsets().last().unwrap().0;
+        assert_eq!(Opcode::Jump(Pos::Offset(end)), normalized.opcodes[0]);
+        assert_eq!(end, normalized.header.labels[1]);
+
+        let mut twice = normalized.clone();
+        twice.normalize();
+        assert_eq!(normalized, twice);
+
+        let bytes = normalized.to_vec().unwrap();
+        assert_eq!(normalized, crate::load_bytes(&bytes).unwrap());
+
+        let mut sample = sample_scene();
+        sample.normalize();
+        let mut twice = sample.clone();
+        twice.normalize();
+        assert_eq!(sample, twice);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::parser::*;
+
+/// A choice shown to the player, see `AVG32Scene::choices`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ChoiceInfo {
+    /// Offset of the `Choice` opcode from the start of the opcode body, as
+    /// used by jump targets.
+    pub offset: u32,
+    /// Variable the index of the picked option is stored in.
+    pub flag: Val,
+    /// Each option, as by `SceneFormattedText::plain_text`. Empty if the
+    /// options aren't given inline.
+    pub texts: Vec<String>,
+}
+
+/// The choice `opcode` at `offset` shows, if it shows one.
+pub(crate) fn choice_info(offset: u32, opcode: &Opcode) -> Option<ChoiceInfo> {
+    match opcode {
+        Opcode::Choice(ChoiceCmd::Choice(flag, _, text)) | Opcode::Choice(ChoiceCmd::Choice2(flag, _, text)) => Some(ChoiceInfo {
+            offset,
+            flag: *flag,
+            texts: text.iter().flat_map(|t| t.texts.iter().map(SceneFormattedText::plain_text)).collect()
+        }),
+        _ => None
+    }
+}
+
+impl AVG32Scene {
+    /// Every `ChoiceCmd::Choice` and `ChoiceCmd::Choice2`, in order.
+    pub fn choices(&self) -> Vec<ChoiceInfo> {
+        self.opcodes_with_offsets()
+            .filter_map(|(offset, opcode)| choice_info(offset, opcode))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::SceneBuilder;
+
+    #[test]
+    fn test_choices() {
+        let scene = SceneBuilder::new()
+            .text("どうする？")
+            .choice(3, &["はい", "いいえ"])
+            .opcode(Opcode::Choice(ChoiceCmd::LoadMenu(Val::constant(0))))
+            .wait_mouse()
+            .build()
+            .unwrap();
+
+        let choices = scene.choices();
+        assert_eq!(vec![ChoiceInfo {
+            offset: scene.opcode_size(0) as u32,
+            flag: Val::constant(3),
+            texts: vec![String::from("はい"), String::from("いいえ")]
+        }], choices);
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let text = SceneFormattedText(vec![
+            SceneFormattedTextEntry::Condition(vec![Condition::Ret(Ret::Choice), Condition::DecDepth]),
+            SceneFormattedTextEntry::TextZenkaku(String::from("もう")),
+            SceneFormattedTextEntry::TextPointer(Val::constant(1)),
+            SceneFormattedTextEntry::TextHankaku(String::from("1")),
+        ]);
+        assert_eq!("もう1", text.plain_text());
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use crate::build::{jump_targets, jump_targets_mut};
+use crate::parser::{AVG32Scene, Opcode, Pos};
+
+/// One difference between two scenes. Indices are into `AVG32Scene::opcodes`
+/// and offsets are relative to the start of the opcode body.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OpcodeDiff {
+    Inserted { index: usize, offset: u32, opcode: Opcode },
+    Removed { index: usize, offset: u32, opcode: Opcode },
+    Modified { index_a: usize, offset_a: u32, index_b: usize, offset_b: u32, old: Opcode, new: Opcode },
+    TextChanged { index_a: usize, offset_a: u32, index_b: usize, offset_b: u32, old: String, new: String },
+}
+
+impl fmt::Display for OpcodeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpcodeDiff::Inserted { index, offset, opcode } =>
+                write!(f, "+ [{} @0x{:04x}] {:?}", index, offset, opcode),
+            OpcodeDiff::Removed { index, offset, opcode } =>
+                write!(f, "- [{} @0x{:04x}] {:?}", index, offset, opcode),
+            OpcodeDiff::Modified { index_a, offset_a, index_b, offset_b, old, new } =>
+                write!(f, "~ [{} @0x{:04x} -> {} @0x{:04x}]\n  - {:?}\n  + {:?}", index_a, offset_a, index_b, offset_b, old, new),
+            OpcodeDiff::TextChanged { index_a, offset_a, index_b, offset_b, old, new } =>
+                write!(f, "~ [{} @0x{:04x} -> {} @0x{:04x}] text\n  - {}\n  + {}", index_a, offset_a, index_b, offset_b, old, new),
+        }
+    }
+}
+
+enum Edit {
+    Same,
+    Remove(usize),
+    Insert(usize),
+}
+
+/// Offset of each opcode in `scene`, followed by the end of the body.
+fn opcode_offsets(scene: &AVG32Scene) -> Vec<u32> {
+    let mut offsets = Vec::with_capacity(scene.opcodes.len() + 1);
+    let mut pos = 0;
+    for index in 0..scene.opcodes.len() {
+        offsets.push(pos);
+        pos += scene.opcode_size(index) as u32;
+    }
+    offsets.push(pos);
+    offsets
+}
+
+fn text_of(opcode: &Opcode) -> Option<&str> {
+    match opcode {
+        Opcode::TextHankaku(_, text) | Opcode::TextZenkaku(_, text) => text.literal(),
+        _ => None
+    }
+}
+
+/// Rewrites jump targets to a name for the opcode they point at, given by
+/// `name` from its index, so that jumps compare equal even when an earlier
+/// edit shifted every offset.
+fn normalize<F: Fn(usize) -> String>(opcode: &Opcode, targets: &HashMap<u32, usize>, name: &F) -> Opcode {
+    let mut opcode = opcode.clone();
+    for pos in jump_targets_mut(&mut opcode) {
+        if let Pos::Offset(o) = pos {
+            if let Some(index) = targets.get(o) {
+                *pos = Pos::Label(name(*index));
+            }
+        }
+    }
+    opcode
+}
+
+/// Maps the offset of each opcode, and of the end of the body, to its index.
+fn target_indices(offsets: &[u32]) -> HashMap<u32, usize> {
+    offsets.iter().enumerate().map(|(i, o)| (*o, i)).collect()
+}
+
+fn normalized<F: Fn(usize) -> String>(opcodes: &[Opcode], offsets: &[u32], name: F) -> Vec<Opcode> {
+    let targets = target_indices(offsets);
+    opcodes.iter().map(|op| normalize(op, &targets, &name)).collect()
+}
+
+/// Pairs each opcode of `b` with the opcode of `a` it lines up with, going
+/// by everything but jump targets. The end of `b` is paired with the end
+/// of `a`.
+fn alignment(a: &[Opcode], b: &[Opcode]) -> HashMap<usize, usize> {
+    let blank_a: Vec<Opcode> = a.iter().map(without_targets).collect();
+    let blank_b: Vec<Opcode> = b.iter().map(without_targets).collect();
+
+    let mut aligned = HashMap::new();
+    let (mut i, mut j) = (0, 0);
+    for edit in edit_script(&blank_a, &blank_b) {
+        match edit {
+            Edit::Same => {
+                aligned.insert(j, i);
+                i += 1;
+                j += 1;
+            },
+            Edit::Remove(_) => i += 1,
+            Edit::Insert(_) => j += 1,
+        }
+    }
+    aligned.insert(b.len(), a.len());
+    aligned
+}
+
+/// Longest common subsequence over the opcodes left after trimming the
+/// common prefix and suffix. Uses Hirschberg's algorithm, so memory stays
+/// linear in the length of `b` instead of holding the whole table.
+fn edit_script(a: &[Opcode], b: &[Opcode]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let ma = &a[prefix..a.len() - suffix];
+    let mb = &b[prefix..b.len() - suffix];
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|_| Edit::Same).collect();
+    lcs_edits(ma, mb, prefix, prefix, &mut edits);
+    edits.extend((0..suffix).map(|_| Edit::Same));
+    edits
+}
+
+/// The last row of the LCS length table of `a` against `b`: element `j` is
+/// the LCS length of `a` and the first `j` opcodes of `b`, or with `rev`,
+/// of `a` and `b` both read backwards.
+fn lcs_row(a: &[Opcode], b: &[Opcode], rev: bool) -> Vec<u32> {
+    let (n, m) = (a.len(), b.len());
+    let at = |s: &'_ [Opcode], i: usize| if rev { s.len() - 1 - i } else { i };
+    let mut prev = vec![0u32; m + 1];
+    let mut cur = vec![0u32; m + 1];
+    for i in 0..n {
+        for j in 0..m {
+            cur[j + 1] = if a[at(a, i)] == b[at(b, j)] {
+                prev[j] + 1
+            } else {
+                prev[j + 1].max(cur[j])
+            };
+        }
+        mem::swap(&mut prev, &mut cur);
+    }
+    prev
+}
+
+/// Appends the edits turning `a` into `b`, which start at indices `ia` and
+/// `ib` of the full opcode lists. Insertions come before removals where
+/// either would do.
+fn lcs_edits(a: &[Opcode], b: &[Opcode], ia: usize, ib: usize, edits: &mut Vec<Edit>) {
+    if a.is_empty() {
+        edits.extend((0..b.len()).map(|j| Edit::Insert(ib + j)));
+        return;
+    }
+    if b.is_empty() {
+        edits.extend((0..a.len()).map(|i| Edit::Remove(ia + i)));
+        return;
+    }
+    if a.len() == 1 {
+        match b.iter().position(|op| *op == a[0]) {
+            Some(j) => {
+                edits.extend((0..j).map(|k| Edit::Insert(ib + k)));
+                edits.push(Edit::Same);
+                edits.extend((j + 1..b.len()).map(|k| Edit::Insert(ib + k)));
+            },
+            None => {
+                edits.extend((0..b.len()).map(|k| Edit::Insert(ib + k)));
+                edits.push(Edit::Remove(ia));
+            }
+        }
+        return;
+    }
+
+    // Split `b` where the LCS of the two halves of `a` is longest.
+    let mid = a.len() / 2;
+    let front = lcs_row(&a[..mid], b, false);
+    let back = lcs_row(&a[mid..], b, true);
+    let m = b.len();
+    let split = (0..=m).max_by_key(|&k| front[k] + back[m - k]).unwrap();
+
+    lcs_edits(&a[..mid], &b[..split], ia, ib, edits);
+    lcs_edits(&a[mid..], &b[split..], ia + mid, ib + split, edits);
+}
+
+/// Compares the opcodes of two scenes, pairing up removed and inserted
+/// opcodes of the same kind as modifications. Jump targets are compared by
+/// the opcode they point at rather than by raw offset: a jump is unchanged
+/// if its target in `b` lines up with its target in `a`, even if opcodes
+/// were inserted or removed before either.
+pub fn diff(a: &AVG32Scene, b: &AVG32Scene) -> Vec<OpcodeDiff> {
+    let offsets_a = opcode_offsets(a);
+    let offsets_b = opcode_offsets(b);
+    let aligned = alignment(&a.opcodes, &b.opcodes);
+    let norm_a = normalized(&a.opcodes, &offsets_a, |i| format!("#{}", i));
+    let norm_b = normalized(&b.opcodes, &offsets_b, |j| match aligned.get(&j) {
+        Some(i) => format!("#{}", i),
+        // Not in `a`, so never the same target as any jump there.
+        None => format!("#b{}", j)
+    });
+
+    let mut res = Vec::new();
+    let mut removed = Vec::new();
+    let mut inserted = Vec::new();
+
+    let flush = |removed: &mut Vec<usize>, inserted: &mut Vec<usize>, res: &mut Vec<OpcodeDiff>| {
+        let paired = removed.len().min(inserted.len());
+        for (&ia, &ib) in removed.iter().zip(inserted.iter()) {
+            let (old, new) = (&a.opcodes[ia], &b.opcodes[ib]);
+            if mem::discriminant(old) != mem::discriminant(new) {
+                res.push(OpcodeDiff::Removed { index: ia, offset: offsets_a[ia], opcode: old.clone() });
+                res.push(OpcodeDiff::Inserted { index: ib, offset: offsets_b[ib], opcode: new.clone() });
+                continue;
+            }
+            res.push(match (text_of(old), text_of(new)) {
+                (Some(s), Some(t)) => OpcodeDiff::TextChanged {
+                    index_a: ia, offset_a: offsets_a[ia],
+                    index_b: ib, offset_b: offsets_b[ib],
+                    old: String::from(s), new: String::from(t)
+                },
+                _ => OpcodeDiff::Modified {
+                    index_a: ia, offset_a: offsets_a[ia],
+                    index_b: ib, offset_b: offsets_b[ib],
+                    old: old.clone(), new: new.clone()
+                }
+            });
+        }
+        for &ia in removed[paired..].iter() {
+            res.push(OpcodeDiff::Removed { index: ia, offset: offsets_a[ia], opcode: a.opcodes[ia].clone() });
+        }
+        for &ib in inserted[paired..].iter() {
+            res.push(OpcodeDiff::Inserted { index: ib, offset: offsets_b[ib], opcode: b.opcodes[ib].clone() });
+        }
+        removed.clear();
+        inserted.clear();
+    };
+
+    for edit in edit_script(&norm_a, &norm_b) {
+        match edit {
+            Edit::Same => flush(&mut removed, &mut inserted, &mut res),
+            Edit::Remove(i) => removed.push(i),
+            Edit::Insert(j) => inserted.push(j),
+        }
+    }
+    flush(&mut removed, &mut inserted, &mut res);
+
+    res
+}
+
+/// A jump target, with offsets replaced by the opcode they point at.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Target<'a> {
+    Index(usize),
+    /// An offset that isn't the start of any opcode.
+    Offset(u32),
+    Label(&'a str),
+}
+
+fn target<'a>(pos: &'a Pos, targets: &HashMap<u32, usize>) -> Target<'a> {
+    match pos {
+        Pos::Offset(o) => match targets.get(o) {
+            Some(index) => Target::Index(*index),
+            None => Target::Offset(*o)
+        },
+        Pos::Label(name) => Target::Label(name)
+    }
+}
+
+/// Index of the first opcode where `a` and `b` differ other than in how
+/// their jump targets are written, see `AVG32Scene::semantically_eq`.
+pub(crate) fn first_semantic_mismatch(a: &AVG32Scene, b: &AVG32Scene) -> Option<usize> {
+    let targets_a = target_indices(&opcode_offsets(a));
+    let targets_b = target_indices(&opcode_offsets(b));
+    let (a, b) = (&a.opcodes, &b.opcodes);
+    // What each label stands for on the other side.
+    let mut labels_a: HashMap<&str, Target> = HashMap::new();
+    let mut labels_b: HashMap<&str, Target> = HashMap::new();
+
+    for (index, (op_a, op_b)) in a.iter().zip(b.iter()).enumerate() {
+        let (poss_a, poss_b) = (jump_targets(op_a), jump_targets(op_b));
+        if poss_a.len() != poss_b.len() || without_targets(op_a) != without_targets(op_b) {
+            return Some(index);
+        }
+
+        for (pos_a, pos_b) in poss_a.into_iter().zip(poss_b) {
+            let (ta, tb) = (target(pos_a, &targets_a), target(pos_b, &targets_b));
+            let mut same = true;
+            if let Target::Label(name) = ta {
+                same &= *labels_a.entry(name).or_insert(tb) == tb;
+            }
+            if let Target::Label(name) = tb {
+                same &= *labels_b.entry(name).or_insert(ta) == ta;
+            }
+            if !matches!((ta, tb), (Target::Label(_), _) | (_, Target::Label(_))) {
+                same &= ta == tb;
+            }
+            if !same {
+                return Some(index);
+            }
+        }
+    }
+
+    if a.len() != b.len() {
+        Some(a.len().min(b.len()))
+    } else {
+        None
+    }
+}
+
+fn without_targets(opcode: &Opcode) -> Opcode {
+    let mut opcode = opcode.clone();
+    for pos in jump_targets_mut(&mut opcode) {
+        *pos = Pos::Offset(0);
+    }
+    opcode
+}
+
+impl AVG32Scene {
+    /// Whether the two scenes are the same, with jump targets compared by
+    /// the opcode they point at instead of how they're written. A label
+    /// matches an offset, or a label of another name, if it does so
+    /// everywhere it's used.
+    pub fn semantically_eq(&self, other: &AVG32Scene) -> bool {
+        self.header == other.header && first_semantic_mismatch(self, other).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::parser::*;
+
+    fn text(s: &str) -> Opcode {
+        Opcode::TextZenkaku(None, SceneText::Literal(String::from(s)))
+    }
+
+    fn scene(opcodes: Vec<Opcode>) -> AVG32Scene {
+        AVG32Scene {
+            header: Header::empty(),
+            opcodes: opcodes,
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let a = scene(vec![text("あ"), Opcode::WaitMouse]);
+        assert_eq!(Vec::<OpcodeDiff>::new(), diff(&a, &a.clone()));
+    }
+
+    #[test]
+    fn test_diff_text_changed() {
+        // The jump target shifts because the string grows, but still points
+        // at the same opcode.
+        let a = scene(vec![Opcode::Jump(Pos::Offset(5 + 4)), text("あ"), Opcode::WaitMouse]);
+        let b = scene(vec![Opcode::Jump(Pos::Offset(5 + 6)), text("あい"), Opcode::WaitMouse]);
+
+        assert_eq!(vec![OpcodeDiff::TextChanged {
+            index_a: 1, offset_a: 5,
+            index_b: 1, offset_b: 5,
+            old: String::from("あ"),
+            new: String::from("あい"),
+        }], diff(&a, &b));
+    }
+
+    #[test]
+    fn test_diff_inserted_removed() {
+        let a = scene(vec![text("あ"), Opcode::WaitMouse, text("い")]);
+        let b = scene(vec![Opcode::WaitMouse, text("い"), Opcode::Op0x05]);
+
+        assert_eq!(vec![
+            OpcodeDiff::Removed { index: 0, offset: 0, opcode: text("あ") },
+            OpcodeDiff::Inserted { index: 2, offset: 5, opcode: Opcode::Op0x05 },
+        ], diff(&a, &b));
+    }
+
+    #[test]
+    fn test_diff_jump_past_insertion() {
+        // Both jumps point at the WaitMouse, which moves by one opcode.
+        let a = scene(vec![text("あ"), Opcode::Jump(Pos::Offset(4 + 5 + 4)), text("い"), Opcode::WaitMouse]);
+        let b = scene(vec![text("あ"), Opcode::Op0x05, Opcode::Jump(Pos::Offset(4 + 1 + 5 + 4)), text("い"), Opcode::WaitMouse]);
+
+        assert_eq!(vec![
+            OpcodeDiff::Inserted { index: 1, offset: 4, opcode: Opcode::Op0x05 },
+        ], diff(&a, &b));
+
+        // Jumping one opcode further is a change.
+        let c = scene(vec![text("あ"), Opcode::Op0x05, Opcode::Jump(Pos::Offset(4 + 1 + 5)), text("い"), Opcode::WaitMouse]);
+        assert!(diff(&a, &c).iter().any(|d| match d {
+            OpcodeDiff::Inserted { opcode, .. } => matches!(opcode, Opcode::Jump(_)),
+            _ => false
+        }));
+    }
+
+    #[test]
+    fn test_edit_script() {
+        // Short runs over a few opcodes, so there are many common
+        // subsequences to choose from.
+        let alphabet = [Opcode::WaitMouse, Opcode::Newline, Opcode::Op0x05];
+        let mut seed: u32 = 1;
+        let mut next = |n: u32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) % n
+        };
+
+        for _ in 0..200 {
+            let a: Vec<Opcode> = (0..next(12)).map(|_| alphabet[next(3) as usize].clone()).collect();
+            let b: Vec<Opcode> = (0..next(12)).map(|_| alphabet[next(3) as usize].clone()).collect();
+
+            // Quadratic LCS length to check against.
+            let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in 0..a.len() {
+                for j in 0..b.len() {
+                    table[i + 1][j + 1] = if a[i] == b[j] { table[i][j] + 1 } else { table[i][j + 1].max(table[i + 1][j]) };
+                }
+            }
+
+            let edits = edit_script(&a, &b);
+            let (mut i, mut j, mut same) = (0, 0, 0);
+            for edit in edits.iter() {
+                match edit {
+                    Edit::Same => {
+                        assert_eq!(a[i], b[j]);
+                        i += 1;
+                        j += 1;
+                        same += 1;
+                    },
+                    Edit::Remove(index) => {
+                        assert_eq!(i, *index);
+                        i += 1;
+                    },
+                    Edit::Insert(index) => {
+                        assert_eq!(j, *index);
+                        j += 1;
+                    }
+                }
+            }
+            assert_eq!((a.len(), b.len()), (i, j));
+            assert_eq!(table[a.len()][b.len()], same, "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_semantically_eq() {
+        let label = |s: &str| Pos::Label(String::from(s));
+        // Jump over the text to the end, then a table back to the start.
+        let offsets = scene(vec![
+            Opcode::Jump(Pos::Offset(5 + 4)),
+            text("あ"),
+            Opcode::TableJump(Val::constant(0), vec![Pos::Offset(0), Pos::Offset(9)]),
+        ]);
+        let labels = scene(vec![
+            Opcode::Jump(label("end")),
+            text("あ"),
+            Opcode::TableJump(Val::constant(0), vec![label("start"), label("end")]),
+        ]);
+        let renamed = scene(vec![
+            Opcode::Jump(label("b")),
+            text("あ"),
+            Opcode::TableJump(Val::constant(0), vec![label("a"), label("b")]),
+        ]);
+        assert!(offsets.semantically_eq(&labels));
+        assert!(labels.semantically_eq(&offsets));
+        assert!(labels.semantically_eq(&renamed));
+        assert_ne!(offsets, labels);
+
+        // "end" can't be both the table's first and second target.
+        let inconsistent = scene(vec![
+            Opcode::Jump(label("end")),
+            text("あ"),
+            Opcode::TableJump(Val::constant(0), vec![label("end"), label("end")]),
+        ]);
+        assert!(!offsets.semantically_eq(&inconsistent));
+        assert_eq!(Some(2), first_semantic_mismatch(&offsets, &inconsistent));
+
+        // Offsets still have to point at the same opcode.
+        let moved = scene(vec![
+            Opcode::Jump(Pos::Offset(5)),
+            text("あ"),
+            Opcode::TableJump(Val::constant(0), vec![Pos::Offset(0), Pos::Offset(9)]),
+        ]);
+        assert!(!offsets.semantically_eq(&moved));
+
+        let changed = scene(vec![
+            Opcode::Jump(label("end")),
+            text("い"),
+            Opcode::TableJump(Val::constant(0), vec![label("start"), label("end")]),
+        ]);
+        assert!(!offsets.semantically_eq(&changed));
+    }
+}
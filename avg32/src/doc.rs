@@ -0,0 +1,135 @@
+use crate::parser::Opcode;
+
+impl Opcode {
+    /// Short lowercase name, unique among the opcode variants.
+    pub fn mnemonic(&self) -> &'static str {
+        self.doc().0
+    }
+
+    /// One-line explanation of what the opcode does. Opcodes whose effect
+    /// isn't known yet say so.
+    pub fn description(&self) -> &'static str {
+        self.doc().1
+    }
+
+    fn doc(&self) -> (&'static str, &'static str) {
+        match self {
+            Opcode::WaitMouse => ("wait_mouse", "Wait for a click"),
+            Opcode::Newline => ("newline", "Start a new line of text"),
+            Opcode::WaitMouseText => ("wait_mouse_text", "Wait for a click, then clear the text window"),
+            Opcode::TextWin(_) => ("textwin", "Show, hide or clear the text window"),
+            Opcode::Op0x05 => ("op_0x05", "Unknown opcode 0x05"),
+            Opcode::Op0x06 => ("op_0x06", "Unknown opcode 0x06"),
+            Opcode::Op0x08 => ("op_0x08", "Unknown opcode 0x08"),
+            Opcode::Graphics(_) => ("grp", "Load and display images"),
+            Opcode::Op0x0c => ("op_0x0c", "Unknown opcode 0x0c"),
+            Opcode::Sound(_) => ("snd", "Play or stop music, sound effects, voices and movies"),
+            Opcode::DrawValText(_) => ("valtext", "Draw the value of a variable or string as text"),
+            Opcode::Fade(_) => ("fade", "Fade or fill the screen with a colour"),
+            Opcode::Condition(..) => ("if", "Jump to the target unless the conditions hold"),
+            Opcode::JumpToScene(_) => ("jump_scene", "Jump to or call another scene"),
+            Opcode::ScreenShake(_) => ("shake", "Shake the screen"),
+            Opcode::Op0x18 => ("op_0x18", "Unknown opcode 0x18"),
+            Opcode::Wait(_) => ("wait", "Wait for a time or a click"),
+            Opcode::Op0x1a => ("op_0x1a", "Unknown opcode 0x1a"),
+            Opcode::Call(_) => ("call", "Call a subroutine in this scene"),
+            Opcode::Jump(_) => ("jump", "Jump to an offset in this scene"),
+            Opcode::TableCall(..) => ("table_call", "Call the subroutine picked by a value from a table"),
+            Opcode::TableJump(..) => ("table_jump", "Jump to the offset picked by a value from a table"),
+            Opcode::Return(_) => ("ret", "Return from a subroutine or scene call"),
+            Opcode::Unknown0x22 => ("unk_0x22", "Unknown opcode 0x22"),
+            Opcode::Unknown0x23 => ("unk_0x23", "Unknown opcode 0x23"),
+            Opcode::Unknown0x24 => ("unk_0x24", "Unknown opcode 0x24"),
+            Opcode::Unknown0x25 => ("unk_0x25", "Unknown opcode 0x25"),
+            Opcode::Unknown0x26 => ("unk_0x26", "Unknown opcode 0x26"),
+            Opcode::Unknown0x27 => ("unk_0x27", "Unknown opcode 0x27"),
+            Opcode::Unknown0x28 => ("unk_0x28", "Unknown opcode 0x28"),
+            Opcode::Unknown0x29 => ("unk_0x29", "Unknown opcode 0x29"),
+            Opcode::Op0x2c => ("op_0x2c", "Unknown opcode 0x2c"),
+            Opcode::Op0x2d => ("op_0x2d", "Unknown opcode 0x2d"),
+            Opcode::ScenarioMenu(_) => ("scenario_menu", "Mark entries of the scenario menu as seen"),
+            Opcode::ScenarioMenu2(_) => ("scenario_menu_2", "Mark entries of the scenario menu as seen, written as 0x2f"),
+            Opcode::Op0x30 => ("op_0x30", "Unknown opcode 0x30"),
+            Opcode::TextRank(_) => ("textrank", "Set or clear the text rank"),
+            Opcode::SetFlag(..) => ("set_flag", "Set a flag to a constant"),
+            Opcode::CopyFlag(..) => ("copy_flag", "Copy one flag to another"),
+            Opcode::SetValLiteral(..) => ("set_val_lit", "Set a variable to a constant"),
+            Opcode::AddVal(..) => ("add", "Add a constant to a variable"),
+            Opcode::SubVal(..) => ("sub", "Subtract a constant from a variable"),
+            Opcode::MulVal(..) => ("mul", "Multiply a variable by a constant"),
+            Opcode::DivVal(..) => ("div", "Divide a variable by a constant"),
+            Opcode::ModVal(..) => ("mod", "Set a variable to its remainder by a constant"),
+            Opcode::AndVal(..) => ("and", "Bitwise and a variable with a constant"),
+            Opcode::OrVal(..) => ("or", "Bitwise or a variable with a constant"),
+            Opcode::XorVal(..) => ("xor", "Bitwise xor a variable with a constant"),
+            Opcode::SetVal(..) => ("set_val", "Copy one variable to another"),
+            Opcode::AddValSelf(..) => ("add_var", "Add a variable to a variable"),
+            Opcode::SubValSelf(..) => ("sub_var", "Subtract a variable from a variable"),
+            Opcode::MulValSelf(..) => ("mul_var", "Multiply a variable by a variable"),
+            Opcode::DivValSelf(..) => ("div_var", "Divide a variable by a variable"),
+            Opcode::ModValSelf(..) => ("mod_var", "Set a variable to its remainder by a variable"),
+            Opcode::AndValSelf(..) => ("and_var", "Bitwise and a variable with a variable"),
+            Opcode::OrValSelf(..) => ("or_var", "Bitwise or a variable with a variable"),
+            Opcode::XorValSelf(..) => ("xor_var", "Bitwise xor a variable with a variable"),
+            Opcode::SetFlagRandom(_) => ("rand_flag", "Set a flag to a random value"),
+            Opcode::SetValRandom(..) => ("rand_val", "Set a variable to a random number in a range"),
+            Opcode::Choice(_) => ("choice", "Show a choice and store the selection"),
+            Opcode::String(_) => ("str", "Copy, compare and convert strings"),
+            Opcode::Op0x5b => ("op_0x5b", "Unknown opcode 0x5b"),
+            Opcode::SetMulti(_) => ("set_multi", "Set a range of variables or flags at once"),
+            Opcode::Op0x5d => ("op_0x5d", "Unknown opcode 0x5d"),
+            Opcode::Op0x5e => ("op_0x5e", "Unknown opcode 0x5e"),
+            Opcode::Op0x5f => ("op_0x5f", "Unknown opcode 0x5f"),
+            Opcode::System(_) => ("system", "Save, load, end the game and other system functions"),
+            Opcode::Name(_) => ("name", "Enter and look up character names"),
+            Opcode::Op0x63 => ("op_0x63", "Unknown opcode 0x63"),
+            Opcode::BufferRegion(_) => ("grp_region", "Draw and apply effects to a region of a graphics buffer"),
+            Opcode::Unknown0x65 => ("unk_0x65", "Unknown opcode 0x65"),
+            Opcode::Buffer(_) => ("grp_buffer", "Copy between graphics buffers and draw strings into them"),
+            Opcode::Flash(_) => ("flash", "Flash or fill the screen with a colour"),
+            Opcode::Op0x69 => ("op_0x69", "Unknown opcode 0x69"),
+            Opcode::MultiPdt(_) => ("multi_pdt", "Show a slideshow or scroll through several images"),
+            Opcode::Op0x66 => ("op_0x66", "Unknown opcode 0x66"),
+            Opcode::AreaBuffer(_) => ("area", "Load and query clickable areas"),
+            Opcode::MouseCtrl(_) => ("mouse", "Control and query the mouse cursor"),
+            Opcode::Op0x6e => ("op_0x6e", "Unknown opcode 0x6e"),
+            Opcode::Op0x6f => ("op_0x6f", "Unknown opcode 0x6f"),
+            Opcode::WindowVar(_) => ("window_var", "Get or set the text window's appearance"),
+            Opcode::MessageWin(_) => ("message_win", "Get or set the position of the message windows"),
+            Opcode::SystemVar(_) => ("system_var", "Get or set text and input settings"),
+            Opcode::PopupMenu(_) => ("popup_menu", "Enable or disable the popup menu and its items"),
+            Opcode::Volume(_) => ("volume", "Get, set or mute the volume of each sound channel"),
+            Opcode::NovelMode(_) => ("novel_mode", "Turn novel mode on or off"),
+            Opcode::Op0x7f => ("op_0x7f", "Unknown opcode 0x7f"),
+            Opcode::Unknown0xea(_) => ("unk_0xea", "Unknown opcode 0xea"),
+            Opcode::TextHankaku(..) => ("text_han", "Display half-width text"),
+            Opcode::TextZenkaku(..) => ("text_zen", "Display full-width text"),
+            Opcode::UnknownRaw(..) => ("raw", "Bytes the parser couldn't read, kept as they were"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_mnemonics_unique() {
+        let mut opcodes = crate::write::tests::every_opcode();
+        opcodes.push(Opcode::UnknownRaw(0x00, Vec::new()));
+
+        let mut seen = HashSet::new();
+        for opcode in opcodes.iter() {
+            assert!(!opcode.mnemonic().is_empty(), "{:?}", opcode);
+            assert!(!opcode.description().is_empty(), "{:?}", opcode);
+            seen.insert((crate::stats::variant_name(opcode), opcode.mnemonic()));
+        }
+
+        // Subcommands of the same opcode share its mnemonic.
+        let mut mnemonics = HashSet::new();
+        for (name, mnemonic) in seen.iter() {
+            assert!(mnemonics.insert(*mnemonic), "{} reuses mnemonic {}", name, mnemonic);
+        }
+    }
+}
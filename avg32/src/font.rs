@@ -5,13 +5,78 @@ use anyhow::{Result, anyhow};
 
 const NUM_CHARS: usize = 4418;
 
+/// Size in bytes of FN.DAT, which has no header to identify it by.
+pub const FILE_SIZE: usize = NUM_CHARS * 576;
+
 pub type FontChar = [u8; 576];
 
+/// Dimensions and bit depth of a font's glyphs.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GlyphSize {
+    pub width: usize,
+    pub height: usize,
+    /// Bits per pixel: 1, 2, 4 or 8.
+    pub bpp: u8,
+}
+
+/// The glyphs in FN.DAT.
+pub const FN_DAT_GLYPH: GlyphSize = GlyphSize { width: 24, height: 24, bpp: 4 };
+
+impl GlyphSize {
+    /// Bytes taken by one glyph.
+    pub fn byte_size(&self) -> usize {
+        (self.width * self.height * self.bpp as usize).div_ceil(8)
+    }
+}
+
+/// Unpacks a glyph into one byte per pixel, row by row. Pixels are read
+/// from the high bits of each byte first, and range from 0 to
+/// `(1 << bpp) - 1`.
+pub fn render_glyph(data: &[u8], size: GlyphSize) -> Result<Vec<u8>> {
+    if ![1, 2, 4, 8].contains(&size.bpp) {
+        return Err(anyhow!("Unsupported glyph bit depth {}", size.bpp));
+    }
+    if data.len() < size.byte_size() {
+        return Err(anyhow!("Glyph needs {} bytes, got {}", size.byte_size(), data.len()));
+    }
+
+    let bpp = size.bpp as usize;
+    let per_byte = 8 / bpp;
+    let mask = ((1u16 << bpp) - 1) as u8;
+    let pixels = (0..size.width * size.height)
+        .map(|i| {
+            let shift = 8 - bpp * (i % per_byte + 1);
+            (data[i / per_byte] >> shift) & mask
+        })
+        .collect();
+    Ok(pixels)
+}
+
 pub struct Font {
     /// Mapping of JIS code -> 24x24 4bpp glyph
     pub chars: Vec<FontChar>
 }
 
+/// Characters for `Font::preview_glyph`, from blank to solid.
+const PREVIEW_SHADES: [char; 4] = [' ', '.', ':', '#'];
+
+impl Font {
+    /// Draws the glyph at `index` as text for a terminal, one line per row
+    /// of pixels, shading each pixel by its value.
+    pub fn preview_glyph(&self, index: usize) -> Option<String> {
+        let size = FN_DAT_GLYPH;
+        let pixels = render_glyph(self.chars.get(index)?, size).ok()?;
+        let levels = 1usize << size.bpp;
+
+        let mut res = String::new();
+        for row in pixels.chunks(size.width) {
+            res.extend(row.iter().map(|&p| PREVIEW_SHADES[p as usize * PREVIEW_SHADES.len() / levels]));
+            res.push('\n');
+        }
+        Some(res)
+    }
+}
+
 pub fn load<T: AsRef<Path>>(filepath: T) -> Result<Font> {
     match File::open(filepath.as_ref()) {
         Ok(mut f) => {
@@ -24,7 +89,7 @@ pub fn load<T: AsRef<Path>>(filepath: T) -> Result<Font> {
 }
 
 pub fn load_bytes(bytes: &[u8]) -> Result<Font> {
-    if bytes.len() != NUM_CHARS * 576 {
+    if bytes.len() != FILE_SIZE {
         return Err(anyhow!("Wrong number of bytes for FN.DAT"));
     }
 
@@ -39,3 +104,63 @@ pub fn load_bytes(bytes: &[u8]) -> Result<Font> {
 
     Ok(Font { chars: chars })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_glyph() {
+        let size = |bpp| GlyphSize { width: 24, height: 24, bpp: bpp };
+
+        let mut data = vec![0u8; 576];
+        data[0] = 0xa5;
+        data[1] = 0x0f;
+        let pixels = render_glyph(&data, size(4)).unwrap();
+        assert_eq!(288, size(4).byte_size());
+        assert_eq!(24 * 24, pixels.len());
+        assert_eq!(&[0xa, 0x5, 0x0, 0xf, 0x0], &pixels[..5]);
+
+        let pixels = render_glyph(&data[..72], size(1)).unwrap();
+        assert_eq!(72, size(1).byte_size());
+        assert_eq!(24 * 24, pixels.len());
+        assert_eq!(&[1, 0, 1, 0, 0, 1, 0, 1, 0, 0, 0, 0, 1, 1, 1, 1, 0], &pixels[..17]);
+
+        let mut data = vec![0u8; 576];
+        data[0] = 0xa5;
+        data[575] = 0xff;
+        let pixels = render_glyph(&data, size(8)).unwrap();
+        assert_eq!(576, size(8).byte_size());
+        assert_eq!(24 * 24, pixels.len());
+        assert_eq!(0xa5, pixels[0]);
+        assert_eq!(0xff, pixels[575]);
+
+        let pixels = render_glyph(&data[..144], size(2)).unwrap();
+        assert_eq!(24 * 24, pixels.len());
+        assert_eq!(&[2, 2, 1, 1], &pixels[..4]);
+
+        assert!(render_glyph(&data, size(3)).is_err());
+        assert!(render_glyph(&data[..575], size(8)).is_err());
+    }
+
+    #[test]
+    fn test_preview_glyph() {
+        let mut glyph = [0u8; 576];
+        glyph[0] = 0x4f;
+        glyph[12] = 0x8c;
+        let font = Font { chars: vec![[0u8; 576], glyph] };
+
+        let blank = font.preview_glyph(0).unwrap();
+        assert_eq!(24, blank.lines().count());
+        assert!(blank.lines().all(|line| line == " ".repeat(24)));
+
+        let preview = font.preview_glyph(1).unwrap();
+        let lines: Vec<&str> = preview.lines().collect();
+        assert_eq!(24, lines.len());
+        assert!(lines.iter().all(|line| line.chars().count() == 24));
+        assert!(lines[0].starts_with(".#  "));
+        assert!(lines[1].starts_with(":#  "));
+
+        assert_eq!(None, font.preview_glyph(2));
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, HashMap};
+use anyhow::{anyhow, Result};
+use crate::build::jump_targets_mut;
+use crate::parser::{AVG32Scene, Header, Pos, Opcode, ValLengths};
+
+/// The kind of opcode that first jumps to a label.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum LabelKind {
+    Condition,
+    Call,
+    Jump,
+    TableCall,
+    TableJump,
+}
+
+/// A jump target, and the kind of opcode jumping to it.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct LabelPos {
+    pub kind: LabelKind,
+    pub pos: Pos
+}
+
+impl LabelPos {
+    pub fn new(kind: LabelKind, pos: Pos) -> Self {
+        LabelPos {
+            kind,
+            pos
+        }
+    }
+}
+
+/// The opcodes from one jump target up to the next, with jumps naming
+/// their targets with `Pos::Label`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Label {
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "opcodes")]
+    pub opcodes: Vec<Opcode>
+}
+
+/// Version of the format `LabelResolvedScene` is written in. Bump it when
+/// a change would make older versions misread what newer ones write.
+pub const FORMAT_VERSION: u32 = 1;
+
+fn first_format_version() -> u32 {
+    1
+}
+
+/// Fails unless `version` is a `FORMAT_VERSION` that can be read.
+pub fn check_format_version(version: u64) -> Result<()> {
+    if version < first_format_version() as u64 || version > FORMAT_VERSION as u64 {
+        return Err(anyhow!("Disassembly is in format version {}, but only versions {} to {} can be read", version, first_format_version(), FORMAT_VERSION));
+    }
+    Ok(())
+}
+
+/// A scene split into labels by `resolve_labels`, as written by the
+/// disassembler.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LabelResolvedScene {
+    /// `FORMAT_VERSION` when written. Read as 1 if missing, for files
+    /// written before it was added.
+    #[serde(rename = "version", default = "first_format_version")]
+    pub version: u32,
+    #[serde(rename = "header")]
+    pub header: Header,
+    #[serde(rename = "labels")]
+    pub labels: Vec<Label>,
+    /// `AVG32Scene::val_lengths`, by index into the opcodes of all the
+    /// labels in order.
+    #[serde(rename = "val_lengths", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub val_lengths: BTreeMap<usize, ValLengths>,
+    #[serde(rename = "trailer", default, skip_serializing_if = "Vec::is_empty")]
+    pub trailer: Vec<u8>
+}
+
+/// The jump targets of `opcode`, or `None` if it doesn't jump.
+pub fn extract_label(opcode: &Opcode) -> Option<Vec<LabelPos>> {
+    match opcode {
+        Opcode::Condition(_, pos) => Some(vec![LabelPos::new(LabelKind::Condition, pos.clone())]),
+        Opcode::Call(pos) => Some(vec![LabelPos::new(LabelKind::Call, pos.clone())]),
+        Opcode::Jump(pos) => Some(vec![LabelPos::new(LabelKind::Jump, pos.clone())]),
+        Opcode::TableCall(_, poss) => {
+            let mut res = vec![];
+            for pos in poss.iter() {
+                res.push(LabelPos::new(LabelKind::TableCall, pos.clone()))
+            }
+            Some(res)
+        },
+        Opcode::TableJump(_, poss) => {
+            let mut res = vec![];
+            for pos in poss.iter() {
+                res.push(LabelPos::new(LabelKind::TableJump, pos.clone()))
+            }
+            Some(res)
+        },
+        _ => None
+    }
+}
+
+/// The jump targets of every opcode, in order.
+pub fn extract_labels(opcodes: &[Opcode]) -> Vec<LabelPos> {
+    opcodes.iter().filter_map(extract_label).flatten().collect()
+}
+
+/// Chooses the names of jump targets in the disassembly.
+pub trait LabelNamer {
+    /// Names the label at `offset` in the opcode body. Called once per label
+    /// in address order. The label at offset 0 is always `start`.
+    fn name(&mut self, kind: LabelKind, offset: u32) -> String;
+}
+
+/// Names labels after the kind of jump and its offset, e.g. `jump_0x1a2b`.
+pub struct DefaultNamer;
+
+impl LabelNamer for DefaultNamer {
+    fn name(&mut self, kind: LabelKind, offset: u32) -> String {
+        format!("{:?}_0x{:x?}", kind, offset).to_lowercase()
+    }
+}
+
+/// Names labels after their offset only, e.g. `loc_1a2b`.
+pub struct AddressNamer;
+
+impl LabelNamer for AddressNamer {
+    fn name(&mut self, _kind: LabelKind, offset: u32) -> String {
+        format!("loc_{:x}", offset)
+    }
+}
+
+/// Numbers labels in address order: `L0`, `L1`, ...
+#[derive(Default)]
+pub struct SequentialNamer {
+    count: usize
+}
+
+impl LabelNamer for SequentialNamer {
+    fn name(&mut self, _kind: LabelKind, _offset: u32) -> String {
+        let name = format!("L{}", self.count);
+        self.count += 1;
+        name
+    }
+}
+
+/// Names every label offset in `labels`. When several opcodes jump to the
+/// same offset, the smallest `LabelKind` wins. Fails if `namer` gives two
+/// offsets the same name, or names one `start`.
+pub fn label_names(labels: &[LabelPos], namer: &mut dyn LabelNamer) -> Result<BTreeMap<u32, String>> {
+    let mut kinds: BTreeMap<u32, LabelKind> = BTreeMap::new();
+    for label in labels.iter() {
+        if let Pos::Offset(pos) = label.pos {
+            let kind = kinds.entry(pos).or_insert(label.kind);
+            *kind = (*kind).min(label.kind);
+        }
+    }
+
+    let mut names = BTreeMap::new();
+    let mut offsets: HashMap<String, u32> = HashMap::new();
+    names.insert(0, String::from("start"));
+    offsets.insert(String::from("start"), 0);
+    for (pos, kind) in kinds.into_iter() {
+        if pos != 0 {
+            let name = namer.name(kind, pos);
+            if let Some(other) = offsets.insert(name.clone(), pos) {
+                return Err(anyhow!("Labels at offsets 0x{:04x?} and 0x{:04x?} are both named {}", other, pos, name));
+            }
+            names.insert(pos, name);
+        }
+    }
+    Ok(names)
+}
+
+/// Splits `scene` at every jump target, naming the targets with `namer`.
+/// Fails if a target is already a label or isn't the start of an opcode.
+pub fn resolve_labels(scene: &AVG32Scene, namer: &mut dyn LabelNamer) -> Result<LabelResolvedScene> {
+    let labels = extract_labels(&scene.opcodes);
+    if labels.iter().any(|label| !matches!(label.pos, Pos::Offset(_))) {
+        return Err(anyhow!("Labels were already resolved"));
+    }
+
+    let names = label_names(&labels, namer)?;
+    let chunks = scene.split_at_labels(&names);
+    if chunks.len() != names.len() {
+        let (offset, name) = names.iter().find(|(_, name)| !chunks.iter().any(|(n, _)| n == *name)).unwrap();
+        return Err(anyhow!("Misaligned label {} at offset 0x{:04x?}, inside an opcode", name, offset));
+    }
+
+    Ok(LabelResolvedScene {
+        version: FORMAT_VERSION,
+        header: scene.header.clone(),
+        labels: chunks.into_iter().map(|(name, opcodes)| Label { name, opcodes }).collect(),
+        val_lengths: scene.val_lengths.clone(),
+        trailer: scene.trailer.clone()
+    })
+}
+
+/// Replaces jumps to the offsets in `names` with jumps to the labels.
+/// Fails if a target isn't an offset in `names`.
+pub fn convert_byte_to_label_positions(opcodes: &mut [Opcode], names: &HashMap<u32, String>) -> Result<()> {
+    for opcode in opcodes.iter_mut() {
+        for pos in jump_targets_mut(opcode) {
+            let name = match pos {
+                Pos::Offset(b) => names.get(b).ok_or_else(|| anyhow!("No label for jump target 0x{:04x?}", b))?,
+                Pos::Label(name) => return Err(anyhow!("Jump target {} is already a label", name))
+            };
+            *pos = Pos::Label(name.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Joins the labels back into a scene, the inverse of `resolve_labels`.
+pub fn compile_labels(resolved: &LabelResolvedScene) -> Result<AVG32Scene> {
+    check_format_version(resolved.version as u64)?;
+
+    let mut scene = AVG32Scene {
+        header: resolved.header.clone(),
+        opcodes: resolved.labels.iter().flat_map(|label| label.opcodes.iter().cloned()).collect(),
+        val_lengths: resolved.val_lengths.clone(),
+        trailer: resolved.trailer.clone()
+    };
+    let mut positions: HashMap<String, u32> = HashMap::new();
+    let mut index = 0;
+    let mut cur_pos = 0;
+
+    for label in resolved.labels.iter() {
+        if positions.insert(label.name.clone(), cur_pos).is_some() {
+            return Err(anyhow!("Label {} is defined more than once", label.name));
+        }
+        for _ in label.opcodes.iter() {
+            cur_pos += scene.opcode_size(index) as u32;
+            index += 1;
+        }
+    }
+
+    convert_label_to_byte_positions(&mut scene.opcodes, &positions)?;
+
+    Ok(scene)
+}
+
+/// Replaces jumps to the labels in `positions` with jumps to their
+/// offsets. Fails if a target isn't a label in `positions`.
+pub fn convert_label_to_byte_positions(opcodes: &mut [Opcode], positions: &HashMap<String, u32>) -> Result<()> {
+    for opcode in opcodes.iter_mut() {
+        for pos in crate::build::jump_targets_mut(opcode) {
+            match pos {
+                Pos::Label(name) => {
+                    let b = positions.get(name).ok_or_else(|| anyhow!("Jump to undefined label {}", name))?;
+                    *pos = Pos::Offset(*b);
+                },
+                Pos::Offset(b) => return Err(anyhow!("Jump to offset 0x{:x} instead of a label", b))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::SceneBuilder;
+    use crate::parser::{SceneText, Val};
+
+    #[test]
+    fn test_resolve_labels_offsets() {
+        let scene = SceneBuilder::new()
+            .text("あいうえお")
+            .jump("second")
+            .label("first")
+            .newline()
+            .wait_mouse()
+            .label("second")
+            .text("かきくけこ")
+            .jump("first")
+            .build()
+            .unwrap();
+
+        let first = scene.header.labels[1];
+        let second = scene.header.labels[2];
+
+        let resolved = resolve_labels(&scene, &mut DefaultNamer).unwrap();
+        let names: Vec<&str> = resolved.labels.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(vec![String::from("start"), format!("jump_0x{:x}", first), format!("jump_0x{:x}", second)], names);
+        assert_eq!(vec![2, 2, 2], resolved.labels.iter().map(|l| l.opcodes.len()).collect::<Vec<_>>());
+
+        assert_eq!(scene, compile_labels(&resolved).unwrap());
+    }
+
+    #[test]
+    fn test_resolve_compile_roundtrip() {
+        let text = |s: &str| Opcode::TextZenkaku(None, SceneText::Literal(String::from(s)));
+        let label = |s: &str| Pos::Label(String::from(s));
+        // 0x00: a table jumping to the text and back to itself, 0x0b: the
+        // text, 0x11: a call to the end of the body, 0x16: wait.
+        let scene = AVG32Scene {
+            header: Header::empty(),
+            opcodes: vec![
+                Opcode::TableJump(Val::variable(1), vec![Pos::Offset(0x0b), Pos::Offset(0)]),
+                text("あい"),
+                Opcode::Call(Pos::Offset(0x17)),
+                Opcode::WaitMouse,
+            ],
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        };
+
+        let resolved = resolve_labels(&scene, &mut AddressNamer).unwrap();
+        assert_eq!(vec![
+            Label { name: String::from("start"), opcodes: vec![Opcode::TableJump(Val::variable(1), vec![label("loc_b"), label("start")])] },
+            Label { name: String::from("loc_b"), opcodes: vec![text("あい"), Opcode::Call(label("loc_17")), Opcode::WaitMouse] },
+            Label { name: String::from("loc_17"), opcodes: vec![] },
+        ], resolved.labels);
+        assert_eq!(scene, compile_labels(&resolved).unwrap());
+
+        assert!(resolve_labels(&compile_labels(&resolved).unwrap(), &mut DefaultNamer).is_ok());
+        let relabeled = AVG32Scene { header: Header::empty(), opcodes: resolved.labels[0].opcodes.clone(), val_lengths: BTreeMap::new(), trailer: Vec::new() };
+        assert!(resolve_labels(&relabeled, &mut DefaultNamer).is_err());
+
+        // 0x01 is inside the table jump.
+        let misaligned = AVG32Scene { header: Header::empty(), opcodes: vec![Opcode::Jump(Pos::Offset(1))], val_lengths: BTreeMap::new(), trailer: Vec::new() };
+        assert!(resolve_labels(&misaligned, &mut DefaultNamer).is_err());
+    }
+
+    #[test]
+    fn test_compile_labels_errors() {
+        let label = |name: &str, target: &str| Label {
+            name: String::from(name),
+            opcodes: vec![Opcode::Jump(Pos::Label(String::from(target)))]
+        };
+        let scene = |labels| LabelResolvedScene {
+            version: FORMAT_VERSION,
+            header: Header::empty(),
+            labels: labels,
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        };
+
+        assert!(compile_labels(&scene(vec![label("start", "end"), label("end", "start")])).is_ok());
+
+        let err = compile_labels(&scene(vec![label("start", "nowhere")])).unwrap_err();
+        assert!(err.to_string().contains("undefined label nowhere"), "{}", err);
+
+        let err = compile_labels(&scene(vec![label("start", "end"), label("end", "start"), label("end", "start")])).unwrap_err();
+        assert!(err.to_string().contains("end is defined more than once"), "{}", err);
+
+        let mut offset = scene(vec![label("start", "start")]);
+        offset.labels[0].opcodes[0] = Opcode::Jump(Pos::Offset(0));
+        assert!(compile_labels(&offset).is_err());
+    }
+
+    struct FixedNamer(&'static str);
+
+    impl LabelNamer for FixedNamer {
+        fn name(&mut self, _kind: LabelKind, _offset: u32) -> String {
+            String::from(self.0)
+        }
+    }
+
+    #[test]
+    fn test_label_names_duplicates() {
+        let jump = |pos| LabelPos { kind: LabelKind::Jump, pos: Pos::Offset(pos) };
+
+        let names = label_names(&[jump(0), jump(4)], &mut FixedNamer("end")).unwrap();
+        assert_eq!(vec![(0, String::from("start")), (4, String::from("end"))], names.into_iter().collect::<Vec<_>>());
+
+        assert!(label_names(&[jump(4)], &mut FixedNamer("start")).is_err());
+        assert!(label_names(&[jump(4), jump(8)], &mut FixedNamer("end")).is_err());
+    }
+
+    #[test]
+    fn test_convert_byte_to_label_positions() {
+        let names: HashMap<u32, String> = vec![(0, String::from("start")), (4, String::from("end"))].into_iter().collect();
+
+        let mut opcodes = vec![Opcode::Jump(Pos::Offset(4)), Opcode::TableCall(Val::variable(1), vec![Pos::Offset(0), Pos::Offset(4)])];
+        convert_byte_to_label_positions(&mut opcodes, &names).unwrap();
+        assert_eq!(vec![
+            Opcode::Jump(Pos::Label(String::from("end"))),
+            Opcode::TableCall(Val::variable(1), vec![Pos::Label(String::from("start")), Pos::Label(String::from("end"))])
+        ], opcodes);
+
+        let err = convert_byte_to_label_positions(&mut [Opcode::Call(Pos::Offset(8))], &names).unwrap_err();
+        assert_eq!("No label for jump target 0x0008", err.to_string());
+        assert!(convert_byte_to_label_positions(&mut opcodes, &names).is_err());
+    }
+}
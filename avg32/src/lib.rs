@@ -5,35 +5,90 @@ extern crate serde;
 extern crate encoding_rs;
 extern crate byteorder;
 extern crate anyhow;
+extern crate serde_json;
+#[macro_use] extern crate log;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
 
 #[cfg(test)]
 #[macro_use] extern crate pretty_assertions;
 
 pub mod archive;
+pub mod assets;
+pub mod build;
+pub mod choices;
+pub mod diff;
+pub mod doc;
 pub mod font;
+pub mod labels;
+pub mod spec;
 pub mod parser;
+pub mod script;
+pub mod stats;
+pub mod text;
+pub mod version;
+pub mod visit;
 pub mod write;
 
+use std::borrow::Cow;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use anyhow::{anyhow, Result};
 
-pub use parser::AVG32Scene;
+pub use diff::{diff, OpcodeDiff};
+pub use parser::{AVG32Scene, Opcode};
+pub use stats::{stats, OpcodeStats, TextMetrics};
+pub use version::detect_version;
 
 pub fn load<T: AsRef<Path>>(filepath: T) -> Result<AVG32Scene> {
     match File::open(filepath.as_ref()) {
-        Ok(mut f) => {
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer).expect("Unable to read file");
-            load_bytes(&buffer)
-        }
+        Ok(f) => load_reader(f),
         Err(e) => Err(anyhow!("Unable to load file: {}", e)),
     }
 }
 
+/// Loads from any reader. The input is currently read into memory in full
+/// before parsing.
+pub fn load_reader<R: Read>(mut reader: R) -> Result<AVG32Scene> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).map_err(|e| anyhow!("Unable to read file: {}", e))?;
+    load_bytes(&buffer)
+}
+
+/// Parses a scene. A scene stored as a compressed `PACK` blob, like an
+/// archive member on its own, is decompressed first.
 pub fn load_bytes(bytes: &[u8]) -> Result<AVG32Scene> {
-    let res = match parser::avg32_scene(bytes) {
+    let bytes = unpack_scene(bytes)?;
+    let res = try_parse(&bytes);
+
+    print_trace!();
+
+    res
+}
+
+/// Decompresses `bytes` if they're a `PACK` blob.
+fn unpack_scene(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+    if identify(bytes) != FileKind::ArchiveMember {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    let member = archive::load_member_bytes(bytes)?;
+    Ok(Cow::Owned(member.decompress()?))
+}
+
+/// Like `load_bytes`, parsing with the given options.
+pub fn load_bytes_with_config(bytes: &[u8], config: &parser::ParseConfig) -> Result<AVG32Scene> {
+    parser::with_config(config, || load_bytes(bytes))
+}
+
+/// Loads a scene, keeping any opcodes that fail to parse as
+/// `Opcode::UnknownRaw` and returning where they were found.
+pub fn load_bytes_lenient(bytes: &[u8]) -> Result<(AVG32Scene, Vec<parser::UnknownOpcode>)> {
+    let bytes = unpack_scene(bytes)?;
+    let res = match parser::avg32_scene_lenient(&bytes) {
         Ok((_, parsed)) => Ok(parsed),
         Err(e) => Err(anyhow!("Not a valid AVG32 scene: {}", e)),
     };
@@ -42,3 +97,429 @@ pub fn load_bytes(bytes: &[u8]) -> Result<AVG32Scene> {
 
     res
 }
+
+/// The kinds of file `identify` recognizes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FileKind {
+    /// A `TPC32` scene.
+    Scene,
+    /// A `PACL` archive such as SEEN.TXT.
+    Archive,
+    /// A single compressed `PACK` archive member.
+    ArchiveMember,
+    /// FN.DAT, recognized by its size.
+    Font,
+    Unknown,
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FileKind::Scene => "an AVG32 scene (TPC32)",
+            FileKind::Archive => "an archive (PACL)",
+            FileKind::ArchiveMember => "a compressed archive member (PACK)",
+            FileKind::Font => "a font (FN.DAT)",
+            FileKind::Unknown => "not a recognized AVG32 file",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Guesses what a file is from its magic bytes, or its size for FN.DAT.
+pub fn identify(bytes: &[u8]) -> FileKind {
+    if bytes.starts_with(b"TPC32") {
+        FileKind::Scene
+    } else if bytes.starts_with(b"PACL") {
+        FileKind::Archive
+    } else if bytes.starts_with(b"PACK") {
+        FileKind::ArchiveMember
+    } else if bytes.len() == font::FILE_SIZE {
+        FileKind::Font
+    } else {
+        FileKind::Unknown
+    }
+}
+
+/// Parses a scene, returning `Err` rather than panicking on any input.
+pub fn try_parse(bytes: &[u8]) -> Result<AVG32Scene> {
+    match parser::avg32_scene(bytes) {
+        Ok((_, parsed)) => Ok(parsed),
+        Err(e) => Err(anyhow!("Not a valid AVG32 scene: {}", e)),
+    }
+}
+
+/// Index and body offset of the first opcode where `a` and `b` differ,
+/// including one list ending before the other. Jump targets are compared
+/// as in `AVG32Scene::semantically_eq`.
+fn first_mismatch(a: &AVG32Scene, b: &AVG32Scene) -> Option<(usize, usize)> {
+    let index = diff::first_semantic_mismatch(a, b)?;
+    Some((index, (0..index).map(|i| a.opcode_size(i)).sum()))
+}
+
+/// Checks that a scene parses, and that writing it back out, trailer and
+/// all, and parsing the result gives the same scene. Errors name the first
+/// opcode that doesn't survive and its offset in `bytes`.
+pub fn verify_roundtrip(bytes: &[u8]) -> Result<()> {
+    use write::Writeable;
+
+    let scene = try_parse(bytes)?;
+    let header_size = scene.header.byte_size();
+
+    let mut written = Vec::new();
+    if let Err(e) = scene.header.write(&mut written) {
+        return Err(anyhow!("Unable to write header: {}", e));
+    }
+    let mut offset = 0;
+    for index in 0..scene.opcodes.len() {
+        if let Err(e) = scene.write_opcode(index, &mut written) {
+            return Err(anyhow!("Unable to write opcode {} at offset 0x{:x}: {}", index, header_size + offset, e));
+        }
+        offset += scene.opcode_size(index);
+    }
+    written.push(0x00);
+    written.extend_from_slice(&scene.trailer);
+
+    check_reparse(&scene, &written)
+}
+
+/// Checks that `written`, the bytes `scene` was written out as, parse back
+/// into the same scene, down to the value lengths and trailer.
+fn check_reparse(scene: &AVG32Scene, written: &[u8]) -> Result<()> {
+    use write::Writeable;
+
+    let header_size = scene.header.byte_size();
+    let reparsed = try_parse(written).map_err(|e| anyhow!("Written scene doesn't parse: {}", e))?;
+    if reparsed.header != scene.header {
+        return Err(anyhow!("Header reads back differently"));
+    }
+    if let Some((index, offset)) = first_mismatch(scene, &reparsed) {
+        return Err(anyhow!("Opcode {} at offset 0x{:x} reads back differently:\n  was {:?}\n  now {:?}",
+                           index, header_size + offset, scene.opcodes.get(index), reparsed.opcodes.get(index)));
+    }
+    if reparsed.val_lengths != scene.val_lengths {
+        return Err(anyhow!("Value lengths read back differently"));
+    }
+    if reparsed.trailer != scene.trailer {
+        return Err(anyhow!("Trailer reads back differently:\n  was {:02x?}\n  now {:02x?}", scene.trailer, reparsed.trailer));
+    }
+    Ok(())
+}
+
+/// Replaces the text of the `TextHankaku` or `TextZenkaku` opcode whose
+/// bytes include `offset`, counted from the start of the scene, and writes
+/// the scene back out. Everything after the text moves by the change in
+/// its length, so jump targets and header labels past it are moved to
+/// match. Fails if `new_text` can't be encoded as SHIFT_JIS.
+pub fn patch_text(scene_bytes: &[u8], offset: usize, new_text: &str) -> Result<Vec<u8>> {
+    use write::Writeable;
+
+    let mut scene = try_parse(scene_bytes)?;
+    let index = scene.opcode_at_offset(offset as u32)
+        .ok_or_else(|| anyhow!("No opcode at offset 0x{:x}", offset))?;
+    let start = scene.opcodes_with_offsets().nth(index).unwrap().0;
+    let old_size = scene.opcode_size(index);
+
+    match &mut scene.opcodes[index] {
+        Opcode::TextHankaku(_, text) | Opcode::TextZenkaku(_, text) => *text = parser::SceneText::Literal(String::from(new_text)),
+        other => return Err(anyhow!("Opcode at offset 0x{:x} is {}, not text", offset, stats::variant_name(other)))
+    }
+    scene.opcodes[index].to_vec()
+        .map_err(|e| anyhow!("Unable to write text at offset 0x{:x}: {}", offset, e))?;
+    let new_size = scene.opcode_size(index);
+
+    let end = start + old_size as u32;
+    let shift = |pos: &mut u32| {
+        if *pos >= end {
+            *pos = *pos + new_size as u32 - old_size as u32;
+        }
+    };
+    for opcode in scene.opcodes.iter_mut() {
+        for pos in build::jump_targets_mut(opcode) {
+            if let parser::Pos::Offset(pos) = pos {
+                shift(pos);
+            }
+        }
+    }
+    for label in scene.header.labels.iter_mut() {
+        shift(label);
+    }
+
+    Ok(scene.to_vec()?)
+}
+
+/// Parses a bare opcode stream with no `TPC32` header. A single trailing
+/// NUL terminator is accepted and ignored.
+pub fn parse_opcodes(bytes: &[u8]) -> Result<Vec<Opcode>> {
+    let mut opcodes = Vec::new();
+    let mut input = bytes;
+
+    while !input.is_empty() && input != [0x00] {
+        match parser::opcode(input) {
+            Ok((rest, opcode)) => {
+                opcodes.push(opcode);
+                input = rest;
+            },
+            Err(e) => return Err(anyhow!("Not a valid AVG32 opcode stream at offset 0x{:x}: {}", bytes.len() - input.len(), e)),
+        }
+    }
+
+    Ok(opcodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Pos, Val, ValType};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_identify() {
+        assert_eq!(FileKind::Scene, identify(&sample_scene_bytes()));
+
+        let mut arc = archive::Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), sample_scene_bytes()).unwrap();
+        arc.finalize();
+        let mut bytes = Vec::new();
+        write::Writeable::write(&arc, &mut bytes).unwrap();
+        assert_eq!(FileKind::Archive, identify(&bytes));
+
+        let mut bytes = Vec::new();
+        write::Writeable::write(&arc.data[0], &mut bytes).unwrap();
+        assert_eq!(FileKind::ArchiveMember, identify(&bytes));
+        assert_eq!(arc.data[0].data, archive::load_member_bytes(&bytes).unwrap().data);
+
+        assert_eq!(FileKind::Font, identify(&vec![0; font::FILE_SIZE]));
+        assert_eq!(FileKind::Unknown, identify(&vec![0; 16]));
+        assert_eq!(FileKind::Unknown, identify(&[]));
+    }
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let bytes = sample_scene_bytes();
+        verify_roundtrip(&bytes).unwrap();
+
+        // Turn the WaitMouse at the end into 0x07, which isn't an opcode.
+        let mut doctored = bytes.clone();
+        let len = doctored.len();
+        doctored[len - 2] = 0x07;
+        let err = verify_roundtrip(&doctored).unwrap_err().to_string();
+        assert!(err.contains(&format!("offset 0x{:x}", len - 2)), "{}", err);
+
+        // Written bytes that parse, but not into the same scene: the
+        // condition's constant 0x123 comes back as 0x124.
+        let encode = |n| {
+            let mut val = Vec::new();
+            write::Writeable::write(&Val(n, ValType::Const), &mut val).unwrap();
+            val
+        };
+        let (old, new) = (encode(0x123), encode(0x124));
+        let pos = bytes.windows(old.len()).position(|w| w == &old[..]).unwrap();
+        let mut written = bytes.clone();
+        written[pos..pos + new.len()].copy_from_slice(&new);
+        let scene = try_parse(&bytes).unwrap();
+        let err = check_reparse(&scene, &written).unwrap_err().to_string();
+        assert!(err.contains(&format!("Opcode 0 at offset 0x{:x} reads back differently", write::Writeable::byte_size(&scene.header))), "{}", err);
+
+        // The trailer is written back too.
+        let mut trailed = bytes.clone();
+        trailed.extend_from_slice(&[0x1a, 0xff]);
+        verify_roundtrip(&trailed).unwrap();
+        let err = check_reparse(&try_parse(&trailed).unwrap(), &bytes).unwrap_err().to_string();
+        assert!(err.contains("Trailer reads back differently"), "{}", err);
+    }
+
+    #[test]
+    fn test_first_mismatch() {
+        let scene = |opcodes: &[Opcode]| opcodes.iter().cloned().fold(build::SceneBuilder::new(), build::SceneBuilder::opcode).build().unwrap();
+        let a = scene(&[Opcode::WaitMouse, Opcode::Newline, Opcode::WaitMouse]);
+        let b = scene(&[Opcode::WaitMouse, Opcode::Newline, Opcode::Newline]);
+        let short = scene(&[Opcode::WaitMouse, Opcode::Newline]);
+        assert_eq!(None, first_mismatch(&a, &a));
+        assert_eq!(Some((2, 2)), first_mismatch(&a, &b));
+        assert_eq!(Some((2, 2)), first_mismatch(&a, &short));
+        assert_eq!(Some((2, 2)), first_mismatch(&short, &a));
+    }
+
+    #[test]
+    fn test_parse_opcodes() {
+        let bytes = [0x01, 0x02, 0x37, 0x10, 0x11, 0x1c, 0x10, 0x00, 0x00, 0x00];
+        let expected = vec![
+            Opcode::WaitMouse,
+            Opcode::Newline,
+            Opcode::SetFlag(Val(0, ValType::Const), Val(1, ValType::Const)),
+            Opcode::Jump(Pos::Offset(0x10)),
+        ];
+
+        assert_eq!(expected, parse_opcodes(&bytes).unwrap());
+    }
+
+    fn sample_scene_bytes() -> Vec<u8> {
+        use crate::parser::*;
+        use crate::write::{Writeable, write_opcodes};
+
+        let header = Header::empty();
+        let opcodes = vec![
+            Opcode::Condition(vec![Condition::IncDepth, Condition::FlagEqConst(Val(1, ValType::Var), Val(0x123, ValType::Const)), Condition::DecDepth], Pos::Offset(0)),
+            Opcode::TextZenkaku(Some(1), SceneText::Literal(String::from("あいう"))),
+            Opcode::Graphics(GrpCmd::Load(SceneText::Literal(String::from("BG001")), Val(0, ValType::Const))),
+            Opcode::WaitMouse,
+        ];
+
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        write_opcodes(&opcodes, &mut bytes, true).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_try_parse_never_panics() {
+        let bytes = sample_scene_bytes();
+        try_parse(&bytes).unwrap();
+
+        for len in 0..bytes.len() {
+            let _ = try_parse(&bytes[..len]);
+        }
+
+        let mut mutated = bytes.clone();
+        for i in 0..bytes.len() {
+            for b in 0..=0xFFu8 {
+                mutated[i] = b;
+                let _ = try_parse(&mutated);
+            }
+            mutated[i] = bytes[i];
+        }
+    }
+
+    #[test]
+    fn test_parse_with_logging() {
+        let bytes = sample_scene_bytes();
+        let truncated = &bytes[..bytes.len() - 3];
+        let parse = || (load_bytes(&bytes).unwrap(), load_bytes_lenient(&bytes).unwrap(), try_parse(truncated).unwrap_err().to_string());
+
+        let quiet = parse();
+        let _ = env_logger::builder().is_test(true).filter_level(log::LevelFilter::Trace).try_init();
+        assert!(log_enabled!(log::Level::Trace));
+        assert_eq!(quiet, parse());
+    }
+
+    #[test]
+    fn test_load_reader() {
+        use std::io::Cursor;
+
+        let bytes = sample_scene_bytes();
+        let scene = load_reader(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(load_bytes(&bytes).unwrap(), scene);
+
+        assert!(load_reader(Cursor::new(vec![0x00, 0x01])).is_err());
+    }
+
+    #[test]
+    fn test_load_packed_scene() {
+        use crate::write::Writeable;
+
+        let raw = sample_scene_bytes();
+        let mut arc = archive::Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), raw.clone()).unwrap();
+        let packed = arc.data[0].to_vec().unwrap();
+        assert_eq!(FileKind::ArchiveMember, identify(&packed));
+
+        let dir = std::env::temp_dir();
+        let raw_path = dir.join(format!("avg32-test-raw-{}.TXT", std::process::id()));
+        let packed_path = dir.join(format!("avg32-test-packed-{}.TXT", std::process::id()));
+        std::fs::write(&raw_path, &raw).unwrap();
+        std::fs::write(&packed_path, &packed).unwrap();
+        let from_raw = load(&raw_path);
+        let from_packed = load(&packed_path);
+        std::fs::remove_file(&raw_path).unwrap();
+        std::fs::remove_file(&packed_path).unwrap();
+
+        let expected = try_parse(&raw).unwrap();
+        assert_eq!(expected, from_raw.unwrap());
+        assert_eq!(expected, from_packed.unwrap());
+        assert_eq!(expected, load_bytes_lenient(&packed).unwrap().0);
+        assert!(load_bytes(&packed[..packed.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_load_archive_reader() {
+        use std::io::Cursor;
+        use crate::write::Writeable;
+
+        let mut arc = archive::Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), sample_scene_bytes()).unwrap();
+        arc.finalize();
+        let mut bytes = Vec::new();
+        arc.write(&mut bytes).unwrap();
+
+        let loaded = archive::load_reader(Cursor::new(bytes)).unwrap();
+        assert_eq!(1, loaded.entries.len());
+        assert_eq!("SEEN001.TXT", loaded.entries[0].filename);
+        assert_eq!(sample_scene_bytes(), loaded.data[0].decompress().unwrap());
+    }
+
+    #[test]
+    fn test_try_parse_archive_never_panics() {
+        use crate::write::Writeable;
+
+        let mut arc = archive::Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), sample_scene_bytes()).unwrap();
+        arc.finalize();
+        let bytes = arc.to_vec().unwrap();
+        assert!(archive::try_parse(&bytes).is_ok());
+
+        let mut mutated = bytes.clone();
+        for i in 0..bytes.len() {
+            for &b in [0x00, 0x01, 0x0F, 0x10, 0x7F, 0x80, 0xFF].iter() {
+                mutated[i] = b;
+                if let Ok(arc) = archive::try_parse(&mutated) {
+                    for data in arc.data.iter() {
+                        let _ = data.decompress();
+                    }
+                }
+            }
+            mutated[i] = bytes[i];
+        }
+
+        let _ = archive::decompress(&[0x00, 0xFF, 0xFF], 16);
+    }
+
+    #[test]
+    fn test_parse_opcodes_invalid() {
+        let err = parse_opcodes(&[0x01, 0x00, 0x01]).unwrap_err();
+        assert!(err.to_string().contains("offset 0x1"));
+    }
+
+    #[test]
+    fn test_patch_text() {
+        use crate::build::SceneBuilder;
+        use crate::parser::SceneText;
+        use crate::write::Writeable;
+
+        let scene = |text: &str| SceneBuilder::new()
+            .jump("end")
+            .text(text)
+            .label("end")
+            .wait_mouse()
+            .build()
+            .unwrap();
+        let original = scene("あ");
+        let bytes = original.to_vec().unwrap();
+        let header_size = original.header.byte_size();
+        let text_offset = header_size + 5;
+
+        let patched = patch_text(&bytes, text_offset, "こんにちは").unwrap();
+        let reparsed = try_parse(&patched).unwrap();
+        assert_eq!(Opcode::TextZenkaku(Some(0), SceneText::Literal(String::from("こんにちは"))), reparsed.opcodes[1]);
+        assert_eq!(bytes.len() + 8, patched.len());
+        assert_eq!(scene("こんにちは"), reparsed);
+
+        // Any offset inside the opcode will do.
+        assert_eq!(patched, patch_text(&bytes, text_offset + 6, "こんにちは").unwrap());
+
+        let err = patch_text(&bytes, header_size, "x").unwrap_err().to_string();
+        assert!(err.contains("is Jump, not text"), "{}", err);
+        let err = patch_text(&bytes, text_offset, "\u{1F600}").unwrap_err().to_string();
+        assert!(err.contains("SHIFT_JIS"), "{}", err);
+        assert!(patch_text(&bytes, 0, "x").is_err());
+    }
+}
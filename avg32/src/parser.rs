@@ -1,11 +1,19 @@
 use nom::error::{ParseError, ErrorKind};
-use nom::IResult;
+use nom::{IResult, Needed};
 use nom::number::streaming::{le_u8, le_u32};
-use encoding_rs::SHIFT_JIS;
+use encoding_rs::{DecoderResult, SHIFT_JIS};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use anyhow::anyhow;
+use crate::write::Writeable;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CustomError<I> {
   MyError(String),
+  /// Message along with the remaining input at the point of failure, so the
+  /// absolute offset can be reported.
+  MyErrorAt(I, String),
   Nom(I, ErrorKind),
 }
 
@@ -24,36 +32,474 @@ pub type ParseResult<'a, I> = IResult<&'a [u8], I, CustomError<&'a [u8]>>;
 // TODO
 const SYS_VERSION: u32 = 1714;
 
-fn sys_version_geq(min_ver: u32) -> bool {
-    SYS_VERSION >= min_ver
+/// How `scene_value` treats values encoded with more bytes than needed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ValEncoding {
+    /// Accept them, remembering their length in `AVG32Scene::val_lengths`
+    /// so they are written back unchanged.
+    Preserve,
+    /// Accept them. They are written back in minimal form.
+    Normalize,
+    /// Fail to parse them.
+    Canonical,
 }
 
+/// Which Shift_JIS table strings are decoded and encoded with.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TextEncoding {
+    /// Windows code page 932, including the NEC and IBM extension rows.
+    Cp932,
+    /// JIS X 0208 only. The extension rows are rejected, and a few symbols
+    /// map to different characters than in CP932, e.g. 0x8160 is U+301C
+    /// WAVE DASH instead of U+FF5E FULLWIDTH TILDE.
+    Strict,
+}
+
+/// Characters that the same bytes decode to in JIS X 0208 and in CP932.
+const STRICT_SJIS_CHARS: [(char, char); 6] = [
+    ('\u{301C}', '\u{FF5E}'), // 0x8160
+    ('\u{2016}', '\u{2225}'), // 0x8161
+    ('\u{2212}', '\u{FF0D}'), // 0x817C
+    ('\u{00A2}', '\u{FFE0}'), // 0x8191
+    ('\u{00A3}', '\u{FFE1}'), // 0x8192
+    ('\u{00AC}', '\u{FFE2}'), // 0x81CA
+];
+
+/// Offset and code of the first double-byte character in `bytes` from the
+/// NEC or IBM extension rows of CP932.
+pub(crate) fn find_sjis_extension(bytes: &[u8]) -> Option<(usize, u16)> {
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            0x81..=0x9f | 0xe0..=0xfc if i + 1 < bytes.len() => {
+                let code = u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+                if let 0x8740..=0x879f | 0xed40..=0xeefc | 0xfa40..=0xfc4b = code {
+                    return Some((i, code));
+                }
+                i += 2;
+            },
+            _ => i += 1
+        }
+    }
+    None
+}
+
+/// `s` with the characters that differ under `TextEncoding::Strict`
+/// replaced by their CP932 counterparts, which `SHIFT_JIS` can encode.
+pub(crate) fn to_cp932_chars(s: &str) -> Cow<'_, str> {
+    if config(|c| c.text_encoding) == TextEncoding::Cp932 || !s.chars().any(|c| STRICT_SJIS_CHARS.iter().any(|(jis, _)| *jis == c)) {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(s.chars()
+        .map(|c| STRICT_SJIS_CHARS.iter().find(|(jis, _)| *jis == c).map(|(_, cp932)| *cp932).unwrap_or(c))
+        .collect())
+}
+
+/// Options affecting how scenes are parsed. Applied with `with_config`.
+/// `text_encoding` also applies when writing.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseConfig {
+    pub sys_version: u32,
+    pub val_encoding: ValEncoding,
+    /// Deepest `IncDepth` nesting allowed in a condition list.
+    pub max_condition_depth: u32,
+    pub text_encoding: TextEncoding,
+    /// Replace invalid Shift_JIS in strings with U+FFFD instead of failing.
+    /// Shift_JIS has no encoding for U+FFFD, so such strings are refused
+    /// when writing rather than silently changing the scene.
+    pub lossy_text: bool,
+    /// Fail on opcodes whose operands aren't modeled, see
+    /// `Opcode::is_placeholder`.
+    pub reject_placeholders: bool,
+    /// Most labels a header may claim.
+    pub max_labels: u32,
+    /// Most menus a header may claim.
+    pub max_menus: u32,
+    /// Most opcodes a scene may contain.
+    pub max_opcodes: u32,
+    /// Most bytes allowed after the 0x00 ending the opcodes, as in some
+    /// archive members. Kept in `AVG32Scene::trailer`.
+    pub max_trailer: u32,
+    /// Most bytes lenient parsing looks ahead for the next opcode after one
+    /// that doesn't parse. Past that, the bytes looked at are skipped.
+    pub max_resync: u32,
+}
+
+impl Default for ParseConfig {
+    fn default() -> Self {
+        ParseConfig {
+            sys_version: SYS_VERSION,
+            val_encoding: ValEncoding::Preserve,
+            max_condition_depth: 32,
+            text_encoding: TextEncoding::Cp932,
+            lossy_text: false,
+            reject_placeholders: false,
+            max_labels: 0x10000,
+            max_menus: 0x100,
+            max_opcodes: 0x100000,
+            max_trailer: 0x10,
+            max_resync: 0x100,
+        }
+    }
+}
+
+thread_local! {
+    static CONFIG: RefCell<ParseConfig> = RefCell::new(ParseConfig::default());
+    /// Lengths of the values `scene_value` has parsed, while they're being
+    /// recorded by `recording_val_lengths`.
+    static PARSED_VAL_LENGTHS: RefCell<Option<ValLengths>> = const { RefCell::new(None) };
+    /// Lengths given to `with_val_lengths`, and how many of them have been
+    /// used.
+    static WRITTEN_VAL_LENGTHS: RefCell<Option<(ValLengths, usize)>> = const { RefCell::new(None) };
+}
+
+struct ConfigGuard(Option<ParseConfig>);
+
+impl Drop for ConfigGuard {
+    fn drop(&mut self) {
+        if let Some(old) = self.0.take() {
+            CONFIG.with(|c| *c.borrow_mut() = old);
+        }
+    }
+}
+
+/// Runs `f` with `config` used by every parser called on this thread,
+/// restoring the previous config afterwards.
+pub fn with_config<T, F: FnOnce() -> T>(config: &ParseConfig, f: F) -> T {
+    let _guard = ConfigGuard(Some(CONFIG.with(|c| c.replace(config.clone()))));
+    f()
+}
+
+/// The config in use on this thread, for passing on to other threads.
+pub fn current_config() -> ParseConfig {
+    config(|c| c.clone())
+}
+
+pub(crate) fn config<T, F: FnOnce(&ParseConfig) -> T>(f: F) -> T {
+    CONFIG.with(|c| f(&c.borrow()))
+}
+
+/// Runs `f`, also returning the lengths of the values `scene_value` parsed
+/// meanwhile, in order.
+fn recording_val_lengths<T, F: FnOnce() -> T>(f: F) -> (T, ValLengths) {
+    let old = PARSED_VAL_LENGTHS.with(|l| l.replace(Some(Vec::new())));
+    let res = f();
+    let lengths = PARSED_VAL_LENGTHS.with(|l| l.replace(old)).unwrap_or_default();
+    (res, lengths)
+}
+
+/// Runs `f` with `lengths` giving the encoded length of each value it
+/// writes, in order. See `AVG32Scene::val_lengths`.
+pub(crate) fn with_val_lengths<T, F: FnOnce() -> T>(lengths: &[Option<u8>], f: F) -> T {
+    let old = WRITTEN_VAL_LENGTHS.with(|l| l.replace(Some((lengths.to_vec(), 0))));
+    let res = f();
+    WRITTEN_VAL_LENGTHS.with(|l| *l.borrow_mut() = old);
+    res
+}
+
+/// The encoded length of the next value written inside `with_val_lengths`.
+pub(crate) fn next_val_length() -> Option<u8> {
+    WRITTEN_VAL_LENGTHS.with(|l| match l.borrow_mut().as_mut() {
+        Some((lengths, next)) => {
+            let len = lengths.get(*next).cloned().flatten();
+            *next += 1;
+            len
+        },
+        None => None
+    })
+}
+
+/// Fails if `count`, read from the input just before `input`, is more than
+/// `limit`. Run before `count!` so a corrupt count fails at once.
+fn check_count<'a>(input: &'a [u8], count: u32, limit: u32, what: &str) -> ParseResult<'a, ()> {
+    if count > limit {
+        return Err(nom::Err::Error(CustomError::MyErrorAt(input, format!("Count of {} {} is more than the limit of {}", count, what, limit))));
+    }
+    Ok((input, ()))
+}
+
+/// Fails if the `count` entries of `what`, at least `min_size` bytes each,
+/// plus `trailing` bytes after them, can't fit in `input`.
+fn check_room<'a>(input: &'a [u8], count: u32, min_size: u64, trailing: u64, what: &str) -> ParseResult<'a, ()> {
+    let needed = count as u64 * min_size + trailing;
+    if needed > input.len() as u64 {
+        return Err(nom::Err::Error(CustomError::MyErrorAt(input, format!("Header claims {} {}, needing at least {} bytes, but only {} remain", count, what, needed, input.len()))));
+    }
+    Ok((input, ()))
+}
+
+/// Parses `count` items with `item`, for lists whose length is read from
+/// the input just before them. Fails at once if there isn't room for
+/// `min_size` bytes per item, and names the list and how many items were
+/// parsed if one of them fails.
+fn counted<'a, O, F>(input: &'a [u8], item: F, count: usize, min_size: usize, what: &str) -> ParseResult<'a, Vec<O>>
+where
+    F: Fn(&'a [u8]) -> ParseResult<'a, O>
+{
+    if count * min_size > input.len() {
+        return Err(nom::Err::Error(CustomError::MyErrorAt(input, format!("Expected {} {}, needing at least {} bytes, but only {} remain", count, what, count * min_size, input.len()))));
+    }
+
+    let mut items = Vec::with_capacity(count);
+    let mut inp = input;
+    for parsed in 0..count {
+        match item(inp) {
+            Ok((i, o)) => {
+                items.push(o);
+                inp = i;
+            },
+            Err(e) => {
+                let msg = match &e {
+                    nom::Err::Error(CustomError::MyError(msg)) | nom::Err::Failure(CustomError::MyError(msg)) |
+                    nom::Err::Error(CustomError::MyErrorAt(_, msg)) | nom::Err::Failure(CustomError::MyErrorAt(_, msg)) => msg.clone(),
+                    nom::Err::Error(CustomError::Nom(_, kind)) | nom::Err::Failure(CustomError::Nom(_, kind)) => format!("{:?}", kind),
+                    nom::Err::Incomplete(_) => String::from("ran out of input")
+                };
+                let err = CustomError::MyErrorAt(inp, format!("Expected {} {}, but only parsed {}: {}", count, what, parsed, msg));
+                return Err(match e {
+                    nom::Err::Failure(_) => nom::Err::Failure(err),
+                    _ => nom::Err::Error(err)
+                });
+            }
+        }
+    }
+    Ok((inp, items))
+}
+
+pub(crate) fn sys_version_geq(min_ver: u32) -> bool {
+    config(|c| c.sys_version >= min_ver)
+}
+
+/// Encoded lengths of the values of one opcode, in the order they're
+/// written: the length of each value encoded with more bytes than needed,
+/// and `None` for the rest.
+pub type ValLengths = Vec<Option<u8>>;
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct AVG32Scene {
+    #[serde(rename = "header")]
     pub header: Header,
-    pub opcodes: Vec<Opcode>
+    #[serde(rename = "opcodes")]
+    pub opcodes: Vec<Opcode>,
+    /// Value lengths kept by `ValEncoding::Preserve`, by opcode index, for
+    /// the opcodes that have a value encoded with more bytes than needed.
+    /// They're written back at that length, unless they no longer fit.
+    #[serde(rename = "val_lengths", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub val_lengths: BTreeMap<usize, ValLengths>,
+    /// Bytes after the 0x00 ending the opcodes, written back as they were.
+    #[serde(rename = "trailer", default, skip_serializing_if = "Vec::is_empty")]
+    pub trailer: Vec<u8>
 }
 
+impl AVG32Scene {
+    /// Size of the opcode at `index`, with its values at the lengths kept
+    /// in `val_lengths`.
+    pub fn opcode_size(&self, index: usize) -> usize {
+        let lengths = match self.val_lengths.get(&index) {
+            Some(lengths) => lengths,
+            None => return self.opcodes[index].byte_size()
+        };
+
+        // Only writing says which value is which, and labels can't be
+        // written, so they're swapped for offsets of the same size.
+        let mut opcode = self.opcodes[index].clone();
+        for pos in crate::build::jump_targets_mut(&mut opcode) {
+            *pos = Pos::Offset(0);
+        }
+        let mut bytes = Vec::new();
+        match with_val_lengths(lengths, || opcode.write(&mut bytes)) {
+            Ok(()) => bytes.len(),
+            Err(_) => opcode.byte_size()
+        }
+    }
+
+    /// Each opcode with its offset from the start of the opcode body.
+    pub fn opcodes_with_offsets(&self) -> impl Iterator<Item = (u32, &Opcode)> {
+        self.opcodes.iter().enumerate().scan(0, move |pos, (index, opcode)| {
+            let offset = *pos;
+            *pos += self.opcode_size(index) as u32;
+            Some((offset, opcode))
+        })
+    }
+
+    /// Index of the opcode whose bytes include `offset`, counted from the
+    /// start of the scene file. `None` for offsets in the header or past
+    /// the last opcode.
+    pub fn opcode_at_offset(&self, offset: u32) -> Option<usize> {
+        let offset = offset.checked_sub(self.header.byte_size() as u32)?;
+        self.opcodes_with_offsets().enumerate()
+            .position(|(index, (start, _))| offset >= start && offset < start + self.opcode_size(index) as u32)
+    }
+}
+
+/// Scene header. Layout, with offsets from the start of the file:
+///
+/// - 0x00: `TPC32` magic
+/// - 0x05: `unk1`, 0x13 bytes
+/// - 0x18: number of labels (u32)
+/// - 0x1c: `counter_start` (u32)
+/// - 0x20: `labels` (u32 each), then `unk2` (0x30 bytes), the number of
+///   menus (u32), `menus`, `menu_strings` and `unk3` (0x05 bytes)
+///
+/// The meaning of the `unk` bytes hasn't been confirmed against real scenes,
+/// so they are kept as raw bytes and written back verbatim. No typed
+/// sub-fields are split out of them until a layout is known; named fields
+/// would only be guesses.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Header {
+    #[serde(rename = "unk1")]
     pub unk1: Vec<u8>,
+    #[serde(rename = "labels")]
     pub labels: Vec<u32>,
+    #[serde(rename = "unk2")]
     pub unk2: Vec<u8>,
+    #[serde(rename = "counter_start")]
     pub counter_start: u32,
+    #[serde(rename = "menus")]
     pub menus: Vec<Menu>,
+    #[serde(rename = "menu_strings")]
     pub menu_strings: Vec<String>,
+    #[serde(rename = "unk3")]
     pub unk3: Vec<u8>,
 }
 
+/// A menu with its label and the labels of its submenus, as returned by
+/// `Header::menu_tree`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MenuView<'a> {
+    pub menu: &'a Menu,
+    pub label: &'a str,
+    pub submenus: Vec<(&'a Submenu, &'a str)>
+}
+
+/// Menu or submenu that a menu string belongs to, by index into
+/// `Header::menus` and `Menu::submenus`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum MenuRef {
+    Menu(usize),
+    Submenu(usize, usize)
+}
+
+impl Header {
+    /// A header with no labels or menus and zeroed unknown bytes, which
+    /// writes out as a valid scene header.
+    pub fn empty() -> Self {
+        Header {
+            unk1: vec![0; 0x13],
+            labels: Vec::new(),
+            unk2: vec![0; 0x30],
+            counter_start: 0,
+            menus: Vec::new(),
+            menu_strings: Vec::new(),
+            unk3: vec![0; 0x05],
+        }
+    }
+
+    /// Pairs each menu string with its owner. Strings are stored as each
+    /// menu's own label followed by the labels of its submenus.
+    pub fn menu_entries(&self) -> Vec<(MenuRef, &str)> {
+        let mut refs = Vec::new();
+        for (i, menu) in self.menus.iter().enumerate() {
+            refs.push(MenuRef::Menu(i));
+            for j in 0..menu.submenus.len() {
+                refs.push(MenuRef::Submenu(i, j));
+            }
+        }
+
+        refs.into_iter().zip(self.menu_strings.iter().map(|s| s.as_str())).collect()
+    }
+
+    /// Number of `menu_strings` the parser reads for these menus: one per
+    /// menu and one per submenu.
+    pub(crate) fn menu_string_count(&self) -> usize {
+        self.menus.iter().map(|m| 1 + m.submenus.len()).sum()
+    }
+
+    /// Index into `menu_strings` of the label of `menu`'s submenu
+    /// `submenu`, which may be one past its last submenu.
+    fn submenu_string_index(&self, menu: usize, submenu: usize) -> usize {
+        let before: usize = self.menus[..menu].iter().map(|m| 1 + m.submenus.len()).sum();
+        before + 1 + submenu
+    }
+
+    fn check_menu_strings(&self) -> anyhow::Result<()> {
+        if self.menu_strings.len() != self.menu_string_count() {
+            return Err(anyhow!("Header has {} menu strings, but its menus need {}", self.menu_strings.len(), self.menu_string_count()));
+        }
+        Ok(())
+    }
+
+    /// Each menu with its submenus and their labels. Menus missing a label
+    /// because `menu_strings` is too short are left out.
+    pub fn menu_tree(&self) -> Vec<MenuView<'_>> {
+        let mut strings = self.menu_strings.iter().map(|s| s.as_str());
+        let mut res = Vec::new();
+        for menu in self.menus.iter() {
+            let label = match strings.next() {
+                Some(label) => label,
+                None => break
+            };
+            let submenus = menu.submenus.iter().zip(strings.by_ref()).collect();
+            res.push(MenuView {
+                menu,
+                label,
+                submenus
+            });
+        }
+        res
+    }
+
+    /// Adds `submenu` at the end of menu `menu`, inserting `label` at the
+    /// matching place in `menu_strings`.
+    pub fn add_submenu(&mut self, menu: usize, submenu: Submenu, label: String) -> anyhow::Result<()> {
+        self.check_menu_strings()?;
+        if menu >= self.menus.len() {
+            return Err(anyhow!("No menu {}, header has {}", menu, self.menus.len()));
+        }
+        if self.menus[menu].submenus.len() >= u8::MAX as usize {
+            return Err(anyhow!("Menu {} already has {} submenus", menu, u8::MAX));
+        }
+
+        let index = self.submenu_string_index(menu, self.menus[menu].submenus.len());
+        self.menus[menu].submenus.push(submenu);
+        self.menu_strings.insert(index, label);
+        Ok(())
+    }
+
+    /// Removes submenu `submenu` of menu `menu` along with its label.
+    pub fn remove_submenu(&mut self, menu: usize, submenu: usize) -> anyhow::Result<(Submenu, String)> {
+        self.check_menu_strings()?;
+        match self.menus.get(menu) {
+            Some(m) if submenu < m.submenus.len() => (),
+            _ => return Err(anyhow!("No submenu {} in menu {}", submenu, menu))
+        }
+
+        let index = self.submenu_string_index(menu, submenu);
+        let removed = self.menus[menu].submenus.remove(submenu);
+        Ok((removed, self.menu_strings.remove(index)))
+    }
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header::empty()
+    }
+}
+
 named!(pub header<&[u8], Header, CustomError<&[u8]>>,
   do_parse!(
     tag!("TPC32") >>
     unk1: count!(le_u8, 0x13) >>
     label_count: le_u32 >>
     counter_start: le_u32 >>
+    call!(check_count, label_count, config(|c| c.max_labels), "labels") >>
+    call!(check_room, label_count, 4, 0x30 + 4 + 0x05, "labels") >>
     labels: count!(le_u32, label_count as usize) >>
     unk2: count!(le_u8, 0x30) >>
     menu_count: le_u32 >>
+    call!(check_count, menu_count, config(|c| c.max_menus), "menus") >>
+    // Each menu has four bytes and a string of at least its terminator.
+    call!(check_room, menu_count, 5, 0x05, "menus") >>
     menus: count!(menu, (menu_count) as usize) >>
     menu_strings: call!(menu_strings, &menus) >>
     unk3: count!(le_u8, 0x05) >>
@@ -69,22 +515,49 @@ named!(pub header<&[u8], Header, CustomError<&[u8]>>,
   )
 );
 
-fn decode_sjis(input: &[u8]) -> Result<String, CustomError<&[u8]>> {
-    let (res, _, errors) = SHIFT_JIS.decode(&input);
-    if errors {
-        Err(CustomError::MyError(String::from("Invalid SHIFT_JIS")))
-    } else {
-        Ok(res.to_string())
+/// Decodes a string without its terminator. Errors point at the offending
+/// bytes, unless `ParseConfig::lossy_text` is set.
+pub(crate) fn decode_sjis(input: &[u8]) -> Result<String, CustomError<&[u8]>> {
+    let mut decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+    let mut res = String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(input.len()).unwrap_or(0));
+    let mut pos = 0;
+    loop {
+        let (result, read) = decoder.decode_to_string_without_replacement(&input[pos..], &mut res, true);
+        pos += read;
+        match result {
+            DecoderResult::InputEmpty => break,
+            DecoderResult::OutputFull => res.reserve(input.len() - pos + 4),
+            DecoderResult::Malformed(len, extra) => {
+                let end = pos - extra as usize;
+                let start = end - len as usize;
+                if config(|c| c.lossy_text) {
+                    res.push('\u{FFFD}');
+                    continue;
+                }
+                let what = if end == input.len() && extra == 0 { "Truncated" } else { "Invalid" };
+                return Err(CustomError::MyErrorAt(&input[start..], format!("{} SHIFT_JIS sequence {:02x?} in string {:02x?}", what, &input[start..end], input)));
+            }
+        }
+    }
+    if config(|c| c.text_encoding) == TextEncoding::Cp932 {
+        return Ok(res);
     }
+
+    if let Some((at, code)) = find_sjis_extension(input) {
+        return Err(CustomError::MyErrorAt(&input[at..], format!("SHIFT_JIS character 0x{:04x} is a CP932 extension, not JIS X 0208", code)));
+    }
+    Ok(res.chars()
+       .map(|c| STRICT_SJIS_CHARS.iter().find(|(_, cp932)| *cp932 == c).map(|(jis, _)| *jis).unwrap_or(c))
+       .collect())
 }
 
-named!(pub c_string<&[u8], String, CustomError<&[u8]>>,
-    do_parse!(
-        s: map_res!(take_until!("\0"), decode_sjis) >>
-        tag!("\0") >>
-        (s)
-    )
-);
+/// A NUL-terminated Shift_JIS string.
+pub fn c_string(input: &[u8]) -> ParseResult<'_, String> {
+    let (rest, bytes) = take_until!(input, "\0")?;
+    let s = decode_sjis(bytes).map_err(nom::Err::Error)?;
+    let (rest, _) = tag!(rest, "\0")?;
+    Ok((rest, s))
+}
 
 fn menu_strings<'a, 'b>(input: &'a [u8], menus: &'b [Menu]) -> ParseResult<'a, Vec<String>> {
     let mut str_count = 0;
@@ -100,9 +573,13 @@ fn menu_strings<'a, 'b>(input: &'a [u8], menus: &'b [Menu]) -> ParseResult<'a, V
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Menu {
+    #[serde(rename = "id")]
     pub id: u8,
+    #[serde(rename = "unk1")]
     pub unk1: u8,
+    #[serde(rename = "unk2")]
     pub unk2: u8,
+    #[serde(rename = "submenus")]
     pub submenus: Vec<Submenu>
 }
 
@@ -124,9 +601,13 @@ named!(pub menu<&[u8], Menu, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Submenu {
+    #[serde(rename = "id")]
     pub id: u8,
+    #[serde(rename = "unk1")]
     pub unk1: u8,
+    #[serde(rename = "unk2")]
     pub unk2: u8,
+    #[serde(rename = "flags")]
     pub flags: Vec<Flag>
 }
 
@@ -148,7 +629,9 @@ named!(pub submenu<&[u8], Submenu, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Flag {
+    #[serde(rename = "unk1")]
     pub unk1: u8,
+    #[serde(rename = "flags")]
     pub flags: Vec<u32>
 }
 
@@ -167,7 +650,9 @@ named!(pub flag<&[u8], Flag, CustomError<&[u8]>>,
 /// Byte position (jump, if, etc.)
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Pos {
+    #[serde(rename = "Offset")]
     Offset(u32),
+    #[serde(rename = "Label")]
     Label(String)
 }
 
@@ -183,7 +668,9 @@ named!(pub scene_pos<&[u8], Pos, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum ValType {
+    #[serde(rename = "Const")]
     Const,
+    #[serde(rename = "Var")]
     Var
 }
 
@@ -191,9 +678,88 @@ pub enum ValType {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Val(pub u32, pub ValType);
 
+impl Val {
+    /// A literal value.
+    ///
+    /// ```
+    /// use avg32::parser::{scene_value, Val};
+    /// use avg32::write::Writeable;
+    ///
+    /// let val = Val::constant(0x1234);
+    /// let mut bytes = Vec::new();
+    /// val.write(&mut bytes).unwrap();
+    /// assert_eq!(val, scene_value(&bytes).unwrap().1);
+    /// assert!(!val.is_var());
+    /// ```
+    pub fn constant(value: u32) -> Self {
+        Val(value, ValType::Const)
+    }
+
+    /// A reference to the variable at `index`.
+    ///
+    /// ```
+    /// use avg32::parser::{scene_value, Val};
+    /// use avg32::write::Writeable;
+    ///
+    /// let val = Val::variable(42);
+    /// let mut bytes = Vec::new();
+    /// val.write(&mut bytes).unwrap();
+    /// assert_eq!(val, scene_value(&bytes).unwrap().1);
+    /// assert!(val.is_var());
+    /// assert_eq!(42, val.value());
+    /// ```
+    pub fn variable(index: u32) -> Self {
+        Val(index, ValType::Var)
+    }
+
+    /// The literal value, or the variable index if this is a variable.
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_var(&self) -> bool {
+        self.1 == ValType::Var
+    }
+
+    /// Whether the value at the start of `bytes` uses the fewest bytes
+    /// possible, which is the form the writer produces.
+    pub fn is_canonical_encoding(bytes: &[u8]) -> bool {
+        match bytes.first() {
+            Some(num) => {
+                let len = ((num >> 4) & 7) as usize;
+                match scene_value(bytes) {
+                    Ok((_, val)) => len == canonical_len(val.0),
+                    Err(_) => false
+                }
+            },
+            None => false
+        }
+    }
+}
+
+/// Number of bytes in the shortest encoding of `value`.
+pub(crate) fn canonical_len(value: u32) -> usize {
+    match value {
+        0x00..=0x0F => 1,
+        0x10..=0xFFF => 2,
+        0x1000..=0xFFFFF => 3,
+        0x100000..=0xFFFFFFF => 4,
+        0x10000000..=0xFFFFFFFF => 5
+    }
+}
+
 pub fn scene_value(input: &[u8]) -> ParseResult<Val> {
-    let num = input[0];
+    let num = match input.first() {
+        Some(num) => *num,
+        None => return Err(nom::Err::Incomplete(Needed::Size(1)))
+    };
     let len = ((num >> 4) & 7) as usize;
+    if len == 0 {
+        return Err(nom::Err::Error(CustomError::MyError(format!("Invalid value length in byte 0x{:02x}", num))));
+    }
+    if input.len() < len {
+        return Err(nom::Err::Incomplete(Needed::Size(len)));
+    }
     let is_var = num & 0x80 == 0x80;
     let kind = if is_var {
         ValType::Var
@@ -210,18 +776,78 @@ pub fn scene_value(input: &[u8]) -> ParseResult<Val> {
     ret <<= 4;
     ret |= (num & 0x0f) as u32;
 
+    let mut encoded_len = None;
+    if len != canonical_len(ret) {
+        match config(|c| c.val_encoding) {
+            ValEncoding::Preserve => encoded_len = Some(len as u8),
+            ValEncoding::Normalize => (),
+            ValEncoding::Canonical => {
+                return Err(nom::Err::Error(CustomError::MyErrorAt(input, format!("Value 0x{:x} encoded in {} bytes instead of {}", ret, len, canonical_len(ret)))));
+            }
+        }
+    }
+
+    PARSED_VAL_LENGTHS.with(|l| {
+        if let Some(lengths) = l.borrow_mut().as_mut() {
+            lengths.push(encoded_len);
+        }
+    });
+
     Ok((&input[len..], Val(ret, kind)))
 }
 
+/// A string operand. `Pointer` is written as '@' and names one of the
+/// engine's string variables instead of giving the text inline. Those
+/// variables are only filled in at runtime, usually by
+/// `StringCmd::StrcpyLiteral`.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum SceneText {
+    #[serde(rename = "Pointer")]
     Pointer(Val),
+    #[serde(rename = "Literal")]
     Literal(String)
 }
 
-fn scene_text(input: &[u8]) -> ParseResult<SceneText> {
-    if input[0] == 0x40 {
-        let (inp, val) = scene_value(input)?;
+impl SceneText {
+    pub fn literal(&self) -> Option<&str> {
+        match self {
+            SceneText::Literal(s) => Some(s),
+            SceneText::Pointer(_) => None
+        }
+    }
+
+    /// The text, looking up pointers in the literals `scene` copies into
+    /// string variables. Gives `None` for a pointer through a variable
+    /// index, or to a string variable that is never set or is set to more
+    /// than one literal.
+    pub fn resolve(&self, scene: &AVG32Scene) -> Option<String> {
+        let index = match self {
+            SceneText::Literal(s) => return Some(s.clone()),
+            SceneText::Pointer(val) if !val.is_var() => val.value(),
+            SceneText::Pointer(_) => return None
+        };
+
+        let mut found: Option<&str> = None;
+        for opcode in scene.opcodes.iter() {
+            if let Opcode::String(StringCmd::StrcpyLiteral(dest, text)) = opcode {
+                if dest.value() != index || dest.is_var() {
+                    continue;
+                }
+                match (found, text.literal()) {
+                    (None, Some(s)) => found = Some(s),
+                    (Some(prev), Some(s)) if prev == s => (),
+                    _ => return None
+                }
+            }
+        }
+        found.map(String::from)
+    }
+}
+
+pub(crate) fn scene_text(input: &[u8]) -> ParseResult<SceneText> {
+    if input.first() == Some(&0x40) {
+        // '@' followed by the index of a string variable.
+        let (inp, val) = scene_value(&input[1..])?;
         Ok((inp, SceneText::Pointer(val)))
     } else {
         let (inp, val) = c_string(input)?;
@@ -231,11 +857,21 @@ fn scene_text(input: &[u8]) -> ParseResult<SceneText> {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum FormattedTextCmd {
+    #[serde(rename = "Integer")]
     Integer(Val), // 0x01
+    #[serde(rename = "IntegerZeroPadded")]
     IntegerZeroPadded(Val, Val), // 0x02
+    #[serde(rename = "TextPointer")]
     TextPointer(Val), // 0x03
-    Unknown1(Val), // 0x11
-    Unknown2 // 0x13
+    /// Formerly `Unknown1`. The name is a guess, not taken from engine
+    /// documentation: it seems to draw the following text in colour `Val`
+    /// of the text palette, until `ResetColor` or the end of the text.
+    #[serde(rename = "Color", alias = "Unknown1")]
+    Color(Val), // 0x11
+    /// Formerly `Unknown2`. Also a guess: it seems to go back to the
+    /// window's default text colour.
+    #[serde(rename = "ResetColor", alias = "Unknown2")]
+    ResetColor // 0x13
 }
 
 named!(pub formatted_text_cmd<&[u8], FormattedTextCmd, CustomError<&[u8]>>,
@@ -243,18 +879,27 @@ named!(pub formatted_text_cmd<&[u8], FormattedTextCmd, CustomError<&[u8]>>,
         0x01 => do_parse!(val: scene_value >> (FormattedTextCmd::Integer(val))) |
         0x02 => do_parse!(val: scene_value >> zeros: scene_value >> (FormattedTextCmd::IntegerZeroPadded(val, zeros))) |
         0x03 => do_parse!(val: scene_value >> (FormattedTextCmd::TextPointer(val))) |
-        0x11 => do_parse!(val: scene_value >> (FormattedTextCmd::Unknown1(val))) |
-        0x13 => value!(FormattedTextCmd::Unknown2)
+        0x11 => do_parse!(val: scene_value >> (FormattedTextCmd::Color(val))) |
+        0x13 => value!(FormattedTextCmd::ResetColor)
     )
 );
 
+/// One piece of a formatted text. There's no known entry for ruby
+/// (furigana); `Unknown` (0x12) is the only entry byte whose meaning is
+/// unknown, and it has no operands, so it can't carry a reading.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum SceneFormattedTextEntry {
+    #[serde(rename = "Command")]
     Command(FormattedTextCmd), // 0x10
+    #[serde(rename = "Unknown")]
     Unknown, // 0x12
+    #[serde(rename = "Condition")]
     Condition(Vec<Condition>), // 0x28
+    #[serde(rename = "TextPointer")]
     TextPointer(Val), // 0xfd
+    #[serde(rename = "TextHankaku")]
     TextHankaku(String), // 0xfe
+    #[serde(rename = "TextZenkaku")]
     TextZenkaku(String), // 0xff
 }
 
@@ -262,7 +907,7 @@ named!(pub scene_formatted_text_entry<&[u8], SceneFormattedTextEntry, CustomErro
        switch!(le_u8,
                0x10 => do_parse!(a: formatted_text_cmd >> (SceneFormattedTextEntry::Command(a))) |
                0x12 => value!(SceneFormattedTextEntry::Unknown) |
-               0x28 => do_parse!(a: scene_conditions >> (SceneFormattedTextEntry::Condition(a))) |
+               0x28 => do_parse!(a: formatted_text_conditions >> (SceneFormattedTextEntry::Condition(a))) |
                0xfd => do_parse!(a: scene_value >> (SceneFormattedTextEntry::TextPointer(a))) |
                0xfe => do_parse!(a: c_string >> (SceneFormattedTextEntry::TextHankaku(a))) |
                0xff => do_parse!(a: c_string >> (SceneFormattedTextEntry::TextZenkaku(a)))
@@ -272,6 +917,18 @@ named!(pub scene_formatted_text_entry<&[u8], SceneFormattedTextEntry, CustomErro
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct SceneFormattedText(pub Vec<SceneFormattedTextEntry>);
 
+impl SceneFormattedText {
+    /// The literal text, leaving out commands, conditions and pointers.
+    pub fn plain_text(&self) -> String {
+        self.0.iter()
+            .filter_map(|entry| match entry {
+                SceneFormattedTextEntry::TextHankaku(s) | SceneFormattedTextEntry::TextZenkaku(s) => Some(s.as_str()),
+                _ => None
+            })
+            .collect()
+    }
+}
+
 named!(pub scene_formatted_text<&[u8], SceneFormattedText, CustomError<&[u8]>>,
     do_parse!(
         res: many_till!(scene_formatted_text_entry, tag!("\0")) >>
@@ -285,7 +942,9 @@ named!(pub scene_formatted_text<&[u8], SceneFormattedText, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum JumpToSceneCmd {
+    #[serde(rename = "Jump")]
     Jump(Val), // 0x01
+    #[serde(rename = "Call")]
     Call(Val), // 0x02
 }
 
@@ -298,10 +957,15 @@ named!(pub jump_to_scene_cmd<&[u8], JumpToSceneCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum TextWinCmd {
+    #[serde(rename = "Hide")]
     Hide, // 0x01
+    #[serde(rename = "HideEffect")]
     HideEffect, // 0x02
+    #[serde(rename = "HideRedraw")]
     HideRedraw, // 0x03
+    #[serde(rename = "MouseWait")]
     MouseWait, // 0x04
+    #[serde(rename = "ClearText")]
     ClearText // 0x05
 }
 
@@ -317,11 +981,17 @@ named!(pub text_win_cmd<&[u8], TextWinCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum FadeCmd {
+    #[serde(rename = "Fade")]
     Fade(Val), // 0x01
+    #[serde(rename = "FadeTimed")]
     FadeTimed(Val, Val), // 0x02
+    #[serde(rename = "FadeColor")]
     FadeColor(Val, Val, Val), // 0x03
+    #[serde(rename = "FadeTimedColor")]
     FadeTimedColor(Val, Val, Val, Val), // 0x04
+    #[serde(rename = "FillScreen")]
     FillScreen(Val), // 0x10
+    #[serde(rename = "FillScreenColor")]
     FillScreenColor(Val, Val, Val), // 0x11
 }
 
@@ -364,21 +1034,37 @@ named!(pub fade_cmd<&[u8], FadeCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct GrpEffect {
+    #[serde(rename = "file")]
     pub file: SceneText,
+    #[serde(rename = "sx1")]
     pub sx1: Val,
+    #[serde(rename = "sy1")]
     pub sy1: Val,
+    #[serde(rename = "sx2")]
     pub sx2: Val,
+    #[serde(rename = "sy2")]
     pub sy2: Val,
+    #[serde(rename = "dx")]
     pub dx: Val,
+    #[serde(rename = "dy")]
     pub dy: Val,
+    #[serde(rename = "steptime")]
     pub steptime: Val,
+    #[serde(rename = "cmd")]
     pub cmd: Val,
+    #[serde(rename = "mask")]
     pub mask: Val,
+    #[serde(rename = "arg1")]
     pub arg1: Val,
+    #[serde(rename = "arg2")]
     pub arg2: Val,
+    #[serde(rename = "arg3")]
     pub arg3: Val,
+    #[serde(rename = "step")]
     pub step: Val,
+    #[serde(rename = "arg5")]
     pub arg5: Val,
+    #[serde(rename = "arg6")]
     pub arg6: Val,
 }
 
@@ -423,15 +1109,21 @@ named!(pub grp_effect<&[u8], GrpEffect, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum GrpCompositeMethod {
+    #[serde(rename = "Corner")]
     Corner, // 0x01
+    #[serde(rename = "Copy")]
     Copy(Val), // 0x02
+    #[serde(rename = "Move1")]
     Move1(Val, Val, Val, Val, Val, Val), // 0x03
+    #[serde(rename = "Move2")]
     Move2(Val, Val, Val, Val, Val, Val, Val) // 0x04
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct GrpCompositeChild {
+    #[serde(rename = "file")]
     pub file: SceneText,
+    #[serde(rename = "method")]
     pub method: GrpCompositeMethod
 }
 
@@ -473,7 +1165,9 @@ fn grp_composite_child(input: &[u8]) -> ParseResult<GrpCompositeChild> {
 
             GrpCompositeMethod::Move2(srcx1, srcy1, srcx2, srcy2, dstx1, dsty1, arg)
         },
-        _ => return Err(nom::Err::Error(CustomError::MyError(format!("Unknown {}", idx))))
+        // Nothing says how many operands an unknown method has, so there's
+        // no telling where the next child starts.
+        _ => return Err(nom::Err::Error(CustomError::MyErrorAt(input, format!("Unknown composite method 0x{:02x}", idx))))
     };
 
     let child = GrpCompositeChild {
@@ -486,8 +1180,11 @@ fn grp_composite_child(input: &[u8]) -> ParseResult<GrpCompositeChild> {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct GrpComposite {
+    #[serde(rename = "base_file")]
     pub base_file: SceneText,
+    #[serde(rename = "idx")]
     pub idx: Val,
+    #[serde(rename = "children")]
     pub children: Vec<GrpCompositeChild>
 }
 
@@ -496,7 +1193,7 @@ named!(pub grp_composite<&[u8], GrpComposite, CustomError<&[u8]>>,
            count: le_u8 >>
                base_file: scene_text >>
                idx: scene_value >>
-               children: count!(grp_composite_child, count as usize) >>
+               children: call!(counted, grp_composite_child, count as usize, 2, "composite children") >>
                (GrpComposite {
                    base_file: base_file,
                    idx: idx,
@@ -507,8 +1204,11 @@ named!(pub grp_composite<&[u8], GrpComposite, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct GrpCompositeIndexed {
+    #[serde(rename = "base_file")]
     pub base_file: Val,
+    #[serde(rename = "idx")]
     pub idx: Val,
+    #[serde(rename = "children")]
     pub children: Vec<GrpCompositeChild>
 }
 
@@ -517,7 +1217,7 @@ named!(pub grp_composite_indexed<&[u8], GrpCompositeIndexed, CustomError<&[u8]>>
            count: le_u8 >>
            base_file: scene_value >>
            idx: scene_value >>
-               children: count!(grp_composite_child, count as usize) >>
+               children: call!(counted, grp_composite_child, count as usize, 2, "composite children") >>
                (GrpCompositeIndexed {
                    base_file: base_file,
                    idx: idx,
@@ -528,25 +1228,45 @@ named!(pub grp_composite_indexed<&[u8], GrpCompositeIndexed, CustomError<&[u8]>>
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum GrpCmd {
+    #[serde(rename = "Load")]
     Load(SceneText, Val), // 0x01
+    #[serde(rename = "LoadEffect")]
     LoadEffect(GrpEffect), // 0x02
+    #[serde(rename = "Load2")]
     Load2(SceneText, Val), // 0x03
+    #[serde(rename = "LoadEffect2")]
     LoadEffect2(GrpEffect), // 0x04
+    #[serde(rename = "Load3")]
     Load3(SceneText, Val), // 0x05
+    #[serde(rename = "LoadEffect3")]
     LoadEffect3(GrpEffect), // 0x06
+    #[serde(rename = "Unknown1")]
     Unknown1, // 0x08
+    #[serde(rename = "LoadToBuf")]
     LoadToBuf(SceneText, Val), // 0x09
+    #[serde(rename = "LoadToBuf2")]
     LoadToBuf2(SceneText, Val), // 0x10
+    #[serde(rename = "LoadCaching")]
     LoadCaching(SceneText), // 0x11
+    #[serde(rename = "GrpCmd0x13")]
     GrpCmd0x13, // 0x13
+    #[serde(rename = "LoadComposite")]
     LoadComposite(GrpComposite), // 0x22
+    #[serde(rename = "LoadCompositeIndexed")]
     LoadCompositeIndexed(GrpCompositeIndexed), // 0x24
+    #[serde(rename = "MacroBufferClear")]
     MacroBufferClear, // 0x30
+    #[serde(rename = "MacroBufferDelete")]
     MacroBufferDelete(Val), // 0x31
+    #[serde(rename = "MacroBufferRead")]
     MacroBufferRead(Val), // 0x32
+    #[serde(rename = "MacroBufferSet")]
     MacroBufferSet(Val), // 0x33
+    #[serde(rename = "BackupScreenCopy")]
     BackupScreenCopy, // 0x50
+    #[serde(rename = "BackupScreenDisplay")]
     BackupScreenDisplay(Val), // 0x52
+    #[serde(rename = "LoadToBuf3")]
     LoadToBuf3(SceneText, Val), // 0x54
 }
 
@@ -632,36 +1352,67 @@ named!(pub grp_cmd<&[u8], GrpCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum SndCmd {
+    #[serde(rename = "BgmLoop")]
     BgmLoop(SceneText), // 0x01
+    #[serde(rename = "BgmWait")]
     BgmWait(SceneText), // 0x02
+    #[serde(rename = "BgmOnce")]
     BgmOnce(SceneText), // 0x03
+    #[serde(rename = "BgmFadeInLoop")]
     BgmFadeInLoop(SceneText, Val), // 0x05
+    #[serde(rename = "BgmFadeInWait")]
     BgmFadeInWait(SceneText, Val), // 0x06
+    #[serde(rename = "BgmFadeInOnce")]
     BgmFadeInOnce(SceneText, Val), // 0x07
+    #[serde(rename = "BgmFadeOut")]
     BgmFadeOut(Val), // 0x10
+    #[serde(rename = "BgmStop")]
     BgmStop, // 0x11
+    #[serde(rename = "BgmRewind")]
     BgmRewind, // 0x12
+    #[serde(rename = "BgmUnknown1")]
     BgmUnknown1, // 0x16
+    #[serde(rename = "KoePlayWait")]
     KoePlayWait(Val), // 0x20
+    #[serde(rename = "KoePlay")]
     KoePlay(Val), // 0x21
+    #[serde(rename = "KoePlay2")]
     KoePlay2(Val, Val), // 0x22
+    #[serde(rename = "WavPlay")]
     WavPlay(SceneText), // 0x30
+    #[serde(rename = "WavPlay2")]
     WavPlay2(SceneText, Val), // 0x31
+    #[serde(rename = "WavLoop")]
     WavLoop(SceneText), // 0x32
+    #[serde(rename = "WavLoop2")]
     WavLoop2(SceneText, Val), // 0x33
+    #[serde(rename = "WavPlayWait")]
     WavPlayWait(SceneText), // 0x34
+    #[serde(rename = "WavPlayWait2")]
     WavPlayWait2(SceneText, Val), // 0x35
+    #[serde(rename = "WavStop")]
     WavStop, // 0x36
+    #[serde(rename = "WavStop2")]
     WavStop2(Val), // 0x37
+    #[serde(rename = "WavStop3")]
     WavStop3, // 0x38
+    #[serde(rename = "WavUnknown0x39")]
     WavUnknown0x39(Val), // 0x39
+    #[serde(rename = "SePlay")]
     SePlay(Val), // 0x44
+    #[serde(rename = "MoviePlay")]
     MoviePlay(SceneText, Val, Val, Val, Val), // 0x50
+    #[serde(rename = "MovieLoop")]
     MovieLoop(SceneText, Val, Val, Val, Val), // 0x51
+    #[serde(rename = "MovieWait")]
     MovieWait(SceneText, Val, Val, Val, Val), // 0x52
+    #[serde(rename = "MovieWaitCancelable")]
     MovieWaitCancelable(SceneText, Val, Val, Val, Val), // 0x53
+    #[serde(rename = "MovieWait2")]
     MovieWait2(SceneText, SceneText, Val, Val, Val, Val), // 0x54
+    #[serde(rename = "MovieWaitCancelable2")]
     MovieWaitCancelable2(SceneText, SceneText, Val, Val, Val, Val), // 0x55
+    #[serde(rename = "Unknown1")]
     Unknown1, // 0x60
 }
 
@@ -746,12 +1497,12 @@ named!(pub snd_cmd<&[u8], SndCmd, CustomError<&[u8]>>,
                    a: scene_value >>
                    (SndCmd::WavStop2(a))
                ) |
-               0x38 => value!(SndCmd::WavStop) |
+               0x38 => value!(SndCmd::WavStop3) |
                0x39 => do_parse!(
                    a: scene_value >>
                    (SndCmd::WavUnknown0x39(a))
                ) |
-               0x40 => do_parse!(
+               0x44 => do_parse!(
                    a: scene_value >>
                    (SndCmd::SePlay(a))
                ) |
@@ -787,7 +1538,7 @@ named!(pub snd_cmd<&[u8], SndCmd, CustomError<&[u8]>>,
                    e: scene_value >>
                    (SndCmd::MovieWaitCancelable(a, b, c, d, e))
                ) |
-               0x50 => do_parse!(
+               0x54 => do_parse!(
                    a: scene_text >>
                    b: scene_text >>
                    c: scene_value >>
@@ -796,7 +1547,7 @@ named!(pub snd_cmd<&[u8], SndCmd, CustomError<&[u8]>>,
                    f: scene_value >>
                    (SndCmd::MovieWait2(a, b, c, d, e, f))
                ) |
-               0x50 => do_parse!(
+               0x55 => do_parse!(
                    a: scene_text >>
                    b: scene_text >>
                    c: scene_value >>
@@ -811,62 +1562,124 @@ named!(pub snd_cmd<&[u8], SndCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum Ret {
+    #[serde(rename = "Color")]
     Color(Val), // 0x20
+    #[serde(rename = "Choice")]
     Choice, // 0x21
+    #[serde(rename = "DisabledChoice")]
     DisabledChoice(Val) // 0x22
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum Condition {
+    #[serde(rename = "And")]
     And, // 0x26
+    #[serde(rename = "Or")]
     Or, // 0x27
+    #[serde(rename = "IncDepth")]
     IncDepth, // 0x28
+    #[serde(rename = "DecDepth")]
     DecDepth, // 0x29
+    #[serde(rename = "BitNotEq")]
     BitNotEq(Val, Val), // 0x36
+    #[serde(rename = "BitEq")]
     BitEq(Val, Val), // 0x37
+    #[serde(rename = "NotEq")]
     NotEq(Val, Val), // 0x38
+    #[serde(rename = "Eq")]
     Eq(Val, Val), // 0x39
+    #[serde(rename = "FlagNotEqConst")]
     FlagNotEqConst(Val, Val), // 0x3A
+    #[serde(rename = "FlagEqConst")]
     FlagEqConst(Val, Val), // 0x3B
+    #[serde(rename = "FlagAndConst")]
     FlagAndConst(Val, Val), // 0x41
+    #[serde(rename = "FlagAndConst2")]
     FlagAndConst2(Val, Val), // 0x42
+    #[serde(rename = "FlagXorConst")]
     FlagXorConst(Val, Val), // 0x43
+    #[serde(rename = "FlagGtConst")]
     FlagGtConst(Val, Val), // 0x44
+    #[serde(rename = "FlagLtConst")]
     FlagLtConst(Val, Val), // 0x45
+    #[serde(rename = "FlagGeqConst")]
     FlagGeqConst(Val, Val), // 0x46
+    #[serde(rename = "FlagLeqConst")]
     FlagLeqConst(Val, Val), // 0x47
+    #[serde(rename = "FlagNotEq")]
     FlagNotEq(Val, Val), // 0x48
+    #[serde(rename = "FlagEq")]
     FlagEq(Val, Val), // 0x49
+    #[serde(rename = "FlagAnd")]
     FlagAnd(Val, Val), // 0x4F
+    #[serde(rename = "FlagAnd2")]
     FlagAnd2(Val, Val), // 0x50
+    #[serde(rename = "FlagXor")]
     FlagXor(Val, Val), // 0x51
+    #[serde(rename = "FlagGt")]
     FlagGt(Val, Val), // 0x52
+    #[serde(rename = "FlagLt")]
     FlagLt(Val, Val), // 0x53
+    #[serde(rename = "FlagGeq")]
     FlagGeq(Val, Val), // 0x54
+    #[serde(rename = "FlagLeq")]
     FlagLeq(Val, Val), // 0x55
+    #[serde(rename = "Ret")]
     Ret(Ret), // 0x58
 }
 
-fn scene_conditions(input: &[u8]) -> ParseResult<Vec<Condition>> {
-    let mut depth = 0;
+/// Parses conditions up to the `DecDepth` closing the first `IncDepth`.
+/// `And`/`Or` must sit between two operands, where an operand is a
+/// comparison, a `Ret` or a parenthesized group.
+pub(crate) fn scene_conditions(input: &[u8]) -> ParseResult<Vec<Condition>> {
+    conditions_at_depth(input, 0)
+}
+
+/// Like `scene_conditions`, for a `SceneFormattedTextEntry::Condition`. Its
+/// 0x28 tag opens the list, so parsing starts inside it and the list ends at
+/// the first unmatched `DecDepth`.
+fn formatted_text_conditions(input: &[u8]) -> ParseResult<'_, Vec<Condition>> {
+    conditions_at_depth(input, 1)
+}
+
+fn conditions_at_depth(input: &[u8], start_depth: u32) -> ParseResult<'_, Vec<Condition>> {
+    let max_depth = config(|c| c.max_condition_depth);
+    let mut depth: u32 = start_depth;
     let mut conditions = vec![];
     let mut finish = false;
     let mut inp = input;
+    let mut after_operand = false;
+    let mut prev = None;
 
     while !finish {
+        let start = inp;
         let (i, num) = le_u8(inp)?;
         inp = i;
 
         let cond = match num {
-            0x26 => Condition::And,
-            0x27 => Condition::Or,
+            0x26 | 0x27 => {
+                let cond = if num == 0x26 { Condition::And } else { Condition::Or };
+                if !after_operand {
+                    return Err(nom::Err::Error(CustomError::MyErrorAt(start, format!("Unbalanced condition: {:?} (0x{:02x}) without a left operand", cond, num))));
+                }
+                cond
+            },
             0x28 => {
-                depth = depth + 1;
+                if depth >= max_depth {
+                    return Err(nom::Err::Error(CustomError::MyErrorAt(start, format!("Condition nested deeper than {} levels", max_depth))));
+                }
+                depth += 1;
                 Condition::IncDepth
             },
             0x29 => {
-                depth = depth - 1;
-                if depth <= 0 {
+                if depth == 0 {
+                    return Err(nom::Err::Error(CustomError::MyErrorAt(start, String::from("Unbalanced condition: DecDepth (0x29) without a matching IncDepth (0x28)"))));
+                }
+                if let Some(op @ 0x26) | Some(op @ 0x27) = prev {
+                    return Err(nom::Err::Error(CustomError::MyErrorAt(start, format!("Unbalanced condition: {} (0x{:02x}) without a right operand", if op == 0x26 { "And" } else { "Or" }, op))));
+                }
+                depth -= 1;
+                if depth == 0 {
                     finish = true;
                 }
                 Condition::DecDepth
@@ -900,10 +1713,11 @@ fn scene_conditions(input: &[u8]) -> ParseResult<Vec<Condition>> {
                     0x53 => Condition::FlagLt(val1, val2),
                     0x54 => Condition::FlagGeq(val1, val2),
                     0x55 => Condition::FlagLeq(val1, val2),
-                    _ => unreachable!()
+                    _ => return Err(nom::Err::Error(CustomError::MyErrorAt(start, format!("Unknown condition operator 0x{:02x}", num))))
                 }
             }
             0x58 => {
+                let attr_start = inp;
                 let (i, attr) = le_u8(inp)?;
                 inp = i;
 
@@ -919,13 +1733,15 @@ fn scene_conditions(input: &[u8]) -> ParseResult<Vec<Condition>> {
                         inp = i;
                         Ret::DisabledChoice(val)
                     },
-                    _ => unreachable!()
+                    _ => return Err(nom::Err::Error(CustomError::MyErrorAt(attr_start, format!("Unknown ret attribute 0x{:02x}", attr))))
                 };
                 Condition::Ret(ret)
             },
-            _ => return Err(nom::Err::Error(CustomError::MyError(format!("Unknown {}", num))))
+            _ => return Err(nom::Err::Error(CustomError::MyErrorAt(start, format!("Unknown condition operator 0x{:02x}", num))))
         };
 
+        after_operand = !matches!(cond, Condition::And | Condition::Or | Condition::IncDepth);
+        prev = Some(num);
         conditions.push(cond);
     }
 
@@ -934,6 +1750,7 @@ fn scene_conditions(input: &[u8]) -> ParseResult<Vec<Condition>> {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum ScreenShakeCmd {
+    #[serde(rename = "ScreenShake")]
     ScreenShake(Val), // 0x01
 }
 
@@ -948,15 +1765,25 @@ named!(pub screen_shake_cmd<&[u8], ScreenShakeCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum WaitCmd {
+    #[serde(rename = "Wait")]
     Wait(Val), // 0x01
+    #[serde(rename = "WaitMouse")]
     WaitMouse(Val, Val), // 0x02
+    #[serde(rename = "SetToBase")]
     SetToBase, // 0x03
+    #[serde(rename = "WaitFromBase")]
     WaitFromBase(Val), // 0x04
+    #[serde(rename = "WaitFromBaseMouse")]
     WaitFromBaseMouse(Val), // 0x05
+    #[serde(rename = "SetToBaseVal")]
     SetToBaseVal(Val), // 0x06
+    #[serde(rename = "Wait0x10")]
     Wait0x10, // 0x10
+    #[serde(rename = "Wait0x11")]
     Wait0x11, // 0x11
+    #[serde(rename = "Wait0x12")]
     Wait0x12, // 0x12
+    #[serde(rename = "Wait0x13")]
     Wait0x13 // 0x13
 }
 
@@ -993,9 +1820,13 @@ named!(pub wait_cmd<&[u8], WaitCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum RetCmd {
+    #[serde(rename = "SameScene")]
     SameScene, // 0x01
+    #[serde(rename = "OtherScene")]
     OtherScene, // 0x02
+    #[serde(rename = "PopStack")]
     PopStack, // 0x03
+    #[serde(rename = "ClearStack")]
     ClearStack // 0x06
 }
 
@@ -1010,7 +1841,9 @@ named!(pub ret_cmd<&[u8], RetCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum ScenarioMenuCmd {
+    #[serde(rename = "SetBit")]
     SetBit(Val), // 0x01
+    #[serde(rename = "SetBit2")]
     SetBit2(Val, Val) // 0x02
 }
 
@@ -1023,7 +1856,9 @@ named!(pub scenario_menu_cmd<&[u8], ScenarioMenuCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum TextRankCmd {
+    #[serde(rename = "Set")]
     Set(Val), // 0x01
+    #[serde(rename = "Clear")]
     Clear, // 0x02
 }
 
@@ -1036,20 +1871,55 @@ named!(pub text_rank_cmd<&[u8], TextRankCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum Choice {
+    #[serde(rename = "Choice")]
     Choice, // 0x22
+    #[serde(rename = "End")]
     End // 0x23
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct ChoiceText {
+    /// Byte skipped before the first text. The parser only skips it when
+    /// it can't start a text entry or end the list. `SceneBuilder` writes
+    /// 0x22; other values are kept as read.
+    #[serde(rename = "pad")]
     pub pad: Option<u8>,
+    #[serde(rename = "texts")]
     pub texts: Vec<SceneFormattedText>
 }
 
+/// Whether `byte` can come first in the texts of a `ChoiceText`: an entry
+/// of `SceneFormattedText`, its 0x00 terminator, or the 0x23 ending the list.
+pub fn is_choice_text_start(byte: u8) -> bool {
+    matches!(byte, 0x00 | 0x10 | 0x12 | 0x28 | 0xfd | 0xfe | 0xff | 0x23)
+}
+
+fn choice_pad(input: &[u8]) -> ParseResult<'_, Option<u8>> {
+    match input.first() {
+        Some(&byte) if !is_choice_text_start(byte) => Ok((&input[1..], Some(byte))),
+        Some(_) => Ok((input, None)),
+        None => Err(nom::Err::Incomplete(Needed::Size(1)))
+    }
+}
+
+named!(pub choice_text<&[u8], ChoiceText, CustomError<&[u8]>>,
+    do_parse!(
+        pad: choice_pad >>
+            texts: many_till!(
+                scene_formatted_text,
+                tag!([0x23])
+            ) >>
+            (ChoiceText { pad, texts: texts.0 })
+    )
+);
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum ChoiceCmd {
+    #[serde(rename = "Choice")]
     Choice(Val, u8, Option<ChoiceText>), // 0x01
+    #[serde(rename = "Choice2")]
     Choice2(Val, u8, Option<ChoiceText>), // 0x02
+    #[serde(rename = "LoadMenu")]
     LoadMenu(Val) // 0x04
 }
 
@@ -1058,31 +1928,13 @@ named!(pub choice_cmd<&[u8], ChoiceCmd, CustomError<&[u8]>>,
             0x01 => do_parse!(
                 index: scene_value >>
                     flag: le_u8 >>
-                    texts: cond!(flag == 0x22,
-                          do_parse!(
-                              pad: opt!(le_u8) >>
-                                  texts: many_till!(
-                                      scene_formatted_text,
-                                      tag!([0x23])
-                                  ) >>
-                                  (ChoiceText { pad: pad, texts: texts.0 })
-                        )
-                    ) >>
+                    texts: cond!(flag == 0x22, choice_text) >>
                     (ChoiceCmd::Choice(index, flag, texts))
             ) |
             0x02 => do_parse!(
                 index: scene_value >>
                     flag: le_u8 >>
-                    texts: cond!(flag == 0x22,
-                          do_parse!(
-                              pad: opt!(le_u8) >>
-                                  texts: many_till!(
-                                      scene_formatted_text,
-                                      tag!([0x23])
-                                  ) >>
-                                  (ChoiceText { pad: pad, texts: texts.0 })
-                        )
-                    ) >>
+                    texts: cond!(flag == 0x22, choice_text) >>
                     (ChoiceCmd::Choice2(index, flag, texts))
             ) |
         0x04 => do_parse!(index: scene_value >> (ChoiceCmd::LoadMenu(index)))
@@ -1091,13 +1943,21 @@ named!(pub choice_cmd<&[u8], ChoiceCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum StringCmd {
+    #[serde(rename = "StrcpyLiteral")]
     StrcpyLiteral(Val, SceneText), // 0x01
+    #[serde(rename = "Strlen")]
     Strlen(Val, Val), // 0x02
+    #[serde(rename = "Strcmp")]
     Strcmp(Val, Val, Val), // 0x03
+    #[serde(rename = "Strcat")]
     Strcat(Val, Val), // 0x04
+    #[serde(rename = "Strcpy")]
     Strcpy(Val, Val), // 0x05
+    #[serde(rename = "Itoa")]
     Itoa(Val, Val, Val), // 0x06
+    #[serde(rename = "HanToZen")]
     HanToZen(Val), // 0x07
+    #[serde(rename = "Atoi")]
     Atoi(Val, Val), // 0x08
 }
 
@@ -1116,7 +1976,9 @@ named!(pub string_cmd<&[u8], StringCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum SetMultiCmd {
+    #[serde(rename = "Val")]
     Val(Val, Val, Val), // 0x01
+    #[serde(rename = "Bit")]
     Bit(Val, Val, Val), // 0x02
 }
 
@@ -1139,13 +2001,21 @@ named!(pub set_multi_cmd<&[u8], SetMultiCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BRGRectColor {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "r")]
     pub r: Val,
+    #[serde(rename = "g")]
     pub g: Val,
+    #[serde(rename = "b")]
     pub b: Val,
 }
 
@@ -1174,10 +2044,15 @@ named!(pub brg_rect_color<&[u8], BRGRectColor, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BRGRect {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
 }
 
@@ -1200,14 +2075,23 @@ named!(pub brg_rect<&[u8], BRGRect, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BRGFadeOutColor {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "r")]
     pub r: Val,
+    #[serde(rename = "g")]
     pub g: Val,
+    #[serde(rename = "b")]
     pub b: Val,
+    #[serde(rename = "count")]
     pub count: Val,
 }
 
@@ -1238,15 +2122,25 @@ named!(pub brg_fade_out_color<&[u8], BRGFadeOutColor, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BRGStretchBlit {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dstx2")]
     pub dstx2: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dsty2")]
     pub dsty2: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
 }
 
@@ -1279,21 +2173,37 @@ named!(pub brg_stretch_blit<&[u8], BRGStretchBlit, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BRGStretchBlitEffect {
+    #[serde(rename = "sx1")]
     pub sx1: Val,
+    #[serde(rename = "sy1")]
     pub sy1: Val,
+    #[serde(rename = "sx2")]
     pub sx2: Val,
+    #[serde(rename = "sy2")]
     pub sy2: Val,
+    #[serde(rename = "ex1")]
     pub ex1: Val,
+    #[serde(rename = "ey1")]
     pub ey1: Val,
+    #[serde(rename = "ex2")]
     pub ex2: Val,
+    #[serde(rename = "ey2")]
     pub ey2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dx1")]
     pub dx1: Val,
+    #[serde(rename = "dy1")]
     pub dy1: Val,
+    #[serde(rename = "dx2")]
     pub dx2: Val,
+    #[serde(rename = "dy2")]
     pub dy2: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
+    #[serde(rename = "step")]
     pub step: Val,
+    #[serde(rename = "steptime")]
     pub steptime: Val
 }
 
@@ -1338,15 +2248,25 @@ named!(pub brg_stretch_blit_effect<&[u8], BRGStretchBlitEffect, CustomError<&[u8
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum BufferRegionGrpCmd {
+    #[serde(rename = "ClearRect")]
     ClearRect(BRGRectColor), // 0x02
+    #[serde(rename = "DrawRectLine")]
     DrawRectLine(BRGRectColor), // 0x04
+    #[serde(rename = "InvertColor")]
     InvertColor(BRGRect), // 0x07
+    #[serde(rename = "ColorMask")]
     ColorMask(BRGRectColor), // 0x10
+    #[serde(rename = "FadeOutColor")]
     FadeOutColor(BRGRect), // 0x11
+    #[serde(rename = "FadeOutColor2")]
     FadeOutColor2(BRGRect), // 0x12
+    #[serde(rename = "FadeOutColor3")]
     FadeOutColor3(BRGFadeOutColor), // 0x15
+    #[serde(rename = "MakeMonoImage")]
     MakeMonoImage(BRGRect), // 0x20
+    #[serde(rename = "StretchBlit")]
     StretchBlit(BRGStretchBlit), // 0x30
+    #[serde(rename = "StretchBlitEffect")]
     StretchBlitEffect(BRGStretchBlitEffect), // 0x32
 }
 
@@ -1367,11 +2287,17 @@ named!(pub buffer_region_grp_cmd<&[u8], BufferRegionGrpCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGCopySamePos {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "flag")]
     pub flag: Val,
 }
 
@@ -1396,14 +2322,23 @@ named!(pub bg_copy_same_pos<&[u8], BGCopySamePos, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGCopyNewPos {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
+    #[serde(rename = "flag")]
     pub flag: Option<Val>
 }
 
@@ -1459,16 +2394,27 @@ named!(pub bg_copy_new_pos_mask<&[u8], BGCopyNewPos, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGCopyColor {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
+    #[serde(rename = "r")]
     pub r: Val,
+    #[serde(rename = "g")]
     pub g: Val,
+    #[serde(rename = "b")]
     pub b: Val
 }
 
@@ -1503,13 +2449,21 @@ named!(pub bg_copy_color<&[u8], BGCopyColor, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGSwap {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
 }
 
@@ -1538,14 +2492,23 @@ named!(pub bg_swap<&[u8], BGSwap, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGCopyWithMask {
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
+    #[serde(rename = "flag")]
     pub flag: Val
 }
 
@@ -1576,8 +2539,11 @@ named!(pub bg_copy_with_mask<&[u8], BGCopyWithMask, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGCopyWholeScreen {
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
+    #[serde(rename = "flag")]
     pub flag: Option<Val>
 }
 
@@ -1609,20 +2575,35 @@ named!(pub bg_copy_whole_screen_mask<&[u8], BGCopyWholeScreen, CustomError<&[u8]
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGDisplayStrings {
+    #[serde(rename = "n")]
     pub n: Val,
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcdx")]
     pub srcdx: Val,
+    #[serde(rename = "srcdy")]
     pub srcdy: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dstx2")]
     pub dstx2: Val,
+    #[serde(rename = "dsty2")]
     pub dsty2: Val,
+    #[serde(rename = "count")]
     pub count: Val,
+    #[serde(rename = "zero")]
     pub zero: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
 }
 
@@ -1665,21 +2646,37 @@ named!(pub bg_display_strings<&[u8], BGDisplayStrings, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGDisplayStringsMask {
+    #[serde(rename = "n")]
     pub n: Val,
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcdx")]
     pub srcdx: Val,
+    #[serde(rename = "srcdy")]
     pub srcdy: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dstx2")]
     pub dstx2: Val,
+    #[serde(rename = "dsty2")]
     pub dsty2: Val,
+    #[serde(rename = "count")]
     pub count: Val,
+    #[serde(rename = "zero")]
     pub zero: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
+    #[serde(rename = "flag")]
     pub flag: Val,
 }
 
@@ -1724,23 +2721,41 @@ named!(pub bg_display_strings_mask<&[u8], BGDisplayStringsMask, CustomError<&[u8
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct BGDisplayStringsColor {
+    #[serde(rename = "n")]
     pub n: Val,
+    #[serde(rename = "srcx1")]
     pub srcx1: Val,
+    #[serde(rename = "srcy1")]
     pub srcy1: Val,
+    #[serde(rename = "srcx2")]
     pub srcx2: Val,
+    #[serde(rename = "srcy2")]
     pub srcy2: Val,
+    #[serde(rename = "srcdx")]
     pub srcdx: Val,
+    #[serde(rename = "srcdy")]
     pub srcdy: Val,
+    #[serde(rename = "srcpdt")]
     pub srcpdt: Val,
+    #[serde(rename = "dstx1")]
     pub dstx1: Val,
+    #[serde(rename = "dsty1")]
     pub dsty1: Val,
+    #[serde(rename = "dstx2")]
     pub dstx2: Val,
+    #[serde(rename = "dsty2")]
     pub dsty2: Val,
+    #[serde(rename = "count")]
     pub count: Val,
+    #[serde(rename = "zero")]
     pub zero: Val,
+    #[serde(rename = "dstpdt")]
     pub dstpdt: Val,
+    #[serde(rename = "r")]
     pub r: Val,
+    #[serde(rename = "g")]
     pub g: Val,
+    #[serde(rename = "b")]
     pub b: Val
 }
 
@@ -1789,16 +2804,27 @@ named!(pub bg_display_strings_color<&[u8], BGDisplayStringsColor, CustomError<&[
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum BufferGrpCmd {
+    #[serde(rename = "CopySamePos")]
     CopySamePos(BGCopySamePos), // 0x00
+    #[serde(rename = "CopyNewPos")]
     CopyNewPos(BGCopyNewPos), // 0x01
+    #[serde(rename = "CopyNewPosMask")]
     CopyNewPosMask(BGCopyNewPos), //0x02
+    #[serde(rename = "CopyColor")]
     CopyColor(BGCopyColor), // 0x03
+    #[serde(rename = "Swap")]
     Swap(BGSwap), // 0x05
+    #[serde(rename = "CopyWithMask")]
     CopyWithMask(BGCopyWithMask), // 0x08
+    #[serde(rename = "CopyWholeScreen")]
     CopyWholeScreen(BGCopyWholeScreen), // 0x11
+    #[serde(rename = "CopyWholeScreenMask")]
     CopyWholeScreenMask(BGCopyWholeScreen), // 0x12
+    #[serde(rename = "DisplayStrings")]
     DisplayStrings(BGDisplayStrings), // 0x20
+    #[serde(rename = "DisplayStringsMask")]
     DisplayStringsMask(BGDisplayStringsMask), // 0x21
+    #[serde(rename = "DisplayStringsColor")]
     DisplayStringsColor(BGDisplayStringsColor), // 0x22
 }
 
@@ -1820,7 +2846,9 @@ named!(pub buffer_grp_cmd<&[u8], BufferGrpCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum FlashGrpCmd {
+    #[serde(rename = "FillColor")]
     FillColor(Val, Val, Val, Val), // 0x01
+    #[serde(rename = "FlashScreen")]
     FlashScreen(Val, Val, Val, Val, Val), // 0x10
 }
 
@@ -1846,7 +2874,9 @@ named!(pub flash_grp_cmd<&[u8], FlashGrpCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct MultiPdtEntry {
+    #[serde(rename = "text")]
     pub text: SceneText,
+    #[serde(rename = "data")]
     pub data: Val
 }
 
@@ -1863,11 +2893,17 @@ named!(pub multi_pdt_entry<&[u8], MultiPdtEntry, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum MultiPdtCmd {
+    #[serde(rename = "Slideshow")]
     Slideshow(Val, Val, Vec<MultiPdtEntry>), // 0x03
+    #[serde(rename = "SlideshowLoop")]
     SlideshowLoop(Val, Val, Vec<MultiPdtEntry>), // 0x04
+    #[serde(rename = "StopSlideshowLoop")]
     StopSlideshowLoop, // 0x05
+    #[serde(rename = "Scroll")]
     Scroll(u8, Val, Val, Val, Vec<MultiPdtEntry>), // 0x10
+    #[serde(rename = "Scroll2")]
     Scroll2(u8, Val, Val, Val, Vec<MultiPdtEntry>), // 0x20
+    #[serde(rename = "ScrollWithCancel")]
     ScrollWithCancel(u8, Val, Val, Val, Val, Vec<MultiPdtEntry>), // 0x30
 }
 
@@ -1877,14 +2913,14 @@ named!(pub multi_pdt_cmd<&[u8], MultiPdtCmd, CustomError<&[u8]>>,
                    count: le_u8 >>
                        pos: scene_value >>
                        wait: scene_value >>
-                       entries: count!(multi_pdt_entry, count as usize) >>
+                       entries: call!(counted, multi_pdt_entry, count as usize, 2, "multi PDT entries") >>
                        (MultiPdtCmd::Slideshow(pos, wait, entries))
                ) |
                0x04 => do_parse!(
                    count: le_u8 >>
                        pos: scene_value >>
                        wait: scene_value >>
-                       entries: count!(multi_pdt_entry, count as usize) >>
+                       entries: call!(counted, multi_pdt_entry, count as usize, 2, "multi PDT entries") >>
                        (MultiPdtCmd::SlideshowLoop(pos, wait, entries))
                ) |
                0x05 => value!(MultiPdtCmd::StopSlideshowLoop) |
@@ -1894,7 +2930,7 @@ named!(pub multi_pdt_cmd<&[u8], MultiPdtCmd, CustomError<&[u8]>>,
                        pos: scene_value >>
                        wait: scene_value >>
                        pixel: scene_value >>
-                       entries: count!(multi_pdt_entry, count as usize) >>
+                       entries: call!(counted, multi_pdt_entry, count as usize, 2, "multi PDT entries") >>
                        (MultiPdtCmd::Scroll(poscmd, pos, wait, pixel, entries))
                ) |
                0x20 => do_parse!(
@@ -1903,7 +2939,7 @@ named!(pub multi_pdt_cmd<&[u8], MultiPdtCmd, CustomError<&[u8]>>,
                        pos: scene_value >>
                        wait: scene_value >>
                        pixel: scene_value >>
-                       entries: count!(multi_pdt_entry, count as usize) >>
+                       entries: call!(counted, multi_pdt_entry, count as usize, 2, "multi PDT entries") >>
                        (MultiPdtCmd::Scroll2(poscmd, pos, wait, pixel, entries))
                ) |
                0x30 => do_parse!(
@@ -1913,22 +2949,32 @@ named!(pub multi_pdt_cmd<&[u8], MultiPdtCmd, CustomError<&[u8]>>,
                        wait: scene_value >>
                        pixel: scene_value >>
                        cancel_index: scene_value >>
-                       entries: count!(multi_pdt_entry, count as usize) >>
+                       entries: call!(counted, multi_pdt_entry, count as usize, 2, "multi PDT entries") >>
                        (MultiPdtCmd::ScrollWithCancel(poscmd, pos, wait, pixel, cancel_index, entries))
                ))
 );
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum SystemCmd {
+    #[serde(rename = "LoadGame")]
     LoadGame(Val), // 0x02
+    #[serde(rename = "SaveGame")]
     SaveGame(Val), // 0x03
+    #[serde(rename = "SetTitle")]
     SetTitle(SceneFormattedText), // 0x04
+    #[serde(rename = "MakePopup")]
     MakePopup, // 0x05
+    #[serde(rename = "GameEnd")]
     GameEnd, // 0x20
+    #[serde(rename = "GetSaveTitle")]
     GetSaveTitle(Val, Val), // 0x30
+    #[serde(rename = "CheckSaveData")]
     CheckSaveData(Val, Val), // 0x31
+    #[serde(rename = "Unknown1")]
     Unknown1(Val, Val), // 0x35
+    #[serde(rename = "Unknown2")]
     Unknown2(Val, Val), // 0x36
+    #[serde(rename = "Unknown3")]
     Unknown3(Val, Val), // 0x37
 }
 
@@ -1949,7 +2995,9 @@ named!(pub system_cmd<&[u8], SystemCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct NameInputItem {
+    #[serde(rename = "idx")]
     pub idx: Val,
+    #[serde(rename = "text")]
     pub text: SceneFormattedText
 }
 
@@ -1966,17 +3014,29 @@ named!(pub name_input_item<&[u8], NameInputItem, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum NameCmd {
+    #[serde(rename = "InputBox")]
     InputBox(Val, Val, Val, Val, Val, Val, Val, Val, Val, Val), // 0x01
+    #[serde(rename = "InputBoxFinish")]
     InputBoxFinish(Val), // 0x02
+    #[serde(rename = "InputBoxStart")]
     InputBoxStart(Val), // 0x03
+    #[serde(rename = "InputBoxClose")]
     InputBoxClose(Val), // 0x04
+    #[serde(rename = "GetName")]
     GetName(Val, Val), // 0x10
+    #[serde(rename = "SetName")]
     SetName(Val, Val), // 0x11
+    #[serde(rename = "GetName2")]
     GetName2(Val, Val), // 0x12
+    #[serde(rename = "NameInputDialog")]
     NameInputDialog(Val), // 0x20
+    #[serde(rename = "Unknown1")]
     Unknown1(Val, SceneText, Val,  Val, Val, Val, Val, Val, Val, Val, Val), // 0x21
+    #[serde(rename = "NameInputDialogMulti")]
     NameInputDialogMulti(Vec<NameInputItem>), // 0x24
+    #[serde(rename = "Unknown2")]
     Unknown2, // 0x30
+    #[serde(rename = "Unknown3")]
     Unknown3, // 0x31
 }
 
@@ -2042,7 +3102,7 @@ named!(pub name_cmd<&[u8], NameCmd, CustomError<&[u8]>>,
                ) |
                0x24 => do_parse!(
                    count: le_u8 >>
-                   items: count!(name_input_item, count as usize) >>
+                   items: call!(counted, name_input_item, count as usize, 2, "name input items") >>
                        (NameCmd::NameInputDialogMulti(items))
                ) |
                0x30 => value!(NameCmd::Unknown2) |
@@ -2052,13 +3112,21 @@ named!(pub name_cmd<&[u8], NameCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum AreaBufferCmd {
+    #[serde(rename = "ReadCurArd")]
     ReadCurArd(SceneText, SceneText), // 0x02
+    #[serde(rename = "Init")]
     Init, // 0x03
+    #[serde(rename = "GetClickedArea")]
     GetClickedArea(Val, Val), // 0x04
+    #[serde(rename = "GetClickedArea2")]
     GetClickedArea2(Val, Val), // 0x05
+    #[serde(rename = "DisableArea")]
     DisableArea(Val), // 0x10
+    #[serde(rename = "EnableArea")]
     EnableArea(Val), // 0x11
+    #[serde(rename = "GetArea")]
     GetArea(Val, Val, Val), // 0x15
+    #[serde(rename = "AssignArea")]
     AssignArea(Val, Val), // 0x20
 }
 
@@ -2092,7 +3160,7 @@ named!(pub area_buffer_cmd<&[u8], AreaBufferCmd, CustomError<&[u8]>>,
                    x: scene_value >>
                    y: scene_value >>
                    area: scene_value >>
-                   (AreaBufferCmd::GetArea(area, x, y))
+                   (AreaBufferCmd::GetArea(x, y, area))
                ) |
                0x20 => do_parse!(
                    area_from: scene_value >>
@@ -2104,10 +3172,15 @@ named!(pub area_buffer_cmd<&[u8], AreaBufferCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum MouseCtrlCmd {
+    #[serde(rename = "WaitForClick")]
     WaitForClick, // 0x01
+    #[serde(rename = "SetPos")]
     SetPos(Val, Val, Val), // 0x02
+    #[serde(rename = "FlushClickData")]
     FlushClickData, // 0x03
+    #[serde(rename = "CursorOff")]
     CursorOff, // 0x20
+    #[serde(rename = "CursorOn")]
     CursorOn // 0x21
 }
 
@@ -2128,17 +3201,29 @@ named!(pub mouse_ctrl_cmd<&[u8], MouseCtrlCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum VolumeCmd {
+    #[serde(rename = "GetBgmVolume")]
     GetBgmVolume(Val), // 0x01
+    #[serde(rename = "GetWavVolume")]
     GetWavVolume(Val), // 0x02
+    #[serde(rename = "GetKoeVolume")]
     GetKoeVolume(Val), // 0x03
+    #[serde(rename = "GetSeVolume")]
     GetSeVolume(Val), // 0x04
+    #[serde(rename = "SetBgmVolume")]
     SetBgmVolume(Val), // 0x11
+    #[serde(rename = "SetWavVolume")]
     SetWavVolume(Val), // 0x12
+    #[serde(rename = "SetKoeVolume")]
     SetKoeVolume(Val), // 0x13
+    #[serde(rename = "SetSeVolume")]
     SetSeVolume(Val), // 0x14
+    #[serde(rename = "MuteBgm")]
     MuteBgm(Val), // 0x21
+    #[serde(rename = "MuteWav")]
     MuteWav(Val), // 0x22
+    #[serde(rename = "MuteKoe")]
     MuteKoe(Val), // 0x23
+    #[serde(rename = "MuteSe")]
     MuteSe(Val), // 0x24
 }
 
@@ -2161,10 +3246,15 @@ named!(pub set_vol_cmd<&[u8], VolumeCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum NovelModeCmd {
+    #[serde(rename = "SetEnabled")]
     SetEnabled(Val), // 0x01
+    #[serde(rename = "Unknown1")]
     Unknown1(Val), // 0x02
+    #[serde(rename = "Unknown2")]
     Unknown2, // 0x03
+    #[serde(rename = "Unknown3")]
     Unknown3, // 0x04
+    #[serde(rename = "Unknown4")]
     Unknown4, // 0x05
 }
 
@@ -2180,13 +3270,21 @@ named!(pub novel_mode_cmd<&[u8], NovelModeCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum WindowVarCmd {
+    #[serde(rename = "GetBgFlagColor")]
     GetBgFlagColor(Val, Val, Val, Val), // 0x01
+    #[serde(rename = "SetBgFlagColor")]
     SetBgFlagColor(Val, Val, Val, Val), // 0x02
+    #[serde(rename = "GetWindowMove")]
     GetWindowMove(Val), // 0x03
+    #[serde(rename = "SetWindowMove")]
     SetWindowMove(Val), // 0x04
+    #[serde(rename = "GetWindowClearBox")]
     GetWindowClearBox(Val), // 0x05
+    #[serde(rename = "SetWindowClearBox")]
     SetWindowClearBox(Val), // 0x06
+    #[serde(rename = "GetWindowWaku")]
     GetWindowWaku(Val), // 0x10
+    #[serde(rename = "SetWindowWaku")]
     SetWindowWaku(Val), // 0x11
 }
 
@@ -2217,15 +3315,25 @@ named!(pub window_var_cmd<&[u8], WindowVarCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum MessageWinCmd {
+    #[serde(rename = "GetWindowMsgPos")]
     GetWindowMsgPos(Val, Val), // 0x01
+    #[serde(rename = "GetWindowComPos")]
     GetWindowComPos(Val, Val), // 0x02
+    #[serde(rename = "GetWindowSysPos")]
     GetWindowSysPos(Val, Val), // 0x03
+    #[serde(rename = "GetWindowSubPos")]
     GetWindowSubPos(Val, Val), // 0x04
+    #[serde(rename = "GetWindowGrpPos")]
     GetWindowGrpPos(Val, Val), // 0x05
+    #[serde(rename = "SetWindowMsgPos")]
     SetWindowMsgPos(Val, Val), // 0x11
+    #[serde(rename = "SetWindowComPos")]
     SetWindowComPos(Val, Val), // 0x12
+    #[serde(rename = "SetWindowSysPos")]
     SetWindowSysPos(Val, Val), // 0x13
+    #[serde(rename = "SetWindowSubPos")]
     SetWindowSubPos(Val, Val), // 0x14
+    #[serde(rename = "SetWindowGrpPos")]
     SetWindowGrpPos(Val, Val), // 0x15
 }
 
@@ -2246,44 +3354,83 @@ named!(pub message_win_cmd<&[u8], MessageWinCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum SystemVarCmd {
+    #[serde(rename = "GetMessageSize")]
     GetMessageSize(Val, Val), // 0x01
+    #[serde(rename = "SetMessageSize")]
     SetMessageSize(Val, Val), // 0x02
+    #[serde(rename = "GetMsgMojiSize")]
     GetMsgMojiSize(Val, Val), // 0x05
+    #[serde(rename = "SetMsgMojiSize")]
     SetMsgMojiSize(Val, Val), // 0x06
+    #[serde(rename = "GetMojiColor")]
     GetMojiColor(Val), // 0x10
+    #[serde(rename = "SetMojiColor")]
     SetMojiColor(Val), // 0x11
+    #[serde(rename = "GetMsgCancel")]
     GetMsgCancel(Val), // 0x12
+    #[serde(rename = "SetMsgCancel")]
     SetMsgCancel(Val), // 0x13
+    #[serde(rename = "GetMojiKage")]
     GetMojiKage(Val), // 0x16
+    #[serde(rename = "SetMojiKage")]
     SetMojiKage(Val), // 0x17
+    #[serde(rename = "GetKageColor")]
     GetKageColor(Val), // 0x18
+    #[serde(rename = "SetKageColor")]
     SetKageColor(Val), // 0x19
+    #[serde(rename = "GetSelCancel")]
     GetSelCancel(Val), // 0x1a
+    #[serde(rename = "SetSelCancel")]
     SetSelCancel(Val), // 0x1b
+    #[serde(rename = "GetCtrlKey")]
     GetCtrlKey(Val), // 0x1c
+    #[serde(rename = "SetCtrlKey")]
     SetCtrlKey(Val), // 0x1d
+    #[serde(rename = "GetSaveStart")]
     GetSaveStart(Val), // 0x1e
+    #[serde(rename = "SetSaveStart")]
     SetSaveStart(Val), // 0x1f
+    #[serde(rename = "GetDisableNvlTextFlag")]
     GetDisableNvlTextFlag(Val), // 0x20
+    #[serde(rename = "SetDisableNvlTextFlag")]
     SetDisableNvlTextFlag(Val), // 0x21
+    #[serde(rename = "GetFadeTime")]
     GetFadeTime(Val), // 0x22
+    #[serde(rename = "SetFadeTime")]
     SetFadeTime(Val), // 0x23
+    #[serde(rename = "GetCursorMono")]
     GetCursorMono(Val), // 0x24
+    #[serde(rename = "SetCursorMono")]
     SetCursorMono(Val), // 0x25
+    #[serde(rename = "GetCopyWindSw")]
     GetCopyWindSw(Val), // 0x26
+    #[serde(rename = "SetCopyWindSw")]
     SetCopyWindSw(Val), // 0x27
+    #[serde(rename = "GetMsgSpeed")]
     GetMsgSpeed(Val), // 0x28
+    #[serde(rename = "SetMsgSpeed")]
     SetMsgSpeed(Val), // 0x29
+    #[serde(rename = "GetMsgSpeed2")]
     GetMsgSpeed2(Val), // 0x2a
+    #[serde(rename = "SetMsgSpeed2")]
     SetMsgSpeed2(Val), // 0x2b
+    #[serde(rename = "GetReturnKeyWait")]
     GetReturnKeyWait(Val), // 0x2c
+    #[serde(rename = "SetReturnKeyWait")]
     SetReturnKeyWait(Val), // 0x2d
+    #[serde(rename = "GetKoeTextType")]
     GetKoeTextType(Val), // 0x2e
+    #[serde(rename = "SetKoeTextType")]
     SetKoeTextType(Val), // 0x2f
+    #[serde(rename = "GetGameSpeckInit")]
     GetGameSpeckInit(Val), // 0x30
+    #[serde(rename = "SetCursorPosition")]
     SetCursorPosition(Val, Val), // 0x31
+    #[serde(rename = "SetDisableKeyMouseFlag")]
     SetDisableKeyMouseFlag(Val), // 0x32
+    #[serde(rename = "GetGameSpeckInit2")]
     GetGameSpeckInit2(Val), // 0x33
+    #[serde(rename = "SetGameSpeckInit")]
     SetGameSpeckInit(Val), // 0x34
 }
 
@@ -2333,9 +3480,13 @@ named!(pub system_var_cmd<&[u8], SystemVarCmd, CustomError<&[u8]>>,
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum PopupMenuCmd {
+    #[serde(rename = "GetMenuDisabled")]
     GetMenuDisabled(Val), // 0x01
+    #[serde(rename = "SetMenuDisabled")]
     SetMenuDisabled(Val), // 0x02
+    #[serde(rename = "GetItemDisabled")]
     GetItemDisabled(Val, Val), // 0x03
+    #[serde(rename = "SetItemDisabled")]
     SetItemDisabled(Val, Val), // 0x04
 }
 
@@ -2348,98 +3499,211 @@ named!(pub popup_menu_cmd<&[u8], PopupMenuCmd, CustomError<&[u8]>>,
        )
 );
 
+/// Variants are renamed to their current names so that the names written
+/// in disassemblies stay the same if a variant is renamed here. The same
+/// goes for the variants and fields of every other serialized type in this
+/// module.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum Opcode {
+    #[serde(rename = "WaitMouse")]
     WaitMouse, // 0x01
+    #[serde(rename = "Newline")]
     Newline, // 0x02
+    #[serde(rename = "WaitMouseText")]
     WaitMouseText, // 0x03
+    #[serde(rename = "TextWin")]
     TextWin(TextWinCmd), // 0x04
+    #[serde(rename = "Op0x05")]
     Op0x05,
+    #[serde(rename = "Op0x06")]
     Op0x06,
+    #[serde(rename = "Op0x08")]
     Op0x08,
+    #[serde(rename = "Graphics")]
     Graphics(GrpCmd), // 0x0b
+    #[serde(rename = "Op0x0c")]
     Op0x0c,
+    #[serde(rename = "Sound")]
     Sound(SndCmd), // 0x0e
+    #[serde(rename = "DrawValText")]
     DrawValText(FormattedTextCmd), // 0x10
+    #[serde(rename = "Fade")]
     Fade(FadeCmd), // 0x13
+    #[serde(rename = "Condition")]
     Condition(Vec<Condition>, Pos), // 0x15
+    #[serde(rename = "JumpToScene")]
     JumpToScene(JumpToSceneCmd), // 0x16
+    #[serde(rename = "ScreenShake")]
     ScreenShake(ScreenShakeCmd), // 0x17
+    #[serde(rename = "Op0x18")]
     Op0x18,
+    #[serde(rename = "Wait")]
     Wait(WaitCmd), // 0x19
+    #[serde(rename = "Op0x1a")]
     Op0x1a,
+    #[serde(rename = "Call")]
     Call(Pos), // 0x1b
+    #[serde(rename = "Jump")]
     Jump(Pos), // 0x1c
+    #[serde(rename = "TableCall")]
     TableCall(Val, Vec<Pos>), // 0x1d
+    #[serde(rename = "TableJump")]
     TableJump(Val, Vec<Pos>), // 0x1e
+    #[serde(rename = "Return")]
     Return(RetCmd), // 0x20
+    #[serde(rename = "Unknown0x22")]
     Unknown0x22, // 0x22
+    #[serde(rename = "Unknown0x23")]
     Unknown0x23, // 0x23
+    #[serde(rename = "Unknown0x24")]
     Unknown0x24, // 0x24
+    #[serde(rename = "Unknown0x25")]
     Unknown0x25, // 0x25
+    #[serde(rename = "Unknown0x26")]
     Unknown0x26, // 0x26
+    #[serde(rename = "Unknown0x27")]
     Unknown0x27, // 0x27
+    #[serde(rename = "Unknown0x28")]
     Unknown0x28, // 0x28
+    #[serde(rename = "Unknown0x29")]
     Unknown0x29, // 0x29
+    #[serde(rename = "Op0x2c")]
     Op0x2c,
+    #[serde(rename = "Op0x2d")]
     Op0x2d,
+    #[serde(rename = "ScenarioMenu")]
     ScenarioMenu(ScenarioMenuCmd), // 0x2e
-    Op0x2f,
+    #[serde(rename = "ScenarioMenu2")]
+    ScenarioMenu2(ScenarioMenuCmd), // 0x2f, same commands as 0x2e
+    #[serde(rename = "Op0x30")]
     Op0x30,
+    #[serde(rename = "TextRank")]
     TextRank(TextRankCmd), // 0x31
+    #[serde(rename = "SetFlag")]
     SetFlag(Val, Val), // 0x37
+    #[serde(rename = "CopyFlag")]
     CopyFlag(Val, Val), // 0x39
+    #[serde(rename = "SetValLiteral")]
     SetValLiteral(Val, Val), // 0x3b
+    #[serde(rename = "AddVal")]
     AddVal(Val, Val), // 0x3c
+    #[serde(rename = "SubVal")]
     SubVal(Val, Val), // 0x3d
+    #[serde(rename = "MulVal")]
     MulVal(Val, Val), // 0x3e
+    #[serde(rename = "DivVal")]
     DivVal(Val, Val), // 0x3f
+    #[serde(rename = "ModVal")]
     ModVal(Val, Val), // 0x40
+    #[serde(rename = "AndVal")]
     AndVal(Val, Val), // 0x41
+    #[serde(rename = "OrVal")]
     OrVal(Val, Val), // 0x42
+    #[serde(rename = "XorVal")]
     XorVal(Val, Val), // 0x43
+    #[serde(rename = "SetVal")]
     SetVal(Val, Val), // 0x49
+    #[serde(rename = "AddValSelf")]
     AddValSelf(Val, Val), // 0x4a
+    #[serde(rename = "SubValSelf")]
     SubValSelf(Val, Val), // 0x4b
+    #[serde(rename = "MulValSelf")]
     MulValSelf(Val, Val), // 0x4c
+    #[serde(rename = "DivValSelf")]
     DivValSelf(Val, Val), // 0x4d
+    #[serde(rename = "ModValSelf")]
     ModValSelf(Val, Val), // 0x4e
+    #[serde(rename = "AndValSelf")]
     AndValSelf(Val, Val), // 0x4f
+    #[serde(rename = "OrValSelf")]
     OrValSelf(Val, Val), // 0x50
+    #[serde(rename = "XorValSelf")]
     XorValSelf(Val, Val), // 0x51
+    #[serde(rename = "SetFlagRandom")]
     SetFlagRandom(Val), // 0x56
+    #[serde(rename = "SetValRandom")]
     SetValRandom(Val, Val), // 0x57
+    #[serde(rename = "Choice")]
     Choice(ChoiceCmd), // 0x58
+    #[serde(rename = "String")]
     String(StringCmd), // 0x59
+    #[serde(rename = "Op0x5b")]
     Op0x5b,
+    #[serde(rename = "SetMulti")]
     SetMulti(SetMultiCmd), // 0x5c
+    #[serde(rename = "Op0x5d")]
     Op0x5d,
+    #[serde(rename = "Op0x5e")]
     Op0x5e,
+    #[serde(rename = "Op0x5f")]
     Op0x5f,
+    #[serde(rename = "System")]
     System(SystemCmd), // 0x60
+    #[serde(rename = "Name")]
     Name(NameCmd), // 0x61
+    #[serde(rename = "Op0x63")]
     Op0x63,
+    #[serde(rename = "BufferRegion")]
     BufferRegion(BufferRegionGrpCmd), // 0x64
+    #[serde(rename = "Unknown0x65")]
     Unknown0x65, // 0x65
+    #[serde(rename = "Buffer")]
     Buffer(BufferGrpCmd), // 0x67
+    #[serde(rename = "Flash")]
     Flash(FlashGrpCmd), // 0x68
+    #[serde(rename = "Op0x69")]
     Op0x69,
+    #[serde(rename = "MultiPdt")]
     MultiPdt(MultiPdtCmd), // 0x6a
+    #[serde(rename = "Op0x66")]
     Op0x66,
+    #[serde(rename = "AreaBuffer")]
     AreaBuffer(AreaBufferCmd), // 0x6c
+    #[serde(rename = "MouseCtrl")]
     MouseCtrl(MouseCtrlCmd), // 0x6d
+    #[serde(rename = "Op0x6e")]
     Op0x6e,
+    #[serde(rename = "Op0x6f")]
     Op0x6f,
+    #[serde(rename = "WindowVar")]
     WindowVar(WindowVarCmd), // 0x70
+    #[serde(rename = "MessageWin")]
     MessageWin(MessageWinCmd), // 0x72
+    #[serde(rename = "SystemVar")]
     SystemVar(SystemVarCmd), // 0x73
+    #[serde(rename = "PopupMenu")]
     PopupMenu(PopupMenuCmd), // 0x74
+    #[serde(rename = "Volume")]
     Volume(VolumeCmd), // 0x75
+    #[serde(rename = "NovelMode")]
     NovelMode(NovelModeCmd), // 0x76
+    #[serde(rename = "Op0x7f")]
     Op0x7f,
+    #[serde(rename = "Unknown0xea")]
     Unknown0xea(Val), // 0xea
+    #[serde(rename = "TextHankaku")]
     TextHankaku(Option<u32>, SceneText), // 0xfe
+    #[serde(rename = "TextZenkaku")]
     TextZenkaku(Option<u32>, SceneText), // 0xff
+    #[serde(rename = "UnknownRaw")]
+    UnknownRaw(u8, Vec<u8>), // Unparseable opcode byte and the bytes skipped after it
+}
+
+impl Opcode {
+    /// Whether this is one of the `Op0xNN` or `UnknownNN` variants standing
+    /// in for an opcode that isn't understood yet, or raw bytes kept by the
+    /// lenient parser.
+    pub fn is_placeholder(&self) -> bool {
+        matches!(self,
+            Opcode::Op0x05 | Opcode::Op0x06 | Opcode::Op0x08 | Opcode::Op0x0c |
+            Opcode::Op0x18 | Opcode::Op0x1a | Opcode::Op0x2c | Opcode::Op0x2d |
+            Opcode::Op0x30 | Opcode::Op0x5b | Opcode::Op0x5d |
+            Opcode::Op0x5e | Opcode::Op0x5f | Opcode::Op0x63 | Opcode::Op0x66 |
+            Opcode::Op0x69 | Opcode::Op0x6e | Opcode::Op0x6f | Opcode::Op0x7f |
+            Opcode::Unknown0x22 | Opcode::Unknown0x23 | Opcode::Unknown0x24 | Opcode::Unknown0x25 |
+            Opcode::Unknown0x26 | Opcode::Unknown0x27 | Opcode::Unknown0x28 | Opcode::Unknown0x29 |
+            Opcode::Unknown0x65 | Opcode::Unknown0xea(_) | Opcode::UnknownRaw(..))
+    }
 }
 
 named!(pub opcode_0x01<&[u8], Opcode, CustomError<&[u8]>>,
@@ -2536,7 +3800,7 @@ named!(pub opcode_0x1d<&[u8], Opcode, CustomError<&[u8]>>,
        do_parse!(
            a: le_u8 >>
            b: scene_value >>
-           c: count!(scene_pos, a as usize) >>
+           c: call!(counted, scene_pos, a as usize, 4, "table positions") >>
            (Opcode::TableCall(b, c))
        )
 );
@@ -2545,7 +3809,7 @@ named!(pub opcode_0x1e<&[u8], Opcode, CustomError<&[u8]>>,
        do_parse!(
            a: le_u8 >>
            b: scene_value >>
-           c: count!(scene_pos, a as usize) >>
+           c: call!(counted, scene_pos, a as usize, 4, "table positions") >>
            (Opcode::TableJump(b, c))
        )
 );
@@ -2599,7 +3863,7 @@ named!(pub opcode_0x2e<&[u8], Opcode, CustomError<&[u8]>>,
 named!(pub opcode_0x2f<&[u8], Opcode, CustomError<&[u8]>>,
        do_parse!(
            a: scenario_menu_cmd >>
-               (Opcode::ScenarioMenu(a))
+               (Opcode::ScenarioMenu2(a))
        )
 );
 
@@ -2937,20 +4201,20 @@ named!(pub opcode<&[u8], Opcode, CustomError<&[u8]>>,
                0x02 => call!(opcode_0x02) |
                0x03 => call!(opcode_0x03) |
                0x04 => call!(opcode_0x04) |
-               // 0x05 => value!(Opcode::Op0x05) |
-               // 0x06 => value!(Opcode::Op0x06) |
-               // 0x08 => value!(Opcode::Op0x08) |
+               0x05 => value!(Opcode::Op0x05) |
+               0x06 => value!(Opcode::Op0x06) |
+               0x08 => value!(Opcode::Op0x08) |
                0x0b => call!(opcode_0x0b) |
-               // 0x0c => value!(Opcode::Op0x0c) |
+               0x0c => value!(Opcode::Op0x0c) |
                0x0e => call!(opcode_0x0e) |
                0x10 => call!(opcode_0x10) |
                0x13 => call!(opcode_0x13) |
                0x15 => call!(opcode_0x15) |
                0x16 => call!(opcode_0x16) |
                0x17 => call!(opcode_0x17) |
-               // 0x18 => value!(Opcode::Op0x18) |
+               0x18 => value!(Opcode::Op0x18) |
                0x19 => call!(opcode_0x19) |
-               // 0x1a => value!(Opcode::Op0x1a) |
+               0x1a => value!(Opcode::Op0x1a) |
                0x1b => call!(opcode_0x1b) |
                0x1c => call!(opcode_0x1c) |
                0x1d => call!(opcode_0x1d) |
@@ -2964,11 +4228,11 @@ named!(pub opcode<&[u8], Opcode, CustomError<&[u8]>>,
                0x27 => call!(opcode_0x27) |
                0x28 => call!(opcode_0x28) |
                0x29 => call!(opcode_0x29) |
-               // 0x2c => value!(Opcode::Op0x2c) |
-               // 0x2d => value!(Opcode::Op0x2d) |
+               0x2c => value!(Opcode::Op0x2c) |
+               0x2d => value!(Opcode::Op0x2d) |
                0x2e => call!(opcode_0x2e) |
                0x2f => call!(opcode_0x2f) |
-               // 0x30 => value!(Opcode::Op0x30) |
+               0x30 => value!(Opcode::Op0x30) |
                0x31 => call!(opcode_0x31) |
                0x37 => call!(opcode_0x37) |
                0x39 => call!(opcode_0x39) |
@@ -2994,59 +4258,993 @@ named!(pub opcode<&[u8], Opcode, CustomError<&[u8]>>,
                0x57 => call!(opcode_0x57) |
                0x58 => call!(opcode_0x58) |
                0x59 => call!(opcode_0x59) |
-               // 0x5b => value!(Opcode::Op0x5b) |
+               0x5b => value!(Opcode::Op0x5b) |
                0x5c => call!(opcode_0x5c) |
-               // 0x5d => value!(Opcode::Op0x5d) |
-               // 0x5e => value!(Opcode::Op0x5e) |
-               // 0x5f => value!(Opcode::Op0x5f) |
+               0x5d => value!(Opcode::Op0x5d) |
+               0x5e => value!(Opcode::Op0x5e) |
+               0x5f => value!(Opcode::Op0x5f) |
                0x60 => call!(opcode_0x60) |
                0x61 => call!(opcode_0x61) |
-               // 0x63 => value!(Opcode::Op0x63) |
+               0x63 => value!(Opcode::Op0x63) |
                0x64 => call!(opcode_0x64) |
-               0x64 => call!(opcode_0x65) |
+               0x65 => call!(opcode_0x65) |
                0x67 => call!(opcode_0x67) |
                0x68 => call!(opcode_0x68) |
-               // 0x69 => value!(Opcode::Op0x69) |
+               0x69 => value!(Opcode::Op0x69) |
                0x6a => call!(opcode_0x6a) |
-               // 0x66 => value!(Opcode::Op0x66) |
+               0x66 => value!(Opcode::Op0x66) |
                0x6c => call!(opcode_0x6c) |
                0x6d => call!(opcode_0x6d) |
-               // 0x6e => value!(Opcode::Op0x6e) |
-               // 0x6f => value!(Opcode::Op0x6f) |
+               0x6e => value!(Opcode::Op0x6e) |
+               0x6f => value!(Opcode::Op0x6f) |
                0x70 => call!(opcode_0x70) |
                0x72 => call!(opcode_0x72) |
                0x73 => call!(opcode_0x73) |
                0x74 => call!(opcode_0x74) |
                0x75 => call!(opcode_0x75) |
                0x76 => call!(opcode_0x76) |
-               // 0x7f => value!(Opcode::Op0x7f) |
+               0x7f => value!(Opcode::Op0x7f) |
                0xea => call!(opcode_0xea) |
                0xfe => call!(opcode_0xfe) |
                0xff => call!(opcode_0xff)
        )
 );
 
-named!(pub avg32_scene<&[u8], AVG32Scene, CustomError<&[u8]>>,
-       do_parse!(
-           header: header >>
-               opcodes: dbg_dmp!(many1!(opcode)) >>
-               dbg_dmp!(tag!("\0")) >>
-               eof!() >>
-               (AVG32Scene {
-                   header: header,
-                   opcodes: opcodes
-               })
-       )
-);
+/// `input` is the whole scene, used to turn `MyErrorAt` positions into
+/// absolute offsets.
+fn describe_error(e: &nom::Err<CustomError<&[u8]>>, input: &[u8]) -> String {
+    match e {
+        nom::Err::Error(CustomError::MyError(msg)) | nom::Err::Failure(CustomError::MyError(msg)) => msg.clone(),
+        nom::Err::Error(CustomError::MyErrorAt(rest, msg)) | nom::Err::Failure(CustomError::MyErrorAt(rest, msg)) =>
+            format!("{} at offset 0x{:x}", msg, rest.as_ptr() as usize - input.as_ptr() as usize),
+        nom::Err::Error(CustomError::Nom(_, kind)) | nom::Err::Failure(CustomError::Nom(_, kind)) => format!("{:?}", kind),
+        nom::Err::Incomplete(_) => String::from("Unexpected end of opcode data")
+    }
+}
+
+/// An opcode that couldn't be parsed in lenient mode.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownOpcode {
+    /// Absolute offset of the opcode byte in the scene.
+    pub offset: usize,
+    pub opcode: u8,
+    /// Number of bytes skipped after the opcode byte before parsing resumed.
+    pub skipped: usize
+}
+
+fn scene_body(input: &[u8], lenient: bool) -> ParseResult<'_, (AVG32Scene, Vec<UnknownOpcode>)> {
+    let (body, header) = header(input)?;
+    let body_start = input.len() - body.len();
+    let max_trailer = config(|c| c.max_trailer) as usize;
+    let max_resync = config(|c| c.max_resync) as usize;
+
+    // The opcodes end at a 0x00 where the next opcode would start, followed
+    // by at most `max_trailer` bytes of anything.
+    let is_end = |inp: &[u8]| inp.first() == Some(&0x00) && inp.len() - 1 <= max_trailer;
+
+    let mut opcodes = Vec::new();
+    let mut val_lengths = BTreeMap::new();
+    let mut unknown = Vec::new();
+    let mut placeholders = Vec::new();
+    let mut inp = body;
+    let max_opcodes = config(|c| c.max_opcodes) as usize;
+    debug!("Parsing {} bytes of opcodes at offset 0x{:x}, {} labels", body.len(), body_start, header.labels.len());
+
+    while !is_end(inp) {
+        let offset = input.len() - inp.len();
+        if inp.is_empty() {
+            return Err(nom::Err::Error(CustomError::MyError(format!("Missing 0x00 terminator at end of scene (offset 0x{:x})", offset))));
+        }
+        if opcodes.len() >= max_opcodes {
+            return Err(nom::Err::Error(CustomError::MyError(format!("Scene has more than the limit of {} opcodes (offset 0x{:x})", max_opcodes, offset))));
+        }
+        let (res, lengths) = recording_val_lengths(|| opcode(inp));
+        match res {
+            Ok((i, op)) => {
+                if lengths.iter().any(Option::is_some) {
+                    val_lengths.insert(opcodes.len(), lengths);
+                }
+                trace!("0x{:x}: {}", offset, crate::stats::variant_name(&op));
+                if op.is_placeholder() {
+                    placeholders.push(format!("{} at offset 0x{:x}", crate::stats::variant_name(&op), offset));
+                }
+                opcodes.push(op);
+                inp = i;
+            },
+            Err(_) if lenient => {
+                // Skip ahead to the next position that parses as an opcode,
+                // or else to the end of the opcodes, looking at most
+                // `max_resync` bytes ahead.
+                let limit = inp.len().min(max_resync + 1);
+                let skipped = (1..limit).find(|&j| opcode(&inp[j..]).is_ok())
+                    .or_else(|| (1..limit).find(|&j| is_end(&inp[j..])))
+                    .unwrap_or(limit);
+                debug!("0x{:x}: unknown opcode 0x{:02x}, skipping {} bytes", offset, inp[0], skipped - 1);
+                unknown.push(UnknownOpcode {
+                    offset,
+                    opcode: inp[0],
+                    skipped: skipped - 1
+                });
+                opcodes.push(Opcode::UnknownRaw(inp[0], inp[1..skipped].to_vec()));
+                inp = &inp[skipped..];
+            },
+            Err(_) if inp[0] == 0x00 => {
+                return Err(nom::Err::Error(CustomError::MyError(format!("0x00 terminator at offset 0x{:x} is followed by {} bytes, more than the limit of {}", offset, inp.len() - 1, max_trailer))))
+            },
+            Err(e) => {
+                let preview = &inp[..inp.len().min(16)];
+                return Err(nom::Err::Error(CustomError::MyError(format!("{} in opcode at offset 0x{:x} ({} unparsed bytes: {:02x?})", describe_error(&e, input), offset, inp.len(), preview))))
+            }
+        }
+    }
+
+    if opcodes.is_empty() {
+        return Err(nom::Err::Error(CustomError::MyError(String::from("Scene contains no opcodes"))));
+    }
+    if !placeholders.is_empty() && config(|c| c.reject_placeholders) {
+        return Err(nom::Err::Error(CustomError::MyError(format!("Scene contains {} placeholder opcodes: {}", placeholders.len(), placeholders.join(", ")))));
+    }
+
+    debug!("Parsed {} opcodes", opcodes.len());
+    let scene = AVG32Scene {
+        header,
+        opcodes,
+        val_lengths,
+        trailer: inp[1..].to_vec()
+    };
+
+    Ok((&inp[inp.len()..], (scene, unknown)))
+}
+
+/// Parses a whole scene. The opcode body runs from the end of the header up to
+/// a `0x00` byte where an opcode would start, followed by at most
+/// `ParseConfig::max_trailer` bytes of anything, kept in
+/// `AVG32Scene::trailer`. Any other byte
+/// that doesn't parse as an opcode is an error.
+pub fn avg32_scene(input: &[u8]) -> ParseResult<'_, AVG32Scene> {
+    let (rest, (scene, _)) = scene_body(input, false)?;
+    Ok((rest, scene))
+}
+
+/// Like `avg32_scene`, but unparseable opcodes are kept as
+/// `Opcode::UnknownRaw` and reported instead of failing the whole scene.
+pub fn avg32_scene_lenient(input: &[u8]) -> ParseResult<'_, (AVG32Scene, Vec<UnknownOpcode>)> {
+    scene_body(input, true)
+}
 
 named!(pub opcodes<&[u8], Vec<Opcode>, CustomError<&[u8]>>,
-               dbg_dmp!(many1!(opcode))
+               many1!(opcode)
 );
 
 
 #[cfg(test)]
 mod tests {
     use crate::parser::*;
+    use crate::write::Writeable;
+
+    fn test_header() -> Header {
+        Header::empty()
+    }
+
+    fn scene_bytes(body: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        test_header().write(&mut bytes).unwrap();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn parse_composite_unknown_method() {
+        // An unknown 0x07 method, followed by a known child.
+        let mut bytes = vec![0x02];
+        bytes.extend_from_slice(b"BASE\0");
+        bytes.push(0x10);
+        bytes.push(0x07);
+        bytes.extend_from_slice(b"A\0");
+        bytes.extend_from_slice(&[0x21, 0x02, 0x95]);
+        bytes.push(0x01);
+        bytes.extend_from_slice(b"B\0");
+
+        match grp_composite(&bytes) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(_, msg))) =>
+                assert!(msg.contains("Unknown composite method 0x07"), "{}", msg),
+            other => panic!("Expected error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn scene_opcode_at_offset() {
+        let scene = AVG32Scene {
+            header: Header::empty(),
+            opcodes: vec![
+                Opcode::WaitMouse,
+                Opcode::Jump(Pos::Offset(0)),
+                Opcode::Newline,
+            ],
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        };
+        let body = scene.header.byte_size() as u32;
+
+        let offsets: Vec<u32> = scene.opcodes_with_offsets().map(|(offset, _)| offset).collect();
+        assert_eq!(vec![0, 1, 6], offsets);
+
+        assert_eq!(None, scene.opcode_at_offset(0));
+        assert_eq!(None, scene.opcode_at_offset(body - 1));
+        assert_eq!(Some(0), scene.opcode_at_offset(body));
+        assert_eq!(Some(1), scene.opcode_at_offset(body + 1));
+        assert_eq!(Some(1), scene.opcode_at_offset(body + 3));
+        assert_eq!(Some(1), scene.opcode_at_offset(body + 5));
+        assert_eq!(Some(2), scene.opcode_at_offset(body + 6));
+        assert_eq!(None, scene.opcode_at_offset(body + 7));
+        assert_eq!(None, scene.opcode_at_offset(u32::MAX));
+    }
+
+    #[test]
+    fn parse_formatted_text_color() {
+        let bytes = [0x10, 0x11, 0x13, 0xff, 0x82, 0xa0, 0x00, 0x10, 0x13, 0x00];
+        let (rest, text) = scene_formatted_text(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(SceneFormattedText(vec![
+            SceneFormattedTextEntry::Command(FormattedTextCmd::Color(Val::constant(3))),
+            SceneFormattedTextEntry::TextZenkaku(String::from("あ")),
+            SceneFormattedTextEntry::Command(FormattedTextCmd::ResetColor),
+        ]), text);
+
+        let out = text.to_vec().unwrap();
+        assert_eq!(&bytes[..], &out[..]);
+        assert_eq!(bytes.len(), text.byte_size());
+    }
+
+    #[test]
+    fn parse_scene_empty_header() {
+        let scene = AVG32Scene {
+            header: Header::empty(),
+            opcodes: vec![Opcode::WaitMouse],
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        };
+        let bytes = scene.to_vec().unwrap();
+        assert_eq!(0x20 + 0x30 + 4 + 0x05, Header::empty().byte_size());
+
+        assert_eq!(scene, avg32_scene(&bytes).unwrap().1);
+        assert_eq!(Header::empty(), Header::default());
+    }
+
+    #[test]
+    fn roundtrip_area_buffer_cmd() {
+        let (c, v) = (Val::constant, Val::variable);
+        let lit = |s: &str| SceneText::Literal(String::from(s));
+        // The subcommand byte, then each field in order. Constants below 16
+        // are written 0x1N, variables 0x9N.
+        let cases = vec![
+            (AreaBufferCmd::ReadCurArd(lit("CUR"), lit("ARD")), vec![0x02, b'C', b'U', b'R', 0x00, b'A', b'R', b'D', 0x00]),
+            (AreaBufferCmd::Init, vec![0x03]),
+            (AreaBufferCmd::GetClickedArea(v(1), c(2)), vec![0x04, 0x91, 0x12]),
+            (AreaBufferCmd::GetClickedArea2(v(3), c(4)), vec![0x05, 0x93, 0x14]),
+            (AreaBufferCmd::DisableArea(c(5)), vec![0x10, 0x15]),
+            (AreaBufferCmd::EnableArea(c(6)), vec![0x11, 0x16]),
+            // x, y, then the variable the area there is stored in.
+            (AreaBufferCmd::GetArea(c(7), c(8), v(9)), vec![0x15, 0x17, 0x18, 0x99]),
+            (AreaBufferCmd::AssignArea(c(10), c(11)), vec![0x20, 0x1a, 0x1b]),
+        ];
+
+        for (cmd, bytes) in cases.iter() {
+            let (rest, parsed) = area_buffer_cmd(bytes).unwrap();
+            assert!(rest.is_empty(), "{:?}", cmd);
+            assert_eq!(*cmd, parsed);
+            assert_eq!(*bytes, cmd.to_vec().unwrap());
+            assert_eq!(bytes.len(), cmd.byte_size());
+        }
+    }
+
+    #[test]
+    fn parse_scene_trailer() {
+        let padded = scene_bytes(&[0x01, 0x00, 0x00, 0x00]);
+        let scene = avg32_scene(&padded).unwrap().1;
+        assert_eq!(vec![Opcode::WaitMouse], scene.opcodes);
+        assert_eq!(vec![0x00, 0x00], scene.trailer);
+        assert_eq!(padded, scene.to_vec().unwrap());
+        assert_eq!(padded.len(), scene.byte_size());
+
+        let (lenient, unknown) = avg32_scene_lenient(&padded).unwrap().1;
+        assert_eq!(scene, lenient);
+        assert!(unknown.is_empty());
+
+        let unpadded = avg32_scene(&scene_bytes(&[0x01, 0x00])).unwrap().1;
+        assert!(unpadded.trailer.is_empty());
+
+        // The trailer isn't only 0x00 bytes in every game.
+        let junk = scene_bytes(&[0x01, 0x00, 0x1a, 0xff]);
+        let scene = avg32_scene(&junk).unwrap().1;
+        assert_eq!(vec![Opcode::WaitMouse], scene.opcodes);
+        assert_eq!(vec![0x1a, 0xff], scene.trailer);
+        assert_eq!(junk, scene.to_vec().unwrap());
+
+        let short = ParseConfig {
+            max_trailer: 1,
+            ..Default::default()
+        };
+        with_config(&short, || {
+            match avg32_scene(&padded) {
+                Err(nom::Err::Error(CustomError::MyError(msg))) => assert!(msg.contains("followed by 2 bytes, more than the limit of 1"), "{}", msg),
+                other => panic!("Expected error, got {:?}", other)
+            }
+        });
+    }
+
+    #[test]
+    fn parse_scene_reject_placeholders() {
+        let strict = ParseConfig {
+            reject_placeholders: true,
+            ..Default::default()
+        };
+        let with = scene_bytes(&[0x01, 0x65, 0x01, 0x00]);
+        let without = scene_bytes(&[0x01, 0x02, 0x01, 0x00]);
+        let offset = test_header().byte_size() + 1;
+
+        assert!(avg32_scene(&with).is_ok());
+        with_config(&strict, || {
+            match avg32_scene(&with) {
+                Err(nom::Err::Error(CustomError::MyError(msg))) =>
+                    assert_eq!(format!("Scene contains 1 placeholder opcodes: Unknown0x65 at offset 0x{:x}", offset), msg),
+                other => panic!("{:?}", other)
+            }
+            assert!(avg32_scene(&without).is_ok());
+        });
+        assert!(Opcode::Op0x30.is_placeholder());
+        assert!(!Opcode::WaitMouse.is_placeholder());
+    }
+
+    #[test]
+    fn parse_scene_embedded_zero_operand() {
+        // Jump to offset 0, whose operand is four zero bytes, then the terminator.
+        let bytes = scene_bytes(&[0x01, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let scene = avg32_scene(&bytes).unwrap().1;
+        assert_eq!(vec![Opcode::WaitMouse, Opcode::Jump(Pos::Offset(0))], scene.opcodes);
+    }
+
+    #[test]
+    fn parse_scene_trailing_bytes() {
+        // 0x07 has no parser.
+        let bytes = scene_bytes(&[0x01, 0x07, 0x00]);
+        let header_size = test_header().byte_size();
+        match avg32_scene(&bytes) {
+            Err(nom::Err::Error(CustomError::MyError(msg))) => {
+                assert!(msg.contains(&format!("offset 0x{:x}", header_size + 1)), "{}", msg);
+                assert!(msg.contains("2 unparsed bytes"), "{}", msg);
+            },
+            other => panic!("Expected error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parse_scene_lenient() {
+        // 0x07 has no parser; the two bytes after it don't parse either.
+        let bytes = scene_bytes(&[0x01, 0x07, 0x00, 0x00, 0x02, 0x00]);
+        let header_size = test_header().byte_size();
+        assert!(avg32_scene(&bytes).is_err());
+
+        let (scene, unknown) = avg32_scene_lenient(&bytes).unwrap().1;
+        assert_eq!(vec![Opcode::WaitMouse, Opcode::UnknownRaw(0x07, vec![0x00, 0x00]), Opcode::Newline], scene.opcodes);
+        assert_eq!(vec![UnknownOpcode { offset: header_size + 1, opcode: 0x07, skipped: 2 }], unknown);
+
+        let mut out = Vec::new();
+        scene.write(&mut out).unwrap();
+        assert_eq!(bytes, out);
+
+        // Looking only one byte ahead, that byte is skipped with the opcode.
+        let bytes = scene_bytes(&[0x01, 0x07, 0x07, 0x07, 0x02, 0x00]);
+        let raw = |rest: &[u8]| Opcode::UnknownRaw(0x07, rest.to_vec());
+        assert_eq!(vec![Opcode::WaitMouse, raw(&[0x07, 0x07]), Opcode::Newline], avg32_scene_lenient(&bytes).unwrap().1 .0.opcodes);
+        let short = ParseConfig {
+            max_resync: 1,
+            ..Default::default()
+        };
+        let (scene, unknown) = with_config(&short, || avg32_scene_lenient(&bytes).unwrap().1);
+        assert_eq!(vec![Opcode::WaitMouse, raw(&[0x07]), raw(&[]), Opcode::Newline], scene.opcodes);
+        assert_eq!(2, unknown.len());
+    }
+
+    #[test]
+    fn roundtrip_single_byte_opcodes() {
+        let ops = [
+            (0x05, Opcode::Op0x05), (0x06, Opcode::Op0x06), (0x08, Opcode::Op0x08),
+            (0x0c, Opcode::Op0x0c), (0x18, Opcode::Op0x18), (0x1a, Opcode::Op0x1a),
+            (0x2c, Opcode::Op0x2c), (0x2d, Opcode::Op0x2d), (0x30, Opcode::Op0x30),
+            (0x5b, Opcode::Op0x5b), (0x5d, Opcode::Op0x5d), (0x5e, Opcode::Op0x5e),
+            (0x5f, Opcode::Op0x5f), (0x63, Opcode::Op0x63), (0x66, Opcode::Op0x66),
+            (0x69, Opcode::Op0x69), (0x6e, Opcode::Op0x6e), (0x6f, Opcode::Op0x6f),
+            (0x7f, Opcode::Op0x7f),
+        ];
+
+        for (byte, expected) in ops.iter() {
+            let bytes = [*byte];
+            let (rest, op) = opcode(&bytes).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(*expected, op);
+
+            let out = op.to_vec().unwrap();
+            assert_eq!(op.byte_size(), out.len());
+            assert_eq!(&bytes[..], &out[..]);
+        }
+    }
+
+    #[test]
+    fn parse_scene_missing_terminator() {
+        let bytes = scene_bytes(&[0x01, 0x02]);
+        assert!(avg32_scene(&bytes).is_err());
+    }
+
+    #[test]
+    fn parse_conditions_depth_underflow() {
+        match scene_conditions(&[0x29, 0x28, 0x29]) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(_, msg))) => assert!(msg.contains("Unbalanced")),
+            other => panic!("expected an error, got {:?}", other)
+        }
+
+        let bytes = scene_bytes(&[0x15, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(avg32_scene(&bytes).is_err());
+
+        assert_eq!(vec![Condition::IncDepth, Condition::DecDepth], scene_conditions(&[0x28, 0x29]).unwrap().1);
+    }
+
+    #[test]
+    fn parse_formatted_text_condition() {
+        // A condition entry, then "あ": the entry's 0x28 tag opens the list.
+        let bytes = [0x28, 0x3b, 0x90, 0x11, 0x29, 0xff, 0x82, 0xa0, 0x00, 0x00];
+        let (rest, text) = scene_formatted_text(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(SceneFormattedText(vec![
+            SceneFormattedTextEntry::Condition(vec![Condition::FlagEqConst(Val::variable(0), Val::constant(1)), Condition::DecDepth]),
+            SceneFormattedTextEntry::TextZenkaku(String::from("あ")),
+        ]), text);
+        assert_eq!(&bytes[..], &text.to_vec().unwrap()[..]);
+    }
+
+    #[test]
+    fn parse_conditions_nested() {
+        // ((flag0 == 1 && (flag1 == 2 || (flag2 == 3))))
+        let bytes = [0x28,
+                     0x3b, 0x90, 0x11, 0x26,
+                     0x28,
+                     0x3b, 0x91, 0x12, 0x27,
+                     0x28, 0x3b, 0x92, 0x13, 0x29,
+                     0x29,
+                     0x29, 0xaa];
+        let c = Val::constant;
+        let v = Val::variable;
+        let (rest, conds) = scene_conditions(&bytes).unwrap();
+        assert_eq!(&[0xaa], rest);
+        assert_eq!(vec![
+            Condition::IncDepth,
+            Condition::FlagEqConst(v(0), c(1)), Condition::And,
+            Condition::IncDepth,
+            Condition::FlagEqConst(v(1), c(2)), Condition::Or,
+            Condition::IncDepth, Condition::FlagEqConst(v(2), c(3)), Condition::DecDepth,
+            Condition::DecDepth,
+            Condition::DecDepth,
+        ], conds);
+
+        let config = ParseConfig {
+            max_condition_depth: 2,
+            ..Default::default()
+        };
+        with_config(&config, || {
+            match scene_conditions(&bytes) {
+                Err(nom::Err::Error(CustomError::MyErrorAt(at, msg))) => {
+                    assert!(msg.contains("deeper than 2"), "{}", msg);
+                    assert_eq!(bytes.len() - 10, at.len());
+                },
+                other => panic!("Expected error, got {:?}", other)
+            }
+        });
+    }
+
+    #[test]
+    fn parse_text_index_by_version() {
+        let at = |sys_version: u32| ParseConfig { sys_version: sys_version, ..Default::default() };
+        let text = |s: &str| SceneText::Literal(String::from(s));
+        // "あ" and "a", with and without a text index of 5.
+        let indexed_zen = [0xff, 0x05, 0x00, 0x00, 0x00, 0x82, 0xa0, 0x00];
+        let indexed_han = [0xfe, 0x05, 0x00, 0x00, 0x00, 0x61, 0x00];
+        let plain_zen = [0xff, 0x82, 0xa0, 0x00];
+        let plain_han = [0xfe, 0x61, 0x00];
+
+        with_config(&at(1714), || {
+            for (bytes, expected) in [(&indexed_zen[..], Opcode::TextZenkaku(Some(5), text("あ"))),
+                                      (&indexed_han[..], Opcode::TextHankaku(Some(5), text("a")))].iter() {
+                let (rest, parsed) = opcode(bytes).unwrap();
+                assert!(rest.is_empty());
+                assert_eq!(*expected, parsed);
+                assert_eq!(bytes.len(), parsed.byte_size());
+                assert_eq!(bytes.to_vec(), parsed.to_vec().unwrap());
+            }
+        });
+
+        with_config(&at(1713), || {
+            for (bytes, expected) in [(&plain_zen[..], Opcode::TextZenkaku(None, text("あ"))),
+                                      (&plain_han[..], Opcode::TextHankaku(None, text("a")))].iter() {
+                let (rest, parsed) = opcode(bytes).unwrap();
+                assert!(rest.is_empty());
+                assert_eq!(*expected, parsed);
+                assert_eq!(bytes.len(), parsed.byte_size());
+                assert_eq!(bytes.to_vec(), parsed.to_vec().unwrap());
+            }
+
+            // Without the index, its first byte is read as the text.
+            let (rest, parsed) = opcode(&indexed_zen).unwrap();
+            assert_eq!(Opcode::TextZenkaku(None, text("\u{5}")), parsed);
+            assert_eq!(&indexed_zen[3..], rest);
+        });
+    }
+
+    #[test]
+    fn roundtrip_scenario_menu_0x2f() {
+        let cmd = ScenarioMenuCmd::SetBit2(Val::constant(1), Val::constant(2));
+        let mut bytes = Opcode::ScenarioMenu(cmd.clone()).to_vec().unwrap();
+        assert_eq!(0x2e, bytes[0]);
+        bytes[0] = 0x2f;
+
+        let (rest, parsed) = opcode(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(Opcode::ScenarioMenu2(cmd), parsed);
+        assert_eq!(bytes, parsed.to_vec().unwrap());
+        assert_eq!(bytes.len(), parsed.byte_size());
+    }
+
+    #[test]
+    fn parse_composite_inflated_count() {
+        // Claims three children but has one, followed by bytes that don't
+        // make a child: a method and a string with invalid Shift_JIS.
+        let mut bytes = vec![0x03];
+        bytes.extend_from_slice(b"BASE\0");
+        bytes.push(0x10);
+        bytes.push(0x01);
+        bytes.extend_from_slice(b"A\0");
+        let child_end = bytes.len();
+        bytes.extend_from_slice(&[0x01, 0x81, 0x00, 0x00]);
+
+        match grp_composite(&bytes) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(at, msg))) => {
+                assert!(msg.starts_with("Expected 3 composite children, but only parsed 1: Truncated SHIFT_JIS"), "{}", msg);
+                assert_eq!(bytes.len() - child_end, at.len());
+            },
+            other => panic!("Expected error, got {:?}", other)
+        }
+
+        // Claims 200 children with a few bytes left.
+        bytes[0] = 200;
+        match grp_composite(&bytes) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(_, msg))) =>
+                assert!(msg.contains("Expected 200 composite children, needing at least 400 bytes, but only 7 remain"), "{}", msg),
+            other => panic!("Expected error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parse_counts_over_limit() {
+        // A header claiming 0xffffffff labels.
+        let mut bytes = test_header().to_vec().unwrap();
+        bytes[0x18..0x1c].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        match header(&bytes) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(at, msg))) => {
+                assert!(msg.contains("4294967295 labels is more than the limit of 65536"), "{}", msg);
+                assert_eq!(bytes.len() - 0x20, at.len());
+            },
+            other => panic!("Expected error, got {:?}", other)
+        }
+
+        let config = ParseConfig {
+            max_opcodes: 2,
+            ..Default::default()
+        };
+        with_config(&config, || {
+            assert!(avg32_scene(&scene_bytes(&[0x01, 0x01, 0x00])).is_ok());
+            match avg32_scene(&scene_bytes(&[0x01, 0x01, 0x01, 0x00])) {
+                Err(nom::Err::Error(CustomError::MyError(msg))) => assert!(msg.contains("more than the limit of 2 opcodes"), "{}", msg),
+                other => panic!("Expected error, got {:?}", other)
+            }
+        });
+    }
+
+    #[test]
+    fn parse_counts_over_input() {
+        let empty = test_header().to_vec().unwrap();
+
+        // 1000 labels, with nothing after the header.
+        let mut bytes = empty.clone();
+        bytes[0x18..0x1c].copy_from_slice(&1000u32.to_le_bytes());
+        match header(&bytes) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(at, msg))) => {
+                assert!(msg.contains(&format!("Header claims 1000 labels, needing at least 4057 bytes, but only {} remain", bytes.len() - 0x20)), "{}", msg);
+                assert_eq!(bytes.len() - 0x20, at.len());
+            },
+            other => panic!("Expected error, got {:?}", other)
+        }
+
+        // 100 menus.
+        let mut bytes = empty.clone();
+        bytes[0x50..0x54].copy_from_slice(&100u32.to_le_bytes());
+        match header(&bytes) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(_, msg))) => assert!(msg.contains("Header claims 100 menus, needing at least 505 bytes"), "{}", msg),
+            other => panic!("Expected error, got {:?}", other)
+        }
+
+        assert!(header(&empty).is_ok());
+    }
+
+    #[test]
+    fn parse_conditions_missing_operand() {
+        // And with no left operand.
+        match scene_conditions(&[0x28, 0x26, 0x3b, 0x90, 0x11, 0x29]) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(at, msg))) => {
+                assert!(msg.contains("And (0x26) without a left operand"), "{}", msg);
+                assert_eq!(5, at.len());
+            },
+            other => panic!("Expected error, got {:?}", other)
+        }
+
+        // Or with no right operand.
+        match scene_conditions(&[0x28, 0x3b, 0x90, 0x11, 0x27, 0x29]) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(_, msg))) => assert!(msg.contains("Or (0x27) without a right operand"), "{}", msg),
+            other => panic!("Expected error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parse_formatted_text_condition_checks() {
+        let error = |bytes: &[u8]| match scene_formatted_text(bytes) {
+            Err(e) => describe_error(&e, bytes),
+            Ok(parsed) => panic!("Expected error, got {:?}", parsed)
+        };
+
+        // flag0 == 1 && flag1 == 2
+        let both = [0x28, 0x3b, 0x90, 0x11, 0x26, 0x3b, 0x91, 0x12, 0x29, 0x00];
+        assert_eq!(SceneFormattedText(vec![SceneFormattedTextEntry::Condition(vec![
+            Condition::FlagEqConst(Val::variable(0), Val::constant(1)), Condition::And,
+            Condition::FlagEqConst(Val::variable(1), Val::constant(2)), Condition::DecDepth,
+        ])]), scene_formatted_text(&both).unwrap().1);
+
+        let msg = error(&[0x28, 0x26, 0x3b, 0x90, 0x11, 0x29, 0x00]);
+        assert!(msg.contains("And (0x26) without a left operand"), "{}", msg);
+        let msg = error(&[0x28, 0x3b, 0x90, 0x11, 0x27, 0x29, 0x00]);
+        assert!(msg.contains("Or (0x27) without a right operand"), "{}", msg);
+
+        // The entry's own 0x28 counts towards the depth limit.
+        let nested = [0x28, 0x28, 0x3b, 0x90, 0x11, 0x29, 0x29, 0x00];
+        assert!(scene_formatted_text(&nested).is_ok());
+        let config = ParseConfig {
+            max_condition_depth: 1,
+            ..Default::default()
+        };
+        with_config(&config, || {
+            let msg = error(&nested);
+            assert!(msg.contains("deeper than 1"), "{}", msg);
+        });
+    }
+
+    #[test]
+    fn parse_conditions_unknown_operator() {
+        // IncDepth, then 0x30, which isn't a condition operator.
+        let bytes = scene_bytes(&[0x01, 0x15, 0x28, 0x30, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let header_size = test_header().byte_size();
+        match avg32_scene(&bytes) {
+            Err(nom::Err::Error(CustomError::MyError(msg))) => {
+                assert!(msg.contains(&format!("Unknown condition operator 0x30 at offset 0x{:x}", header_size + 3)), "{}", msg);
+                assert!(msg.contains(&format!("in opcode at offset 0x{:x}", header_size + 1)), "{}", msg);
+            },
+            other => panic!("Expected error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parse_conditions_unknown_ret_attr() {
+        let bytes = scene_bytes(&[0x15, 0x28, 0x58, 0x2f, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let header_size = test_header().byte_size();
+        match avg32_scene(&bytes) {
+            Err(nom::Err::Error(CustomError::MyError(msg))) => {
+                assert!(msg.contains(&format!("Unknown ret attribute 0x2f at offset 0x{:x}", header_size + 3)), "{}", msg);
+            },
+            other => panic!("Expected error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn roundtrip_header_unknown_bytes() {
+        let header = Header {
+            unk1: (0..0x13).collect(),
+            labels: vec![0x10, 0x2000],
+            unk2: (0..0x30).map(|i| 0xff - i).collect(),
+            counter_start: 0x1234,
+            menus: vec![],
+            menu_strings: vec![],
+            unk3: vec![0xde, 0xad, 0xbe, 0xef, 0x01]
+        };
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+
+        assert_eq!(b"TPC32", &bytes[0x00..0x05]);
+        assert_eq!(&header.unk1[..], &bytes[0x05..0x18]);
+        assert_eq!(&[0x02, 0x00, 0x00, 0x00], &bytes[0x18..0x1c]);
+        assert_eq!(&[0x34, 0x12, 0x00, 0x00], &bytes[0x1c..0x20]);
+        assert_eq!(header, self::header(&bytes).unwrap().1);
+    }
+
+    #[test]
+    fn roundtrip_header_label_table() {
+        let mut header = Header::empty();
+        header.labels = vec![0, 0x1c, 0x345, 0x1_0000, 0xdead_beef];
+        header.counter_start = 0x0102_0304;
+
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(header.byte_size(), bytes.len());
+
+        assert_eq!(&[0x05, 0x00, 0x00, 0x00], &bytes[0x18..0x1c]);
+        assert_eq!(&[0x04, 0x03, 0x02, 0x01], &bytes[0x1c..0x20]);
+        let labels: Vec<u32> = bytes[0x20..0x20 + 4 * 5].chunks(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(header.labels, labels);
+        assert_eq!(&header.unk2[..], &bytes[0x34..0x64]);
+
+        let (rest, parsed) = self::header(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(header, parsed);
+    }
+
+    #[test]
+    fn header_menu_entries() {
+        let submenu = |id| Submenu { id: id, unk1: 0, unk2: 0, flags: vec![] };
+        let mut header = test_header();
+        header.menus = vec![
+            Menu { id: 1, unk1: 0, unk2: 0, submenus: vec![submenu(1), submenu(2)] },
+            Menu { id: 2, unk1: 0, unk2: 0, submenus: vec![] },
+            Menu { id: 3, unk1: 0, unk2: 0, submenus: vec![submenu(1)] },
+        ];
+        header.menu_strings = ["A", "A-1", "A-2", "B", "C", "C-1"].iter().map(|s| String::from(*s)).collect();
+
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        let header = self::header(&bytes).unwrap().1;
+
+        assert_eq!(vec![
+            (MenuRef::Menu(0), "A"),
+            (MenuRef::Submenu(0, 0), "A-1"),
+            (MenuRef::Submenu(0, 1), "A-2"),
+            (MenuRef::Menu(1), "B"),
+            (MenuRef::Menu(2), "C"),
+            (MenuRef::Submenu(2, 0), "C-1"),
+        ], header.menu_entries());
+    }
+
+    #[test]
+    fn header_add_remove_submenu() {
+        let submenu = |id| Submenu { id: id, unk1: 0, unk2: 0, flags: vec![] };
+        let mut header = test_header();
+        header.menus = vec![
+            Menu { id: 1, unk1: 0, unk2: 0, submenus: vec![submenu(1)] },
+            Menu { id: 2, unk1: 0, unk2: 0, submenus: vec![] },
+        ];
+        header.menu_strings = ["A", "A-1", "B"].iter().map(|s| String::from(*s)).collect();
+
+        let flagged = Submenu { id: 2, unk1: 0, unk2: 0, flags: vec![Flag { unk1: 0, flags: vec![1, 2] }] };
+        header.add_submenu(0, flagged.clone(), String::from("A-2")).unwrap();
+        header.add_submenu(1, submenu(1), String::from("B-1")).unwrap();
+        assert!(header.add_submenu(2, submenu(1), String::from("C-1")).is_err());
+
+        let bytes = header.to_vec().unwrap();
+        let parsed = self::header(&bytes).unwrap().1;
+        assert_eq!(header, parsed);
+
+        let tree = parsed.menu_tree();
+        assert_eq!(2, tree.len());
+        assert_eq!("A", tree[0].label);
+        assert_eq!(vec![(&submenu(1), "A-1"), (&flagged, "A-2")], tree[0].submenus);
+        assert_eq!(vec![1, 2], tree[0].submenus[1].0.flags[0].flags);
+        assert_eq!("B", tree[1].label);
+        assert_eq!(vec![(&submenu(1), "B-1")], tree[1].submenus);
+
+        let (removed, label) = header.remove_submenu(0, 0).unwrap();
+        assert_eq!((submenu(1), String::from("A-1")), (removed, label));
+        assert_eq!(vec!["A", "A-2", "B", "B-1"], header.menu_strings);
+        assert!(header.remove_submenu(0, 1).is_err());
+
+        header.menu_strings.pop();
+        assert!(header.add_submenu(0, submenu(3), String::from("A-3")).is_err());
+    }
+
+    #[test]
+    fn write_header_menu_strings_mismatch() {
+        let mut header = test_header();
+        header.menus = vec![Menu { id: 1, unk1: 0, unk2: 0, submenus: vec![] }];
+        header.menu_strings = vec![String::from("A"), String::from("B")];
+
+        let err = header.write(&mut Vec::new()).unwrap_err();
+        assert_eq!("Header has 2 menu strings, but its menus need 1", err.to_string());
+
+        header.menu_strings.clear();
+        assert!(header.write(&mut Vec::new()).is_err());
+
+        header.menu_strings.push(String::from("A"));
+        assert!(header.write(&mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn scene_text_literal_and_resolve() {
+        let scene = crate::build::SceneBuilder::new()
+            .opcode(Opcode::String(StringCmd::StrcpyLiteral(Val::constant(3), SceneText::Literal(String::from("ゆき")))))
+            .opcode(Opcode::String(StringCmd::StrcpyLiteral(Val::constant(4), SceneText::Literal(String::from("あ")))))
+            .opcode(Opcode::String(StringCmd::StrcpyLiteral(Val::constant(4), SceneText::Literal(String::from("い")))))
+            .build()
+            .unwrap();
+
+        let literal = SceneText::Literal(String::from("BG001"));
+        assert_eq!(Some("BG001"), literal.literal());
+        assert_eq!(Some(String::from("BG001")), literal.resolve(&scene));
+
+        let pointer = SceneText::Pointer(Val::constant(3));
+        assert_eq!(None, pointer.literal());
+        assert_eq!(Some(String::from("ゆき")), pointer.resolve(&scene));
+
+        // Set to two different literals, never set, or through a variable.
+        assert_eq!(None, SceneText::Pointer(Val::constant(4)).resolve(&scene));
+        assert_eq!(None, SceneText::Pointer(Val::constant(5)).resolve(&scene));
+        assert_eq!(None, SceneText::Pointer(Val::variable(3)).resolve(&scene));
+
+        let bytes = [0x40, 0x13];
+        assert_eq!(pointer, scene_text(&bytes).unwrap().1);
+    }
+
+    #[test]
+    fn parse_choice_pad() {
+        let text = SceneFormattedText(vec![SceneFormattedTextEntry::TextZenkaku(String::from("はい"))]);
+        let test = |bytes: &[u8], pad: Option<u8>| {
+            let expected = ChoiceCmd::Choice(Val::constant(1), 0x22, Some(ChoiceText {
+                pad: pad,
+                texts: vec![text.clone()]
+            }));
+            let (rest, cmd) = choice_cmd(bytes).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(expected, cmd);
+
+            let out = cmd.to_vec().unwrap();
+            assert_eq!(bytes, &out[..]);
+        };
+
+        // With the pad.
+        test(&[0x01, 0x11, 0x22, 0x22, 0xff, 0x82, 0xcd, 0x82, 0xa2, 0x00, 0x00, 0x23], Some(0x22));
+        // Without it, the first byte is the start of the text and isn't skipped.
+        test(&[0x01, 0x11, 0x22, 0xff, 0x82, 0xcd, 0x82, 0xa2, 0x00, 0x00, 0x23], None);
+    }
+
+    #[test]
+    fn write_choice_pad_ambiguous() {
+        let text = ChoiceText { pad: Some(0xff), texts: Vec::new() };
+        assert!(text.write(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn text_encoding_strict() {
+        // FULLWIDTH TILDE in CP932, WAVE DASH in JIS X 0208.
+        let tilde = [0x81, 0x60, 0x00];
+        // CIRCLED DIGIT ONE, from the NEC extension row.
+        let circled = [0x87, 0x40, 0x00];
+        let strict = ParseConfig {
+            text_encoding: TextEncoding::Strict,
+            ..Default::default()
+        };
+
+        assert_eq!("\u{FF5E}", c_string(&tilde).unwrap().1);
+        assert_eq!("\u{2460}", c_string(&circled).unwrap().1);
+
+        with_config(&strict, || {
+            assert_eq!("\u{301C}", c_string(&tilde).unwrap().1);
+            assert!(c_string(&circled).is_err());
+
+            let mut bytes = Vec::new();
+            "\u{301C}".write(&mut bytes).unwrap();
+            assert_eq!(&tilde[..], &bytes[..]);
+            assert_eq!(3, "\u{301C}".byte_size());
+            assert!("\u{2460}".write(&mut Vec::new()).is_err());
+        });
+
+        assert!("\u{301C}".write(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn c_string_invalid_sjis() {
+        let error_at = |bytes: &[u8]| match c_string(bytes) {
+            Err(nom::Err::Error(CustomError::MyErrorAt(at, msg))) => (at.as_ptr() as usize - bytes.as_ptr() as usize, msg),
+            other => panic!("{:?}", other)
+        };
+
+        assert_eq!(Ok((&[][..], String::from("はい"))), c_string(&[0x82, 0xcd, 0x82, 0xa2, 0x00]));
+
+        // 0xfd never starts a character.
+        let invalid = [0x82, 0xcd, 0xfd, 0x41, 0x00];
+        let (offset, msg) = error_at(&invalid);
+        assert_eq!(2, offset);
+        assert!(msg.starts_with("Invalid SHIFT_JIS sequence [fd]"), "{}", msg);
+
+        // The trail byte of the last character is cut off by the terminator.
+        let truncated = [0x82, 0xcd, 0x82, 0x00];
+        let (offset, msg) = error_at(&truncated);
+        assert_eq!(2, offset);
+        assert!(msg.starts_with("Truncated SHIFT_JIS sequence [82]"), "{}", msg);
+
+        let lossy = ParseConfig {
+            lossy_text: true,
+            ..Default::default()
+        };
+        with_config(&lossy, || {
+            assert_eq!("は\u{FFFD}A", c_string(&invalid).unwrap().1);
+            assert_eq!("は\u{FFFD}", c_string(&truncated).unwrap().1);
+            assert!("は\u{FFFD}".write(&mut Vec::new()).is_err());
+        });
+    }
+
+    #[test]
+    fn val_canonical_encoding() {
+        assert!(Val::is_canonical_encoding(&[0x1A]));
+        assert!(Val::is_canonical_encoding(&[0x20, 0x80]));
+        assert!(!Val::is_canonical_encoding(&[0x2A, 0x00]));
+        assert!(!Val::is_canonical_encoding(&[0x30, 0x80, 0x00]));
+        assert!(!Val::is_canonical_encoding(&[]));
+        assert!(!Val::is_canonical_encoding(&[0x20]));
+
+        // 0x0A padded out to two bytes.
+        let padded = [0x2A, 0x00];
+
+        let config = ParseConfig {
+            val_encoding: ValEncoding::Normalize,
+            ..Default::default()
+        };
+        let val = with_config(&config, || scene_value(&padded).unwrap().1);
+        assert_eq!(Val(0x0A, ValType::Const), val);
+        let mut bytes = Vec::new();
+        val.write(&mut bytes).unwrap();
+        assert_eq!(vec![0x1A], bytes);
+
+        let config = ParseConfig {
+            val_encoding: ValEncoding::Canonical,
+            ..Default::default()
+        };
+        with_config(&config, || {
+            assert!(scene_value(&padded).is_err());
+            assert_eq!(Val(0x0A, ValType::Const), scene_value(&[0x1A]).unwrap().1);
+        });
+        assert!(scene_value(&padded).is_ok());
+    }
+
+    #[test]
+    fn val_preserve_encoding() {
+        // SetVal with 0x0A padded out to three bytes, then a jump to the
+        // WaitMouse after it.
+        let body = [0x49, 0x3A, 0x00, 0x00, 0x20, 0x80, 0x1c, 0x0b, 0x00, 0x00, 0x00, 0x01, 0x00];
+        let bytes = scene_bytes(&body);
+
+        let scene = avg32_scene(&bytes).unwrap().1;
+        assert_eq!(Opcode::SetVal(Val::constant(0x0A), Val::constant(0x800)), scene.opcodes[0]);
+        assert_eq!(Some(&vec![Some(3), None]), scene.val_lengths.get(&0));
+        assert_eq!(1, scene.val_lengths.len());
+        assert_eq!(vec![0, 6, 11], scene.opcodes_with_offsets().map(|(offset, _)| offset).collect::<Vec<_>>());
+        assert_eq!(bytes, scene.to_vec().unwrap());
+        assert_eq!(bytes.len(), scene.byte_size());
+
+        // The opcodes alone are written in minimal form.
+        assert_eq!(vec![0x49, 0x1A, 0x20, 0x80], scene.opcodes[0].to_vec().unwrap());
+
+        let config = ParseConfig {
+            val_encoding: ValEncoding::Normalize,
+            ..Default::default()
+        };
+        let normalized = with_config(&config, || avg32_scene(&bytes).unwrap().1);
+        assert!(normalized.val_lengths.is_empty());
+        assert_eq!(scene.opcodes, normalized.opcodes);
+        assert_eq!(bytes.len() - 2, normalized.to_vec().unwrap().len());
+    }
 
     #[test]
     fn parse_value() {
@@ -0,0 +1,86 @@
+use crate::choices::{self, ChoiceInfo};
+use crate::parser::*;
+
+/// One step of a scene as a reader sees it, see `AVG32Scene::to_script`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum ScriptLine {
+    /// Text shown in the message window, joining text opcodes until the
+    /// next line break, wait or choice. `offset` is that of the first one,
+    /// from the start of the opcode body.
+    Text { offset: u32, text: String },
+    Choice(ChoiceInfo),
+    /// `Newline`.
+    Newline,
+    /// `WaitMouse` or `WaitMouseText`, waiting for a click.
+    Wait,
+}
+
+impl AVG32Scene {
+    /// The scene's text, choices, line breaks and waits, in opcode order.
+    /// Pointers in text are resolved with `SceneText::resolve`; ones that
+    /// can't be are shown as `{@N}`, naming the string variable.
+    pub fn to_script(&self) -> Vec<ScriptLine> {
+        let mut lines = Vec::new();
+        for (offset, opcode) in self.opcodes_with_offsets() {
+            let line = match opcode {
+                Opcode::TextHankaku(_, text) | Opcode::TextZenkaku(_, text) => {
+                    let s = text.resolve(self).unwrap_or_else(|| match text {
+                        SceneText::Pointer(val) => format!("{{@{}}}", val.value()),
+                        SceneText::Literal(s) => s.clone()
+                    });
+                    if let Some(ScriptLine::Text { text, .. }) = lines.last_mut() {
+                        text.push_str(&s);
+                        continue;
+                    }
+                    ScriptLine::Text { offset, text: s }
+                },
+                Opcode::Newline => ScriptLine::Newline,
+                Opcode::WaitMouse | Opcode::WaitMouseText => ScriptLine::Wait,
+                _ => match choices::choice_info(offset, opcode) {
+                    Some(choice) => ScriptLine::Choice(choice),
+                    None => continue
+                }
+            };
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::SceneBuilder;
+
+    #[test]
+    fn test_to_script() {
+        let scene = SceneBuilder::new()
+            .opcode(Opcode::String(StringCmd::StrcpyLiteral(Val::constant(1), SceneText::Literal(String::from("祐一")))))
+            .opcode(Opcode::TextZenkaku(Some(0), SceneText::Pointer(Val::constant(1))))
+            .text("「おはよう」")
+            .newline()
+            .opcode(Opcode::TextZenkaku(Some(2), SceneText::Pointer(Val::variable(2))))
+            .wait_mouse()
+            .choice(3, &["はい", "いいえ"])
+            .text("そうか")
+            .opcode(Opcode::WaitMouseText)
+            .build()
+            .unwrap();
+        let offsets: Vec<u32> = scene.opcodes_with_offsets().map(|(offset, _)| offset).collect();
+        let text = |i: usize, s: &str| ScriptLine::Text { offset: offsets[i], text: String::from(s) };
+
+        assert_eq!(vec![
+            text(1, "祐一「おはよう」"),
+            ScriptLine::Newline,
+            text(4, "{@2}"),
+            ScriptLine::Wait,
+            ScriptLine::Choice(ChoiceInfo {
+                offset: offsets[6],
+                flag: Val::constant(3),
+                texts: vec![String::from("はい"), String::from("いいえ")]
+            }),
+            text(7, "そうか"),
+            ScriptLine::Wait,
+        ], scene.to_script());
+    }
+}
@@ -0,0 +1,321 @@
+use serde_json::{json, Value};
+
+/// Shape of one operand of an opcode, in the order the bytes appear.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operand {
+    /// A variable or constant, see `Val`.
+    Val,
+    /// A NUL-terminated Shift_JIS string, or `@` followed by a `Val`
+    /// naming a string variable.
+    Text,
+    /// A `u32` index into the scene's text table, present from
+    /// `sys_version` 1714 on.
+    TextIndex,
+    /// A `u32` offset into the opcode body.
+    Pos,
+    /// A condition list. It opens with `0x28` and ends at the `0x29`
+    /// matching it, with comparisons joined by `0x26` (And) and `0x27` (Or)
+    /// and nested in further `0x28`/`0x29` pairs.
+    Conditions,
+    /// A `u8` count, a `Val` to pick with, then that many `Pos`.
+    PosTable,
+    /// A subcommand byte followed by the operands of the named command
+    /// enum's variant for it. `COMMANDS` lists the subcommand bytes.
+    Command(&'static str),
+}
+
+impl Operand {
+    fn to_json(self) -> Value {
+        match self {
+            Operand::Val => json!("val"),
+            Operand::Text => json!("text"),
+            Operand::TextIndex => json!("text_index"),
+            Operand::Pos => json!("pos"),
+            Operand::Conditions => json!("conditions"),
+            Operand::PosTable => json!("pos_table"),
+            Operand::Command(name) => json!({ "command": name }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct OpcodeSpec {
+    pub byte: u8,
+    /// Variant of `Opcode` that the byte parses as.
+    pub name: &'static str,
+    pub operands: &'static [Operand],
+}
+
+const fn op(byte: u8, name: &'static str, operands: &'static [Operand]) -> OpcodeSpec {
+    OpcodeSpec {
+        byte,
+        name,
+        operands
+    }
+}
+
+const VAL2: &[Operand] = &[Operand::Val, Operand::Val];
+
+/// Every opcode byte the parser accepts, in byte order. The tests check this
+/// against `parser::opcode` and the writer.
+pub const OPCODES: &[OpcodeSpec] = &[
+    op(0x01, "WaitMouse", &[]),
+    op(0x02, "Newline", &[]),
+    op(0x03, "WaitMouseText", &[]),
+    op(0x04, "TextWin", &[Operand::Command("TextWinCmd")]),
+    op(0x05, "Op0x05", &[]),
+    op(0x06, "Op0x06", &[]),
+    op(0x08, "Op0x08", &[]),
+    op(0x0b, "Graphics", &[Operand::Command("GrpCmd")]),
+    op(0x0c, "Op0x0c", &[]),
+    op(0x0e, "Sound", &[Operand::Command("SndCmd")]),
+    op(0x10, "DrawValText", &[Operand::Command("FormattedTextCmd")]),
+    op(0x13, "Fade", &[Operand::Command("FadeCmd")]),
+    op(0x15, "Condition", &[Operand::Conditions, Operand::Pos]),
+    op(0x16, "JumpToScene", &[Operand::Command("JumpToSceneCmd")]),
+    op(0x17, "ScreenShake", &[Operand::Command("ScreenShakeCmd")]),
+    op(0x18, "Op0x18", &[]),
+    op(0x19, "Wait", &[Operand::Command("WaitCmd")]),
+    op(0x1a, "Op0x1a", &[]),
+    op(0x1b, "Call", &[Operand::Pos]),
+    op(0x1c, "Jump", &[Operand::Pos]),
+    op(0x1d, "TableCall", &[Operand::PosTable]),
+    op(0x1e, "TableJump", &[Operand::PosTable]),
+    op(0x20, "Return", &[Operand::Command("RetCmd")]),
+    op(0x22, "Unknown0x22", &[]),
+    op(0x23, "Unknown0x23", &[]),
+    op(0x24, "Unknown0x24", &[]),
+    op(0x25, "Unknown0x25", &[]),
+    op(0x26, "Unknown0x26", &[]),
+    op(0x27, "Unknown0x27", &[]),
+    op(0x28, "Unknown0x28", &[]),
+    op(0x29, "Unknown0x29", &[]),
+    op(0x2c, "Op0x2c", &[]),
+    op(0x2d, "Op0x2d", &[]),
+    op(0x2e, "ScenarioMenu", &[Operand::Command("ScenarioMenuCmd")]),
+    op(0x2f, "ScenarioMenu2", &[Operand::Command("ScenarioMenuCmd")]),
+    op(0x30, "Op0x30", &[]),
+    op(0x31, "TextRank", &[Operand::Command("TextRankCmd")]),
+    op(0x37, "SetFlag", VAL2),
+    op(0x39, "CopyFlag", VAL2),
+    op(0x3b, "SetValLiteral", VAL2),
+    op(0x3c, "AddVal", VAL2),
+    op(0x3d, "SubVal", VAL2),
+    op(0x3e, "MulVal", VAL2),
+    op(0x3f, "DivVal", VAL2),
+    op(0x40, "ModVal", VAL2),
+    op(0x41, "AndVal", VAL2),
+    op(0x42, "OrVal", VAL2),
+    op(0x43, "XorVal", VAL2),
+    op(0x49, "SetVal", VAL2),
+    op(0x4a, "AddValSelf", VAL2),
+    op(0x4b, "SubValSelf", VAL2),
+    op(0x4c, "MulValSelf", VAL2),
+    op(0x4d, "DivValSelf", VAL2),
+    op(0x4e, "ModValSelf", VAL2),
+    op(0x4f, "AndValSelf", VAL2),
+    op(0x50, "OrValSelf", VAL2),
+    op(0x51, "XorValSelf", VAL2),
+    op(0x56, "SetFlagRandom", &[Operand::Val]),
+    op(0x57, "SetValRandom", VAL2),
+    op(0x58, "Choice", &[Operand::Command("ChoiceCmd")]),
+    op(0x59, "String", &[Operand::Command("StringCmd")]),
+    op(0x5b, "Op0x5b", &[]),
+    op(0x5c, "SetMulti", &[Operand::Command("SetMultiCmd")]),
+    op(0x5d, "Op0x5d", &[]),
+    op(0x5e, "Op0x5e", &[]),
+    op(0x5f, "Op0x5f", &[]),
+    op(0x60, "System", &[Operand::Command("SystemCmd")]),
+    op(0x61, "Name", &[Operand::Command("NameCmd")]),
+    op(0x63, "Op0x63", &[]),
+    op(0x64, "BufferRegion", &[Operand::Command("BufferRegionGrpCmd")]),
+    op(0x65, "Unknown0x65", &[]),
+    op(0x66, "Op0x66", &[]),
+    op(0x67, "Buffer", &[Operand::Command("BufferGrpCmd")]),
+    op(0x68, "Flash", &[Operand::Command("FlashGrpCmd")]),
+    op(0x69, "Op0x69", &[]),
+    op(0x6a, "MultiPdt", &[Operand::Command("MultiPdtCmd")]),
+    op(0x6c, "AreaBuffer", &[Operand::Command("AreaBufferCmd")]),
+    op(0x6d, "MouseCtrl", &[Operand::Command("MouseCtrlCmd")]),
+    op(0x6e, "Op0x6e", &[]),
+    op(0x6f, "Op0x6f", &[]),
+    op(0x70, "WindowVar", &[Operand::Command("WindowVarCmd")]),
+    op(0x72, "MessageWin", &[Operand::Command("MessageWinCmd")]),
+    op(0x73, "SystemVar", &[Operand::Command("SystemVarCmd")]),
+    op(0x74, "PopupMenu", &[Operand::Command("PopupMenuCmd")]),
+    op(0x75, "Volume", &[Operand::Command("VolumeCmd")]),
+    op(0x76, "NovelMode", &[Operand::Command("NovelModeCmd")]),
+    op(0x7f, "Op0x7f", &[]),
+    op(0xea, "Unknown0xea", &[Operand::Val]),
+    op(0xfe, "TextHankaku", &[Operand::TextIndex, Operand::Text]),
+    op(0xff, "TextZenkaku", &[Operand::TextIndex, Operand::Text]),
+];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CommandSpec {
+    /// Command enum named by `Operand::Command`.
+    pub name: &'static str,
+    /// Every subcommand byte the parser accepts, in byte order.
+    pub subcommands: &'static [u8],
+}
+
+const fn cmd(name: &'static str, subcommands: &'static [u8]) -> CommandSpec {
+    CommandSpec {
+        name,
+        subcommands
+    }
+}
+
+/// Every command enum in `OPCODES`, in the order they first appear there.
+/// The tests check this against the parser and the writer.
+pub const COMMANDS: &[CommandSpec] = &[
+    cmd("TextWinCmd", &[0x01, 0x02, 0x03, 0x04, 0x05]),
+    cmd("GrpCmd", &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x08, 0x09, 0x10, 0x11, 0x13, 0x22, 0x24, 0x30, 0x31, 0x32, 0x33, 0x50, 0x52, 0x54]),
+    cmd("SndCmd", &[0x01, 0x02, 0x03, 0x05, 0x06, 0x07, 0x10, 0x11, 0x12, 0x16, 0x20, 0x21, 0x22, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x44, 0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x60]),
+    cmd("FormattedTextCmd", &[0x01, 0x02, 0x03, 0x11, 0x13]),
+    cmd("FadeCmd", &[0x01, 0x02, 0x03, 0x04, 0x10, 0x11]),
+    cmd("JumpToSceneCmd", &[0x01, 0x02]),
+    cmd("ScreenShakeCmd", &[0x01]),
+    cmd("WaitCmd", &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x10, 0x11, 0x12, 0x13]),
+    cmd("RetCmd", &[0x01, 0x02, 0x03, 0x06]),
+    cmd("ScenarioMenuCmd", &[0x01, 0x02]),
+    cmd("TextRankCmd", &[0x01, 0x02]),
+    cmd("ChoiceCmd", &[0x01, 0x02, 0x04]),
+    cmd("StringCmd", &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]),
+    cmd("SetMultiCmd", &[0x01, 0x02]),
+    cmd("SystemCmd", &[0x02, 0x03, 0x04, 0x05, 0x20, 0x30, 0x31, 0x35, 0x36, 0x37]),
+    cmd("NameCmd", &[0x01, 0x02, 0x03, 0x04, 0x10, 0x11, 0x12, 0x20, 0x21, 0x24, 0x30, 0x31]),
+    cmd("BufferRegionGrpCmd", &[0x02, 0x04, 0x07, 0x10, 0x11, 0x12, 0x15, 0x20, 0x30, 0x32]),
+    cmd("BufferGrpCmd", &[0x00, 0x01, 0x02, 0x03, 0x05, 0x08, 0x11, 0x12, 0x20, 0x21, 0x22]),
+    cmd("FlashGrpCmd", &[0x01, 0x10]),
+    cmd("MultiPdtCmd", &[0x03, 0x04, 0x05, 0x10, 0x20, 0x30]),
+    cmd("AreaBufferCmd", &[0x02, 0x03, 0x04, 0x05, 0x10, 0x11, 0x15, 0x20]),
+    cmd("MouseCtrlCmd", &[0x01, 0x02, 0x03, 0x20, 0x21]),
+    cmd("WindowVarCmd", &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x10, 0x11]),
+    cmd("MessageWinCmd", &[0x01, 0x02, 0x03, 0x04, 0x05, 0x11, 0x12, 0x13, 0x14, 0x15]),
+    cmd("SystemVarCmd", &[0x01, 0x02, 0x05, 0x06, 0x10, 0x11, 0x12, 0x13, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34]),
+    cmd("PopupMenuCmd", &[0x01, 0x02, 0x03, 0x04]),
+    cmd("VolumeCmd", &[0x01, 0x02, 0x03, 0x04, 0x11, 0x12, 0x13, 0x14, 0x21, 0x22, 0x23, 0x24]),
+    cmd("NovelModeCmd", &[0x01, 0x02, 0x03, 0x04, 0x05]),
+];
+
+/// `OPCODES` and `COMMANDS` as JSON, for tools that can't read the Rust
+/// source:
+///
+/// ```json
+/// {"opcodes": [{"byte": 21, "name": "Condition", "operands": ["conditions", "pos"]}, ...],
+///  "commands": [{"name": "TextWinCmd", "subcommands": [1, 2, 3, 4, 5]}, ...]}
+/// ```
+pub fn dump() -> Value {
+    let opcodes: Vec<Value> = OPCODES.iter()
+        .map(|spec| json!({
+            "byte": spec.byte,
+            "name": spec.name,
+            "operands": spec.operands.iter().map(|o| o.to_json()).collect::<Vec<_>>()
+        }))
+        .collect();
+    let commands: Vec<Value> = COMMANDS.iter()
+        .map(|spec| json!({
+            "name": spec.name,
+            "subcommands": spec.subcommands
+        }))
+        .collect();
+
+    json!({ "opcodes": opcodes, "commands": commands })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{self, CustomError};
+    use crate::stats::variant_name;
+    use crate::write::Writeable;
+
+    /// Whether `parser::opcode` has a case for `bytes`, as opposed to
+    /// failing on their last byte.
+    fn parser_handles(bytes: &[u8]) -> bool {
+        let mut input = bytes.to_vec();
+        input.extend_from_slice(&[0u8; 64]);
+        match parser::opcode(&input) {
+            Err(nom::Err::Error(CustomError::Nom(at, nom::error::ErrorKind::Switch))) => at.len() != input.len() - bytes.len() + 1,
+            _ => true
+        }
+    }
+
+    fn command(name: &str) -> &'static CommandSpec {
+        COMMANDS.iter().find(|spec| spec.name == name).unwrap_or_else(|| panic!("{} missing from COMMANDS", name))
+    }
+
+    /// Consumes the operands in `operands` from `input` with the parser's
+    /// own operand parsers, stopping at a `Command`'s subcommand byte.
+    fn skip_operands<'a>(operands: &[Operand], mut input: &'a [u8]) -> &'a [u8] {
+        for operand in operands.iter() {
+            input = match operand {
+                Operand::Val => parser::scene_value(input).unwrap().0,
+                Operand::Text => parser::scene_text(input).unwrap().0,
+                Operand::TextIndex if parser::sys_version_geq(1714) => &input[4..],
+                Operand::TextIndex => input,
+                Operand::Pos => parser::scene_pos(input).unwrap().0,
+                Operand::Conditions => {
+                    assert_eq!(Some(&0x28), input.first());
+                    parser::scene_conditions(input).unwrap().0
+                },
+                Operand::PosTable => {
+                    let (inp, _) = parser::scene_value(&input[1..]).unwrap();
+                    &inp[input[0] as usize * 4..]
+                },
+                Operand::Command(_) => return input
+            };
+        }
+        input
+    }
+
+    #[test]
+    fn test_spec_matches_parser() {
+        let listed: Vec<u8> = OPCODES.iter().map(|spec| spec.byte).collect();
+        let handled: Vec<u8> = (0..=255).filter(|&b| parser_handles(&[b])).collect();
+        assert_eq!(handled, listed);
+    }
+
+    #[test]
+    fn test_commands_match_parser() {
+        let mut named = Vec::new();
+        for spec in OPCODES.iter() {
+            if let Some(Operand::Command(name)) = spec.operands.last() {
+                let handled: Vec<u8> = (0..=255).filter(|&b| parser_handles(&[spec.byte, b])).collect();
+                assert_eq!(handled, command(name).subcommands, "{}", name);
+                if !named.contains(name) {
+                    named.push(*name);
+                }
+            }
+        }
+        assert_eq!(named, COMMANDS.iter().map(|spec| spec.name).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_spec_matches_writer() {
+        for opcode in crate::write::tests::every_opcode().iter() {
+            let bytes = opcode.to_vec().unwrap();
+            let spec = OPCODES.iter().find(|spec| spec.byte == bytes[0]).unwrap();
+            assert_eq!(variant_name(opcode), spec.name, "{:?}", opcode);
+
+            let rest = skip_operands(spec.operands, &bytes[1..]);
+            match spec.operands.last() {
+                Some(Operand::Command(name)) => assert!(command(name).subcommands.contains(&rest[0]), "{:?}", opcode),
+                _ => assert!(rest.is_empty(), "{:?} has {} bytes left over", opcode, rest.len())
+            }
+        }
+    }
+
+    #[test]
+    fn test_dump() {
+        let dump = dump();
+        let opcodes = dump["opcodes"].as_array().unwrap();
+        assert_eq!(OPCODES.len(), opcodes.len());
+        assert_eq!(json!({ "byte": 0x1d, "name": "TableCall", "operands": ["pos_table"] }), opcodes[20]);
+        assert_eq!(json!({ "command": "GrpCmd" }), opcodes[7]["operands"][0]);
+        let commands = dump["commands"].as_array().unwrap();
+        assert_eq!(COMMANDS.len(), commands.len());
+        assert_eq!(json!({ "name": "JumpToSceneCmd", "subcommands": [1, 2] }), commands[5]);
+    }
+}
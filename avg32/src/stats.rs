@@ -0,0 +1,805 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::parser::*;
+
+/// Opcode frequencies for one or more scenes.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct OpcodeStats {
+    /// Count of each `Opcode` variant, e.g. `Graphics`.
+    pub opcodes: BTreeMap<String, usize>,
+    /// Count of each nested command, e.g. `Graphics::Load`.
+    pub subcommands: BTreeMap<String, usize>,
+    /// Characters in literal text opcodes.
+    pub text_chars: usize,
+    /// Conditions, jumps, calls and table jumps/calls.
+    pub branches: usize,
+}
+
+impl OpcodeStats {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add(&mut self, opcode: &Opcode) {
+        let name = variant_name(opcode);
+        if let Some(sub) = subcommand(opcode) {
+            *self.subcommands.entry(format!("{}::{}", name, variant_name(sub))).or_insert(0) += 1;
+        }
+        *self.opcodes.entry(name).or_insert(0) += 1;
+
+        match opcode {
+            Opcode::TextHankaku(_, SceneText::Literal(s)) |
+            Opcode::TextZenkaku(_, SceneText::Literal(s)) => self.text_chars += s.chars().count(),
+            Opcode::Condition(..) | Opcode::Call(_) | Opcode::Jump(_) |
+            Opcode::TableCall(..) | Opcode::TableJump(..) => self.branches += 1,
+            _ => ()
+        }
+    }
+
+    pub fn merge(&mut self, other: &OpcodeStats) {
+        for (name, count) in other.opcodes.iter() {
+            *self.opcodes.entry(name.clone()).or_insert(0) += count;
+        }
+        for (name, count) in other.subcommands.iter() {
+            *self.subcommands.entry(name.clone()).or_insert(0) += count;
+        }
+        self.text_chars += other.text_chars;
+        self.branches += other.branches;
+    }
+
+    pub fn total(&self) -> usize {
+        self.opcodes.values().sum()
+    }
+}
+
+/// Amount of text a reader sees in a scene.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct TextMetrics {
+    /// Runs of text ended by `Newline`, `WaitMouseText` or the end of the
+    /// scene.
+    pub lines: usize,
+    /// Characters in literal text opcodes.
+    pub chars: usize,
+    pub choices: usize,
+}
+
+impl TextMetrics {
+    pub fn merge(&mut self, other: &TextMetrics) {
+        self.lines += other.lines;
+        self.chars += other.chars;
+        self.choices += other.choices;
+    }
+}
+
+impl AVG32Scene {
+    pub fn text_metrics(&self) -> TextMetrics {
+        let mut res = TextMetrics::default();
+        let mut in_line = false;
+        for opcode in self.opcodes.iter() {
+            match opcode {
+                Opcode::TextHankaku(_, text) | Opcode::TextZenkaku(_, text) => {
+                    if let SceneText::Literal(s) = text {
+                        res.chars += s.chars().count();
+                    }
+                    in_line = true;
+                },
+                Opcode::Newline | Opcode::WaitMouseText => {
+                    if in_line {
+                        res.lines += 1;
+                    }
+                    in_line = false;
+                },
+                Opcode::Choice(_) => res.choices += 1,
+                _ => ()
+            }
+        }
+        if in_line {
+            res.lines += 1;
+        }
+        res
+    }
+
+    /// Distinct bytes of the top-level opcodes in the scene.
+    pub fn opcode_set(&self) -> BTreeSet<u8> {
+        self.opcodes.iter().map(|opcode| opcode.byte()).collect()
+    }
+
+    /// Distinct nested commands in the scene, named like
+    /// `OpcodeStats::subcommands`.
+    pub fn subcommand_set(&self) -> BTreeSet<String> {
+        self.opcodes.iter()
+            .filter(|opcode| subcommand(opcode).is_some())
+            .map(qualified_name)
+            .collect()
+    }
+}
+
+/// Name of the opcode's variant and of its nested command if it has one,
+/// e.g. `Graphics::Load` or `WaitMouse`.
+pub fn qualified_name(opcode: &Opcode) -> String {
+    match subcommand(opcode) {
+        Some(sub) => format!("{}::{}", variant_name(opcode), variant_name(sub)),
+        None => variant_name(opcode)
+    }
+}
+
+/// Name of an enum variant, e.g. `Sound` for any `Opcode::Sound`.
+pub fn variant_name<T: VariantName + ?Sized>(value: &T) -> String {
+    String::from(value.variant_name())
+}
+
+/// Names the variant of `Opcode` or of a command enum, as written in the
+/// source.
+pub trait VariantName {
+    fn variant_name(&self) -> &'static str;
+}
+
+fn subcommand(opcode: &Opcode) -> Option<&dyn VariantName> {
+    match opcode {
+        Opcode::TextWin(c) => Some(c),
+        Opcode::Graphics(c) => Some(c),
+        Opcode::Sound(c) => Some(c),
+        Opcode::DrawValText(c) => Some(c),
+        Opcode::Fade(c) => Some(c),
+        Opcode::JumpToScene(c) => Some(c),
+        Opcode::ScreenShake(c) => Some(c),
+        Opcode::Wait(c) => Some(c),
+        Opcode::Return(c) => Some(c),
+        Opcode::ScenarioMenu(c) | Opcode::ScenarioMenu2(c) => Some(c),
+        Opcode::TextRank(c) => Some(c),
+        Opcode::Choice(c) => Some(c),
+        Opcode::String(c) => Some(c),
+        Opcode::SetMulti(c) => Some(c),
+        Opcode::System(c) => Some(c),
+        Opcode::Name(c) => Some(c),
+        Opcode::BufferRegion(c) => Some(c),
+        Opcode::Buffer(c) => Some(c),
+        Opcode::Flash(c) => Some(c),
+        Opcode::MultiPdt(c) => Some(c),
+        Opcode::AreaBuffer(c) => Some(c),
+        Opcode::MouseCtrl(c) => Some(c),
+        Opcode::WindowVar(c) => Some(c),
+        Opcode::MessageWin(c) => Some(c),
+        Opcode::SystemVar(c) => Some(c),
+        Opcode::PopupMenu(c) => Some(c),
+        Opcode::Volume(c) => Some(c),
+        Opcode::NovelMode(c) => Some(c),
+        _ => None
+    }
+}
+
+impl VariantName for Opcode {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            Opcode::WaitMouse => "WaitMouse",
+            Opcode::Newline => "Newline",
+            Opcode::WaitMouseText => "WaitMouseText",
+            Opcode::TextWin(..) => "TextWin",
+            Opcode::Op0x05 => "Op0x05",
+            Opcode::Op0x06 => "Op0x06",
+            Opcode::Op0x08 => "Op0x08",
+            Opcode::Graphics(..) => "Graphics",
+            Opcode::Op0x0c => "Op0x0c",
+            Opcode::Sound(..) => "Sound",
+            Opcode::DrawValText(..) => "DrawValText",
+            Opcode::Fade(..) => "Fade",
+            Opcode::Condition(..) => "Condition",
+            Opcode::JumpToScene(..) => "JumpToScene",
+            Opcode::ScreenShake(..) => "ScreenShake",
+            Opcode::Op0x18 => "Op0x18",
+            Opcode::Wait(..) => "Wait",
+            Opcode::Op0x1a => "Op0x1a",
+            Opcode::Call(..) => "Call",
+            Opcode::Jump(..) => "Jump",
+            Opcode::TableCall(..) => "TableCall",
+            Opcode::TableJump(..) => "TableJump",
+            Opcode::Return(..) => "Return",
+            Opcode::Unknown0x22 => "Unknown0x22",
+            Opcode::Unknown0x23 => "Unknown0x23",
+            Opcode::Unknown0x24 => "Unknown0x24",
+            Opcode::Unknown0x25 => "Unknown0x25",
+            Opcode::Unknown0x26 => "Unknown0x26",
+            Opcode::Unknown0x27 => "Unknown0x27",
+            Opcode::Unknown0x28 => "Unknown0x28",
+            Opcode::Unknown0x29 => "Unknown0x29",
+            Opcode::Op0x2c => "Op0x2c",
+            Opcode::Op0x2d => "Op0x2d",
+            Opcode::ScenarioMenu(..) => "ScenarioMenu",
+            Opcode::ScenarioMenu2(..) => "ScenarioMenu2",
+            Opcode::Op0x30 => "Op0x30",
+            Opcode::TextRank(..) => "TextRank",
+            Opcode::SetFlag(..) => "SetFlag",
+            Opcode::CopyFlag(..) => "CopyFlag",
+            Opcode::SetValLiteral(..) => "SetValLiteral",
+            Opcode::AddVal(..) => "AddVal",
+            Opcode::SubVal(..) => "SubVal",
+            Opcode::MulVal(..) => "MulVal",
+            Opcode::DivVal(..) => "DivVal",
+            Opcode::ModVal(..) => "ModVal",
+            Opcode::AndVal(..) => "AndVal",
+            Opcode::OrVal(..) => "OrVal",
+            Opcode::XorVal(..) => "XorVal",
+            Opcode::SetVal(..) => "SetVal",
+            Opcode::AddValSelf(..) => "AddValSelf",
+            Opcode::SubValSelf(..) => "SubValSelf",
+            Opcode::MulValSelf(..) => "MulValSelf",
+            Opcode::DivValSelf(..) => "DivValSelf",
+            Opcode::ModValSelf(..) => "ModValSelf",
+            Opcode::AndValSelf(..) => "AndValSelf",
+            Opcode::OrValSelf(..) => "OrValSelf",
+            Opcode::XorValSelf(..) => "XorValSelf",
+            Opcode::SetFlagRandom(..) => "SetFlagRandom",
+            Opcode::SetValRandom(..) => "SetValRandom",
+            Opcode::Choice(..) => "Choice",
+            Opcode::String(..) => "String",
+            Opcode::Op0x5b => "Op0x5b",
+            Opcode::SetMulti(..) => "SetMulti",
+            Opcode::Op0x5d => "Op0x5d",
+            Opcode::Op0x5e => "Op0x5e",
+            Opcode::Op0x5f => "Op0x5f",
+            Opcode::System(..) => "System",
+            Opcode::Name(..) => "Name",
+            Opcode::Op0x63 => "Op0x63",
+            Opcode::BufferRegion(..) => "BufferRegion",
+            Opcode::Unknown0x65 => "Unknown0x65",
+            Opcode::Buffer(..) => "Buffer",
+            Opcode::Flash(..) => "Flash",
+            Opcode::Op0x69 => "Op0x69",
+            Opcode::MultiPdt(..) => "MultiPdt",
+            Opcode::Op0x66 => "Op0x66",
+            Opcode::AreaBuffer(..) => "AreaBuffer",
+            Opcode::MouseCtrl(..) => "MouseCtrl",
+            Opcode::Op0x6e => "Op0x6e",
+            Opcode::Op0x6f => "Op0x6f",
+            Opcode::WindowVar(..) => "WindowVar",
+            Opcode::MessageWin(..) => "MessageWin",
+            Opcode::SystemVar(..) => "SystemVar",
+            Opcode::PopupMenu(..) => "PopupMenu",
+            Opcode::Volume(..) => "Volume",
+            Opcode::NovelMode(..) => "NovelMode",
+            Opcode::Op0x7f => "Op0x7f",
+            Opcode::Unknown0xea(..) => "Unknown0xea",
+            Opcode::TextHankaku(..) => "TextHankaku",
+            Opcode::TextZenkaku(..) => "TextZenkaku",
+            Opcode::UnknownRaw(..) => "UnknownRaw",
+        }
+    }
+}
+
+impl VariantName for TextWinCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            TextWinCmd::Hide => "Hide",
+            TextWinCmd::HideEffect => "HideEffect",
+            TextWinCmd::HideRedraw => "HideRedraw",
+            TextWinCmd::MouseWait => "MouseWait",
+            TextWinCmd::ClearText => "ClearText",
+        }
+    }
+}
+
+impl VariantName for GrpCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            GrpCmd::Load(..) => "Load",
+            GrpCmd::LoadEffect(..) => "LoadEffect",
+            GrpCmd::Load2(..) => "Load2",
+            GrpCmd::LoadEffect2(..) => "LoadEffect2",
+            GrpCmd::Load3(..) => "Load3",
+            GrpCmd::LoadEffect3(..) => "LoadEffect3",
+            GrpCmd::Unknown1 => "Unknown1",
+            GrpCmd::LoadToBuf(..) => "LoadToBuf",
+            GrpCmd::LoadToBuf2(..) => "LoadToBuf2",
+            GrpCmd::LoadCaching(..) => "LoadCaching",
+            GrpCmd::GrpCmd0x13 => "GrpCmd0x13",
+            GrpCmd::LoadComposite(..) => "LoadComposite",
+            GrpCmd::LoadCompositeIndexed(..) => "LoadCompositeIndexed",
+            GrpCmd::MacroBufferClear => "MacroBufferClear",
+            GrpCmd::MacroBufferDelete(..) => "MacroBufferDelete",
+            GrpCmd::MacroBufferRead(..) => "MacroBufferRead",
+            GrpCmd::MacroBufferSet(..) => "MacroBufferSet",
+            GrpCmd::BackupScreenCopy => "BackupScreenCopy",
+            GrpCmd::BackupScreenDisplay(..) => "BackupScreenDisplay",
+            GrpCmd::LoadToBuf3(..) => "LoadToBuf3",
+        }
+    }
+}
+
+impl VariantName for SndCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            SndCmd::BgmLoop(..) => "BgmLoop",
+            SndCmd::BgmWait(..) => "BgmWait",
+            SndCmd::BgmOnce(..) => "BgmOnce",
+            SndCmd::BgmFadeInLoop(..) => "BgmFadeInLoop",
+            SndCmd::BgmFadeInWait(..) => "BgmFadeInWait",
+            SndCmd::BgmFadeInOnce(..) => "BgmFadeInOnce",
+            SndCmd::BgmFadeOut(..) => "BgmFadeOut",
+            SndCmd::BgmStop => "BgmStop",
+            SndCmd::BgmRewind => "BgmRewind",
+            SndCmd::BgmUnknown1 => "BgmUnknown1",
+            SndCmd::KoePlayWait(..) => "KoePlayWait",
+            SndCmd::KoePlay(..) => "KoePlay",
+            SndCmd::KoePlay2(..) => "KoePlay2",
+            SndCmd::WavPlay(..) => "WavPlay",
+            SndCmd::WavPlay2(..) => "WavPlay2",
+            SndCmd::WavLoop(..) => "WavLoop",
+            SndCmd::WavLoop2(..) => "WavLoop2",
+            SndCmd::WavPlayWait(..) => "WavPlayWait",
+            SndCmd::WavPlayWait2(..) => "WavPlayWait2",
+            SndCmd::WavStop => "WavStop",
+            SndCmd::WavStop2(..) => "WavStop2",
+            SndCmd::WavStop3 => "WavStop3",
+            SndCmd::WavUnknown0x39(..) => "WavUnknown0x39",
+            SndCmd::SePlay(..) => "SePlay",
+            SndCmd::MoviePlay(..) => "MoviePlay",
+            SndCmd::MovieLoop(..) => "MovieLoop",
+            SndCmd::MovieWait(..) => "MovieWait",
+            SndCmd::MovieWaitCancelable(..) => "MovieWaitCancelable",
+            SndCmd::MovieWait2(..) => "MovieWait2",
+            SndCmd::MovieWaitCancelable2(..) => "MovieWaitCancelable2",
+            SndCmd::Unknown1 => "Unknown1",
+        }
+    }
+}
+
+impl VariantName for FormattedTextCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            FormattedTextCmd::Integer(..) => "Integer",
+            FormattedTextCmd::IntegerZeroPadded(..) => "IntegerZeroPadded",
+            FormattedTextCmd::TextPointer(..) => "TextPointer",
+            FormattedTextCmd::Color(..) => "Color",
+            FormattedTextCmd::ResetColor => "ResetColor",
+        }
+    }
+}
+
+impl VariantName for FadeCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            FadeCmd::Fade(..) => "Fade",
+            FadeCmd::FadeTimed(..) => "FadeTimed",
+            FadeCmd::FadeColor(..) => "FadeColor",
+            FadeCmd::FadeTimedColor(..) => "FadeTimedColor",
+            FadeCmd::FillScreen(..) => "FillScreen",
+            FadeCmd::FillScreenColor(..) => "FillScreenColor",
+        }
+    }
+}
+
+impl VariantName for JumpToSceneCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            JumpToSceneCmd::Jump(..) => "Jump",
+            JumpToSceneCmd::Call(..) => "Call",
+        }
+    }
+}
+
+impl VariantName for ScreenShakeCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            ScreenShakeCmd::ScreenShake(..) => "ScreenShake",
+        }
+    }
+}
+
+impl VariantName for WaitCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            WaitCmd::Wait(..) => "Wait",
+            WaitCmd::WaitMouse(..) => "WaitMouse",
+            WaitCmd::SetToBase => "SetToBase",
+            WaitCmd::WaitFromBase(..) => "WaitFromBase",
+            WaitCmd::WaitFromBaseMouse(..) => "WaitFromBaseMouse",
+            WaitCmd::SetToBaseVal(..) => "SetToBaseVal",
+            WaitCmd::Wait0x10 => "Wait0x10",
+            WaitCmd::Wait0x11 => "Wait0x11",
+            WaitCmd::Wait0x12 => "Wait0x12",
+            WaitCmd::Wait0x13 => "Wait0x13",
+        }
+    }
+}
+
+impl VariantName for RetCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            RetCmd::SameScene => "SameScene",
+            RetCmd::OtherScene => "OtherScene",
+            RetCmd::PopStack => "PopStack",
+            RetCmd::ClearStack => "ClearStack",
+        }
+    }
+}
+
+impl VariantName for ScenarioMenuCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            ScenarioMenuCmd::SetBit(..) => "SetBit",
+            ScenarioMenuCmd::SetBit2(..) => "SetBit2",
+        }
+    }
+}
+
+impl VariantName for TextRankCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            TextRankCmd::Set(..) => "Set",
+            TextRankCmd::Clear => "Clear",
+        }
+    }
+}
+
+impl VariantName for ChoiceCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            ChoiceCmd::Choice(..) => "Choice",
+            ChoiceCmd::Choice2(..) => "Choice2",
+            ChoiceCmd::LoadMenu(..) => "LoadMenu",
+        }
+    }
+}
+
+impl VariantName for StringCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            StringCmd::StrcpyLiteral(..) => "StrcpyLiteral",
+            StringCmd::Strlen(..) => "Strlen",
+            StringCmd::Strcmp(..) => "Strcmp",
+            StringCmd::Strcat(..) => "Strcat",
+            StringCmd::Strcpy(..) => "Strcpy",
+            StringCmd::Itoa(..) => "Itoa",
+            StringCmd::HanToZen(..) => "HanToZen",
+            StringCmd::Atoi(..) => "Atoi",
+        }
+    }
+}
+
+impl VariantName for SetMultiCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            SetMultiCmd::Val(..) => "Val",
+            SetMultiCmd::Bit(..) => "Bit",
+        }
+    }
+}
+
+impl VariantName for SystemCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            SystemCmd::LoadGame(..) => "LoadGame",
+            SystemCmd::SaveGame(..) => "SaveGame",
+            SystemCmd::SetTitle(..) => "SetTitle",
+            SystemCmd::MakePopup => "MakePopup",
+            SystemCmd::GameEnd => "GameEnd",
+            SystemCmd::GetSaveTitle(..) => "GetSaveTitle",
+            SystemCmd::CheckSaveData(..) => "CheckSaveData",
+            SystemCmd::Unknown1(..) => "Unknown1",
+            SystemCmd::Unknown2(..) => "Unknown2",
+            SystemCmd::Unknown3(..) => "Unknown3",
+        }
+    }
+}
+
+impl VariantName for NameCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            NameCmd::InputBox(..) => "InputBox",
+            NameCmd::InputBoxFinish(..) => "InputBoxFinish",
+            NameCmd::InputBoxStart(..) => "InputBoxStart",
+            NameCmd::InputBoxClose(..) => "InputBoxClose",
+            NameCmd::GetName(..) => "GetName",
+            NameCmd::SetName(..) => "SetName",
+            NameCmd::GetName2(..) => "GetName2",
+            NameCmd::NameInputDialog(..) => "NameInputDialog",
+            NameCmd::Unknown1(..) => "Unknown1",
+            NameCmd::NameInputDialogMulti(..) => "NameInputDialogMulti",
+            NameCmd::Unknown2 => "Unknown2",
+            NameCmd::Unknown3 => "Unknown3",
+        }
+    }
+}
+
+impl VariantName for BufferRegionGrpCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            BufferRegionGrpCmd::ClearRect(..) => "ClearRect",
+            BufferRegionGrpCmd::DrawRectLine(..) => "DrawRectLine",
+            BufferRegionGrpCmd::InvertColor(..) => "InvertColor",
+            BufferRegionGrpCmd::ColorMask(..) => "ColorMask",
+            BufferRegionGrpCmd::FadeOutColor(..) => "FadeOutColor",
+            BufferRegionGrpCmd::FadeOutColor2(..) => "FadeOutColor2",
+            BufferRegionGrpCmd::FadeOutColor3(..) => "FadeOutColor3",
+            BufferRegionGrpCmd::MakeMonoImage(..) => "MakeMonoImage",
+            BufferRegionGrpCmd::StretchBlit(..) => "StretchBlit",
+            BufferRegionGrpCmd::StretchBlitEffect(..) => "StretchBlitEffect",
+        }
+    }
+}
+
+impl VariantName for BufferGrpCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            BufferGrpCmd::CopySamePos(..) => "CopySamePos",
+            BufferGrpCmd::CopyNewPos(..) => "CopyNewPos",
+            BufferGrpCmd::CopyNewPosMask(..) => "CopyNewPosMask",
+            BufferGrpCmd::CopyColor(..) => "CopyColor",
+            BufferGrpCmd::Swap(..) => "Swap",
+            BufferGrpCmd::CopyWithMask(..) => "CopyWithMask",
+            BufferGrpCmd::CopyWholeScreen(..) => "CopyWholeScreen",
+            BufferGrpCmd::CopyWholeScreenMask(..) => "CopyWholeScreenMask",
+            BufferGrpCmd::DisplayStrings(..) => "DisplayStrings",
+            BufferGrpCmd::DisplayStringsMask(..) => "DisplayStringsMask",
+            BufferGrpCmd::DisplayStringsColor(..) => "DisplayStringsColor",
+        }
+    }
+}
+
+impl VariantName for FlashGrpCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            FlashGrpCmd::FillColor(..) => "FillColor",
+            FlashGrpCmd::FlashScreen(..) => "FlashScreen",
+        }
+    }
+}
+
+impl VariantName for MultiPdtCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            MultiPdtCmd::Slideshow(..) => "Slideshow",
+            MultiPdtCmd::SlideshowLoop(..) => "SlideshowLoop",
+            MultiPdtCmd::StopSlideshowLoop => "StopSlideshowLoop",
+            MultiPdtCmd::Scroll(..) => "Scroll",
+            MultiPdtCmd::Scroll2(..) => "Scroll2",
+            MultiPdtCmd::ScrollWithCancel(..) => "ScrollWithCancel",
+        }
+    }
+}
+
+impl VariantName for AreaBufferCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            AreaBufferCmd::ReadCurArd(..) => "ReadCurArd",
+            AreaBufferCmd::Init => "Init",
+            AreaBufferCmd::GetClickedArea(..) => "GetClickedArea",
+            AreaBufferCmd::GetClickedArea2(..) => "GetClickedArea2",
+            AreaBufferCmd::DisableArea(..) => "DisableArea",
+            AreaBufferCmd::EnableArea(..) => "EnableArea",
+            AreaBufferCmd::GetArea(..) => "GetArea",
+            AreaBufferCmd::AssignArea(..) => "AssignArea",
+        }
+    }
+}
+
+impl VariantName for MouseCtrlCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            MouseCtrlCmd::WaitForClick => "WaitForClick",
+            MouseCtrlCmd::SetPos(..) => "SetPos",
+            MouseCtrlCmd::FlushClickData => "FlushClickData",
+            MouseCtrlCmd::CursorOff => "CursorOff",
+            MouseCtrlCmd::CursorOn => "CursorOn",
+        }
+    }
+}
+
+impl VariantName for WindowVarCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            WindowVarCmd::GetBgFlagColor(..) => "GetBgFlagColor",
+            WindowVarCmd::SetBgFlagColor(..) => "SetBgFlagColor",
+            WindowVarCmd::GetWindowMove(..) => "GetWindowMove",
+            WindowVarCmd::SetWindowMove(..) => "SetWindowMove",
+            WindowVarCmd::GetWindowClearBox(..) => "GetWindowClearBox",
+            WindowVarCmd::SetWindowClearBox(..) => "SetWindowClearBox",
+            WindowVarCmd::GetWindowWaku(..) => "GetWindowWaku",
+            WindowVarCmd::SetWindowWaku(..) => "SetWindowWaku",
+        }
+    }
+}
+
+impl VariantName for MessageWinCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            MessageWinCmd::GetWindowMsgPos(..) => "GetWindowMsgPos",
+            MessageWinCmd::GetWindowComPos(..) => "GetWindowComPos",
+            MessageWinCmd::GetWindowSysPos(..) => "GetWindowSysPos",
+            MessageWinCmd::GetWindowSubPos(..) => "GetWindowSubPos",
+            MessageWinCmd::GetWindowGrpPos(..) => "GetWindowGrpPos",
+            MessageWinCmd::SetWindowMsgPos(..) => "SetWindowMsgPos",
+            MessageWinCmd::SetWindowComPos(..) => "SetWindowComPos",
+            MessageWinCmd::SetWindowSysPos(..) => "SetWindowSysPos",
+            MessageWinCmd::SetWindowSubPos(..) => "SetWindowSubPos",
+            MessageWinCmd::SetWindowGrpPos(..) => "SetWindowGrpPos",
+        }
+    }
+}
+
+impl VariantName for SystemVarCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            SystemVarCmd::GetMessageSize(..) => "GetMessageSize",
+            SystemVarCmd::SetMessageSize(..) => "SetMessageSize",
+            SystemVarCmd::GetMsgMojiSize(..) => "GetMsgMojiSize",
+            SystemVarCmd::SetMsgMojiSize(..) => "SetMsgMojiSize",
+            SystemVarCmd::GetMojiColor(..) => "GetMojiColor",
+            SystemVarCmd::SetMojiColor(..) => "SetMojiColor",
+            SystemVarCmd::GetMsgCancel(..) => "GetMsgCancel",
+            SystemVarCmd::SetMsgCancel(..) => "SetMsgCancel",
+            SystemVarCmd::GetMojiKage(..) => "GetMojiKage",
+            SystemVarCmd::SetMojiKage(..) => "SetMojiKage",
+            SystemVarCmd::GetKageColor(..) => "GetKageColor",
+            SystemVarCmd::SetKageColor(..) => "SetKageColor",
+            SystemVarCmd::GetSelCancel(..) => "GetSelCancel",
+            SystemVarCmd::SetSelCancel(..) => "SetSelCancel",
+            SystemVarCmd::GetCtrlKey(..) => "GetCtrlKey",
+            SystemVarCmd::SetCtrlKey(..) => "SetCtrlKey",
+            SystemVarCmd::GetSaveStart(..) => "GetSaveStart",
+            SystemVarCmd::SetSaveStart(..) => "SetSaveStart",
+            SystemVarCmd::GetDisableNvlTextFlag(..) => "GetDisableNvlTextFlag",
+            SystemVarCmd::SetDisableNvlTextFlag(..) => "SetDisableNvlTextFlag",
+            SystemVarCmd::GetFadeTime(..) => "GetFadeTime",
+            SystemVarCmd::SetFadeTime(..) => "SetFadeTime",
+            SystemVarCmd::GetCursorMono(..) => "GetCursorMono",
+            SystemVarCmd::SetCursorMono(..) => "SetCursorMono",
+            SystemVarCmd::GetCopyWindSw(..) => "GetCopyWindSw",
+            SystemVarCmd::SetCopyWindSw(..) => "SetCopyWindSw",
+            SystemVarCmd::GetMsgSpeed(..) => "GetMsgSpeed",
+            SystemVarCmd::SetMsgSpeed(..) => "SetMsgSpeed",
+            SystemVarCmd::GetMsgSpeed2(..) => "GetMsgSpeed2",
+            SystemVarCmd::SetMsgSpeed2(..) => "SetMsgSpeed2",
+            SystemVarCmd::GetReturnKeyWait(..) => "GetReturnKeyWait",
+            SystemVarCmd::SetReturnKeyWait(..) => "SetReturnKeyWait",
+            SystemVarCmd::GetKoeTextType(..) => "GetKoeTextType",
+            SystemVarCmd::SetKoeTextType(..) => "SetKoeTextType",
+            SystemVarCmd::GetGameSpeckInit(..) => "GetGameSpeckInit",
+            SystemVarCmd::SetCursorPosition(..) => "SetCursorPosition",
+            SystemVarCmd::SetDisableKeyMouseFlag(..) => "SetDisableKeyMouseFlag",
+            SystemVarCmd::GetGameSpeckInit2(..) => "GetGameSpeckInit2",
+            SystemVarCmd::SetGameSpeckInit(..) => "SetGameSpeckInit",
+        }
+    }
+}
+
+impl VariantName for PopupMenuCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            PopupMenuCmd::GetMenuDisabled(..) => "GetMenuDisabled",
+            PopupMenuCmd::SetMenuDisabled(..) => "SetMenuDisabled",
+            PopupMenuCmd::GetItemDisabled(..) => "GetItemDisabled",
+            PopupMenuCmd::SetItemDisabled(..) => "SetItemDisabled",
+        }
+    }
+}
+
+impl VariantName for VolumeCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            VolumeCmd::GetBgmVolume(..) => "GetBgmVolume",
+            VolumeCmd::GetWavVolume(..) => "GetWavVolume",
+            VolumeCmd::GetKoeVolume(..) => "GetKoeVolume",
+            VolumeCmd::GetSeVolume(..) => "GetSeVolume",
+            VolumeCmd::SetBgmVolume(..) => "SetBgmVolume",
+            VolumeCmd::SetWavVolume(..) => "SetWavVolume",
+            VolumeCmd::SetKoeVolume(..) => "SetKoeVolume",
+            VolumeCmd::SetSeVolume(..) => "SetSeVolume",
+            VolumeCmd::MuteBgm(..) => "MuteBgm",
+            VolumeCmd::MuteWav(..) => "MuteWav",
+            VolumeCmd::MuteKoe(..) => "MuteKoe",
+            VolumeCmd::MuteSe(..) => "MuteSe",
+        }
+    }
+}
+
+impl VariantName for NovelModeCmd {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            NovelModeCmd::SetEnabled(..) => "SetEnabled",
+            NovelModeCmd::Unknown1(..) => "Unknown1",
+            NovelModeCmd::Unknown2 => "Unknown2",
+            NovelModeCmd::Unknown3 => "Unknown3",
+            NovelModeCmd::Unknown4 => "Unknown4",
+        }
+    }
+}
+
+/// Tallies the opcodes used by a scene.
+pub fn stats(scene: &AVG32Scene) -> OpcodeStats {
+    let mut res = OpcodeStats::new();
+    for opcode in scene.opcodes.iter() {
+        res.add(opcode);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::*;
+
+    #[test]
+    fn test_stats() {
+        let scene = AVG32Scene {
+            header: Header::empty(),
+            opcodes: vec![
+                Opcode::TextZenkaku(None, SceneText::Literal(String::from("あいう"))),
+                Opcode::WaitMouse,
+                Opcode::Graphics(GrpCmd::Load(SceneText::Literal(String::from("BG001")), Val(0, ValType::Const))),
+                Opcode::Graphics(GrpCmd::Load(SceneText::Literal(String::from("BG002")), Val(0, ValType::Const))),
+                Opcode::Graphics(GrpCmd::LoadCaching(SceneText::Literal(String::from("BG003")))),
+                Opcode::Jump(Pos::Offset(0)),
+                Opcode::Condition(Vec::new(), Pos::Offset(0)),
+                Opcode::TextHankaku(None, SceneText::Literal(String::from("ab"))),
+                Opcode::WaitMouse,
+            ],
+            val_lengths: BTreeMap::new(),
+            trailer: Vec::new()
+        };
+
+        let stats = stats(&scene);
+
+        let opcodes: Vec<(&str, usize)> = stats.opcodes.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        assert_eq!(vec![("Condition", 1), ("Graphics", 3), ("Jump", 1), ("TextHankaku", 1),
+                        ("TextZenkaku", 1), ("WaitMouse", 2)], opcodes);
+        let subcommands: Vec<(&str, usize)> = stats.subcommands.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        assert_eq!(vec![("Graphics::Load", 2), ("Graphics::LoadCaching", 1)], subcommands);
+        assert_eq!(5, stats.text_chars);
+        assert_eq!(2, stats.branches);
+        assert_eq!(9, stats.total());
+    }
+
+    #[test]
+    fn test_text_metrics() {
+        let scene = crate::build::SceneBuilder::new()
+            .text("あいう")
+            .text("えお")
+            .newline()
+            .newline()
+            .choice(0, &["はい", "いいえ"])
+            .opcode(Opcode::TextHankaku(None, SceneText::Literal(String::from("abc"))))
+            .opcode(Opcode::WaitMouseText)
+            .wait_mouse()
+            .build()
+            .unwrap();
+
+        assert_eq!(TextMetrics { lines: 2, chars: 8, choices: 1 }, scene.text_metrics());
+
+        let mut total = scene.text_metrics();
+        total.merge(&scene.text_metrics());
+        assert_eq!(TextMetrics { lines: 4, chars: 16, choices: 2 }, total);
+    }
+
+    #[test]
+    fn test_opcode_set() {
+        let scene = crate::build::SceneBuilder::new()
+            .text("あ")
+            .wait_mouse()
+            .opcode(Opcode::Graphics(GrpCmd::LoadCaching(SceneText::Literal(String::from("BG001")))))
+            .opcode(Opcode::Graphics(GrpCmd::LoadCaching(SceneText::Literal(String::from("BG002")))))
+            .opcode(Opcode::Fade(FadeCmd::Fade(Val::constant(1))))
+            .text("い")
+            .wait_mouse()
+            .build()
+            .unwrap();
+
+        assert_eq!(vec![0x01, 0x0b, 0x13, 0xff], scene.opcode_set().into_iter().collect::<Vec<u8>>());
+        assert_eq!(vec!["Fade::Fade", "Graphics::LoadCaching"], scene.subcommand_set().into_iter().collect::<Vec<String>>());
+        assert_eq!("Fade::Fade", qualified_name(&scene.opcodes[4]));
+        assert_eq!("WaitMouse", qualified_name(&scene.opcodes[1]));
+    }
+
+    #[test]
+    fn test_variant_names() {
+        // The names match how the variants are printed.
+        for opcode in crate::write::tests::every_opcode().iter() {
+            let debug = format!("{:?}", opcode);
+            let mut parts = debug.split(|c: char| !c.is_alphanumeric()).filter(|part| !part.is_empty());
+            let expected = match subcommand(opcode) {
+                Some(_) => format!("{}::{}", parts.next().unwrap(), parts.next().unwrap()),
+                None => String::from(parts.next().unwrap())
+            };
+            assert_eq!(expected, qualified_name(opcode));
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/// Full-width equivalents of the half-width katakana block (U+FF61..=U+FF9F).
+const ZENKAKU_KANA: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン゛゜";
+
+const HANKAKU_KANA_START: u32 = 0xFF61;
+const HANKAKU_DAKUTEN: char = '\u{FF9E}';
+const HANKAKU_HANDAKUTEN: char = '\u{FF9F}';
+
+/// Katakana that take a dakuten by adding 1 to their code point.
+const DAKUTEN_BASES: &str = "カキクケコサシスセソタチツテトハヒフヘホ";
+
+/// Katakana that take a handakuten by adding 2 to their code point.
+const HANDAKUTEN_BASES: &str = "ハヒフヘホ";
+
+fn hankaku_kana_to_zen(c: char) -> Option<char> {
+    let idx = (c as u32).checked_sub(HANKAKU_KANA_START)?;
+    ZENKAKU_KANA.chars().nth(idx as usize)
+}
+
+fn zenkaku_kana_to_han(c: char) -> Option<char> {
+    ZENKAKU_KANA.chars()
+        .position(|z| z == c)
+        .and_then(|i| std::char::from_u32(HANKAKU_KANA_START + i as u32))
+}
+
+fn combine_dakuten(base: char, mark: char) -> Option<char> {
+    match mark {
+        HANKAKU_DAKUTEN if base == 'ウ' => Some('ヴ'),
+        HANKAKU_DAKUTEN if DAKUTEN_BASES.contains(base) => std::char::from_u32(base as u32 + 1),
+        HANKAKU_HANDAKUTEN if HANDAKUTEN_BASES.contains(base) => std::char::from_u32(base as u32 + 2),
+        _ => None
+    }
+}
+
+fn split_dakuten(c: char) -> Option<(char, char)> {
+    if c == 'ヴ' {
+        return Some(('ウ', HANKAKU_DAKUTEN));
+    }
+    let code = c as u32;
+    let base = |offset: u32| code.checked_sub(offset).and_then(std::char::from_u32);
+    if let Some(b) = base(1).filter(|b| DAKUTEN_BASES.contains(*b)) {
+        return Some((b, HANKAKU_DAKUTEN));
+    }
+    if let Some(b) = base(2).filter(|b| HANDAKUTEN_BASES.contains(*b)) {
+        return Some((b, HANKAKU_HANDAKUTEN));
+    }
+    None
+}
+
+/// Converts half-width ASCII and katakana to their full-width forms, the
+/// same as `StringCmd::HanToZen`. Characters without a full-width form are
+/// passed through unchanged.
+pub fn han_to_zen(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' => res.push('\u{3000}'),
+            '!'..='~' => res.push(std::char::from_u32(c as u32 - 0x21 + 0xFF01).unwrap()),
+            '\u{FF61}'..='\u{FF9F}' => {
+                let zen = hankaku_kana_to_zen(c).unwrap();
+                match chars.peek().and_then(|&mark| combine_dakuten(zen, mark)) {
+                    Some(combined) => {
+                        chars.next();
+                        res.push(combined)
+                    },
+                    None => res.push(zen)
+                }
+            },
+            _ => res.push(c)
+        }
+    }
+
+    res
+}
+
+/// Inverse of `han_to_zen`. Voiced katakana are split into the base
+/// character followed by a half-width (han)dakuten.
+pub fn zen_to_han(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\u{3000}' => res.push(' '),
+            '\u{FF01}'..='\u{FF5E}' => res.push(std::char::from_u32(c as u32 - 0xFF01 + 0x21).unwrap()),
+            _ => {
+                if let Some(han) = zenkaku_kana_to_han(c) {
+                    res.push(han);
+                } else if let Some((base, mark)) = split_dakuten(c) {
+                    res.push(zenkaku_kana_to_han(base).unwrap());
+                    res.push(mark);
+                } else {
+                    res.push(c);
+                }
+            }
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_kana_table_size() {
+        assert_eq!(0xFF9F - 0xFF61 + 1, ZENKAKU_KANA.chars().count());
+    }
+
+    #[test]
+    fn test_han_to_zen_ascii() {
+        assert_eq!("０１２３４５６７８９", han_to_zen("0123456789"));
+        assert_eq!("ＡＶＧ３２！\u{3000}ａ～", han_to_zen("AVG32! a~"));
+    }
+
+    #[test]
+    fn test_han_to_zen_kana() {
+        assert_eq!("アイウエオ", han_to_zen("ｱｲｳｴｵ"));
+        assert_eq!("ガギパヴ", han_to_zen("ｶﾞｷﾞﾊﾟｳﾞ"));
+        assert_eq!("「ア」。ー", han_to_zen("｢ｱ｣｡ｰ"));
+        assert_eq!("ア゛", han_to_zen("ｱﾞ"));
+    }
+
+    #[test]
+    fn test_han_to_zen_passthrough() {
+        assert_eq!("漢字ひらがなアイ", han_to_zen("漢字ひらがなアイ"));
+    }
+
+    #[test]
+    fn test_zen_to_han() {
+        assert_eq!("0123456789", zen_to_han("０１２３４５６７８９"));
+        assert_eq!("ｶﾞｷﾞﾊﾟｳﾞ ｱ", zen_to_han("ガギパヴ\u{3000}ア"));
+        assert_eq!("漢字ひらがな", zen_to_han("漢字ひらがな"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let s = "ｺﾝﾆﾁﾊ, AVG32! ﾎﾟｹｯﾄ";
+        assert_eq!(s, zen_to_han(&han_to_zen(s)));
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use crate::archive;
+use crate::parser::{self, AVG32Scene, Opcode, ParseConfig};
+
+/// One `sys_version` from each range the parser treats differently, newest
+/// first: the text index (1714), the extra `Val` in some `Grp` commands
+/// (1704) and the one in some `BufferRegion` commands (1613).
+const VERSION_STEPS: &[u32] = &[1714, 1713, 1703, 1612];
+
+/// Text indices at or above this are taken as text read at the wrong
+/// version. Four bytes of Shift_JIS or ASCII make a much larger `u32`.
+const MAX_TEXT_INDEX: u32 = 0x0100_0000;
+
+/// Guesses the `sys_version` of the game in `game_dir`, for use in
+/// `ParseConfig`, from the scenes in its `SEEN.TXT`. They are parsed at each
+/// version in `VERSION_STEPS`, and the newest version that parses the most
+/// scenes wins, not counting scenes with text indices of `MAX_TEXT_INDEX`
+/// or more. Versions in the same step parse identically, so the result is
+/// only as precise as the step; pass the version explicitly, e.g. with
+/// `parse_version`, for a game this gets wrong.
+///
+/// The file name is matched case-insensitively. Returns `None` if there is
+/// no `SEEN.TXT` or no scene parses at any version.
+pub fn detect_version<P: AsRef<Path>>(game_dir: P) -> Option<u32> {
+    let seen = fs::read(find_file(game_dir.as_ref(), "SEEN.TXT")?).ok()?;
+    archive_version(&seen)
+}
+
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path.is_file() && path.file_name().is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name)))
+}
+
+/// Reads a version written as a number, `1714`, or the way it's written in
+/// the parser's comments, `17N` or `1.7N`, with `A` as 1.
+pub fn parse_version(s: &str) -> Result<u32> {
+    if let Ok(version) = s.parse::<u32>() {
+        return Ok(version);
+    }
+
+    let invalid = || anyhow!("Invalid version {:?}, expected e.g. 1714, 17N or 1.7N", s);
+    let digits = s.replace('.', "");
+    let letter = digits.chars().last().ok_or_else(invalid)?.to_ascii_uppercase();
+    let number = digits[..digits.len() - letter.len_utf8()].parse::<u32>().map_err(|_| invalid())?;
+    if !letter.is_ascii_uppercase() || number == 0 {
+        return Err(invalid());
+    }
+    number.checked_mul(100)
+        .and_then(|version| version.checked_add(letter as u32 - 'A' as u32 + 1))
+        .ok_or_else(|| anyhow!("Version {:?} is too large", s))
+}
+
+fn archive_version(bytes: &[u8]) -> Option<u32> {
+    let arc = archive::load_bytes(bytes).ok()?;
+    let scenes: Vec<Vec<u8>> = arc.data.iter().filter_map(|data| data.decompress().ok()).collect();
+
+    let mut best = None;
+    let mut best_count = 0;
+    for &version in VERSION_STEPS.iter() {
+        let config = ParseConfig {
+            sys_version: version,
+            ..Default::default()
+        };
+        let count = parser::with_config(&config, || scenes.iter().filter(|scene| crate::try_parse(scene).is_ok_and(|s| plausible(&s))).count());
+        if count > best_count {
+            best = Some(version);
+            best_count = count;
+        }
+    }
+    best
+}
+
+fn plausible(scene: &AVG32Scene) -> bool {
+    scene.opcodes.iter().all(|opcode| match opcode {
+        Opcode::TextHankaku(Some(index), _) | Opcode::TextZenkaku(Some(index), _) => *index < MAX_TEXT_INDEX,
+        _ => true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Archive;
+    use crate::build::SceneBuilder;
+    use crate::parser::SceneText;
+    use crate::write::Writeable;
+
+    fn seen_txt(index: bool) -> Vec<u8> {
+        let mut builder = SceneBuilder::new();
+        for (i, text) in ["あいう", "えお", "かきくけこ"].iter().enumerate() {
+            let index = if index { Some(i as u32) } else { None };
+            builder = builder.opcode(Opcode::TextZenkaku(index, SceneText::Literal(String::from(*text)))).wait_mouse();
+        }
+        let scene = builder.build().unwrap();
+
+        let mut arc = Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), scene.to_vec().unwrap()).unwrap();
+        arc.finalize();
+        arc.to_vec().unwrap()
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(1714, parse_version("1714").unwrap());
+        assert_eq!(1714, parse_version("17N").unwrap());
+        assert_eq!(1704, parse_version("1.7D").unwrap());
+        assert_eq!(1613, parse_version("16m").unwrap());
+        assert!(parse_version("17?").is_err());
+        assert!(parse_version("N").is_err());
+        assert!(parse_version("").is_err());
+        assert!(parse_version("42949673A").unwrap_err().to_string().contains("too large"));
+    }
+
+    #[test]
+    fn test_detect_version() {
+        let dir = std::env::temp_dir().join(format!("avg32-test-detect-version-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let empty = detect_version(&dir);
+        fs::write(dir.join("SEEN.TXT"), seen_txt(true)).unwrap();
+        let with_index = detect_version(&dir);
+        fs::write(dir.join("SEEN.TXT"), seen_txt(false)).unwrap();
+        let without_index = detect_version(&dir);
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(None, empty);
+        assert_eq!(Some(1714), with_index);
+        assert_eq!(Some(1713), without_index);
+    }
+}
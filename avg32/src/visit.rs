@@ -0,0 +1,944 @@
+use crate::parser::*;
+
+/// Walks every `Val` nested in an opcode or command, including string
+/// pointers and the operands of conditions.
+pub trait VisitMut {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val));
+}
+
+impl VisitMut for Val {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        f(self)
+    }
+}
+
+impl VisitMut for SceneText {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        if let SceneText::Pointer(val) = self {
+            f(val)
+        }
+    }
+}
+
+impl<T: VisitMut> VisitMut for Vec<T> {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        for item in self.iter_mut() {
+            item.visit_vals_mut(f);
+        }
+    }
+}
+
+impl<T: VisitMut> VisitMut for Option<T> {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        if let Some(item) = self {
+            item.visit_vals_mut(f);
+        }
+    }
+}
+
+impl VisitMut for AVG32Scene {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.opcodes.visit_vals_mut(f);
+    }
+}
+
+impl AVG32Scene {
+    /// Replaces the index of every variable the scene uses with `f(index)`.
+    /// Constants are left alone. New indices may take more or fewer bytes,
+    /// so jump targets and header labels are moved to match.
+    pub fn remap_vars<F: Fn(u32) -> u32>(&mut self, f: F) {
+        self.resize_opcodes(|scene| {
+            scene.visit_vals_mut(&mut |val| {
+                if val.is_var() {
+                    val.0 = f(val.0);
+                }
+            });
+        });
+    }
+}
+
+impl VisitMut for FormattedTextCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            FormattedTextCmd::Integer(v0) => v0.visit_vals_mut(f),
+            FormattedTextCmd::IntegerZeroPadded(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            FormattedTextCmd::TextPointer(v0) => v0.visit_vals_mut(f),
+            FormattedTextCmd::Color(v0) => v0.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for SceneFormattedTextEntry {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            SceneFormattedTextEntry::Command(v0) => v0.visit_vals_mut(f),
+            SceneFormattedTextEntry::Condition(v0) => v0.visit_vals_mut(f),
+            SceneFormattedTextEntry::TextPointer(v0) => v0.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for SceneFormattedText {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.0.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for JumpToSceneCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            JumpToSceneCmd::Jump(v0) => v0.visit_vals_mut(f),
+            JumpToSceneCmd::Call(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for TextWinCmd {
+    fn visit_vals_mut(&mut self, _f: &mut dyn FnMut(&mut Val)) {}
+}
+
+impl VisitMut for FadeCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            FadeCmd::Fade(v0) => v0.visit_vals_mut(f),
+            FadeCmd::FadeTimed(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            FadeCmd::FadeColor(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            FadeCmd::FadeTimedColor(v0, v1, v2, v3) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); },
+            FadeCmd::FillScreen(v0) => v0.visit_vals_mut(f),
+            FadeCmd::FillScreenColor(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+        }
+    }
+}
+
+impl VisitMut for GrpEffect {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.file.visit_vals_mut(f);
+        self.sx1.visit_vals_mut(f);
+        self.sy1.visit_vals_mut(f);
+        self.sx2.visit_vals_mut(f);
+        self.sy2.visit_vals_mut(f);
+        self.dx.visit_vals_mut(f);
+        self.dy.visit_vals_mut(f);
+        self.steptime.visit_vals_mut(f);
+        self.cmd.visit_vals_mut(f);
+        self.mask.visit_vals_mut(f);
+        self.arg1.visit_vals_mut(f);
+        self.arg2.visit_vals_mut(f);
+        self.arg3.visit_vals_mut(f);
+        self.step.visit_vals_mut(f);
+        self.arg5.visit_vals_mut(f);
+        self.arg6.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for GrpCompositeMethod {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            GrpCompositeMethod::Copy(v0) => v0.visit_vals_mut(f),
+            GrpCompositeMethod::Move1(v0, v1, v2, v3, v4, v5) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); v5.visit_vals_mut(f); },
+            GrpCompositeMethod::Move2(v0, v1, v2, v3, v4, v5, v6) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); v5.visit_vals_mut(f); v6.visit_vals_mut(f); },
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for GrpCompositeChild {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.file.visit_vals_mut(f);
+        self.method.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for GrpComposite {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.base_file.visit_vals_mut(f);
+        self.idx.visit_vals_mut(f);
+        self.children.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for GrpCompositeIndexed {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.base_file.visit_vals_mut(f);
+        self.idx.visit_vals_mut(f);
+        self.children.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for GrpCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            GrpCmd::Load(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            GrpCmd::LoadEffect(v0) => v0.visit_vals_mut(f),
+            GrpCmd::Load2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            GrpCmd::LoadEffect2(v0) => v0.visit_vals_mut(f),
+            GrpCmd::Load3(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            GrpCmd::LoadEffect3(v0) => v0.visit_vals_mut(f),
+            GrpCmd::LoadToBuf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            GrpCmd::LoadToBuf2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            GrpCmd::LoadCaching(v0) => v0.visit_vals_mut(f),
+            GrpCmd::LoadComposite(v0) => v0.visit_vals_mut(f),
+            GrpCmd::LoadCompositeIndexed(v0) => v0.visit_vals_mut(f),
+            GrpCmd::MacroBufferDelete(v0) => v0.visit_vals_mut(f),
+            GrpCmd::MacroBufferRead(v0) => v0.visit_vals_mut(f),
+            GrpCmd::MacroBufferSet(v0) => v0.visit_vals_mut(f),
+            GrpCmd::BackupScreenDisplay(v0) => v0.visit_vals_mut(f),
+            GrpCmd::LoadToBuf3(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for SndCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            SndCmd::BgmLoop(v0) => v0.visit_vals_mut(f),
+            SndCmd::BgmWait(v0) => v0.visit_vals_mut(f),
+            SndCmd::BgmOnce(v0) => v0.visit_vals_mut(f),
+            SndCmd::BgmFadeInLoop(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SndCmd::BgmFadeInWait(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SndCmd::BgmFadeInOnce(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SndCmd::BgmFadeOut(v0) => v0.visit_vals_mut(f),
+            SndCmd::KoePlayWait(v0) => v0.visit_vals_mut(f),
+            SndCmd::KoePlay(v0) => v0.visit_vals_mut(f),
+            SndCmd::KoePlay2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SndCmd::WavPlay(v0) => v0.visit_vals_mut(f),
+            SndCmd::WavPlay2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SndCmd::WavLoop(v0) => v0.visit_vals_mut(f),
+            SndCmd::WavLoop2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SndCmd::WavPlayWait(v0) => v0.visit_vals_mut(f),
+            SndCmd::WavPlayWait2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SndCmd::WavStop2(v0) => v0.visit_vals_mut(f),
+            SndCmd::WavUnknown0x39(v0) => v0.visit_vals_mut(f),
+            SndCmd::SePlay(v0) => v0.visit_vals_mut(f),
+            SndCmd::MoviePlay(v0, v1, v2, v3, v4) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); },
+            SndCmd::MovieLoop(v0, v1, v2, v3, v4) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); },
+            SndCmd::MovieWait(v0, v1, v2, v3, v4) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); },
+            SndCmd::MovieWaitCancelable(v0, v1, v2, v3, v4) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); },
+            SndCmd::MovieWait2(v0, v1, v2, v3, v4, v5) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); v5.visit_vals_mut(f); },
+            SndCmd::MovieWaitCancelable2(v0, v1, v2, v3, v4, v5) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); v5.visit_vals_mut(f); },
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for Ret {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            Ret::Color(v0) => v0.visit_vals_mut(f),
+            Ret::DisabledChoice(v0) => v0.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for Condition {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            Condition::BitNotEq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::BitEq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::NotEq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::Eq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagNotEqConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagEqConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagAndConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagAndConst2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagXorConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagGtConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagLtConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagGeqConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagLeqConst(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagNotEq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagEq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagAnd(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagAnd2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagXor(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagGt(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagLt(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagGeq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::FlagLeq(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Condition::Ret(v0) => v0.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for ScreenShakeCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            ScreenShakeCmd::ScreenShake(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for WaitCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            WaitCmd::Wait(v0) => v0.visit_vals_mut(f),
+            WaitCmd::WaitMouse(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            WaitCmd::WaitFromBase(v0) => v0.visit_vals_mut(f),
+            WaitCmd::WaitFromBaseMouse(v0) => v0.visit_vals_mut(f),
+            WaitCmd::SetToBaseVal(v0) => v0.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for RetCmd {
+    fn visit_vals_mut(&mut self, _f: &mut dyn FnMut(&mut Val)) {}
+}
+
+impl VisitMut for ScenarioMenuCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            ScenarioMenuCmd::SetBit(v0) => v0.visit_vals_mut(f),
+            ScenarioMenuCmd::SetBit2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+        }
+    }
+}
+
+impl VisitMut for TextRankCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        if let TextRankCmd::Set(v0) = self {
+            v0.visit_vals_mut(f);
+        }
+    }
+}
+
+impl VisitMut for Choice {
+    fn visit_vals_mut(&mut self, _f: &mut dyn FnMut(&mut Val)) {}
+}
+
+impl VisitMut for ChoiceText {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.texts.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for ChoiceCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            ChoiceCmd::Choice(v0, _, v2) => { v0.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            ChoiceCmd::Choice2(v0, _, v2) => { v0.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            ChoiceCmd::LoadMenu(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for StringCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            StringCmd::StrcpyLiteral(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            StringCmd::Strlen(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            StringCmd::Strcmp(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            StringCmd::Strcat(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            StringCmd::Strcpy(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            StringCmd::Itoa(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            StringCmd::HanToZen(v0) => v0.visit_vals_mut(f),
+            StringCmd::Atoi(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+        }
+    }
+}
+
+impl VisitMut for SetMultiCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            SetMultiCmd::Val(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            SetMultiCmd::Bit(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+        }
+    }
+}
+
+impl VisitMut for BRGRectColor {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.r.visit_vals_mut(f);
+        self.g.visit_vals_mut(f);
+        self.b.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BRGRect {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BRGFadeOutColor {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.r.visit_vals_mut(f);
+        self.g.visit_vals_mut(f);
+        self.b.visit_vals_mut(f);
+        self.count.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BRGStretchBlit {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dstx2.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dsty2.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BRGStretchBlitEffect {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.sx1.visit_vals_mut(f);
+        self.sy1.visit_vals_mut(f);
+        self.sx2.visit_vals_mut(f);
+        self.sy2.visit_vals_mut(f);
+        self.ex1.visit_vals_mut(f);
+        self.ey1.visit_vals_mut(f);
+        self.ex2.visit_vals_mut(f);
+        self.ey2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dx1.visit_vals_mut(f);
+        self.dy1.visit_vals_mut(f);
+        self.dx2.visit_vals_mut(f);
+        self.dy2.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+        self.step.visit_vals_mut(f);
+        self.steptime.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BufferRegionGrpCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            BufferRegionGrpCmd::ClearRect(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::DrawRectLine(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::InvertColor(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::ColorMask(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::FadeOutColor(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::FadeOutColor2(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::FadeOutColor3(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::MakeMonoImage(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::StretchBlit(v0) => v0.visit_vals_mut(f),
+            BufferRegionGrpCmd::StretchBlitEffect(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for BGCopySamePos {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.flag.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGCopyNewPos {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+        self.flag.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGCopyColor {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+        self.r.visit_vals_mut(f);
+        self.g.visit_vals_mut(f);
+        self.b.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGSwap {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGCopyWithMask {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+        self.flag.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGCopyWholeScreen {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.srcpdt.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+        self.flag.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGDisplayStrings {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.n.visit_vals_mut(f);
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcdx.visit_vals_mut(f);
+        self.srcdy.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dstx2.visit_vals_mut(f);
+        self.dsty2.visit_vals_mut(f);
+        self.count.visit_vals_mut(f);
+        self.zero.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGDisplayStringsMask {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.n.visit_vals_mut(f);
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcdx.visit_vals_mut(f);
+        self.srcdy.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dstx2.visit_vals_mut(f);
+        self.dsty2.visit_vals_mut(f);
+        self.count.visit_vals_mut(f);
+        self.zero.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+        self.flag.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BGDisplayStringsColor {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.n.visit_vals_mut(f);
+        self.srcx1.visit_vals_mut(f);
+        self.srcy1.visit_vals_mut(f);
+        self.srcx2.visit_vals_mut(f);
+        self.srcy2.visit_vals_mut(f);
+        self.srcdx.visit_vals_mut(f);
+        self.srcdy.visit_vals_mut(f);
+        self.srcpdt.visit_vals_mut(f);
+        self.dstx1.visit_vals_mut(f);
+        self.dsty1.visit_vals_mut(f);
+        self.dstx2.visit_vals_mut(f);
+        self.dsty2.visit_vals_mut(f);
+        self.count.visit_vals_mut(f);
+        self.zero.visit_vals_mut(f);
+        self.dstpdt.visit_vals_mut(f);
+        self.r.visit_vals_mut(f);
+        self.g.visit_vals_mut(f);
+        self.b.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for BufferGrpCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            BufferGrpCmd::CopySamePos(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::CopyNewPos(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::CopyNewPosMask(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::CopyColor(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::Swap(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::CopyWithMask(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::CopyWholeScreen(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::CopyWholeScreenMask(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::DisplayStrings(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::DisplayStringsMask(v0) => v0.visit_vals_mut(f),
+            BufferGrpCmd::DisplayStringsColor(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for FlashGrpCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            FlashGrpCmd::FillColor(v0, v1, v2, v3) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); },
+            FlashGrpCmd::FlashScreen(v0, v1, v2, v3, v4) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); },
+        }
+    }
+}
+
+impl VisitMut for MultiPdtEntry {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.text.visit_vals_mut(f);
+        self.data.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for MultiPdtCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            MultiPdtCmd::Slideshow(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            MultiPdtCmd::SlideshowLoop(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            MultiPdtCmd::Scroll(_, v1, v2, v3, v4) => { v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); },
+            MultiPdtCmd::Scroll2(_, v1, v2, v3, v4) => { v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); },
+            MultiPdtCmd::ScrollWithCancel(_, v1, v2, v3, v4, v5) => { v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); v5.visit_vals_mut(f); },
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for SystemCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            SystemCmd::LoadGame(v0) => v0.visit_vals_mut(f),
+            SystemCmd::SaveGame(v0) => v0.visit_vals_mut(f),
+            SystemCmd::SetTitle(v0) => v0.visit_vals_mut(f),
+            SystemCmd::GetSaveTitle(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemCmd::CheckSaveData(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemCmd::Unknown1(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemCmd::Unknown2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemCmd::Unknown3(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for NameInputItem {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        self.idx.visit_vals_mut(f);
+        self.text.visit_vals_mut(f);
+    }
+}
+
+impl VisitMut for NameCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            NameCmd::InputBox(v0, v1, v2, v3, v4, v5, v6, v7, v8, v9) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); v5.visit_vals_mut(f); v6.visit_vals_mut(f); v7.visit_vals_mut(f); v8.visit_vals_mut(f); v9.visit_vals_mut(f); },
+            NameCmd::InputBoxFinish(v0) => v0.visit_vals_mut(f),
+            NameCmd::InputBoxStart(v0) => v0.visit_vals_mut(f),
+            NameCmd::InputBoxClose(v0) => v0.visit_vals_mut(f),
+            NameCmd::GetName(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            NameCmd::SetName(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            NameCmd::GetName2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            NameCmd::NameInputDialog(v0) => v0.visit_vals_mut(f),
+            NameCmd::Unknown1(v0, v1, v2, v3, v4, v5, v6, v7, v8, v9, v10) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); v4.visit_vals_mut(f); v5.visit_vals_mut(f); v6.visit_vals_mut(f); v7.visit_vals_mut(f); v8.visit_vals_mut(f); v9.visit_vals_mut(f); v10.visit_vals_mut(f); },
+            NameCmd::NameInputDialogMulti(v0) => v0.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for AreaBufferCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            AreaBufferCmd::ReadCurArd(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            AreaBufferCmd::GetClickedArea(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            AreaBufferCmd::GetClickedArea2(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            AreaBufferCmd::DisableArea(v0) => v0.visit_vals_mut(f),
+            AreaBufferCmd::EnableArea(v0) => v0.visit_vals_mut(f),
+            AreaBufferCmd::GetArea(v0, v1, v2) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); },
+            AreaBufferCmd::AssignArea(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for MouseCtrlCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        if let MouseCtrlCmd::SetPos(v0, v1, v2) = self {
+            v0.visit_vals_mut(f);
+            v1.visit_vals_mut(f);
+            v2.visit_vals_mut(f);
+        }
+    }
+}
+
+impl VisitMut for VolumeCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            VolumeCmd::GetBgmVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::GetWavVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::GetKoeVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::GetSeVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::SetBgmVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::SetWavVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::SetKoeVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::SetSeVolume(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::MuteBgm(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::MuteWav(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::MuteKoe(v0) => v0.visit_vals_mut(f),
+            VolumeCmd::MuteSe(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for NovelModeCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            NovelModeCmd::SetEnabled(v0) => v0.visit_vals_mut(f),
+            NovelModeCmd::Unknown1(v0) => v0.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+impl VisitMut for WindowVarCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            WindowVarCmd::GetBgFlagColor(v0, v1, v2, v3) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); },
+            WindowVarCmd::SetBgFlagColor(v0, v1, v2, v3) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); v2.visit_vals_mut(f); v3.visit_vals_mut(f); },
+            WindowVarCmd::GetWindowMove(v0) => v0.visit_vals_mut(f),
+            WindowVarCmd::SetWindowMove(v0) => v0.visit_vals_mut(f),
+            WindowVarCmd::GetWindowClearBox(v0) => v0.visit_vals_mut(f),
+            WindowVarCmd::SetWindowClearBox(v0) => v0.visit_vals_mut(f),
+            WindowVarCmd::GetWindowWaku(v0) => v0.visit_vals_mut(f),
+            WindowVarCmd::SetWindowWaku(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for MessageWinCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            MessageWinCmd::GetWindowMsgPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::GetWindowComPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::GetWindowSysPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::GetWindowSubPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::GetWindowGrpPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::SetWindowMsgPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::SetWindowComPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::SetWindowSysPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::SetWindowSubPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            MessageWinCmd::SetWindowGrpPos(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+        }
+    }
+}
+
+impl VisitMut for SystemVarCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            SystemVarCmd::GetMessageSize(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemVarCmd::SetMessageSize(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemVarCmd::GetMsgMojiSize(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemVarCmd::SetMsgMojiSize(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemVarCmd::GetMojiColor(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetMojiColor(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetMsgCancel(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetMsgCancel(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetMojiKage(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetMojiKage(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetKageColor(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetKageColor(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetSelCancel(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetSelCancel(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetCtrlKey(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetCtrlKey(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetSaveStart(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetSaveStart(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetDisableNvlTextFlag(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetDisableNvlTextFlag(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetFadeTime(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetFadeTime(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetCursorMono(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetCursorMono(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetCopyWindSw(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetCopyWindSw(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetMsgSpeed(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetMsgSpeed(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetMsgSpeed2(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetMsgSpeed2(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetReturnKeyWait(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetReturnKeyWait(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetKoeTextType(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetKoeTextType(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetGameSpeckInit(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetCursorPosition(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            SystemVarCmd::SetDisableKeyMouseFlag(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::GetGameSpeckInit2(v0) => v0.visit_vals_mut(f),
+            SystemVarCmd::SetGameSpeckInit(v0) => v0.visit_vals_mut(f),
+        }
+    }
+}
+
+impl VisitMut for PopupMenuCmd {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            PopupMenuCmd::GetMenuDisabled(v0) => v0.visit_vals_mut(f),
+            PopupMenuCmd::SetMenuDisabled(v0) => v0.visit_vals_mut(f),
+            PopupMenuCmd::GetItemDisabled(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            PopupMenuCmd::SetItemDisabled(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+        }
+    }
+}
+
+impl VisitMut for Opcode {
+    fn visit_vals_mut(&mut self, f: &mut dyn FnMut(&mut Val)) {
+        match self {
+            Opcode::TextWin(v0) => v0.visit_vals_mut(f),
+            Opcode::Graphics(v0) => v0.visit_vals_mut(f),
+            Opcode::Sound(v0) => v0.visit_vals_mut(f),
+            Opcode::DrawValText(v0) => v0.visit_vals_mut(f),
+            Opcode::Fade(v0) => v0.visit_vals_mut(f),
+            Opcode::Condition(v0, _) => v0.visit_vals_mut(f),
+            Opcode::JumpToScene(v0) => v0.visit_vals_mut(f),
+            Opcode::ScreenShake(v0) => v0.visit_vals_mut(f),
+            Opcode::Wait(v0) => v0.visit_vals_mut(f),
+            Opcode::TableCall(v0, _) => v0.visit_vals_mut(f),
+            Opcode::TableJump(v0, _) => v0.visit_vals_mut(f),
+            Opcode::Return(v0) => v0.visit_vals_mut(f),
+            Opcode::ScenarioMenu(v0) | Opcode::ScenarioMenu2(v0) => v0.visit_vals_mut(f),
+            Opcode::TextRank(v0) => v0.visit_vals_mut(f),
+            Opcode::SetFlag(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::CopyFlag(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::SetValLiteral(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::AddVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::SubVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::MulVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::DivVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::ModVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::AndVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::OrVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::XorVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::SetVal(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::AddValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::SubValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::MulValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::DivValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::ModValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::AndValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::OrValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::XorValSelf(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::SetFlagRandom(v0) => v0.visit_vals_mut(f),
+            Opcode::SetValRandom(v0, v1) => { v0.visit_vals_mut(f); v1.visit_vals_mut(f); },
+            Opcode::Choice(v0) => v0.visit_vals_mut(f),
+            Opcode::String(v0) => v0.visit_vals_mut(f),
+            Opcode::SetMulti(v0) => v0.visit_vals_mut(f),
+            Opcode::System(v0) => v0.visit_vals_mut(f),
+            Opcode::Name(v0) => v0.visit_vals_mut(f),
+            Opcode::BufferRegion(v0) => v0.visit_vals_mut(f),
+            Opcode::Buffer(v0) => v0.visit_vals_mut(f),
+            Opcode::Flash(v0) => v0.visit_vals_mut(f),
+            Opcode::MultiPdt(v0) => v0.visit_vals_mut(f),
+            Opcode::AreaBuffer(v0) => v0.visit_vals_mut(f),
+            Opcode::MouseCtrl(v0) => v0.visit_vals_mut(f),
+            Opcode::WindowVar(v0) => v0.visit_vals_mut(f),
+            Opcode::MessageWin(v0) => v0.visit_vals_mut(f),
+            Opcode::SystemVar(v0) => v0.visit_vals_mut(f),
+            Opcode::PopupMenu(v0) => v0.visit_vals_mut(f),
+            Opcode::Volume(v0) => v0.visit_vals_mut(f),
+            Opcode::NovelMode(v0) => v0.visit_vals_mut(f),
+            Opcode::Unknown0xea(v0) => v0.visit_vals_mut(f),
+            Opcode::TextHankaku(_, v1) => v1.visit_vals_mut(f),
+            Opcode::TextZenkaku(_, v1) => v1.visit_vals_mut(f),
+            _ => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::Writeable;
+
+    fn vals(scene: &mut AVG32Scene) -> Vec<Val> {
+        let mut res = Vec::new();
+        scene.visit_vals_mut(&mut |val| res.push(*val));
+        res
+    }
+
+    #[test]
+    fn test_remap_vars() {
+        let mut scene = crate::build::sample_scene();
+        scene.opcodes.push(Opcode::Condition(vec![
+            Condition::IncDepth,
+            Condition::FlagEqConst(Val::variable(7), Val::constant(7)),
+            Condition::And,
+            Condition::Eq(Val::variable(8), Val::variable(9)),
+            Condition::DecDepth
+        ], Pos::Offset(0)));
+        scene.opcodes.push(Opcode::Graphics(GrpCmd::Load(SceneText::Pointer(Val::variable(3)), Val::constant(3))));
+        let original = scene.clone();
+
+        let before = vals(&mut scene);
+        scene.remap_vars(|idx| idx + 100);
+        let after = vals(&mut scene);
+        assert!(scene.byte_size() > original.byte_size());
+
+        assert_eq!(before.len(), after.len());
+        assert!(before.iter().filter(|v| v.is_var()).count() > 10);
+        for (old, new) in before.iter().zip(after.iter()) {
+            if old.is_var() {
+                assert_eq!(Val::variable(old.0 + 100), *new);
+            } else {
+                assert_eq!(old, new);
+            }
+        }
+
+        let n = scene.opcodes.len();
+        assert_eq!(Opcode::Graphics(GrpCmd::Load(SceneText::Pointer(Val::variable(103)), Val::constant(3))), scene.opcodes[n - 1]);
+        match &scene.opcodes[n - 2] {
+            Opcode::Condition(conds, _) => assert_eq!(&Condition::Eq(Val::variable(108), Val::variable(109)), &conds[3]),
+            other => panic!("Expected condition, got {:?}", other)
+        }
+
+        // Jumps and labels still point at the same opcodes.
+        let index_of = |scene: &AVG32Scene, offset: u32| scene.opcodes_with_offsets().position(|(o, _)| o == offset);
+        let targets = |scene: &AVG32Scene| -> Vec<Option<usize>> {
+            scene.opcodes.iter()
+                .flat_map(|op| crate::build::jump_targets(op))
+                .map(|pos| match pos {
+                    Pos::Offset(offset) => index_of(scene, *offset),
+                    Pos::Label(name) => panic!("Unresolved label {}", name)
+                })
+                .collect()
+        };
+        let labels = |scene: &AVG32Scene| -> Vec<Option<usize>> {
+            scene.header.labels.iter().map(|l| index_of(scene, *l)).collect()
+        };
+        assert!(targets(&original).len() >= 5);
+        assert!(targets(&original).iter().all(Option::is_some));
+        assert_eq!(targets(&original), targets(&scene));
+        assert_eq!(labels(&original), labels(&scene));
+    }
+}
@@ -8,6 +8,13 @@ use crate::parser::*;
 pub trait Writeable {
     fn byte_size(&self) -> usize;
     fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>;
+
+    /// Writes into a new `Vec`, using `byte_size` as the capacity.
+    fn to_vec(&self) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = Vec::with_capacity(self.byte_size());
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
 }
 
 impl Writeable for u8 {
@@ -40,19 +47,52 @@ impl Writeable for u32 {
     }
 }
 
+/// Returns the first character in `s` (and its byte index) that has no
+/// SHIFT_JIS representation.
+pub fn find_unencodable_sjis(s: &str) -> Option<(usize, char)> {
+    let mut buf = [0; 4];
+    s.char_indices().find(|(_, c)| try_encode_sjis(c.encode_utf8(&mut buf)).is_none())
+}
+
+/// Returns true if every character in `s` can be encoded as SHIFT_JIS.
+pub fn can_encode_sjis(s: &str) -> bool {
+    find_unencodable_sjis(s).is_none()
+}
+
+/// Encodes `s` with the configured `TextEncoding`, or `None` if some
+/// character has no representation in it.
+fn try_encode_sjis(s: &str) -> Option<Vec<u8>> {
+    let s = to_cp932_chars(s);
+    let (bytes, _, errors) = SHIFT_JIS.encode(&s);
+    let strict = config(|c| c.text_encoding) == TextEncoding::Strict;
+    if errors || (strict && find_sjis_extension(&bytes).is_some()) {
+        return None;
+    }
+    Some(bytes.into_owned())
+}
+
+fn encode_sjis(s: &str) -> Result<Vec<u8>, io::Error> {
+    try_encode_sjis(s).ok_or_else(|| {
+        let msg = match find_unencodable_sjis(s) {
+            Some((i, c)) => format!("Cannot encode {:?} (U+{:04X}) at byte {} of {:?} as SHIFT_JIS", c, c as u32, i, s),
+            None => format!("Cannot encode {:?} as SHIFT_JIS", s)
+        };
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    })
+}
+
 // Assumes SHIFT_JIS encoding
 impl Writeable for &str {
     fn byte_size(&self) -> usize {
-        let (bytes, _, errors) = SHIFT_JIS.encode(self);
-        assert!(!errors, "Cannot encode as SHIFT_JIS");
+        // Unencodable characters are reported by `write`, so only the
+        // encoded length is needed here.
+        let s = to_cp932_chars(self);
+        let (bytes, _, _) = SHIFT_JIS.encode(&s);
         bytes.len() + 1 // Null byte
     }
 
     fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
-        let (bytes, _, errors) = SHIFT_JIS.encode(self);
-        if errors {
-            return Err(io::Error::new(io::ErrorKind::Other, "Cannot encode as SHIFT_JIS"));
-        }
+        let bytes = encode_sjis(self)?;
         writer.write_all(&bytes)?;
         writer.write_all(&[0x00])
     }
@@ -115,6 +155,11 @@ impl Writeable for Header {
     }
 
     fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        if self.menu_strings.len() != self.menu_string_count() {
+            let msg = format!("Header has {} menu strings, but its menus need {}", self.menu_strings.len(), self.menu_string_count());
+            return Err(io::Error::other(msg));
+        }
+
         writer.write_all(b"TPC32")?;
         self.unk1.write(writer)?;
         (self.labels.len() as u32).write(writer)?;
@@ -194,17 +239,13 @@ impl Writeable for Pos {
 
 impl Writeable for Val {
     fn byte_size(&self) -> usize {
-        match self.0 {
-            0x00..=0x0F => 1,
-            0x10..=0xFFF => 2,
-            0x1000..=0xFFFFF => 3,
-            0x100000..=0xFFFFFFF => 4,
-            0x10000000..=0xFFFFFFFF => 5
-        }
+        canonical_len(self.0)
     }
 
     fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
-        let len = (self.byte_size() - 1) as u8;
+        // Longer if the scene being written kept this value's length.
+        let encoded_len = next_val_length().unwrap_or(0) as usize;
+        let len = (self.byte_size().max(encoded_len) - 1) as u8;
         let mut v = self.0;
 
         let mut len_byte = ((len + 1) << 4) | (v as u8) & 0x0F;
@@ -251,8 +292,8 @@ impl Writeable for FormattedTextCmd {
             FormattedTextCmd::Integer(idx) => 1 + idx.byte_size(),
             FormattedTextCmd::IntegerZeroPadded(idx, zeros) => 1 + idx.byte_size() + zeros.byte_size(),
             FormattedTextCmd::TextPointer(idx) => 1 + idx.byte_size(),
-            FormattedTextCmd::Unknown1(idx) => 1 + idx.byte_size(),
-            FormattedTextCmd::Unknown2 => 1
+            FormattedTextCmd::Color(idx) => 1 + idx.byte_size(),
+            FormattedTextCmd::ResetColor => 1
         }
     }
 
@@ -271,11 +312,11 @@ impl Writeable for FormattedTextCmd {
                 (0x03u8).write(writer)?;
                 idx.write(writer)
             },
-            FormattedTextCmd::Unknown1(idx) => {
+            FormattedTextCmd::Color(idx) => {
                 (0x11u8).write(writer)?;
                 idx.write(writer)
             },
-            FormattedTextCmd::Unknown2 => (0x13u8).write(writer)
+            FormattedTextCmd::ResetColor => (0x13u8).write(writer)
         }
     }
 }
@@ -1152,6 +1193,11 @@ impl Writeable for ChoiceText {
     }
 
     fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        if let Some(pad) = self.pad {
+            if is_choice_text_start(pad) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Choice pad byte 0x{:02x} would be read back as text", pad)));
+            }
+        }
         self.pad.write(writer)?;
         self.texts.write(writer)?;
         (0x23u8).write(writer)
@@ -1365,8 +1411,8 @@ impl Writeable for BRGStretchBlit {
         self.srcy2.write(writer)?;
         self.srcpdt.write(writer)?;
         self.dstx1.write(writer)?;
-        self.dstx2.write(writer)?;
         self.dsty1.write(writer)?;
+        self.dstx2.write(writer)?;
         self.dsty2.write(writer)?;
         self.dstpdt.write(writer)
     }
@@ -1987,7 +2033,7 @@ impl Writeable for NameCmd {
             NameCmd::SetName(idx, text) => 1 + idx.byte_size() + text.byte_size(),
             NameCmd::GetName2(idx, text) => 1 + idx.byte_size() + text.byte_size(),
             NameCmd::NameInputDialog(idx) => 1 + idx.byte_size(),
-            NameCmd::Unknown1(idx, text, a, b, c, d, e, f, g, h, i) => idx.byte_size() + text.byte_size() + a.byte_size() + b.byte_size() + c.byte_size() + d.byte_size() + e.byte_size() + f.byte_size() + g.byte_size() + h.byte_size() + i.byte_size(),
+            NameCmd::Unknown1(idx, text, a, b, c, d, e, f, g, h, i) => 1 + idx.byte_size() + text.byte_size() + a.byte_size() + b.byte_size() + c.byte_size() + d.byte_size() + e.byte_size() + f.byte_size() + g.byte_size() + h.byte_size() + i.byte_size(),
             NameCmd::NameInputDialogMulti(items) => 1 + mem::size_of::<u8>() + items.byte_size(),
             NameCmd::Unknown2 => 1,
             NameCmd::Unknown3 => 1
@@ -2435,7 +2481,7 @@ impl Writeable for SystemVarCmd {
                 b.write(writer)
             },
             SystemVarCmd::GetMsgMojiSize(a, b) => {
-                (0x04u8).write(writer)?;
+                (0x05u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
@@ -2623,6 +2669,116 @@ impl Writeable for PopupMenuCmd {
     }
 }
 
+impl Opcode {
+    /// The byte this opcode is written with.
+    pub fn byte(&self) -> u8 {
+        match self {
+            Opcode::WaitMouse => 0x01,
+            Opcode::Newline => 0x02,
+            Opcode::WaitMouseText => 0x03,
+            Opcode::TextWin(_) => 0x04,
+            Opcode::Op0x05 => 0x05,
+            Opcode::Op0x06 => 0x06,
+            Opcode::Op0x08 => 0x08,
+            Opcode::Graphics(_) => 0x0b,
+            Opcode::Op0x0c => 0x0c,
+            Opcode::Sound(_) => 0x0e,
+            Opcode::DrawValText(_) => 0x10,
+            Opcode::Fade(_) => 0x13,
+            Opcode::Condition(..) => 0x15,
+            Opcode::JumpToScene(_) => 0x16,
+            Opcode::ScreenShake(_) => 0x17,
+            Opcode::Op0x18 => 0x18,
+            Opcode::Wait(_) => 0x19,
+            Opcode::Op0x1a => 0x1a,
+            Opcode::Call(_) => 0x1b,
+            Opcode::Jump(_) => 0x1c,
+            Opcode::TableCall(..) => 0x1d,
+            Opcode::TableJump(..) => 0x1e,
+            Opcode::Return(_) => 0x20,
+            Opcode::Unknown0x22 => 0x22,
+            Opcode::Unknown0x23 => 0x23,
+            Opcode::Unknown0x24 => 0x24,
+            Opcode::Unknown0x25 => 0x25,
+            Opcode::Unknown0x26 => 0x26,
+            Opcode::Unknown0x27 => 0x27,
+            Opcode::Unknown0x28 => 0x28,
+            Opcode::Unknown0x29 => 0x29,
+            Opcode::Op0x2c => 0x2c,
+            Opcode::Op0x2d => 0x2d,
+            Opcode::ScenarioMenu(_) => 0x2e,
+            Opcode::ScenarioMenu2(_) => 0x2f,
+            Opcode::Op0x30 => 0x30,
+            Opcode::TextRank(_) => 0x31,
+            Opcode::SetFlag(..) => 0x37,
+            Opcode::CopyFlag(..) => 0x39,
+            Opcode::SetValLiteral(..) => 0x3b,
+            Opcode::AddVal(..) => 0x3c,
+            Opcode::SubVal(..) => 0x3d,
+            Opcode::MulVal(..) => 0x3e,
+            Opcode::DivVal(..) => 0x3f,
+            Opcode::ModVal(..) => 0x40,
+            Opcode::AndVal(..) => 0x41,
+            Opcode::OrVal(..) => 0x42,
+            Opcode::XorVal(..) => 0x43,
+            Opcode::SetVal(..) => 0x49,
+            Opcode::AddValSelf(..) => 0x4a,
+            Opcode::SubValSelf(..) => 0x4b,
+            Opcode::MulValSelf(..) => 0x4c,
+            Opcode::DivValSelf(..) => 0x4d,
+            Opcode::ModValSelf(..) => 0x4e,
+            Opcode::AndValSelf(..) => 0x4f,
+            Opcode::OrValSelf(..) => 0x50,
+            Opcode::XorValSelf(..) => 0x51,
+            Opcode::SetFlagRandom(_) => 0x56,
+            Opcode::SetValRandom(..) => 0x57,
+            Opcode::Choice(_) => 0x58,
+            Opcode::String(_) => 0x59,
+            Opcode::Op0x5b => 0x5b,
+            Opcode::SetMulti(_) => 0x5c,
+            Opcode::Op0x5d => 0x5d,
+            Opcode::Op0x5e => 0x5e,
+            Opcode::Op0x5f => 0x5f,
+            Opcode::System(_) => 0x60,
+            Opcode::Name(_) => 0x61,
+            Opcode::Op0x63 => 0x63,
+            Opcode::BufferRegion(_) => 0x64,
+            Opcode::Unknown0x65 => 0x65,
+            Opcode::Buffer(_) => 0x67,
+            Opcode::Flash(_) => 0x68,
+            Opcode::Op0x69 => 0x69,
+            Opcode::MultiPdt(_) => 0x6a,
+            Opcode::Op0x66 => 0x66,
+            Opcode::AreaBuffer(_) => 0x6c,
+            Opcode::MouseCtrl(_) => 0x6d,
+            Opcode::Op0x6e => 0x6e,
+            Opcode::Op0x6f => 0x6f,
+            Opcode::WindowVar(_) => 0x70,
+            Opcode::MessageWin(_) => 0x72,
+            Opcode::SystemVar(_) => 0x73,
+            Opcode::PopupMenu(_) => 0x74,
+            Opcode::Volume(_) => 0x75,
+            Opcode::NovelMode(_) => 0x76,
+            Opcode::Op0x7f => 0x7f,
+            Opcode::Unknown0xea(_) => 0xea,
+            Opcode::TextHankaku(..) => 0xfe,
+            Opcode::TextZenkaku(..) => 0xff,
+            Opcode::UnknownRaw(byte, _) => *byte,
+        }
+    }
+}
+
+impl Opcode {
+    /// The bytes written after `byte`, for hex views or splicing operands
+    /// into another opcode. Fails like `write` on text Shift_JIS can't
+    /// encode.
+    pub fn operand_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = self.to_vec()?;
+        bytes.remove(0);
+        Ok(bytes)
+    }
+}
+
 impl Writeable for Opcode {
     fn byte_size(&self) -> usize {
         match self {
@@ -2660,7 +2816,7 @@ impl Writeable for Opcode {
             Opcode::Op0x2c => 1,
             Opcode::Op0x2d => 1,
             Opcode::ScenarioMenu(a) => 1 + a.byte_size(),
-            Opcode::Op0x2f => 1,
+            Opcode::ScenarioMenu2(a) => 1 + a.byte_size(),
             Opcode::Op0x30 => 1,
             Opcode::TextRank(a) => 1 + a.byte_size(),
             Opcode::SetFlag(a, b) => 1 + a.byte_size() + b.byte_size(),
@@ -2716,325 +2872,261 @@ impl Writeable for Opcode {
             Opcode::Unknown0xea(a) => 1 + a.byte_size(),
             Opcode::TextHankaku(a, b) => 1 + a.byte_size() + b.byte_size(),
             Opcode::TextZenkaku(a, b) => 1 + a.byte_size() + b.byte_size(),
+            Opcode::UnknownRaw(_, a) => 1 + a.len(),
         }
     }
 
     fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        self.byte().write(writer)?;
         match self {
-            Opcode::WaitMouse => (0x01u8).write(writer),
-            Opcode::Newline => (0x02u8).write(writer),
-            Opcode::WaitMouseText => (0x03u8).write(writer),
-            Opcode::TextWin(a) => {
-                (0x04u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x05 => (0x05u8).write(writer),
-            Opcode::Op0x06 => (0x06u8).write(writer),
-            Opcode::Op0x08 => (0x08u8).write(writer),
-            Opcode::Graphics(a) => {
-                (0x0bu8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x0c => (0x0cu8).write(writer),
-            Opcode::Sound(a) => {
-                (0x0eu8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::DrawValText(a) => {
-                (0x10u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Fade(a) => {
-                (0x13u8).write(writer)?;
-                a.write(writer)
-            },
+            Opcode::WaitMouse => Ok(()),
+            Opcode::Newline => Ok(()),
+            Opcode::WaitMouseText => Ok(()),
+            Opcode::TextWin(a) => a.write(writer),
+            Opcode::Op0x05 => Ok(()),
+            Opcode::Op0x06 => Ok(()),
+            Opcode::Op0x08 => Ok(()),
+            Opcode::Graphics(a) => a.write(writer),
+            Opcode::Op0x0c => Ok(()),
+            Opcode::Sound(a) => a.write(writer),
+            Opcode::DrawValText(a) => a.write(writer),
+            Opcode::Fade(a) => a.write(writer),
             Opcode::Condition(a, b) => {
-                (0x15u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
-            Opcode::JumpToScene(a) => {
-                (0x16u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::ScreenShake(a) => {
-                (0x17u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x18 => (0x18u8).write(writer),
-            Opcode::Wait(a) => {
-                (0x19u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x1a => (0x1au8).write(writer),
-            Opcode::Call(a) => {
-                (0x1bu8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Jump(a) => {
-                (0x1cu8).write(writer)?;
-                a.write(writer)
-            },
+            Opcode::JumpToScene(a) => a.write(writer),
+            Opcode::ScreenShake(a) => a.write(writer),
+            Opcode::Op0x18 => Ok(()),
+            Opcode::Wait(a) => a.write(writer),
+            Opcode::Op0x1a => Ok(()),
+            Opcode::Call(a) => a.write(writer),
+            Opcode::Jump(a) => a.write(writer),
             Opcode::TableCall(a, b) => {
-                (0x1du8).write(writer)?;
                 (b.len() as u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::TableJump(a, b) => {
-                (0x1eu8).write(writer)?;
                 (b.len() as u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
-            Opcode::Return(a) => {
-                (0x20u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Unknown0x22 => (0x22u8).write(writer),
-            Opcode::Unknown0x23 => (0x23u8).write(writer),
-            Opcode::Unknown0x24 => (0x24u8).write(writer),
-            Opcode::Unknown0x25 => (0x25u8).write(writer),
-            Opcode::Unknown0x26 => (0x26u8).write(writer),
-            Opcode::Unknown0x27 => (0x27u8).write(writer),
-            Opcode::Unknown0x28 => (0x28u8).write(writer),
-            Opcode::Unknown0x29 => (0x29u8).write(writer),
-            Opcode::Op0x2c => (0x2cu8).write(writer),
-            Opcode::Op0x2d => (0x2du8).write(writer),
-            Opcode::ScenarioMenu(a) => {
-                (0x2eu8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x2f => (0x2fu8).write(writer),
-            Opcode::Op0x30 => (0x30u8).write(writer),
-            Opcode::TextRank(a) => {
-                (0x31u8).write(writer)?;
-                a.write(writer)
-            },
+            Opcode::Return(a) => a.write(writer),
+            Opcode::Unknown0x22 => Ok(()),
+            Opcode::Unknown0x23 => Ok(()),
+            Opcode::Unknown0x24 => Ok(()),
+            Opcode::Unknown0x25 => Ok(()),
+            Opcode::Unknown0x26 => Ok(()),
+            Opcode::Unknown0x27 => Ok(()),
+            Opcode::Unknown0x28 => Ok(()),
+            Opcode::Unknown0x29 => Ok(()),
+            Opcode::Op0x2c => Ok(()),
+            Opcode::Op0x2d => Ok(()),
+            Opcode::ScenarioMenu(a) => a.write(writer),
+            Opcode::ScenarioMenu2(a) => a.write(writer),
+            Opcode::Op0x30 => Ok(()),
+            Opcode::TextRank(a) => a.write(writer),
             Opcode::SetFlag(a, b) => {
-                (0x37u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::CopyFlag(a, b) => {
-                (0x39u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::SetValLiteral(a, b) => {
-                (0x3bu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::AddVal(a, b) => {
-                (0x3cu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::SubVal(a, b) => {
-                (0x3du8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::MulVal(a, b) => {
-                (0x3eu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::DivVal(a, b) => {
-                (0x3fu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::ModVal(a, b) => {
-                (0x40u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::AndVal(a, b) => {
-                (0x41u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::OrVal(a, b) => {
-                (0x42u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::XorVal(a, b) => {
-                (0x43u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::SetVal(a, b) => {
-                (0x49u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::AddValSelf(a, b) => {
-                (0x4au8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::SubValSelf(a, b) => {
-                (0x4bu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::MulValSelf(a, b) => {
-                (0x4cu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::DivValSelf(a, b) => {
-                (0x4du8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::ModValSelf(a, b) => {
-                (0x4eu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::AndValSelf(a, b) => {
-                (0x4fu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::OrValSelf(a, b) => {
-                (0x50u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::XorValSelf(a, b) => {
-                (0x51u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
-            Opcode::SetFlagRandom(a) => {
-                (0x56u8).write(writer)?;
-                a.write(writer)
-            },
+            Opcode::SetFlagRandom(a) => a.write(writer),
             Opcode::SetValRandom(a, b) => {
-                (0x57u8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
-            Opcode::Choice(a) => {
-                (0x58u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::String(a) => {
-                (0x59u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x5b => (0x5bu8).write(writer),
-            Opcode::SetMulti(a) => {
-                (0x5cu8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x5d => (0x5du8).write(writer),
-            Opcode::Op0x5e => (0x5eu8).write(writer),
-            Opcode::Op0x5f => (0x5fu8).write(writer),
-            Opcode::System(a) => {
-                (0x60u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Name(a) => {
-                (0x61u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x63 => (0x63u8).write(writer),
-            Opcode::BufferRegion(a) => {
-                (0x64u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Unknown0x65 => (0x65u8).write(writer),
-            Opcode::Buffer(a) => {
-                (0x67u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Flash(a) => {
-                (0x68u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x69 => (0x69u8).write(writer),
-            Opcode::MultiPdt(a) => {
-                (0x6au8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x66 => (0x66u8).write(writer),
-            Opcode::AreaBuffer(a) => {
-                (0x6cu8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::MouseCtrl(a) => {
-                (0x6du8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x6e => (0x6eu8).write(writer),
-            Opcode::Op0x6f => (0x6fu8).write(writer),
-            Opcode::WindowVar(a) => {
-                (0x70u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::MessageWin(a) => {
-                (0x72u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::SystemVar(a) => {
-                (0x73u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::PopupMenu(a) => {
-                (0x74u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Volume(a) => {
-                (0x75u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::NovelMode(a) => {
-                (0x76u8).write(writer)?;
-                a.write(writer)
-            },
-            Opcode::Op0x7f => (0x7fu8).write(writer),
-            Opcode::Unknown0xea(a) => {
-                (0xeau8).write(writer)?;
-                a.write(writer)
-            },
+            Opcode::Choice(a) => a.write(writer),
+            Opcode::String(a) => a.write(writer),
+            Opcode::Op0x5b => Ok(()),
+            Opcode::SetMulti(a) => a.write(writer),
+            Opcode::Op0x5d => Ok(()),
+            Opcode::Op0x5e => Ok(()),
+            Opcode::Op0x5f => Ok(()),
+            Opcode::System(a) => a.write(writer),
+            Opcode::Name(a) => a.write(writer),
+            Opcode::Op0x63 => Ok(()),
+            Opcode::BufferRegion(a) => a.write(writer),
+            Opcode::Unknown0x65 => Ok(()),
+            Opcode::Buffer(a) => a.write(writer),
+            Opcode::Flash(a) => a.write(writer),
+            Opcode::Op0x69 => Ok(()),
+            Opcode::MultiPdt(a) => a.write(writer),
+            Opcode::Op0x66 => Ok(()),
+            Opcode::AreaBuffer(a) => a.write(writer),
+            Opcode::MouseCtrl(a) => a.write(writer),
+            Opcode::Op0x6e => Ok(()),
+            Opcode::Op0x6f => Ok(()),
+            Opcode::WindowVar(a) => a.write(writer),
+            Opcode::MessageWin(a) => a.write(writer),
+            Opcode::SystemVar(a) => a.write(writer),
+            Opcode::PopupMenu(a) => a.write(writer),
+            Opcode::Volume(a) => a.write(writer),
+            Opcode::NovelMode(a) => a.write(writer),
+            Opcode::Op0x7f => Ok(()),
+            Opcode::Unknown0xea(a) => a.write(writer),
             Opcode::TextHankaku(a, b) => {
-                (0xfeu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
             Opcode::TextZenkaku(a, b) => {
-                (0xffu8).write(writer)?;
                 a.write(writer)?;
                 b.write(writer)
             },
+            Opcode::UnknownRaw(_, b) => b.write(writer),
         }
     }
 }
 
+/// Writes an opcode body, optionally followed by the `0x00` terminator that
+/// ends a scene.
+pub fn write_opcodes<W: Write>(opcodes: &[Opcode], writer: &mut W, with_terminator: bool) -> Result<(), io::Error> {
+    for opcode in opcodes.iter() {
+        opcode.write(writer)?;
+    }
+    if with_terminator {
+        writer.write_all(&[0x00])?;
+    }
+    Ok(())
+}
+
 impl Writeable for AVG32Scene {
     fn byte_size(&self) -> usize {
-        self.header.byte_size() + self.opcodes.byte_size() + 1 // \0
+        let opcodes: usize = (0..self.opcodes.len()).map(|index| self.opcode_size(index)).sum();
+        self.header.byte_size() + opcodes + 1 /* \0 */ + self.trailer.len()
     }
 
     fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
         self.header.write(writer)?;
-        self.opcodes.write(writer)?;
-        writer.write_all(&[0x00])
+        for index in 0..self.opcodes.len() {
+            self.write_opcode(index, writer)?;
+        }
+        writer.write_all(&[0x00])?;
+        writer.write_all(&self.trailer)
+    }
+}
+
+impl AVG32Scene {
+    /// Writes the opcode at `index`, with its values at the lengths kept in
+    /// `val_lengths`.
+    pub(crate) fn write_opcode<W: Write>(&self, index: usize, writer: &mut W) -> Result<(), io::Error> {
+        let opcode = &self.opcodes[index];
+        match self.val_lengths.get(&index) {
+            Some(lengths) => with_val_lengths(lengths, || opcode.write(writer)),
+            None => opcode.write(writer)
+        }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::parser;
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn test_to_vec() {
+        fn check<T: Writeable>(x: &T) -> Vec<u8> {
+            let bytes = x.to_vec().unwrap();
+            assert_eq!(x.byte_size(), bytes.len());
+            bytes
+        }
+
+        assert_eq!(vec![0x12], check(&0x12u8));
+        assert_eq!(vec![0x78, 0x56, 0x34, 0x12], check(&0x12345678u32));
+        check(&Val::constant(0x1234));
+        check(&Val::variable(3));
+        check(&"あいう");
+        check(&Opcode::Jump(Pos::Offset(4)));
+        check(&Header::empty());
+
+        let scene = crate::build::sample_scene();
+        let bytes = check(&scene);
+        assert_eq!(scene, crate::load_bytes(&bytes).unwrap());
+
+        let mut arc = crate::archive::Archive::new();
+        arc.add_entry(String::from("SEEN001.TXT"), bytes).unwrap();
+        arc.finalize();
+        let arc_bytes = check(&arc);
+        assert!(crate::archive::load_bytes(&arc_bytes).is_ok());
+    }
+
     #[test]
     fn test_roundtrip_value() {
         let test = |bytes: &[u8]| {
@@ -3058,6 +3150,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs the original game's SEEN files in ../SEEN"]
     fn test_roundtrip_scene() {
         use std::fs;
         for entry in fs::read_dir("../SEEN").unwrap() {
@@ -3078,11 +3171,606 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_roundtrip_opcodes() {
+        let opcodes = vec![
+            Opcode::WaitMouse,
+            Opcode::SetVal(Val(0x12, ValType::Var), Val(0x345, ValType::Const)),
+            Opcode::TextZenkaku(Some(1), SceneText::Literal(String::from("あいう"))),
+            Opcode::Jump(Pos::Offset(0x20)),
+        ];
+
+        let mut bare = Vec::new();
+        write_opcodes(&opcodes, &mut bare, false).unwrap();
+        assert_eq!(opcodes.byte_size(), bare.len());
+        assert_eq!(opcodes, crate::parse_opcodes(&bare).unwrap());
+
+        let mut terminated = Vec::new();
+        write_opcodes(&opcodes, &mut terminated, true).unwrap();
+        assert_eq!(Some(&0x00), terminated.last());
+        assert_eq!(&bare[..], &terminated[..terminated.len() - 1]);
+        assert_eq!(opcodes, crate::parse_opcodes(&terminated).unwrap());
+    }
+
+    fn c(n: u32) -> Val {
+        Val::constant(n)
+    }
+
+    fn lit(s: &str) -> SceneText {
+        SceneText::Literal(String::from(s))
+    }
+
+    fn ftext(s: &str) -> SceneFormattedText {
+        SceneFormattedText(vec![SceneFormattedTextEntry::TextZenkaku(String::from(s))])
+    }
+
+    /// Formatted text using every kind of entry.
+    fn rich_text() -> SceneFormattedText {
+        SceneFormattedText(vec![
+            SceneFormattedTextEntry::TextZenkaku(String::from("あ")),
+            SceneFormattedTextEntry::Command(FormattedTextCmd::Integer(c(1))),
+            SceneFormattedTextEntry::Command(FormattedTextCmd::IntegerZeroPadded(c(2), c(3))),
+            SceneFormattedTextEntry::Command(FormattedTextCmd::TextPointer(c(4))),
+            SceneFormattedTextEntry::Command(FormattedTextCmd::Color(c(5))),
+            SceneFormattedTextEntry::Command(FormattedTextCmd::ResetColor),
+            SceneFormattedTextEntry::Unknown,
+            SceneFormattedTextEntry::Condition(vec![Condition::Ret(Ret::Color(c(6))), Condition::DecDepth]),
+            SceneFormattedTextEntry::TextPointer(c(7)),
+            SceneFormattedTextEntry::TextHankaku(String::from("abc")),
+        ])
+    }
+
+    fn pdt(n: u32) -> MultiPdtEntry {
+        MultiPdtEntry { text: lit("PDT"), data: c(n) }
+    }
+
+    fn composite_children() -> Vec<GrpCompositeChild> {
+        vec![
+            GrpCompositeChild { file: lit("A"), method: GrpCompositeMethod::Corner },
+            GrpCompositeChild { file: lit("B"), method: GrpCompositeMethod::Copy(c(1)) },
+            GrpCompositeChild { file: lit("C"), method: GrpCompositeMethod::Move1(c(1), c(2), c(3), c(4), c(5), c(6)) },
+            GrpCompositeChild { file: lit("D"), method: GrpCompositeMethod::Move2(c(1), c(2), c(3), c(4), c(5), c(6), c(7)) },
+        ]
+    }
+
+    /// One of every opcode and subcommand variant, with distinct operands
+    /// so that swapped fields show up. `UnknownRaw` is left out since it
+    /// stands for bytes the parser rejected.
+    pub(crate) fn every_opcode() -> Vec<Opcode> {
+        vec![
+            Opcode::TextWin(TextWinCmd::Hide),
+            Opcode::TextWin(TextWinCmd::HideEffect),
+            Opcode::TextWin(TextWinCmd::HideRedraw),
+            Opcode::TextWin(TextWinCmd::MouseWait),
+            Opcode::TextWin(TextWinCmd::ClearText),
+            Opcode::Graphics(GrpCmd::Load(lit("FILE1"), c(2))),
+            Opcode::Graphics(GrpCmd::LoadEffect(GrpEffect { file: lit("FILE1"), sx1: c(2), sy1: c(3), sx2: c(4), sy2: c(5), dx: c(6), dy: c(7), steptime: c(8), cmd: c(9), mask: c(10), arg1: c(11), arg2: c(12), arg3: c(13), step: c(14), arg5: c(15), arg6: c(16) })),
+            Opcode::Graphics(GrpCmd::Load2(lit("FILE1"), c(2))),
+            Opcode::Graphics(GrpCmd::LoadEffect2(GrpEffect { file: lit("FILE1"), sx1: c(2), sy1: c(3), sx2: c(4), sy2: c(5), dx: c(6), dy: c(7), steptime: c(8), cmd: c(9), mask: c(10), arg1: c(11), arg2: c(12), arg3: c(13), step: c(14), arg5: c(15), arg6: c(16) })),
+            Opcode::Graphics(GrpCmd::Load3(lit("FILE1"), c(2))),
+            Opcode::Graphics(GrpCmd::LoadEffect3(GrpEffect { file: lit("FILE1"), sx1: c(2), sy1: c(3), sx2: c(4), sy2: c(5), dx: c(6), dy: c(7), steptime: c(8), cmd: c(9), mask: c(10), arg1: c(11), arg2: c(12), arg3: c(13), step: c(14), arg5: c(15), arg6: c(16) })),
+            Opcode::Graphics(GrpCmd::Unknown1),
+            Opcode::Graphics(GrpCmd::LoadToBuf(lit("FILE1"), c(2))),
+            Opcode::Graphics(GrpCmd::LoadToBuf2(lit("FILE1"), c(2))),
+            Opcode::Graphics(GrpCmd::LoadCaching(lit("FILE1"))),
+            Opcode::Graphics(GrpCmd::GrpCmd0x13),
+            Opcode::Graphics(GrpCmd::LoadComposite(GrpComposite { base_file: lit("BASE"), idx: c(1), children: composite_children() })),
+            Opcode::Graphics(GrpCmd::LoadCompositeIndexed(GrpCompositeIndexed { base_file: c(1), idx: c(2), children: composite_children() })),
+            Opcode::Graphics(GrpCmd::MacroBufferClear),
+            Opcode::Graphics(GrpCmd::MacroBufferDelete(c(1))),
+            Opcode::Graphics(GrpCmd::MacroBufferRead(c(1))),
+            Opcode::Graphics(GrpCmd::MacroBufferSet(c(1))),
+            Opcode::Graphics(GrpCmd::BackupScreenCopy),
+            Opcode::Graphics(GrpCmd::BackupScreenDisplay(c(1))),
+            Opcode::Graphics(GrpCmd::LoadToBuf3(lit("FILE1"), c(2))),
+            Opcode::Sound(SndCmd::BgmLoop(lit("FILE1"))),
+            Opcode::Sound(SndCmd::BgmWait(lit("FILE1"))),
+            Opcode::Sound(SndCmd::BgmOnce(lit("FILE1"))),
+            Opcode::Sound(SndCmd::BgmFadeInLoop(lit("FILE1"), c(2))),
+            Opcode::Sound(SndCmd::BgmFadeInWait(lit("FILE1"), c(2))),
+            Opcode::Sound(SndCmd::BgmFadeInOnce(lit("FILE1"), c(2))),
+            Opcode::Sound(SndCmd::BgmFadeOut(c(1))),
+            Opcode::Sound(SndCmd::BgmStop),
+            Opcode::Sound(SndCmd::BgmRewind),
+            Opcode::Sound(SndCmd::BgmUnknown1),
+            Opcode::Sound(SndCmd::KoePlayWait(c(1))),
+            Opcode::Sound(SndCmd::KoePlay(c(1))),
+            Opcode::Sound(SndCmd::KoePlay2(c(1), c(2))),
+            Opcode::Sound(SndCmd::WavPlay(lit("FILE1"))),
+            Opcode::Sound(SndCmd::WavPlay2(lit("FILE1"), c(2))),
+            Opcode::Sound(SndCmd::WavLoop(lit("FILE1"))),
+            Opcode::Sound(SndCmd::WavLoop2(lit("FILE1"), c(2))),
+            Opcode::Sound(SndCmd::WavPlayWait(lit("FILE1"))),
+            Opcode::Sound(SndCmd::WavPlayWait2(lit("FILE1"), c(2))),
+            Opcode::Sound(SndCmd::WavStop),
+            Opcode::Sound(SndCmd::WavStop2(c(1))),
+            Opcode::Sound(SndCmd::WavStop3),
+            Opcode::Sound(SndCmd::WavUnknown0x39(c(1))),
+            Opcode::Sound(SndCmd::SePlay(c(1))),
+            Opcode::Sound(SndCmd::MoviePlay(lit("FILE1"), c(2), c(3), c(4), c(5))),
+            Opcode::Sound(SndCmd::MovieLoop(lit("FILE1"), c(2), c(3), c(4), c(5))),
+            Opcode::Sound(SndCmd::MovieWait(lit("FILE1"), c(2), c(3), c(4), c(5))),
+            Opcode::Sound(SndCmd::MovieWaitCancelable(lit("FILE1"), c(2), c(3), c(4), c(5))),
+            Opcode::Sound(SndCmd::MovieWait2(lit("FILE1"), lit("FILE2"), c(3), c(4), c(5), c(6))),
+            Opcode::Sound(SndCmd::MovieWaitCancelable2(lit("FILE1"), lit("FILE2"), c(3), c(4), c(5), c(6))),
+            Opcode::Sound(SndCmd::Unknown1),
+            Opcode::DrawValText(FormattedTextCmd::Integer(c(1))),
+            Opcode::DrawValText(FormattedTextCmd::IntegerZeroPadded(c(1), c(2))),
+            Opcode::DrawValText(FormattedTextCmd::TextPointer(c(1))),
+            Opcode::DrawValText(FormattedTextCmd::Color(c(1))),
+            Opcode::DrawValText(FormattedTextCmd::ResetColor),
+            Opcode::Fade(FadeCmd::Fade(c(1))),
+            Opcode::Fade(FadeCmd::FadeTimed(c(1), c(2))),
+            Opcode::Fade(FadeCmd::FadeColor(c(1), c(2), c(3))),
+            Opcode::Fade(FadeCmd::FadeTimedColor(c(1), c(2), c(3), c(4))),
+            Opcode::Fade(FadeCmd::FillScreen(c(1))),
+            Opcode::Fade(FadeCmd::FillScreenColor(c(1), c(2), c(3))),
+            Opcode::JumpToScene(JumpToSceneCmd::Jump(c(1))),
+            Opcode::JumpToScene(JumpToSceneCmd::Call(c(1))),
+            Opcode::ScreenShake(ScreenShakeCmd::ScreenShake(c(1))),
+            Opcode::Wait(WaitCmd::Wait(c(1))),
+            Opcode::Wait(WaitCmd::WaitMouse(c(1), c(2))),
+            Opcode::Wait(WaitCmd::SetToBase),
+            Opcode::Wait(WaitCmd::WaitFromBase(c(1))),
+            Opcode::Wait(WaitCmd::WaitFromBaseMouse(c(1))),
+            Opcode::Wait(WaitCmd::SetToBaseVal(c(1))),
+            Opcode::Wait(WaitCmd::Wait0x10),
+            Opcode::Wait(WaitCmd::Wait0x11),
+            Opcode::Wait(WaitCmd::Wait0x12),
+            Opcode::Wait(WaitCmd::Wait0x13),
+            Opcode::Return(RetCmd::SameScene),
+            Opcode::Return(RetCmd::OtherScene),
+            Opcode::Return(RetCmd::PopStack),
+            Opcode::Return(RetCmd::ClearStack),
+            Opcode::ScenarioMenu(ScenarioMenuCmd::SetBit(c(1))),
+            Opcode::ScenarioMenu(ScenarioMenuCmd::SetBit2(c(1), c(2))),
+            Opcode::ScenarioMenu2(ScenarioMenuCmd::SetBit(c(1))),
+            Opcode::TextRank(TextRankCmd::Set(c(1))),
+            Opcode::TextRank(TextRankCmd::Clear),
+            Opcode::Choice(ChoiceCmd::Choice(c(1), 0x22, Some(ChoiceText { pad: Some(0x22), texts: vec![ftext("はい"), rich_text()] }))),
+            Opcode::Choice(ChoiceCmd::Choice(c(1), 0x00, None)),
+            Opcode::Choice(ChoiceCmd::Choice2(c(1), 0x22, Some(ChoiceText { pad: None, texts: vec![ftext("はい"), ftext("いいえ")] }))),
+            Opcode::Choice(ChoiceCmd::Choice2(c(1), 0x00, None)),
+            Opcode::Choice(ChoiceCmd::LoadMenu(c(1))),
+            Opcode::String(StringCmd::StrcpyLiteral(c(1), lit("FILE2"))),
+            Opcode::String(StringCmd::Strlen(c(1), c(2))),
+            Opcode::String(StringCmd::Strcmp(c(1), c(2), c(3))),
+            Opcode::String(StringCmd::Strcat(c(1), c(2))),
+            Opcode::String(StringCmd::Strcpy(c(1), c(2))),
+            Opcode::String(StringCmd::Itoa(c(1), c(2), c(3))),
+            Opcode::String(StringCmd::HanToZen(c(1))),
+            Opcode::String(StringCmd::Atoi(c(1), c(2))),
+            Opcode::SetMulti(SetMultiCmd::Val(c(1), c(2), c(3))),
+            Opcode::SetMulti(SetMultiCmd::Bit(c(1), c(2), c(3))),
+            Opcode::System(SystemCmd::LoadGame(c(1))),
+            Opcode::System(SystemCmd::SaveGame(c(1))),
+            Opcode::System(SystemCmd::SetTitle(rich_text())),
+            Opcode::System(SystemCmd::MakePopup),
+            Opcode::System(SystemCmd::GameEnd),
+            Opcode::System(SystemCmd::GetSaveTitle(c(1), c(2))),
+            Opcode::System(SystemCmd::CheckSaveData(c(1), c(2))),
+            Opcode::System(SystemCmd::Unknown1(c(1), c(2))),
+            Opcode::System(SystemCmd::Unknown2(c(1), c(2))),
+            Opcode::System(SystemCmd::Unknown3(c(1), c(2))),
+            Opcode::Name(NameCmd::InputBox(c(1), c(2), c(3), c(4), c(5), c(6), c(7), c(8), c(9), c(10))),
+            Opcode::Name(NameCmd::InputBoxFinish(c(1))),
+            Opcode::Name(NameCmd::InputBoxStart(c(1))),
+            Opcode::Name(NameCmd::InputBoxClose(c(1))),
+            Opcode::Name(NameCmd::GetName(c(1), c(2))),
+            Opcode::Name(NameCmd::SetName(c(1), c(2))),
+            Opcode::Name(NameCmd::GetName2(c(1), c(2))),
+            Opcode::Name(NameCmd::NameInputDialog(c(1))),
+            Opcode::Name(NameCmd::Unknown1(c(1), lit("FILE2"), c(3), c(4), c(5), c(6), c(7), c(8), c(9), c(10), c(11))),
+            Opcode::Name(NameCmd::NameInputDialogMulti(vec![NameInputItem { idx: c(1), text: ftext("名前") }, NameInputItem { idx: c(2), text: ftext("苗字") }])),
+            Opcode::Name(NameCmd::Unknown2),
+            Opcode::Name(NameCmd::Unknown3),
+            Opcode::BufferRegion(BufferRegionGrpCmd::ClearRect(BRGRectColor { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), r: c(6), g: c(7), b: c(8) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::DrawRectLine(BRGRectColor { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), r: c(6), g: c(7), b: c(8) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::InvertColor(BRGRect { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::ColorMask(BRGRectColor { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), r: c(6), g: c(7), b: c(8) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::FadeOutColor(BRGRect { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::FadeOutColor2(BRGRect { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::FadeOutColor3(BRGFadeOutColor { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), r: c(6), g: c(7), b: c(8), count: c(9) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::MakeMonoImage(BRGRect { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::StretchBlit(BRGStretchBlit { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), dstx1: c(6), dstx2: c(7), dsty1: c(8), dsty2: c(9), dstpdt: c(10) })),
+            Opcode::BufferRegion(BufferRegionGrpCmd::StretchBlitEffect(BRGStretchBlitEffect { sx1: c(1), sy1: c(2), sx2: c(3), sy2: c(4), ex1: c(5), ey1: c(6), ex2: c(7), ey2: c(8), srcpdt: c(9), dx1: c(10), dy1: c(11), dx2: c(12), dy2: c(13), dstpdt: c(14), step: c(15), steptime: c(16) })),
+            Opcode::Buffer(BufferGrpCmd::CopySamePos(BGCopySamePos { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), flag: c(6) })),
+            Opcode::Buffer(BufferGrpCmd::CopyNewPos(BGCopyNewPos { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), dstx1: c(6), dsty1: c(7), dstpdt: c(8), flag: Some(c(9)) })),
+            Opcode::Buffer(BufferGrpCmd::CopyNewPosMask(BGCopyNewPos { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), dstx1: c(6), dsty1: c(7), dstpdt: c(8), flag: Some(c(9)) })),
+            Opcode::Buffer(BufferGrpCmd::CopyColor(BGCopyColor { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), dstx1: c(6), dsty1: c(7), dstpdt: c(8), r: c(9), g: c(10), b: c(11) })),
+            Opcode::Buffer(BufferGrpCmd::Swap(BGSwap { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), dstx1: c(6), dsty1: c(7), dstpdt: c(8) })),
+            Opcode::Buffer(BufferGrpCmd::CopyWithMask(BGCopyWithMask { srcx1: c(1), srcy1: c(2), srcx2: c(3), srcy2: c(4), srcpdt: c(5), dstx1: c(6), dsty1: c(7), dstpdt: c(8), flag: c(9) })),
+            Opcode::Buffer(BufferGrpCmd::CopyWholeScreen(BGCopyWholeScreen { srcpdt: c(1), dstpdt: c(2), flag: Some(c(3)) })),
+            Opcode::Buffer(BufferGrpCmd::CopyWholeScreenMask(BGCopyWholeScreen { srcpdt: c(1), dstpdt: c(2), flag: Some(c(3)) })),
+            Opcode::Buffer(BufferGrpCmd::DisplayStrings(BGDisplayStrings { n: c(1), srcx1: c(2), srcy1: c(3), srcx2: c(4), srcy2: c(5), srcdx: c(6), srcdy: c(7), srcpdt: c(8), dstx1: c(9), dsty1: c(10), dstx2: c(11), dsty2: c(12), count: c(13), zero: c(14), dstpdt: c(15) })),
+            Opcode::Buffer(BufferGrpCmd::DisplayStringsMask(BGDisplayStringsMask { n: c(1), srcx1: c(2), srcy1: c(3), srcx2: c(4), srcy2: c(5), srcdx: c(6), srcdy: c(7), srcpdt: c(8), dstx1: c(9), dsty1: c(10), dstx2: c(11), dsty2: c(12), count: c(13), zero: c(14), dstpdt: c(15), flag: c(16) })),
+            Opcode::Buffer(BufferGrpCmd::DisplayStringsColor(BGDisplayStringsColor { n: c(1), srcx1: c(2), srcy1: c(3), srcx2: c(4), srcy2: c(5), srcdx: c(6), srcdy: c(7), srcpdt: c(8), dstx1: c(9), dsty1: c(10), dstx2: c(11), dsty2: c(12), count: c(13), zero: c(14), dstpdt: c(15), r: c(16), g: c(17), b: c(18) })),
+            Opcode::Flash(FlashGrpCmd::FillColor(c(1), c(2), c(3), c(4))),
+            Opcode::Flash(FlashGrpCmd::FlashScreen(c(1), c(2), c(3), c(4), c(5))),
+            Opcode::MultiPdt(MultiPdtCmd::Slideshow(c(1), c(2), vec![pdt(3), pdt(4)])),
+            Opcode::MultiPdt(MultiPdtCmd::SlideshowLoop(c(1), c(2), vec![pdt(3), pdt(4)])),
+            Opcode::MultiPdt(MultiPdtCmd::StopSlideshowLoop),
+            Opcode::MultiPdt(MultiPdtCmd::Scroll(0x01, c(1), c(2), c(3), vec![pdt(4), pdt(5)])),
+            Opcode::MultiPdt(MultiPdtCmd::Scroll2(0x01, c(1), c(2), c(3), vec![pdt(4), pdt(5)])),
+            Opcode::MultiPdt(MultiPdtCmd::ScrollWithCancel(0x01, c(1), c(2), c(3), c(4), vec![pdt(5), pdt(6)])),
+            Opcode::AreaBuffer(AreaBufferCmd::ReadCurArd(lit("FILE1"), lit("FILE2"))),
+            Opcode::AreaBuffer(AreaBufferCmd::Init),
+            Opcode::AreaBuffer(AreaBufferCmd::GetClickedArea(c(1), c(2))),
+            Opcode::AreaBuffer(AreaBufferCmd::GetClickedArea2(c(1), c(2))),
+            Opcode::AreaBuffer(AreaBufferCmd::DisableArea(c(1))),
+            Opcode::AreaBuffer(AreaBufferCmd::EnableArea(c(1))),
+            Opcode::AreaBuffer(AreaBufferCmd::GetArea(c(1), c(2), c(3))),
+            Opcode::AreaBuffer(AreaBufferCmd::AssignArea(c(1), c(2))),
+            Opcode::MouseCtrl(MouseCtrlCmd::WaitForClick),
+            Opcode::MouseCtrl(MouseCtrlCmd::SetPos(c(1), c(2), c(3))),
+            Opcode::MouseCtrl(MouseCtrlCmd::FlushClickData),
+            Opcode::MouseCtrl(MouseCtrlCmd::CursorOff),
+            Opcode::MouseCtrl(MouseCtrlCmd::CursorOn),
+            Opcode::WindowVar(WindowVarCmd::GetBgFlagColor(c(1), c(2), c(3), c(4))),
+            Opcode::WindowVar(WindowVarCmd::SetBgFlagColor(c(1), c(2), c(3), c(4))),
+            Opcode::WindowVar(WindowVarCmd::GetWindowMove(c(1))),
+            Opcode::WindowVar(WindowVarCmd::SetWindowMove(c(1))),
+            Opcode::WindowVar(WindowVarCmd::GetWindowClearBox(c(1))),
+            Opcode::WindowVar(WindowVarCmd::SetWindowClearBox(c(1))),
+            Opcode::WindowVar(WindowVarCmd::GetWindowWaku(c(1))),
+            Opcode::WindowVar(WindowVarCmd::SetWindowWaku(c(1))),
+            Opcode::MessageWin(MessageWinCmd::GetWindowMsgPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::GetWindowComPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::GetWindowSysPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::GetWindowSubPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::GetWindowGrpPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::SetWindowMsgPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::SetWindowComPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::SetWindowSysPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::SetWindowSubPos(c(1), c(2))),
+            Opcode::MessageWin(MessageWinCmd::SetWindowGrpPos(c(1), c(2))),
+            Opcode::SystemVar(SystemVarCmd::GetMessageSize(c(1), c(2))),
+            Opcode::SystemVar(SystemVarCmd::SetMessageSize(c(1), c(2))),
+            Opcode::SystemVar(SystemVarCmd::GetMsgMojiSize(c(1), c(2))),
+            Opcode::SystemVar(SystemVarCmd::SetMsgMojiSize(c(1), c(2))),
+            Opcode::SystemVar(SystemVarCmd::GetMojiColor(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetMojiColor(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetMsgCancel(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetMsgCancel(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetMojiKage(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetMojiKage(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetKageColor(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetKageColor(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetSelCancel(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetSelCancel(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetCtrlKey(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetCtrlKey(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetSaveStart(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetSaveStart(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetDisableNvlTextFlag(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetDisableNvlTextFlag(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetFadeTime(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetFadeTime(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetCursorMono(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetCursorMono(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetCopyWindSw(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetCopyWindSw(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetMsgSpeed(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetMsgSpeed(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetMsgSpeed2(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetMsgSpeed2(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetReturnKeyWait(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetReturnKeyWait(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetKoeTextType(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetKoeTextType(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetGameSpeckInit(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetCursorPosition(c(1), c(2))),
+            Opcode::SystemVar(SystemVarCmd::SetDisableKeyMouseFlag(c(1))),
+            Opcode::SystemVar(SystemVarCmd::GetGameSpeckInit2(c(1))),
+            Opcode::SystemVar(SystemVarCmd::SetGameSpeckInit(c(1))),
+            Opcode::PopupMenu(PopupMenuCmd::GetMenuDisabled(c(1))),
+            Opcode::PopupMenu(PopupMenuCmd::SetMenuDisabled(c(1))),
+            Opcode::PopupMenu(PopupMenuCmd::GetItemDisabled(c(1), c(2))),
+            Opcode::PopupMenu(PopupMenuCmd::SetItemDisabled(c(1), c(2))),
+            Opcode::Volume(VolumeCmd::GetBgmVolume(c(1))),
+            Opcode::Volume(VolumeCmd::GetWavVolume(c(1))),
+            Opcode::Volume(VolumeCmd::GetKoeVolume(c(1))),
+            Opcode::Volume(VolumeCmd::GetSeVolume(c(1))),
+            Opcode::Volume(VolumeCmd::SetBgmVolume(c(1))),
+            Opcode::Volume(VolumeCmd::SetWavVolume(c(1))),
+            Opcode::Volume(VolumeCmd::SetKoeVolume(c(1))),
+            Opcode::Volume(VolumeCmd::SetSeVolume(c(1))),
+            Opcode::Volume(VolumeCmd::MuteBgm(c(1))),
+            Opcode::Volume(VolumeCmd::MuteWav(c(1))),
+            Opcode::Volume(VolumeCmd::MuteKoe(c(1))),
+            Opcode::Volume(VolumeCmd::MuteSe(c(1))),
+            Opcode::NovelMode(NovelModeCmd::SetEnabled(c(1))),
+            Opcode::NovelMode(NovelModeCmd::Unknown1(c(1))),
+            Opcode::NovelMode(NovelModeCmd::Unknown2),
+            Opcode::NovelMode(NovelModeCmd::Unknown3),
+            Opcode::NovelMode(NovelModeCmd::Unknown4),
+            Opcode::WaitMouse,
+            Opcode::Newline,
+            Opcode::WaitMouseText,
+            Opcode::Op0x05,
+            Opcode::Op0x06,
+            Opcode::Op0x08,
+            Opcode::Op0x0c,
+            Opcode::Op0x18,
+            Opcode::Op0x1a,
+            Opcode::Unknown0x22,
+            Opcode::Unknown0x23,
+            Opcode::Unknown0x24,
+            Opcode::Unknown0x25,
+            Opcode::Unknown0x26,
+            Opcode::Unknown0x27,
+            Opcode::Unknown0x28,
+            Opcode::Unknown0x29,
+            Opcode::Op0x2c,
+            Opcode::Op0x2d,
+            Opcode::Op0x30,
+            Opcode::SetFlag(c(1), c(2)),
+            Opcode::CopyFlag(c(1), c(2)),
+            Opcode::SetValLiteral(c(1), c(2)),
+            Opcode::AddVal(c(1), c(2)),
+            Opcode::SubVal(c(1), c(2)),
+            Opcode::MulVal(c(1), c(2)),
+            Opcode::DivVal(c(1), c(2)),
+            Opcode::ModVal(c(1), c(2)),
+            Opcode::AndVal(c(1), c(2)),
+            Opcode::OrVal(c(1), c(2)),
+            Opcode::XorVal(c(1), c(2)),
+            Opcode::SetVal(c(1), c(2)),
+            Opcode::AddValSelf(c(1), c(2)),
+            Opcode::SubValSelf(c(1), c(2)),
+            Opcode::MulValSelf(c(1), c(2)),
+            Opcode::DivValSelf(c(1), c(2)),
+            Opcode::ModValSelf(c(1), c(2)),
+            Opcode::AndValSelf(c(1), c(2)),
+            Opcode::OrValSelf(c(1), c(2)),
+            Opcode::XorValSelf(c(1), c(2)),
+            Opcode::SetFlagRandom(c(1)),
+            Opcode::SetValRandom(c(1), c(2)),
+            Opcode::Op0x5b,
+            Opcode::Op0x5d,
+            Opcode::Op0x5e,
+            Opcode::Op0x5f,
+            Opcode::Op0x63,
+            Opcode::Unknown0x65,
+            Opcode::Op0x69,
+            Opcode::Op0x66,
+            Opcode::Op0x6e,
+            Opcode::Op0x6f,
+            Opcode::Op0x7f,
+            Opcode::Unknown0xea(c(1)),
+            Opcode::Condition(vec![
+                Condition::IncDepth,
+                Condition::BitNotEq(c(1), c(2)), Condition::And,
+                Condition::BitEq(c(3), c(4)), Condition::Or,
+                Condition::NotEq(c(5), c(6)), Condition::And,
+                Condition::Eq(c(7), c(8)), Condition::Or,
+                Condition::FlagNotEqConst(c(9), c(10)), Condition::And,
+                Condition::FlagEqConst(c(11), c(12)), Condition::Or,
+                Condition::FlagAndConst(c(13), c(14)), Condition::And,
+                Condition::FlagAndConst2(c(15), c(16)), Condition::Or,
+                Condition::FlagXorConst(c(17), c(18)), Condition::And,
+                Condition::FlagGtConst(c(19), c(20)), Condition::Or,
+                Condition::FlagLtConst(c(21), c(22)), Condition::And,
+                Condition::FlagGeqConst(c(23), c(24)), Condition::Or,
+                Condition::FlagLeqConst(c(25), c(26)), Condition::And,
+                Condition::FlagNotEq(c(27), c(28)), Condition::Or,
+                Condition::FlagEq(c(29), c(30)), Condition::And,
+                Condition::FlagAnd(c(31), c(32)), Condition::Or,
+                Condition::FlagAnd2(c(33), c(34)), Condition::And,
+                Condition::FlagXor(c(35), c(36)), Condition::Or,
+                Condition::FlagGt(c(37), c(38)), Condition::And,
+                Condition::FlagLt(c(39), c(40)), Condition::Or,
+                Condition::FlagGeq(c(41), c(42)), Condition::And,
+                Condition::FlagLeq(c(43), c(44)), Condition::Or,
+                Condition::IncDepth, Condition::Ret(Ret::Color(c(45))), Condition::Or, Condition::Ret(Ret::Choice), Condition::Or, Condition::Ret(Ret::DisabledChoice(c(46))), Condition::DecDepth,
+                Condition::DecDepth,
+            ], Pos::Offset(0x10)),
+            Opcode::Call(Pos::Offset(0x10)),
+            Opcode::Jump(Pos::Offset(0x20)),
+            Opcode::TableCall(c(1), vec![Pos::Offset(0x10), Pos::Offset(0x20)]),
+            Opcode::TableJump(c(1), vec![Pos::Offset(0x10), Pos::Offset(0x20)]),
+            Opcode::TextHankaku(Some(1), lit("abc")),
+            Opcode::TextHankaku(Some(2), SceneText::Pointer(c(1))),
+            Opcode::TextZenkaku(Some(3), lit("あいう")),
+            Opcode::TextZenkaku(Some(4), SceneText::Pointer(c(2))),
+        ]
+    }
+
+    /// The values bound by `$val` in each of `every_opcode` matching `$pat`.
+    macro_rules! every {
+        ($($pat:pat => $val:expr),+) => {
+            every_opcode().iter().flat_map(|op| {
+                let mut found = Vec::new();
+                $(
+                    if let $pat = op {
+                        found.push($val.clone());
+                    }
+                )+
+                found
+            }).collect::<Vec<_>>()
+        };
+    }
+
+    /// Writes each value and checks that `byte_size` matches the number of
+    /// bytes written, naming the type and value of every one that doesn't.
+    macro_rules! assert_byte_sizes {
+        ($($ty:ty => $values:expr),+ $(,)?) => {{
+            let mut failures = Vec::new();
+            $(
+                let values: Vec<$ty> = $values;
+                assert!(!values.is_empty(), "No values for {}", stringify!($ty));
+                for value in values.iter() {
+                    match value.to_vec() {
+                        Ok(bytes) if bytes.len() == value.byte_size() => (),
+                        Ok(bytes) => failures.push(format!("{}: {:?}\n    byte_size {} but wrote {} bytes: {:02x?}",
+                                                           stringify!($ty), value, value.byte_size(), bytes.len(), bytes)),
+                        Err(e) => failures.push(format!("{}: {:?}\n    write failed: {}", stringify!($ty), value, e))
+                    }
+                }
+            )+
+            assert!(failures.is_empty(), "{} values have the wrong byte_size:\n{}", failures.len(), failures.join("\n"));
+        }};
+    }
+
+    #[test]
+    fn test_byte_size_every_type() {
+        let flag = Flag { unk1: 1, flags: vec![2, 3] };
+        let submenu = Submenu { id: 1, unk1: 2, unk2: 3, flags: vec![flag.clone(), Flag { unk1: 0, flags: vec![] }] };
+        let menu = Menu { id: 1, unk1: 2, unk2: 3, submenus: vec![submenu.clone()] };
+        let scene = crate::build::sample_scene();
+
+        let mut opcodes = every_opcode();
+        opcodes.push(Opcode::UnknownRaw(0x00, vec![0x01, 0x02]));
+
+        assert_byte_sizes! {
+            u8 => vec![0x00, 0xff],
+            u16 => vec![0x1234],
+            u32 => vec![0x12345678],
+            &str => vec!["", "abc", "あいう"],
+            String => vec![String::from("あいう")],
+            Option<Val> => vec![None, Some(c(1))],
+            Vec<Val> => vec![vec![], vec![c(1), c(0x12345)]],
+            Header => vec![Header::empty(), scene.header.clone()],
+            Menu => vec![menu, Menu { id: 2, unk1: 0, unk2: 0, submenus: vec![] }],
+            Submenu => vec![submenu],
+            Flag => vec![flag],
+            Pos => vec![Pos::Offset(0x10)],
+            Val => vec![c(0), c(0x10), c(0x1000), c(0x100000), c(0x10000000), Val::variable(3)],
+            SceneText => vec![lit(""), lit("FILE1"), SceneText::Pointer(c(1))],
+            FormattedTextCmd => every!(Opcode::DrawValText(x) => x),
+            Ret => vec![Ret::Color(c(1)), Ret::Choice, Ret::DisabledChoice(c(2))],
+            Condition => every!(Opcode::Condition(x, _) => x).concat(),
+            SceneFormattedTextEntry => rich_text().0,
+            SceneFormattedText => vec![SceneFormattedText(vec![]), ftext("はい"), rich_text()],
+            JumpToSceneCmd => every!(Opcode::JumpToScene(x) => x),
+            TextWinCmd => every!(Opcode::TextWin(x) => x),
+            FadeCmd => every!(Opcode::Fade(x) => x),
+            GrpEffect => every!(Opcode::Graphics(GrpCmd::LoadEffect(x)) => x),
+            GrpCompositeChild => composite_children(),
+            GrpComposite => every!(Opcode::Graphics(GrpCmd::LoadComposite(x)) => x),
+            GrpCompositeIndexed => every!(Opcode::Graphics(GrpCmd::LoadCompositeIndexed(x)) => x),
+            GrpCmd => every!(Opcode::Graphics(x) => x),
+            ScreenShakeCmd => every!(Opcode::ScreenShake(x) => x),
+            SndCmd => every!(Opcode::Sound(x) => x),
+            WaitCmd => every!(Opcode::Wait(x) => x),
+            RetCmd => every!(Opcode::Return(x) => x),
+            ScenarioMenuCmd => every!(Opcode::ScenarioMenu(x) => x),
+            TextRankCmd => every!(Opcode::TextRank(x) => x),
+            Choice => vec![Choice::Choice, Choice::End],
+            ChoiceText => every!(Opcode::Choice(ChoiceCmd::Choice(_, _, Some(x))) => x,
+                                 Opcode::Choice(ChoiceCmd::Choice2(_, _, Some(x))) => x),
+            ChoiceCmd => every!(Opcode::Choice(x) => x),
+            StringCmd => every!(Opcode::String(x) => x),
+            SetMultiCmd => every!(Opcode::SetMulti(x) => x),
+            BRGRectColor => every!(Opcode::BufferRegion(BufferRegionGrpCmd::ClearRect(x)) => x),
+            BRGRect => every!(Opcode::BufferRegion(BufferRegionGrpCmd::InvertColor(x)) => x),
+            BRGFadeOutColor => every!(Opcode::BufferRegion(BufferRegionGrpCmd::FadeOutColor3(x)) => x),
+            BRGStretchBlit => every!(Opcode::BufferRegion(BufferRegionGrpCmd::StretchBlit(x)) => x),
+            BRGStretchBlitEffect => every!(Opcode::BufferRegion(BufferRegionGrpCmd::StretchBlitEffect(x)) => x),
+            BufferRegionGrpCmd => every!(Opcode::BufferRegion(x) => x),
+            BGCopySamePos => every!(Opcode::Buffer(BufferGrpCmd::CopySamePos(x)) => x),
+            BGCopyNewPos => every!(Opcode::Buffer(BufferGrpCmd::CopyNewPos(x)) => x),
+            BGCopyColor => every!(Opcode::Buffer(BufferGrpCmd::CopyColor(x)) => x),
+            BGSwap => every!(Opcode::Buffer(BufferGrpCmd::Swap(x)) => x),
+            BGCopyWithMask => every!(Opcode::Buffer(BufferGrpCmd::CopyWithMask(x)) => x),
+            BGCopyWholeScreen => every!(Opcode::Buffer(BufferGrpCmd::CopyWholeScreen(x)) => x),
+            BGDisplayStrings => every!(Opcode::Buffer(BufferGrpCmd::DisplayStrings(x)) => x),
+            BGDisplayStringsMask => every!(Opcode::Buffer(BufferGrpCmd::DisplayStringsMask(x)) => x),
+            BGDisplayStringsColor => every!(Opcode::Buffer(BufferGrpCmd::DisplayStringsColor(x)) => x),
+            BufferGrpCmd => every!(Opcode::Buffer(x) => x),
+            FlashGrpCmd => every!(Opcode::Flash(x) => x),
+            MultiPdtEntry => vec![pdt(1), MultiPdtEntry { text: SceneText::Pointer(c(2)), data: c(3) }],
+            MultiPdtCmd => every!(Opcode::MultiPdt(x) => x),
+            SystemCmd => every!(Opcode::System(x) => x),
+            NameInputItem => every!(Opcode::Name(NameCmd::NameInputDialogMulti(x)) => x).concat(),
+            NameCmd => every!(Opcode::Name(x) => x),
+            AreaBufferCmd => every!(Opcode::AreaBuffer(x) => x),
+            MouseCtrlCmd => every!(Opcode::MouseCtrl(x) => x),
+            VolumeCmd => every!(Opcode::Volume(x) => x),
+            NovelModeCmd => every!(Opcode::NovelMode(x) => x),
+            WindowVarCmd => every!(Opcode::WindowVar(x) => x),
+            MessageWinCmd => every!(Opcode::MessageWin(x) => x),
+            SystemVarCmd => every!(Opcode::SystemVar(x) => x),
+            PopupMenuCmd => every!(Opcode::PopupMenu(x) => x),
+            Opcode => opcodes,
+            AVG32Scene => vec![scene],
+        }
+    }
+
+    /// Writes each opcode, parses it back and checks that the result is
+    /// the same opcode, listing every one that isn't with its bytes.
+    fn assert_opcode_tables_consistent(opcodes: &[Opcode]) {
+        let mut failures = Vec::new();
+
+        for opcode in opcodes.iter() {
+            let mut bytes = Vec::new();
+            if let Err(e) = opcode.write(&mut bytes) {
+                failures.push(format!("{:?}\n    write failed: {}", opcode, e));
+                continue;
+            }
+            if bytes.len() != opcode.byte_size() {
+                failures.push(format!("{:?}\n    byte_size {} but wrote {} bytes: {:02x?}", opcode, opcode.byte_size(), bytes.len(), bytes));
+                continue;
+            }
+
+            match parser::opcode(&bytes) {
+                Ok((rest, ref parsed)) if rest.is_empty() && parsed == opcode => (),
+                Ok((rest, parsed)) => {
+                    let reparsed = parsed.to_vec().unwrap();
+                    failures.push(format!("{:?}\n    wrote     {:02x?}\n    read back {:?}\n    which is  {:02x?} ({} bytes left over)", opcode, bytes, parsed, reparsed, rest.len()));
+                },
+                Err(e) => failures.push(format!("{:?}\n    wrote {:02x?}\n    parse failed: {:?}", opcode, bytes, e))
+            }
+        }
+
+        assert!(failures.is_empty(), "{} of {} opcodes don't round-trip:\n{}", failures.len(), opcodes.len(), failures.join("\n"));
+    }
+
+    #[test]
+    fn test_opcode_tables_consistent() {
+        assert_opcode_tables_consistent(&every_opcode());
+    }
+
+    #[test]
+    fn test_opcode_byte() {
+        for opcode in every_opcode().iter() {
+            assert_eq!(opcode.to_vec().unwrap()[0], opcode.byte(), "{:?}", opcode);
+        }
+        assert_eq!(0x99, Opcode::UnknownRaw(0x99, vec![1, 2]).byte());
+    }
+
+    #[test]
+    fn test_operand_bytes() {
+        for opcode in every_opcode().iter() {
+            assert_eq!(opcode.byte_size(), opcode.operand_bytes().unwrap().len() + 1, "{:?}", opcode);
+        }
+
+        let text = Opcode::TextZenkaku(Some(2), SceneText::Literal(String::from("あ")));
+        assert_eq!(vec![0x02, 0x00, 0x00, 0x00, 0x82, 0xa0, 0x00], text.operand_bytes().unwrap());
+        assert_eq!(vec![0x10, 0x00, 0x00, 0x00], Opcode::Jump(Pos::Offset(0x10)).operand_bytes().unwrap());
+        assert!(Opcode::WaitMouse.operand_bytes().unwrap().is_empty());
+        assert_eq!(vec![1, 2], Opcode::UnknownRaw(0x99, vec![1, 2]).operand_bytes().unwrap());
+        assert!(Opcode::TextHankaku(None, SceneText::Literal(String::from("\u{1F600}"))).operand_bytes().is_err());
+    }
+
     #[test]
     fn test_string_size() {
         assert_eq!(11, "あいうえお".byte_size());
     }
 
+    #[test]
+    fn test_string_unencodable() {
+        let s = "あい😀う";
+        assert!(!can_encode_sjis(s));
+        assert_eq!(Some((6, '😀')), find_unencodable_sjis(s));
+        assert!(s.byte_size() > 0);
+
+        let mut out = Vec::new();
+        let err = s.write(&mut out).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().contains("U+1F600"));
+    }
+
     #[test]
     fn test_val_size() {
         assert_eq!(1, Val(0x00, ValType::Const).byte_size());